@@ -0,0 +1,249 @@
+use std::collections::BTreeMap;
+
+use chrono::{DateTime, Duration, Utc};
+use hickory_proto::op::ResponseCode;
+use tracing::{info, warn};
+
+
+// weight of the newest interval in the moving average and variance
+const EWMA_ALPHA: f64 = 0.1;
+
+// intervals needed to establish a baseline before alerting
+const WARMUP_INTERVALS: u64 = 10;
+
+// gaps in the traffic longer than this many intervals restart the baseline
+const MAX_EMPTY_INTERVALS: i32 = 1000;
+
+
+#[derive(Clone, Copy, Debug, Eq, Hash, Ord, PartialEq, PartialOrd)]
+pub enum AnomalyMetric {
+    QueryRate,
+    NxdomainRatio,
+    ServfailRatio,
+}
+
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Anomaly {
+    pub metric: AnomalyMetric,
+    pub value: f64,
+    pub baseline: f64,
+}
+
+
+/// Tracks the exponentially weighted moving average and variance of a value and flags values that
+/// deviate from the average by more than the given number of standard deviations.
+#[derive(Clone, Debug, PartialEq)]
+pub struct EwmaDetector {
+    threshold: f64,
+    observation_count: u64,
+    mean: f64,
+    variance: f64,
+    active: bool,
+}
+impl EwmaDetector {
+    pub fn new(threshold: f64) -> Self {
+        Self {
+            threshold,
+            observation_count: 0,
+            mean: 0.0,
+            variance: 0.0,
+            active: false,
+        }
+    }
+
+    pub fn active(&self) -> bool {
+        self.active
+    }
+
+    /// Checks the value against the baseline, then includes it in the baseline. Returns whether the
+    /// value is anomalous.
+    pub fn observe(&mut self, value: f64) -> bool {
+        self.observation_count += 1;
+        if self.observation_count == 1 {
+            self.mean = value;
+            self.variance = 0.0;
+            return false;
+        }
+
+        let difference = value - self.mean;
+        let standard_deviation = self.variance.sqrt();
+        self.active =
+            self.observation_count > WARMUP_INTERVALS
+            && difference.abs() > self.threshold * standard_deviation
+            && difference.abs() > f64::EPSILON;
+
+        let increment = EWMA_ALPHA * difference;
+        self.mean += increment;
+        self.variance = (1.0 - EWMA_ALPHA) * (self.variance + difference * increment);
+
+        self.active
+    }
+}
+
+
+/// Detects sudden changes in the query rate and in the share of NXDOMAIN and SERVFAIL responses,
+/// evaluated over consecutive intervals of packet time.
+#[derive(Clone, Debug, PartialEq)]
+pub struct AnomalyDetector {
+    interval: Duration,
+    interval_start: Option<DateTime<Utc>>,
+    query_count: u64,
+    response_count: u64,
+    nxdomain_count: u64,
+    servfail_count: u64,
+    metric_to_detector: BTreeMap<AnomalyMetric, EwmaDetector>,
+    new_anomalies: Vec<Anomaly>,
+}
+impl AnomalyDetector {
+    pub fn new(interval: Duration, threshold: f64) -> Self {
+        let mut metric_to_detector = BTreeMap::new();
+        for metric in [AnomalyMetric::QueryRate, AnomalyMetric::NxdomainRatio, AnomalyMetric::ServfailRatio] {
+            metric_to_detector.insert(metric, EwmaDetector::new(threshold));
+        }
+        Self {
+            interval,
+            interval_start: None,
+            query_count: 0,
+            response_count: 0,
+            nxdomain_count: 0,
+            servfail_count: 0,
+            metric_to_detector,
+            new_anomalies: Vec::new(),
+        }
+    }
+
+    fn evaluate(&mut self, metric: AnomalyMetric, value: f64) {
+        let detector = self.metric_to_detector.get_mut(&metric).unwrap();
+        let was_active = detector.active();
+        let baseline = detector.mean;
+        let is_active = detector.observe(value);
+        if is_active && !was_active {
+            warn!(?metric, value, baseline, "anomaly detected");
+            self.new_anomalies.push(Anomaly {
+                metric,
+                value,
+                baseline,
+            });
+        } else if was_active && !is_active {
+            info!(?metric, value, baseline, "anomaly cleared");
+        }
+    }
+
+    /// Evaluates the intervals that have ended before the given timestamp.
+    fn advance(&mut self, timestamp: DateTime<Utc>) {
+        let mut interval_start = match self.interval_start {
+            Some(s) => s,
+            None => {
+                self.interval_start = Some(timestamp);
+                return;
+            },
+        };
+
+        let mut evaluated_intervals = 0;
+        while timestamp >= interval_start + self.interval {
+            if evaluated_intervals == MAX_EMPTY_INTERVALS {
+                // the traffic has not been seen for a long time; start over
+                let threshold = self.metric_to_detector[&AnomalyMetric::QueryRate].threshold;
+                *self = Self::new(self.interval, threshold);
+                self.interval_start = Some(timestamp);
+                return;
+            }
+
+            let interval_secs = self.interval.num_milliseconds() as f64 / 1000.0;
+            self.evaluate(AnomalyMetric::QueryRate, self.query_count as f64 / interval_secs);
+            if self.response_count > 0 {
+                let response_count = self.response_count as f64;
+                self.evaluate(AnomalyMetric::NxdomainRatio, self.nxdomain_count as f64 / response_count);
+                self.evaluate(AnomalyMetric::ServfailRatio, self.servfail_count as f64 / response_count);
+            }
+
+            self.query_count = 0;
+            self.response_count = 0;
+            self.nxdomain_count = 0;
+            self.servfail_count = 0;
+            interval_start = interval_start + self.interval;
+            evaluated_intervals += 1;
+        }
+        self.interval_start = Some(interval_start);
+    }
+
+    pub fn observe_query(&mut self, timestamp: DateTime<Utc>) {
+        self.advance(timestamp);
+        self.query_count += 1;
+    }
+
+    pub fn observe_response(&mut self, timestamp: DateTime<Utc>, response_code: ResponseCode) {
+        self.advance(timestamp);
+        self.response_count += 1;
+        match response_code {
+            ResponseCode::NXDomain => self.nxdomain_count += 1,
+            ResponseCode::ServFail => self.servfail_count += 1,
+            _ => {},
+        }
+    }
+
+    /// Returns the anomalies that have been detected since the last call.
+    pub fn take_new_anomalies(&mut self) -> Vec<Anomaly> {
+        std::mem::take(&mut self.new_anomalies)
+    }
+
+    /// Returns whether an anomaly is currently active for each metric.
+    pub fn active_anomalies(&self) -> BTreeMap<AnomalyMetric, bool> {
+        self.metric_to_detector.iter()
+            .map(|(metric, detector)| (*metric, detector.active()))
+            .collect()
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use chrono::{Duration, TimeZone, Utc};
+    use hickory_proto::op::ResponseCode;
+
+    use super::{AnomalyDetector, AnomalyMetric};
+
+    #[test]
+    fn test_query_spike() {
+        let start = Utc.with_ymd_and_hms(2022, 10, 1, 12, 0, 0).unwrap();
+        let mut detector = AnomalyDetector::new(Duration::seconds(10), 4.0);
+
+        // 20 intervals of alternating 10 and 12 queries with no errors
+        let mut timestamp = start;
+        for interval in 0..20 {
+            let query_count = if interval % 2 == 0 { 10 } else { 12 };
+            for i in 0..query_count {
+                detector.observe_query(timestamp + Duration::milliseconds(i * 100));
+                detector.observe_response(timestamp + Duration::milliseconds(i * 100 + 50), ResponseCode::NoError);
+            }
+            timestamp = timestamp + Duration::seconds(10);
+        }
+        detector.observe_query(timestamp);
+        assert_eq!(detector.active_anomalies().values().filter(|a| **a).count(), 0);
+
+        // then a burst of failing queries
+        for i in 1..200 {
+            detector.observe_query(timestamp + Duration::milliseconds(i * 10));
+            detector.observe_response(timestamp + Duration::milliseconds(i * 10 + 5), ResponseCode::NXDomain);
+        }
+        detector.observe_query(timestamp + Duration::seconds(10));
+        let active = detector.active_anomalies();
+        assert_eq!(active[&AnomalyMetric::QueryRate], true);
+        assert_eq!(active[&AnomalyMetric::NxdomainRatio], true);
+        assert_eq!(active[&AnomalyMetric::ServfailRatio], false);
+        let new_anomalies: Vec<AnomalyMetric> = detector.take_new_anomalies().iter()
+            .map(|a| a.metric)
+            .collect();
+        assert_eq!(new_anomalies, vec![AnomalyMetric::QueryRate, AnomalyMetric::NxdomainRatio]);
+        assert_eq!(detector.take_new_anomalies().len(), 0);
+
+        // and back to normal
+        for i in 0..10 {
+            detector.observe_query(timestamp + Duration::seconds(10) + Duration::milliseconds(i * 100));
+            detector.observe_response(timestamp + Duration::seconds(10) + Duration::milliseconds(i * 100 + 50), ResponseCode::NoError);
+        }
+        detector.observe_query(timestamp + Duration::seconds(20));
+        assert_eq!(detector.active_anomalies()[&AnomalyMetric::QueryRate], false);
+    }
+}
@@ -0,0 +1,187 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt;
+use std::fs;
+use std::io;
+use std::net::IpAddr;
+use std::path::Path;
+
+use crate::ip::mask_address;
+
+
+#[derive(Debug)]
+pub enum WatchlistError {
+    Io(io::Error),
+    InvalidNetwork(String),
+}
+impl fmt::Display for WatchlistError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Io(e)
+                => write!(f, "I/O error: {}", e),
+            Self::InvalidNetwork(s)
+                => write!(f, "invalid address or network {:?}", s),
+        }
+    }
+}
+impl std::error::Error for WatchlistError {
+}
+impl From<io::Error> for WatchlistError {
+    fn from(e: io::Error) -> Self { Self::Io(e) }
+}
+
+
+fn parse_network(s: &str) -> Result<(IpAddr, u8), WatchlistError> {
+    let (address_str, prefix_length_str) = match s.split_once('/') {
+        Some((a, p)) => (a, Some(p)),
+        None => (s, None),
+    };
+    let address: IpAddr = address_str.parse()
+        .map_err(|_| WatchlistError::InvalidNetwork(s.to_owned()))?;
+    let max_prefix_length = if address.is_ipv4() { 32 } else { 128 };
+    let prefix_length = match prefix_length_str {
+        Some(p) => p.parse().map_err(|_| WatchlistError::InvalidNetwork(s.to_owned()))?,
+        None => max_prefix_length,
+    };
+    if prefix_length > max_prefix_length {
+        return Err(WatchlistError::InvalidNetwork(s.to_owned()));
+    }
+    Ok((mask_address(address, prefix_length), prefix_length))
+}
+
+
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+struct WatchedName {
+    expected_networks: Vec<(IpAddr, u8)>, // if empty, the addresses are learned
+    seen_addresses: BTreeSet<IpAddr>,
+}
+impl WatchedName {
+    fn is_expected(&self, address: IpAddr) -> bool {
+        if self.expected_networks.len() == 0 {
+            // the first answer establishes the expected addresses
+            return self.seen_addresses.len() == 0 || self.seen_addresses.contains(&address);
+        }
+        self.expected_networks.iter()
+            .any(|(network, prefix_length)| network.is_ipv4() == address.is_ipv4() && mask_address(address, *prefix_length) == *network)
+    }
+}
+
+
+/// An address in an answer that the watchlist did not expect.
+#[derive(Clone, Copy, Debug, Eq, Hash, Ord, PartialEq, PartialOrd)]
+pub struct UnexpectedAnswer {
+    pub address: IpAddr,
+    pub first_seen: bool,
+}
+
+
+/// Keeps track of the addresses that critical names (e.g. single sign-on domains) resolve to,
+/// noticing when an unexpected one appears.
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct AnswerWatchlist {
+    name_to_watch: BTreeMap<String, WatchedName>,
+}
+impl AnswerWatchlist {
+    pub fn new() -> Self {
+        Self {
+            name_to_watch: BTreeMap::new(),
+        }
+    }
+
+    pub fn load_file<P: AsRef<Path>>(&mut self, path: P) -> Result<(), WatchlistError> {
+        let text = fs::read_to_string(path)?;
+        self.add_entries(&text)
+    }
+
+    /// Adds the entries from a watchlist. Each line contains a name, optionally followed by the
+    /// addresses and networks (`address/prefix`) it may resolve to; if none are given, the
+    /// addresses of the first answer are expected and each new address is only reported once.
+    /// Comments start with `#`.
+    pub fn add_entries(&mut self, text: &str) -> Result<(), WatchlistError> {
+        for line in text.lines() {
+            let content = match line.find('#') {
+                Some(hash_index) => &line[..hash_index],
+                None => line,
+            };
+
+            let mut tokens = content.split_whitespace();
+            let name = match tokens.next() {
+                Some(n) => n.trim_end_matches('.').to_ascii_lowercase(),
+                None => continue, // empty line
+            };
+            let watch = self.name_to_watch.entry(name).or_default();
+            for token in tokens {
+                watch.expected_networks.push(parse_network(token)?);
+            }
+        }
+        Ok(())
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.name_to_watch.len() == 0
+    }
+
+    /// Checks the addresses answered for the given normalized name, returning those that were not
+    /// expected.
+    pub fn observe(&mut self, normalized_name: &str, addresses: &[IpAddr]) -> Vec<UnexpectedAnswer> {
+        let watch = match self.name_to_watch.get_mut(normalized_name) {
+            Some(w) => w,
+            None => return Vec::new(),
+        };
+
+        let unexpected: Vec<UnexpectedAnswer> = addresses.iter()
+            .filter(|address| !watch.is_expected(**address))
+            .map(|address| UnexpectedAnswer {
+                address: *address,
+                first_seen: !watch.seen_addresses.contains(address),
+            })
+            .collect();
+        watch.seen_addresses.extend(addresses.iter().copied());
+        unexpected
+    }
+
+    /// The number of distinct addresses seen in the answers for each watched name.
+    pub fn seen_address_counts(&self) -> impl Iterator<Item = (&str, usize)> {
+        self.name_to_watch.iter()
+            .map(|(name, watch)| (name.as_str(), watch.seen_addresses.len()))
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use std::net::IpAddr;
+
+    use super::{AnswerWatchlist, UnexpectedAnswer};
+
+    #[test]
+    fn test_observe() {
+        let mut watchlist = AnswerWatchlist::new();
+        watchlist.add_entries("sso.example.com. 192.0.2.0/28 2001:db8::1 # identity provider\nmail.example.com\n").unwrap();
+        let address = |s: &str| -> IpAddr { s.parse().unwrap() };
+
+        // configured networks
+        assert_eq!(watchlist.observe("sso.example.com", &[address("192.0.2.5"), address("2001:db8::1")]), vec![]);
+        assert_eq!(
+            watchlist.observe("sso.example.com", &[address("198.51.100.7")]),
+            vec![UnexpectedAnswer { address: address("198.51.100.7"), first_seen: true }],
+        );
+        assert_eq!(
+            watchlist.observe("sso.example.com", &[address("198.51.100.7")]),
+            vec![UnexpectedAnswer { address: address("198.51.100.7"), first_seen: false }],
+        );
+
+        // learned addresses
+        assert_eq!(watchlist.observe("mail.example.com", &[address("192.0.2.25"), address("192.0.2.26")]), vec![]);
+        assert_eq!(watchlist.observe("mail.example.com", &[address("192.0.2.26")]), vec![]);
+        assert_eq!(
+            watchlist.observe("mail.example.com", &[address("203.0.113.25")]),
+            vec![UnexpectedAnswer { address: address("203.0.113.25"), first_seen: true }],
+        );
+        assert_eq!(watchlist.observe("www.example.com", &[address("203.0.113.80")]), vec![]);
+
+        let counts: Vec<(&str, usize)> = watchlist.seen_address_counts().collect();
+        assert_eq!(counts, vec![("mail.example.com", 3), ("sso.example.com", 3)]);
+
+        assert!(watchlist.add_entries("bad.example.com 192.0.2.0/33").is_err());
+    }
+}
@@ -0,0 +1,134 @@
+use std::collections::HashMap;
+use std::fs;
+use std::io;
+use std::path::Path;
+
+
+// a starting point; deployments can add their own or override these
+const DEFAULT_CATEGORIES: [(&str, &str); 40] = [
+    ("doubleclick.net", "ads"),
+    ("googlesyndication.com", "ads"),
+    ("googleadservices.com", "ads"),
+    ("adnxs.com", "ads"),
+    ("adsrvr.org", "ads"),
+    ("criteo.com", "ads"),
+    ("taboola.com", "ads"),
+    ("outbrain.com", "ads"),
+    ("google-analytics.com", "telemetry"),
+    ("app-measurement.com", "telemetry"),
+    ("events.data.microsoft.com", "telemetry"),
+    ("telemetry.microsoft.com", "telemetry"),
+    ("metrics.icloud.com", "telemetry"),
+    ("crashlytics.com", "telemetry"),
+    ("sentry.io", "telemetry"),
+    ("scorecardresearch.com", "telemetry"),
+    ("googlevideo.com", "video"),
+    ("ytimg.com", "video"),
+    ("nflxvideo.net", "video"),
+    ("nflxso.net", "video"),
+    ("ttvnw.net", "video"),
+    ("vimeocdn.com", "video"),
+    ("aiv-cdn.net", "video"),
+    ("dssott.com", "video"),
+    ("windowsupdate.com", "updates"),
+    ("update.microsoft.com", "updates"),
+    ("delivery.mp.microsoft.com", "updates"),
+    ("swcdn.apple.com", "updates"),
+    ("mesu.apple.com", "updates"),
+    ("dl.google.com", "updates"),
+    ("download.docker.com", "updates"),
+    ("security.debian.org", "updates"),
+    ("archive.ubuntu.com", "updates"),
+    ("alexa.amazon.com", "smart-home"),
+    ("meethue.com", "smart-home"),
+    ("nest.com", "smart-home"),
+    ("home.nest.com", "smart-home"),
+    ("tuyaeu.com", "smart-home"),
+    ("tuyaus.com", "smart-home"),
+    ("smartthings.com", "smart-home"),
+];
+
+
+/// Assigns queried names to application categories (such as ads, telemetry or video) by their
+/// domain suffix.
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct AppCategories {
+    suffix_to_category: HashMap<String, String>,
+}
+impl AppCategories {
+    pub fn new() -> Self {
+        Self {
+            suffix_to_category: HashMap::new(),
+        }
+    }
+
+    /// Creates the mapping with the built-in categories.
+    pub fn with_defaults() -> Self {
+        let suffix_to_category = DEFAULT_CATEGORIES.iter()
+            .map(|(suffix, category)| ((*suffix).to_owned(), (*category).to_owned()))
+            .collect();
+        Self {
+            suffix_to_category,
+        }
+    }
+
+    pub fn load_file<P: AsRef<Path>>(&mut self, path: P) -> Result<(), io::Error> {
+        let text = fs::read_to_string(path)?;
+        self.add_entries(&text);
+        Ok(())
+    }
+
+    /// Adds the entries from a mapping with one `suffix category` pair per line, replacing any
+    /// previous category of the same suffix. Comments start with `#`.
+    pub fn add_entries(&mut self, text: &str) {
+        for line in text.lines() {
+            let content = match line.find('#') {
+                Some(hash_index) => &line[..hash_index],
+                None => line,
+            };
+
+            let mut tokens = content.split_whitespace();
+            let (suffix, category) = match (tokens.next(), tokens.next()) {
+                (Some(s), Some(c)) => (s, c),
+                _ => continue, // empty or incomplete line
+            };
+            let suffix = suffix.trim_end_matches('.').to_ascii_lowercase();
+            self.suffix_to_category.insert(suffix, category.to_owned());
+        }
+    }
+
+    /// Returns the category of the given normalized name, going by the longest matching suffix.
+    pub fn category(&self, normalized_name: &str) -> Option<&str> {
+        let mut suffix = normalized_name;
+        loop {
+            if let Some(category) = self.suffix_to_category.get(suffix) {
+                return Some(category.as_str());
+            }
+            match suffix.find('.') {
+                Some(dot_index) => suffix = &suffix[dot_index+1..],
+                None => return None,
+            }
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::AppCategories;
+
+    #[test]
+    fn test_category() {
+        let mut categories = AppCategories::with_defaults();
+        assert_eq!(categories.category("rr3---sn-4g5e6nsz.googlevideo.com"), Some("video"));
+        assert_eq!(categories.category("googlevideo.com"), Some("video"));
+        assert_eq!(categories.category("notgooglevideo.com"), None);
+        assert_eq!(categories.category("example.com"), None);
+
+        categories.add_entries("nest.com iot # rename\nexample.com. intranet\nincomplete.example\n");
+        assert_eq!(categories.category("home.nest.com"), Some("smart-home"));
+        assert_eq!(categories.category("video.nest.com"), Some("iot"));
+        assert_eq!(categories.category("www.example.com"), Some("intranet"));
+        assert_eq!(categories.category("incomplete.example"), None);
+    }
+}
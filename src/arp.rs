@@ -0,0 +1,52 @@
+use std::net::Ipv4Addr;
+
+use macaddr::MacAddr6;
+
+use crate::bytes::TryFromBytes;
+use crate::ethernet::ETHERTYPE_IPV4;
+use crate::packet::PacketDissection;
+
+
+// managed by IANA: https://www.iana.org/assignments/arp-parameters/arp-parameters.xhtml
+pub const ARP_HARDWARE_ETHERNET: u16 = 1;
+
+
+#[derive(Clone, Copy, Debug, Eq, Hash, Ord, PartialEq, PartialOrd)]
+// as defined in RFC826, "Packet format" section; only Ethernet and IPv4 are supported
+pub struct ArpPacket {
+    pub operation: u16,
+    pub sender_hardware_address: MacAddr6,
+    pub sender_protocol_address: Ipv4Addr,
+    pub target_hardware_address: MacAddr6,
+    pub target_protocol_address: Ipv4Addr,
+}
+impl ArpPacket {
+    pub fn try_take(bytes: &[u8]) -> PacketDissection<Self> {
+        if bytes.len() < 28 {
+            return PacketDissection::TooShort;
+        }
+
+        let hardware_type = u16::from_be_bytes(bytes[0..2].try_into().unwrap());
+        let protocol_type = u16::from_be_bytes(bytes[2..4].try_into().unwrap());
+        let hardware_length = bytes[4];
+        let protocol_length = bytes[5];
+        if hardware_type != ARP_HARDWARE_ETHERNET || protocol_type != ETHERTYPE_IPV4 || hardware_length != 6 || protocol_length != 4 {
+            return PacketDissection::WrongType;
+        }
+
+        let operation = u16::from_be_bytes(bytes[6..8].try_into().unwrap());
+        let sender_hardware_address = MacAddr6::try_from_bytes(&bytes[8..14]).unwrap();
+        let sender_protocol_address = Ipv4Addr::try_from_bytes(&bytes[14..18]).unwrap();
+        let target_hardware_address = MacAddr6::try_from_bytes(&bytes[18..24]).unwrap();
+        let target_protocol_address = Ipv4Addr::try_from_bytes(&bytes[24..28]).unwrap();
+
+        let packet = Self {
+            operation,
+            sender_hardware_address,
+            sender_protocol_address,
+            target_hardware_address,
+            target_protocol_address,
+        };
+        PacketDissection::Success { header: packet, rest: &bytes[28..] }
+    }
+}
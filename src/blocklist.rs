@@ -0,0 +1,197 @@
+use std::collections::HashSet;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::net::IpAddr;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+use hickory_proto::rr::Name;
+
+#[cfg(feature = "http")] use crate::http::{get, HttpError, HttpUrl};
+
+
+/// Converts a DNS name into the normalized form used for blocklist lookups: lowercase labels
+/// separated by dots, without a trailing dot.
+pub fn normalize_name(name: &Name) -> String {
+    let labels: Vec<String> = name.iter()
+        .map(|label| String::from_utf8_lossy(label).to_ascii_lowercase())
+        .collect();
+    labels.join(".")
+}
+
+
+#[derive(Debug)]
+pub enum BlocklistError {
+    Io(PathBuf, io::Error),
+    #[cfg(feature = "http")] Http(String, HttpError),
+    #[cfg(not(feature = "http"))] UrlUnsupported(String),
+}
+impl fmt::Display for BlocklistError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Io(path, e)
+                => write!(f, "failed to read blocklist {:?}: {}", path, e),
+            #[cfg(feature = "http")]
+            Self::Http(url, e)
+                => write!(f, "failed to fetch blocklist {:?}: {}", url, e),
+            #[cfg(not(feature = "http"))]
+            Self::UrlUnsupported(url)
+                => write!(f, "fetching blocklist {:?} requires the http feature", url),
+        }
+    }
+}
+impl std::error::Error for BlocklistError {
+}
+
+
+/// Where a blocklist is loaded from: a local file or, with the http feature, a URL.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum BlocklistSource {
+    File(PathBuf),
+    #[cfg(feature = "http")] Url(String, HttpUrl),
+}
+#[cfg(feature = "http")]
+impl BlocklistSource {
+    pub fn is_url(&self) -> bool {
+        match self {
+            Self::File(_) => false,
+            Self::Url(_, _) => true,
+        }
+    }
+}
+impl FromStr for BlocklistSource {
+    type Err = BlocklistError;
+
+    /// Takes anything starting with `http://` or `https://` as a URL and everything else as a path.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if !(s.starts_with("http://") || s.starts_with("https://")) {
+            return Ok(Self::File(PathBuf::from(s)));
+        }
+        #[cfg(feature = "http")]
+        {
+            let url = HttpUrl::parse(s)
+                .map_err(|e| BlocklistError::Http(s.to_owned(), e))?;
+            Ok(Self::Url(s.to_owned(), url))
+        }
+        #[cfg(not(feature = "http"))]
+        Err(BlocklistError::UrlUnsupported(s.to_owned()))
+    }
+}
+
+
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct Blocklist {
+    domains: HashSet<String>,
+}
+impl Blocklist {
+    pub fn new() -> Self {
+        Self {
+            domains: HashSet::new(),
+        }
+    }
+
+    pub fn load_file<P: AsRef<Path>>(&mut self, path: P) -> Result<(), io::Error> {
+        let text = fs::read_to_string(path)?;
+        self.add_entries(&text);
+        Ok(())
+    }
+
+    /// Loads a blocklist combining the entries from all the given sources.
+    pub async fn load(sources: &[BlocklistSource]) -> Result<Self, BlocklistError> {
+        let mut blocklist = Self::new();
+        for source in sources {
+            match source {
+                BlocklistSource::File(path) => {
+                    blocklist.load_file(path)
+                        .map_err(|e| BlocklistError::Io(path.clone(), e))?;
+                },
+                #[cfg(feature = "http")]
+                BlocklistSource::Url(url_string, url) => {
+                    let body = get(url, &[], None).await
+                        .map_err(|e| BlocklistError::Http(url_string.clone(), e))?;
+                    blocklist.add_entries(&String::from_utf8_lossy(&body));
+                },
+            }
+        }
+        Ok(blocklist)
+    }
+
+    /// Adds the entries from a blocklist in either hosts-file format (`0.0.0.0 evil.example`) or
+    /// domain-per-line format (`evil.example`). Comments start with `#`.
+    pub fn add_entries(&mut self, text: &str) {
+        for line in text.lines() {
+            let content = match line.find('#') {
+                Some(hash_index) => &line[..hash_index],
+                None => line,
+            };
+
+            let mut tokens = content.split_whitespace().peekable();
+            let first_token = match tokens.peek() {
+                Some(t) => *t,
+                None => continue, // empty line
+            };
+            if first_token.parse::<IpAddr>().is_ok() {
+                // hosts-file format; skip the address
+                tokens.next();
+            }
+
+            for token in tokens {
+                if token.parse::<IpAddr>().is_ok() {
+                    // some lists start with entries such as "0.0.0.0 0.0.0.0"
+                    continue;
+                }
+                let domain = token.trim_end_matches('.').to_ascii_lowercase();
+                if domain.len() > 0 {
+                    self.domains.insert(domain);
+                }
+            }
+        }
+    }
+
+    /// Returns the blocklist entry matching the given normalized name, either exactly or as one of
+    /// its parent domains.
+    pub fn matching_entry(&self, normalized_name: &str) -> Option<&str> {
+        let mut suffix = normalized_name;
+        loop {
+            if let Some(entry) = self.domains.get(suffix) {
+                return Some(entry.as_str());
+            }
+            match suffix.find('.') {
+                Some(dot_index) => suffix = &suffix[dot_index+1..],
+                None => return None,
+            }
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use super::{Blocklist, BlocklistSource};
+
+    #[test]
+    fn test_formats_and_matching() {
+        let mut blocklist = Blocklist::new();
+        blocklist.add_entries("# hosts-style\n0.0.0.0 0.0.0.0\n0.0.0.0 Evil.Example. # trailing comment\n127.0.0.1 a.test b.test\n\nbad.example\n");
+        assert_eq!(blocklist.domains.len(), 4);
+
+        assert_eq!(blocklist.matching_entry("evil.example"), Some("evil.example"));
+        assert_eq!(blocklist.matching_entry("www.evil.example"), Some("evil.example"));
+        assert_eq!(blocklist.matching_entry("b.test"), Some("b.test"));
+        assert_eq!(blocklist.matching_entry("c.test"), None);
+        assert_eq!(blocklist.matching_entry("notbad.example"), None);
+        assert_eq!(blocklist.matching_entry("example"), None);
+    }
+    #[test]
+    fn test_source() {
+        assert_eq!("/etc/blocklist.txt".parse::<BlocklistSource>().unwrap(), BlocklistSource::File(PathBuf::from("/etc/blocklist.txt")));
+        let url = "http://lists.example/hosts".parse::<BlocklistSource>();
+        #[cfg(feature = "http")]
+        assert!(url.unwrap().is_url());
+        #[cfg(not(feature = "http"))]
+        assert!(url.is_err());
+    }
+}
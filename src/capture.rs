@@ -0,0 +1,902 @@
+use std::fmt;
+#[cfg(not(feature = "libpcap"))] use std::fs::File;
+use std::io;
+#[cfg(not(feature = "libpcap"))] use std::io::BufReader;
+use std::path::Path;
+
+use clap::ValueEnum;
+#[cfg(feature = "libpcap")] use pcap::{Active, Capture, Offline};
+
+use crate::packet::{Linktype, OwnedPacket, Precision};
+use crate::packet_filter::{self, FilterError, FilterInstruction};
+#[cfg(not(feature = "libpcap"))] use crate::packet_filter::filter_accepts;
+#[cfg(feature = "libpcap")] use crate::packet_filter::parse_filter_instruction;
+#[cfg(not(feature = "libpcap"))] use crate::savefile::SavefileReader;
+
+
+/// How packets are captured from the network interfaces.
+#[derive(Clone, Copy, Debug, Eq, PartialEq, ValueEnum)]
+pub enum CaptureBackendKind {
+    #[cfg(feature = "libpcap")] Pcap,
+    Rawsocket, // AF_PACKET on Linux, BPF devices on macOS and FreeBSD
+}
+impl CaptureBackendKind {
+    #[cfg(feature = "libpcap")]
+    pub const DEFAULT: Self = Self::Pcap;
+    #[cfg(not(feature = "libpcap"))]
+    pub const DEFAULT: Self = Self::Rawsocket;
+}
+
+
+#[derive(Debug)]
+pub enum CaptureError {
+    #[cfg(feature = "libpcap")] Pcap(pcap::Error),
+    Io(io::Error),
+    Filter(FilterError),
+    UnsupportedInterface { name: String, hardware_type: u16 },
+}
+impl fmt::Display for CaptureError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            #[cfg(feature = "libpcap")]
+            Self::Pcap(e)
+                => write!(f, "{}", e),
+            Self::Io(e)
+                => write!(f, "{}", e),
+            Self::Filter(e)
+                => write!(f, "{}", e),
+            Self::UnsupportedInterface { name, hardware_type }
+                => write!(f, "interface {} has unsupported hardware type {}", name, hardware_type),
+        }
+    }
+}
+impl std::error::Error for CaptureError {
+}
+#[cfg(feature = "libpcap")]
+impl From<pcap::Error> for CaptureError {
+    fn from(e: pcap::Error) -> Self { Self::Pcap(e) }
+}
+impl From<io::Error> for CaptureError {
+    fn from(e: io::Error) -> Self { Self::Io(e) }
+}
+impl From<FilterError> for CaptureError {
+    fn from(e: FilterError) -> Self { Self::Filter(e) }
+}
+
+
+/// The number of packets a capture has received and lost since it was opened.
+#[derive(Clone, Copy, Debug, Default, Eq, Hash, PartialEq)]
+pub struct CaptureStats {
+    pub received: u64,
+    pub dropped: u64,
+    pub dropped_by_interface: u64,
+}
+
+
+/// A source of packets captured live on a network interface.
+pub trait CaptureBackend: Send {
+    fn linktype(&self) -> Linktype;
+
+    /// Waits for the next packet. Returns `None` if none has arrived before the timeout expired,
+    /// giving the caller a chance to stop capturing.
+    fn next_packet(&mut self) -> Result<Option<OwnedPacket>, CaptureError>;
+
+    fn stats(&mut self) -> Result<CaptureStats, CaptureError>;
+}
+#[cfg(feature = "libpcap")]
+impl CaptureBackend for Capture<Active> {
+    fn linktype(&self) -> Linktype {
+        self.get_datalink().into()
+    }
+
+    fn next_packet(&mut self) -> Result<Option<OwnedPacket>, CaptureError> {
+        match Capture::next_packet(self) {
+            Ok(p) => Ok(Some(OwnedPacket::from(p))),
+            Err(pcap::Error::TimeoutExpired) => Ok(None),
+            Err(e) => Err(CaptureError::Pcap(e)),
+        }
+    }
+
+    fn stats(&mut self) -> Result<CaptureStats, CaptureError> {
+        let stat = Capture::stats(self)?;
+        Ok(CaptureStats {
+            received: stat.received.into(),
+            dropped: stat.dropped.into(),
+            dropped_by_interface: stat.if_dropped.into(),
+        })
+    }
+}
+
+
+/// A source of packets read from a capture file.
+pub trait CaptureFile {
+    fn linktype(&self) -> Linktype;
+
+    /// Skips the packets not matching the given filter from now on.
+    fn set_filter(&mut self, filter: &str) -> Result<(), CaptureError>;
+
+    /// Reads the next packet passing the filter. Returns `None` at the end of the file.
+    fn next_packet(&mut self) -> Result<Option<OwnedPacket>, CaptureError>;
+}
+#[cfg(feature = "libpcap")]
+impl CaptureFile for Capture<Offline> {
+    fn linktype(&self) -> Linktype {
+        self.get_datalink().into()
+    }
+
+    fn set_filter(&mut self, filter: &str) -> Result<(), CaptureError> {
+        Ok(self.filter(filter, true)?)
+    }
+
+    fn next_packet(&mut self) -> Result<Option<OwnedPacket>, CaptureError> {
+        match Capture::next_packet(self) {
+            Ok(p) => Ok(Some(OwnedPacket::from(p))),
+            Err(pcap::Error::NoMorePackets) => Ok(None),
+            Err(e) => Err(CaptureError::Pcap(e)),
+        }
+    }
+}
+
+
+/// Reads a pcap file without libpcap, running the filter on each packet.
+#[cfg(not(feature = "libpcap"))]
+struct FilteredSavefile {
+    reader: SavefileReader<BufReader<File>>,
+    filter: Option<Vec<FilterInstruction>>,
+}
+#[cfg(not(feature = "libpcap"))]
+impl CaptureFile for FilteredSavefile {
+    fn linktype(&self) -> Linktype {
+        self.reader.linktype()
+    }
+
+    fn set_filter(&mut self, filter: &str) -> Result<(), CaptureError> {
+        self.filter = Some(compile_filter(self.reader.linktype(), filter)?);
+        Ok(())
+    }
+
+    fn next_packet(&mut self) -> Result<Option<OwnedPacket>, CaptureError> {
+        while let Some(packet) = self.reader.next_packet()? {
+            let passes = match &self.filter {
+                Some(f) => filter_accepts(f, &packet.data, packet.header.len),
+                None => true,
+            };
+            if passes {
+                return Ok(Some(packet));
+            }
+        }
+        Ok(None)
+    }
+}
+
+
+/// Opens a capture file, converting the timestamps to the given precision.
+#[cfg(feature = "libpcap")]
+pub fn open_capture_file(path: &Path, precision: Precision) -> Result<Box<dyn CaptureFile>, CaptureError> {
+    Ok(Box::new(Capture::from_file_with_precision(path, precision.into())?))
+}
+
+/// Opens a capture file, converting the timestamps to the given precision. Without libpcap, only
+/// pcap files can be read, not pcapng files.
+#[cfg(not(feature = "libpcap"))]
+pub fn open_capture_file(path: &Path, precision: Precision) -> Result<Box<dyn CaptureFile>, CaptureError> {
+    Ok(Box::new(FilteredSavefile {
+        reader: SavefileReader::open(path, precision)?,
+        filter: None,
+    }))
+}
+
+
+#[cfg(target_os = "linux")]
+pub use self::af_packet::AfPacketCapture;
+#[cfg(any(target_os = "macos", target_os = "freebsd"))]
+pub use self::bpf::BpfCapture;
+
+
+/// Lists the names of the network interfaces, in the order of their indexes, without consulting
+/// libpcap.
+#[cfg(any(target_os = "linux", target_os = "macos", target_os = "freebsd"))]
+pub fn list_interfaces() -> Result<Vec<String>, io::Error> {
+    let name_index = unsafe { libc::if_nameindex() };
+    if name_index.is_null() {
+        return Err(io::Error::last_os_error());
+    }
+
+    let mut index_and_names = Vec::new();
+    let mut entry = name_index;
+    unsafe {
+        while (*entry).if_index != 0 && !(*entry).if_name.is_null() {
+            let name = std::ffi::CStr::from_ptr((*entry).if_name).to_string_lossy().into_owned();
+            index_and_names.push(((*entry).if_index, name));
+            entry = entry.add(1);
+        }
+        libc::if_freenameindex(name_index);
+    }
+    index_and_names.sort_unstable();
+    Ok(index_and_names.into_iter().map(|(_index, name)| name).collect())
+}
+
+#[cfg(not(any(target_os = "linux", target_os = "macos", target_os = "freebsd")))]
+pub fn list_interfaces() -> Result<Vec<String>, io::Error> {
+    Err(io::Error::new(io::ErrorKind::Unsupported, "raw socket capture is not supported on this platform"))
+}
+
+
+/// Opens a capture on the given interface without going through libpcap to capture. The filter is
+/// handed to the kernel directly.
+#[cfg(target_os = "linux")]
+pub fn open_raw_capture(interface: &str, filter: Option<&str>, inbound_only: bool, precision: Precision) -> Result<Box<dyn CaptureBackend>, CaptureError> {
+    Ok(Box::new(AfPacketCapture::open(interface, filter, inbound_only, precision)?))
+}
+
+#[cfg(any(target_os = "macos", target_os = "freebsd"))]
+pub fn open_raw_capture(interface: &str, filter: Option<&str>, inbound_only: bool, precision: Precision) -> Result<Box<dyn CaptureBackend>, CaptureError> {
+    Ok(Box::new(BpfCapture::open(interface, filter, inbound_only, precision)?))
+}
+
+#[cfg(not(any(target_os = "linux", target_os = "macos", target_os = "freebsd")))]
+pub fn open_raw_capture(_interface: &str, _filter: Option<&str>, _inbound_only: bool, _precision: Precision) -> Result<Box<dyn CaptureBackend>, CaptureError> {
+    Err(io::Error::new(io::ErrorKind::Unsupported, "raw socket capture is not supported on this platform").into())
+}
+
+
+/// Compiles a capture filter for packets of the given link type into BPF instructions. Filters the
+/// built-in compiler does not understand are compiled by libpcap, without opening a capture.
+#[cfg(feature = "libpcap")]
+pub fn compile_filter(linktype: Linktype, filter: &str) -> Result<Vec<FilterInstruction>, CaptureError> {
+    if let Ok(instructions) = packet_filter::compile(linktype, filter) {
+        return Ok(instructions);
+    }
+    let program = Capture::dead(linktype.into())?
+        .compile(filter, true)?;
+    program.get_instructions().iter()
+        .map(|instruction| parse_filter_instruction(&instruction.to_string()))
+        .collect::<Option<Vec<FilterInstruction>>>()
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "libpcap compiled an unreadable filter").into())
+}
+
+/// Compiles a capture filter for packets of the given link type into BPF instructions.
+#[cfg(not(feature = "libpcap"))]
+pub fn compile_filter(linktype: Linktype, filter: &str) -> Result<Vec<FilterInstruction>, CaptureError> {
+    Ok(packet_filter::compile(linktype, filter)?)
+}
+
+
+/// Checks whether a capture filter compiles for Ethernet, so that a bad filter is reported before
+/// any capture is opened.
+#[cfg(feature = "libpcap")]
+pub fn check_filter(filter: &str) -> Result<(), CaptureError> {
+    if packet_filter::compile(Linktype::ETHERNET, filter).is_err() {
+        Capture::dead(Linktype::ETHERNET.into())?
+            .compile(filter, true)?;
+    }
+    Ok(())
+}
+
+/// Checks whether a capture filter compiles for Ethernet, so that a bad filter is reported before
+/// any capture is opened.
+#[cfg(not(feature = "libpcap"))]
+pub fn check_filter(filter: &str) -> Result<(), CaptureError> {
+    packet_filter::compile(Linktype::ETHERNET, filter)?;
+    Ok(())
+}
+
+
+/// The layout of the header preceding each packet read from a BPF device, which differs between
+/// the operating systems.
+#[cfg(any(test, target_os = "macos", target_os = "freebsd"))]
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+struct BpfHeaderLayout {
+    seconds_size: usize,
+    microseconds_size: usize,
+    timestamp_size: usize,
+    alignment: usize,
+}
+#[cfg(any(test, target_os = "macos", target_os = "freebsd"))]
+impl BpfHeaderLayout {
+    // macOS uses struct timeval32
+    #[cfg(any(test, target_os = "macos"))]
+    const MACOS: Self = Self {
+        seconds_size: 4,
+        microseconds_size: 4,
+        timestamp_size: 8,
+        alignment: 4,
+    };
+
+    #[cfg(target_os = "freebsd")]
+    const FREEBSD: Self = Self {
+        seconds_size: std::mem::size_of::<libc::time_t>(),
+        microseconds_size: std::mem::size_of::<libc::suseconds_t>(),
+        timestamp_size: std::mem::size_of::<libc::timeval>(),
+        alignment: std::mem::size_of::<libc::c_long>(),
+    };
+}
+
+
+/// A packet read from a BPF device: its timestamp (seconds and microseconds), its original length
+/// and the range of the buffer holding its captured bytes.
+#[cfg(any(test, target_os = "macos", target_os = "freebsd"))]
+#[derive(Clone, Debug, Eq, PartialEq)]
+struct BpfRecord {
+    seconds: i64,
+    microseconds: i64,
+    length: u32,
+    data: std::ops::Range<usize>,
+}
+
+
+#[cfg(any(test, target_os = "macos", target_os = "freebsd"))]
+fn read_native_int(bytes: &[u8]) -> i64 {
+    match bytes.len() {
+        2 => i16::from_ne_bytes(bytes.try_into().unwrap()).into(),
+        4 => i32::from_ne_bytes(bytes.try_into().unwrap()).into(),
+        8 => i64::from_ne_bytes(bytes.try_into().unwrap()),
+        other => panic!("unexpected integer size {}", other),
+    }
+}
+
+
+/// Splits the contents of a BPF read buffer into the packets it contains.
+#[cfg(any(test, target_os = "macos", target_os = "freebsd"))]
+fn parse_bpf_buffer(buffer: &[u8], layout: &BpfHeaderLayout) -> Vec<BpfRecord> {
+    let mut records = Vec::new();
+    let mut offset = 0;
+    let fixed_size = layout.timestamp_size + 10; // caplen, datalen and hdrlen
+    while offset + fixed_size <= buffer.len() {
+        let header = &buffer[offset..offset + fixed_size];
+        let seconds = read_native_int(&header[..layout.seconds_size]);
+        let microseconds = read_native_int(&header[layout.seconds_size..layout.seconds_size + layout.microseconds_size]);
+        let rest = &header[layout.timestamp_size..];
+        let captured_length = u32::from_ne_bytes(rest[0..4].try_into().unwrap());
+        let length = u32::from_ne_bytes(rest[4..8].try_into().unwrap());
+        let header_length = u16::from_ne_bytes(rest[8..10].try_into().unwrap());
+
+        let data_start = offset + usize::from(header_length);
+        let data_end = data_start + usize::try_from(captured_length).unwrap();
+        if data_end > buffer.len() {
+            break;
+        }
+        records.push(BpfRecord {
+            seconds,
+            microseconds,
+            length,
+            data: data_start..data_end,
+        });
+
+        // the next header starts at the next word boundary
+        let record_length = usize::from(header_length) + usize::try_from(captured_length).unwrap();
+        offset += (record_length + layout.alignment - 1) & !(layout.alignment - 1);
+    }
+    records
+}
+
+
+/// Captures packets using a Linux packet socket, without going through libpcap.
+#[cfg(target_os = "linux")]
+mod af_packet {
+    use std::ffi::CString;
+    use std::io;
+    use std::mem::{size_of, zeroed};
+    use std::os::fd::{AsRawFd, FromRawFd, OwnedFd};
+    use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+    use super::{CaptureBackend, CaptureError, CaptureStats, compile_filter, FilterInstruction};
+    use crate::packet::{Linktype, OwnedPacket, PacketHeader, Precision};
+
+
+    const BUFFER_SIZE: usize = 65536;
+    const TIMEOUT_MS: i64 = 1000;
+
+    // room for the timestamp control message, as u64s to align it like struct cmsghdr
+    const CONTROL_BUFFER_WORDS: usize = 8;
+
+    // from linux/if_arp.h
+    const ARPHRD_ETHER: u16 = 1;
+    const ARPHRD_LOOPBACK: u16 = 772;
+    const ARPHRD_NONE: u16 = 65534;
+
+
+    fn check(result: libc::c_int) -> Result<libc::c_int, io::Error> {
+        if result < 0 {
+            Err(io::Error::last_os_error())
+        } else {
+            Ok(result)
+        }
+    }
+
+
+    /// Asks the kernel for the hardware type of the given interface, which determines the
+    /// link-layer header of the packets received on it.
+    ///
+    /// Unlike sysfs, the socket sees the interfaces of the network namespace it was opened in.
+    fn hardware_type(socket: &OwnedFd, interface_name: &CString) -> Result<u16, io::Error> {
+        let mut request: libc::ifreq = unsafe { zeroed() };
+        let name_bytes = interface_name.as_bytes_with_nul();
+        if name_bytes.len() > request.ifr_name.len() {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, "interface name too long"));
+        }
+        for (target, source) in request.ifr_name.iter_mut().zip(name_bytes) {
+            *target = *source as libc::c_char;
+        }
+        check(unsafe { libc::ioctl(socket.as_raw_fd(), libc::SIOCGIFHWADDR as _, &mut request as *mut libc::ifreq) })?;
+        Ok(unsafe { request.ifr_ifru.ifru_hwaddr.sa_family })
+    }
+
+
+    /// Whether a packet of the given type (as reported by the socket) is skipped. On the loopback
+    /// interface, each packet is seen both leaving and arriving; a frame forwarded by a bridge is
+    /// seen leaving one member after arriving at another.
+    pub(super) fn skip_packet_type(packet_type: u8, loopback: bool, inbound_only: bool) -> bool {
+        packet_type == libc::PACKET_OUTGOING && (loopback || inbound_only)
+    }
+
+
+    /// Hands a compiled filter to the kernel, which then only queues the packets passing it.
+    fn attach_filter(socket: &OwnedFd, instructions: &[FilterInstruction]) -> Result<(), io::Error> {
+        // FilterInstruction has the layout of sock_filter
+        let program = libc::sock_fprog {
+            len: instructions.len().try_into()
+                .map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, "capture filter too long"))?,
+            filter: instructions.as_ptr() as *mut libc::sock_filter,
+        };
+        check(unsafe {
+            libc::setsockopt(
+                socket.as_raw_fd(),
+                libc::SOL_SOCKET,
+                libc::SO_ATTACH_FILTER,
+                &program as *const libc::sock_fprog as *const libc::c_void,
+                size_of::<libc::sock_fprog>() as libc::socklen_t,
+            )
+        })?;
+        Ok(())
+    }
+
+
+    /// Finds the time the kernel received a packet in the control messages read along with it.
+    fn kernel_timestamp(message: &libc::msghdr) -> Option<Duration> {
+        unsafe {
+            let mut control_message = libc::CMSG_FIRSTHDR(message);
+            while !control_message.is_null() {
+                if (*control_message).cmsg_level == libc::SOL_SOCKET && (*control_message).cmsg_type == libc::SCM_TIMESTAMPNS {
+                    let timestamp = std::ptr::read_unaligned(libc::CMSG_DATA(control_message) as *const libc::timespec);
+                    return Some(Duration::new(timestamp.tv_sec.try_into().ok()?, timestamp.tv_nsec.try_into().ok()?));
+                }
+                control_message = libc::CMSG_NXTHDR(message, control_message);
+            }
+        }
+        None
+    }
+
+
+    /// Captures the packets on an interface using a raw packet socket.
+    ///
+    /// The capture filter is attached to the socket, so that the kernel does the filtering as it
+    /// would for libpcap. The packets carry the time the kernel received them, as with libpcap.
+    pub struct AfPacketCapture {
+        socket: OwnedFd,
+        linktype: Linktype,
+        loopback: bool,
+        inbound_only: bool,
+        precision: Precision,
+        buffer: Vec<u8>,
+        stats: CaptureStats,
+    }
+    impl AfPacketCapture {
+        pub fn open(interface: &str, filter: Option<&str>, inbound_only: bool, precision: Precision) -> Result<Self, CaptureError> {
+            let interface_name = CString::new(interface)
+                .map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, "interface name contains NUL"))?;
+            let interface_index = unsafe { libc::if_nametoindex(interface_name.as_ptr()) };
+            if interface_index == 0 {
+                return Err(io::Error::last_os_error().into());
+            }
+
+            // the socket receives nothing until it is bound to a protocol, leaving time to attach the
+            // filter before the first packet is queued
+            let socket = unsafe {
+                let fd = check(libc::socket(libc::AF_PACKET, libc::SOCK_RAW | libc::SOCK_CLOEXEC, 0))?;
+                OwnedFd::from_raw_fd(fd)
+            };
+
+            let hardware_type = hardware_type(&socket, &interface_name)?;
+            let linktype = match hardware_type {
+                ARPHRD_ETHER|ARPHRD_LOOPBACK => Linktype::ETHERNET,
+                ARPHRD_NONE => Linktype::RAW,
+                other => return Err(CaptureError::UnsupportedInterface { name: interface.to_owned(), hardware_type: other }),
+            };
+            if let Some(f) = filter {
+                attach_filter(&socket, &compile_filter(linktype, f)?)?;
+            }
+
+            // the protocol is given in network byte order
+            let protocol = (libc::ETH_P_ALL as u16).to_be();
+            let mut address: libc::sockaddr_ll = unsafe { zeroed() };
+            address.sll_family = libc::AF_PACKET as u16;
+            address.sll_protocol = protocol;
+            address.sll_ifindex = interface_index.try_into().unwrap();
+            check(unsafe {
+                libc::bind(
+                    socket.as_raw_fd(),
+                    &address as *const libc::sockaddr_ll as *const libc::sockaddr,
+                    size_of::<libc::sockaddr_ll>() as libc::socklen_t,
+                )
+            })?;
+
+            // wake up regularly so that the capture can be stopped
+            let timeout = libc::timeval {
+                tv_sec: (TIMEOUT_MS / 1000) as libc::time_t,
+                tv_usec: ((TIMEOUT_MS % 1000) * 1000) as libc::suseconds_t,
+            };
+            check(unsafe {
+                libc::setsockopt(
+                    socket.as_raw_fd(),
+                    libc::SOL_SOCKET,
+                    libc::SO_RCVTIMEO,
+                    &timeout as *const libc::timeval as *const libc::c_void,
+                    size_of::<libc::timeval>() as libc::socklen_t,
+                )
+            })?;
+
+            let enable: libc::c_int = 1;
+            check(unsafe {
+                libc::setsockopt(
+                    socket.as_raw_fd(),
+                    libc::SOL_SOCKET,
+                    libc::SO_TIMESTAMPNS,
+                    &enable as *const libc::c_int as *const libc::c_void,
+                    size_of::<libc::c_int>() as libc::socklen_t,
+                )
+            })?;
+
+            Ok(Self {
+                socket,
+                linktype,
+                loopback: hardware_type == ARPHRD_LOOPBACK,
+                inbound_only,
+                precision,
+                buffer: vec![0; BUFFER_SIZE],
+                stats: CaptureStats::default(),
+            })
+        }
+
+        fn header(&self, since_epoch: Duration, captured_length: usize, length: usize) -> PacketHeader {
+            let subseconds = match self.precision {
+                Precision::Micro => since_epoch.subsec_micros(),
+                Precision::Nano => since_epoch.subsec_nanos(),
+            };
+            PacketHeader {
+                ts: libc::timeval {
+                    tv_sec: since_epoch.as_secs() as libc::time_t,
+                    tv_usec: subseconds as libc::suseconds_t,
+                },
+                caplen: captured_length.try_into().unwrap(),
+                len: length.try_into().unwrap_or(u32::MAX),
+            }
+        }
+    }
+    impl CaptureBackend for AfPacketCapture {
+        fn linktype(&self) -> Linktype {
+            self.linktype
+        }
+
+        fn next_packet(&mut self) -> Result<Option<OwnedPacket>, CaptureError> {
+            loop {
+                let mut address: libc::sockaddr_ll = unsafe { zeroed() };
+                let mut control_buffer = [0u64; CONTROL_BUFFER_WORDS];
+                let mut data = libc::iovec {
+                    iov_base: self.buffer.as_mut_ptr() as *mut libc::c_void,
+                    iov_len: self.buffer.len(),
+                };
+                let mut message: libc::msghdr = unsafe { zeroed() };
+                message.msg_name = &mut address as *mut libc::sockaddr_ll as *mut libc::c_void;
+                message.msg_namelen = size_of::<libc::sockaddr_ll>() as libc::socklen_t;
+                message.msg_iov = &mut data;
+                message.msg_iovlen = 1;
+                message.msg_control = control_buffer.as_mut_ptr() as *mut libc::c_void;
+                message.msg_controllen = size_of::<[u64; CONTROL_BUFFER_WORDS]>() as _;
+
+                // MSG_TRUNC makes the call return the full length of truncated packets
+                let result = unsafe { libc::recvmsg(self.socket.as_raw_fd(), &mut message, libc::MSG_TRUNC) };
+                if result < 0 {
+                    let error = io::Error::last_os_error();
+                    return match error.kind() {
+                        io::ErrorKind::WouldBlock|io::ErrorKind::TimedOut => Ok(None),
+                        io::ErrorKind::Interrupted => continue,
+                        _ => Err(error.into()),
+                    };
+                }
+
+                if skip_packet_type(address.sll_pkttype, self.loopback, self.inbound_only) {
+                    continue;
+                }
+
+                // fall back to the time of reading if the kernel has not attached a timestamp
+                let since_epoch = kernel_timestamp(&message)
+                    .unwrap_or_else(|| SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default());
+                let length = result as usize;
+                let captured_length = length.min(self.buffer.len());
+                self.stats.received += 1;
+                return Ok(Some(OwnedPacket {
+                    header: self.header(since_epoch, captured_length, length),
+                    data: self.buffer[..captured_length].to_vec(),
+                }));
+            }
+        }
+
+        fn stats(&mut self) -> Result<CaptureStats, CaptureError> {
+            // the kernel resets its counters whenever they are read
+            let mut kernel_stats: libc::tpacket_stats = unsafe { zeroed() };
+            let mut kernel_stats_length = size_of::<libc::tpacket_stats>() as libc::socklen_t;
+            check(unsafe {
+                libc::getsockopt(
+                    self.socket.as_raw_fd(),
+                    libc::SOL_PACKET,
+                    libc::PACKET_STATISTICS,
+                    &mut kernel_stats as *mut libc::tpacket_stats as *mut libc::c_void,
+                    &mut kernel_stats_length,
+                )
+            })?;
+            self.stats.dropped += u64::from(kernel_stats.tp_drops);
+            Ok(self.stats)
+        }
+    }
+}
+
+
+/// Captures packets using a BPF device on macOS or FreeBSD, without going through libpcap.
+#[cfg(any(target_os = "macos", target_os = "freebsd"))]
+mod bpf {
+    use std::collections::VecDeque;
+    use std::ffi::CString;
+    use std::io;
+    use std::mem::zeroed;
+    use std::os::fd::{AsRawFd, FromRawFd, OwnedFd};
+
+    use super::{
+        BpfHeaderLayout, CaptureBackend, CaptureError, CaptureStats, compile_filter, FilterInstruction,
+        parse_bpf_buffer,
+    };
+    use crate::packet::{Linktype, OwnedPacket, PacketHeader, Precision};
+
+
+    const TIMEOUT_MS: libc::c_int = 1000;
+    const MAX_DEVICE_NUMBER: u32 = 255;
+
+    #[cfg(target_os = "macos")]
+    const HEADER_LAYOUT: BpfHeaderLayout = BpfHeaderLayout::MACOS;
+    #[cfg(target_os = "freebsd")]
+    const HEADER_LAYOUT: BpfHeaderLayout = BpfHeaderLayout::FREEBSD;
+
+    #[cfg(target_os = "macos")]
+    use libc::BIOCSSEESENT;
+    // _IOW('B', 119, u_int) from net/bpf.h, which the libc crate lacks on FreeBSD
+    #[cfg(target_os = "freebsd")]
+    const BIOCSSEESENT: libc::c_ulong = 0x80044277;
+
+
+    #[repr(C)]
+    struct InterfaceRequest {
+        name: [libc::c_char; libc::IFNAMSIZ],
+        data: [u8; 16],
+    }
+
+    #[repr(C)]
+    struct BpfProgram {
+        length: libc::c_uint,
+        instructions: *const FilterInstruction,
+    }
+
+    #[repr(C)]
+    struct BpfStats {
+        received: libc::c_uint,
+        dropped: libc::c_uint,
+    }
+
+
+    fn check(result: libc::c_int) -> Result<libc::c_int, io::Error> {
+        if result < 0 {
+            Err(io::Error::last_os_error())
+        } else {
+            Ok(result)
+        }
+    }
+
+
+    /// Opens the first BPF device that is not in use; older systems provide a fixed number of
+    /// them, newer ones clone /dev/bpf.
+    fn open_device() -> Result<OwnedFd, io::Error> {
+        let mut last_error = io::Error::new(io::ErrorKind::NotFound, "no BPF device found");
+        let paths = std::iter::once("/dev/bpf".to_owned())
+            .chain((0..=MAX_DEVICE_NUMBER).map(|n| format!("/dev/bpf{}", n)));
+        for path in paths {
+            let c_path = CString::new(path).unwrap();
+            let fd = unsafe { libc::open(c_path.as_ptr(), libc::O_RDONLY | libc::O_CLOEXEC) };
+            if fd >= 0 {
+                return Ok(unsafe { OwnedFd::from_raw_fd(fd) });
+            }
+            last_error = io::Error::last_os_error();
+            if last_error.raw_os_error() != Some(libc::EBUSY) && last_error.kind() != io::ErrorKind::NotFound {
+                break;
+            }
+        }
+        Err(last_error)
+    }
+
+
+    /// Captures the packets on an interface using a BPF device.
+    ///
+    /// The capture filter is set on the device, so that the kernel does the filtering as it would
+    /// for libpcap.
+    pub struct BpfCapture {
+        device: OwnedFd,
+        linktype: Linktype,
+        precision: Precision,
+        buffer: Vec<u8>,
+        pending: VecDeque<OwnedPacket>,
+    }
+    impl BpfCapture {
+        pub fn open(interface: &str, filter: Option<&str>, inbound_only: bool, precision: Precision) -> Result<Self, CaptureError> {
+            let device = open_device()?;
+            let fd = device.as_raw_fd();
+
+            let mut buffer_length: libc::c_uint = 0;
+            check(unsafe { libc::ioctl(fd, libc::BIOCGBLEN, &mut buffer_length) })?;
+
+            let mut request: InterfaceRequest = unsafe { zeroed() };
+            if interface.len() >= libc::IFNAMSIZ {
+                return Err(io::Error::new(io::ErrorKind::InvalidInput, "interface name too long").into());
+            }
+            for (target, source) in request.name.iter_mut().zip(interface.bytes()) {
+                *target = source as libc::c_char;
+            }
+            check(unsafe { libc::ioctl(fd, libc::BIOCSETIF, &request) })?;
+
+            // deliver packets as they arrive instead of when the buffer is full
+            let immediate: libc::c_uint = 1;
+            check(unsafe { libc::ioctl(fd, libc::BIOCIMMEDIATE, &immediate) })?;
+
+            if inbound_only {
+                let see_sent: libc::c_uint = 0;
+                check(unsafe { libc::ioctl(fd, BIOCSSEESENT, &see_sent) })?;
+            }
+
+            let mut datalink: libc::c_uint = 0;
+            check(unsafe { libc::ioctl(fd, libc::BIOCGDLT, &mut datalink) })?;
+            let linktype = Linktype(datalink as i32);
+
+            // setting the filter also discards the packets captured so far
+            if let Some(f) = filter {
+                let instructions = compile_filter(linktype, f)?;
+                let program = BpfProgram {
+                    length: instructions.len().try_into().unwrap(),
+                    instructions: instructions.as_ptr(),
+                };
+                check(unsafe { libc::ioctl(fd, libc::BIOCSETF, &program) })?;
+            }
+
+            Ok(Self {
+                device,
+                linktype,
+                precision,
+                buffer: vec![0; usize::try_from(buffer_length).unwrap()],
+                pending: VecDeque::new(),
+            })
+        }
+
+        fn read_packets(&mut self) -> Result<(), io::Error> {
+            // wait with a timeout so that the capture can be stopped
+            let mut poll_fd = libc::pollfd {
+                fd: self.device.as_raw_fd(),
+                events: libc::POLLIN,
+                revents: 0,
+            };
+            if check(unsafe { libc::poll(&mut poll_fd, 1, TIMEOUT_MS) })? == 0 {
+                return Ok(());
+            }
+
+            let result = unsafe {
+                libc::read(self.device.as_raw_fd(), self.buffer.as_mut_ptr() as *mut libc::c_void, self.buffer.len())
+            };
+            if result < 0 {
+                return Err(io::Error::last_os_error());
+            }
+            let buffer = &self.buffer[..result as usize];
+            for record in parse_bpf_buffer(buffer, &HEADER_LAYOUT) {
+                let subseconds = match self.precision {
+                    Precision::Micro => record.microseconds,
+                    Precision::Nano => record.microseconds * 1000,
+                };
+                let header = PacketHeader {
+                    ts: libc::timeval {
+                        tv_sec: record.seconds as libc::time_t,
+                        tv_usec: subseconds as libc::suseconds_t,
+                    },
+                    caplen: (record.data.end - record.data.start).try_into().unwrap(),
+                    len: record.length,
+                };
+                self.pending.push_back(OwnedPacket {
+                    header,
+                    data: buffer[record.data].to_vec(),
+                });
+            }
+            Ok(())
+        }
+    }
+    impl CaptureBackend for BpfCapture {
+        fn linktype(&self) -> Linktype {
+            self.linktype
+        }
+
+        fn next_packet(&mut self) -> Result<Option<OwnedPacket>, CaptureError> {
+            if self.pending.is_empty() {
+                match self.read_packets() {
+                    Ok(()) => {},
+                    Err(e) if e.kind() == io::ErrorKind::Interrupted => {},
+                    Err(e) => return Err(e.into()),
+                }
+            }
+            Ok(self.pending.pop_front())
+        }
+
+        fn stats(&mut self) -> Result<CaptureStats, CaptureError> {
+            let mut bpf_stats = BpfStats { received: 0, dropped: 0 };
+            check(unsafe { libc::ioctl(self.device.as_raw_fd(), libc::BIOCGSTATS, &mut bpf_stats) })?;
+            Ok(CaptureStats {
+                received: bpf_stats.received.into(),
+                dropped: bpf_stats.dropped.into(),
+                dropped_by_interface: 0,
+            })
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::{BpfHeaderLayout, BpfRecord, parse_bpf_buffer};
+
+    fn bpf_header(seconds: i32, microseconds: i32, captured_length: u32, length: u32) -> Vec<u8> {
+        let mut header = Vec::new();
+        header.extend_from_slice(&seconds.to_ne_bytes());
+        header.extend_from_slice(&microseconds.to_ne_bytes());
+        header.extend_from_slice(&captured_length.to_ne_bytes());
+        header.extend_from_slice(&length.to_ne_bytes());
+        header.extend_from_slice(&20u16.to_ne_bytes());
+        header.extend_from_slice(&[0, 0]); // padding up to the header length
+        header
+    }
+
+    #[test]
+    fn test_parse_bpf_buffer() {
+        let mut buffer = bpf_header(1664625600, 123456, 5, 60);
+        buffer.extend_from_slice(b"hello");
+        buffer.extend_from_slice(&[0, 0, 0]); // word alignment
+        buffer.extend_from_slice(&bpf_header(1664625601, 7, 4, 4));
+        buffer.extend_from_slice(b"dns!");
+        buffer.extend_from_slice(&bpf_header(1664625602, 0, 100, 100)); // truncated read
+
+        let records = parse_bpf_buffer(&buffer, &BpfHeaderLayout::MACOS);
+        assert_eq!(records, vec![
+            BpfRecord { seconds: 1664625600, microseconds: 123456, length: 60, data: 20..25 },
+            BpfRecord { seconds: 1664625601, microseconds: 7, length: 4, data: 48..52 },
+        ]);
+        assert_eq!(&buffer[records[1].data.clone()], b"dns!");
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn test_forwarded_frame() {
+        use super::af_packet::skip_packet_type;
+
+        // a frame forwarded by a bridge arrives at one member and leaves through another
+        let seen_on_members = [libc::PACKET_OTHERHOST, libc::PACKET_OUTGOING];
+        let kept_count = seen_on_members.iter()
+            .filter(|packet_type| !skip_packet_type(**packet_type, false, true))
+            .count();
+        assert_eq!(kept_count, 1);
+
+        // a standalone interface keeps what it sends, the loopback interface does not
+        assert!(!skip_packet_type(libc::PACKET_OUTGOING, false, false));
+        assert!(skip_packet_type(libc::PACKET_OUTGOING, true, false));
+        assert!(!skip_packet_type(libc::PACKET_HOST, true, true));
+    }
+}
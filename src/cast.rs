@@ -0,0 +1,65 @@
+use std::net::{IpAddr, Ipv4Addr};
+
+use macaddr::MacAddr6;
+
+
+/// Whether a packet is addressed to a single host, to a group of hosts or to all of them.
+#[derive(Clone, Copy, Debug, Eq, Hash, Ord, PartialEq, PartialOrd)]
+pub enum CastKind {
+    Unicast,
+    Multicast,
+    Broadcast,
+}
+impl CastKind {
+    #[cfg(feature = "http")]
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::Unicast => "unicast",
+            Self::Multicast => "multicast",
+            Self::Broadcast => "broadcast",
+        }
+    }
+
+    pub fn of_link_address(address: MacAddr6) -> Self {
+        if address.is_broadcast() {
+            Self::Broadcast
+        } else if address.is_multicast() {
+            Self::Multicast
+        } else {
+            Self::Unicast
+        }
+    }
+
+    /// Classifies a network address. Directed broadcasts (to the last address of a subnet) cannot
+    /// be told apart from unicasts without knowing the subnet, so only the limited broadcast
+    /// address counts.
+    pub fn of_network_address(address: IpAddr) -> Self {
+        if address == IpAddr::V4(Ipv4Addr::BROADCAST) {
+            Self::Broadcast
+        } else if address.is_multicast() {
+            Self::Multicast
+        } else {
+            Self::Unicast
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use macaddr::MacAddr6;
+
+    use super::CastKind;
+
+    #[test]
+    fn test_classify() {
+        assert_eq!(CastKind::of_link_address(MacAddr6::new(0x02, 0x00, 0x5E, 0x10, 0x00, 0x01)), CastKind::Unicast);
+        assert_eq!(CastKind::of_link_address(MacAddr6::new(0x01, 0x00, 0x5E, 0x00, 0x00, 0xFB)), CastKind::Multicast);
+        assert_eq!(CastKind::of_link_address(MacAddr6::broadcast()), CastKind::Broadcast);
+
+        assert_eq!(CastKind::of_network_address("192.0.2.53".parse().unwrap()), CastKind::Unicast);
+        assert_eq!(CastKind::of_network_address("224.0.0.251".parse().unwrap()), CastKind::Multicast);
+        assert_eq!(CastKind::of_network_address("ff02::fb".parse().unwrap()), CastKind::Multicast);
+        assert_eq!(CastKind::of_network_address("255.255.255.255".parse().unwrap()), CastKind::Broadcast);
+    }
+}
@@ -0,0 +1,116 @@
+use std::fs::File;
+use std::io::BufWriter;
+use std::net::IpAddr;
+use std::path::PathBuf;
+
+use chrono::{DateTime, Duration, Utc};
+use tracing::{error, info};
+
+use crate::packet::{Linktype, OwnedPacket, Precision};
+use crate::savefile::SavefileWriter;
+
+
+/// Writes the DNS traffic of a single client to a pcap file for a limited time.
+///
+/// The time starts running with the first DNS packet processed, whether it belongs to the client
+/// or not.
+pub struct ClientCapture {
+    client: IpAddr,
+    duration: Duration,
+    path: PathBuf,
+    precision: Precision,
+    until: Option<DateTime<Utc>>,
+    savefile: Option<(Linktype, SavefileWriter<BufWriter<File>>)>,
+}
+impl ClientCapture {
+    pub fn new(client: IpAddr, duration: Duration, directory: PathBuf, precision: Precision) -> Self {
+        let file_name = format!("client-{}.pcap", client).replace(':', "-");
+        Self {
+            client,
+            duration,
+            path: directory.join(file_name),
+            precision,
+            until: None,
+            savefile: None,
+        }
+    }
+
+    /// Writes the packet if it is from or to the client and the capture is still running.
+    pub fn observe(&mut self, timestamp: DateTime<Utc>, linktype: Linktype, source: IpAddr, destination: IpAddr, packet: &OwnedPacket) {
+        let until = *self.until.get_or_insert_with(|| timestamp + self.duration);
+        if timestamp > until {
+            self.close();
+            return;
+        }
+        if source != self.client && destination != self.client {
+            return;
+        }
+
+        if self.savefile.is_none() {
+            match SavefileWriter::create(&self.path, linktype, self.precision) {
+                Ok(sf) => {
+                    info!("capturing traffic of {} to {}", self.client, self.path.display());
+                    self.savefile = Some((linktype, sf));
+                },
+                Err(e) => {
+                    error!("failed to create client capture {}: {}", self.path.display(), e);
+                    // don't try again for every packet
+                    self.until = Some(timestamp - Duration::nanoseconds(1));
+                    return;
+                },
+            }
+        }
+        if let Some((savefile_linktype, savefile)) = self.savefile.as_mut() {
+            if *savefile_linktype == linktype {
+                if let Err(e) = savefile.write(packet) {
+                    error!("failed to write client capture {}: {}", self.path.display(), e);
+                    self.until = Some(timestamp - Duration::nanoseconds(1));
+                    self.close();
+                }
+            }
+        }
+    }
+
+    /// Finishes the pcap file, if one has been started.
+    pub fn close(&mut self) {
+        if let Some((_linktype, mut savefile)) = self.savefile.take() {
+            if let Err(e) = savefile.flush() {
+                error!("failed to flush client capture {}: {}", self.path.display(), e);
+            }
+            info!("client capture {} finished", self.path.display());
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use chrono::{Duration, TimeZone, Utc};
+    use crate::packet::{Linktype, PacketHeader, Precision};
+
+    use super::ClientCapture;
+    use crate::packet::OwnedPacket;
+
+    #[test]
+    fn test_window() {
+        let start = Utc.with_ymd_and_hms(2022, 10, 1, 12, 0, 0).unwrap();
+        let mut capture = ClientCapture::new("2001:db8::1".parse().unwrap(), Duration::seconds(60), "/tmp".into(), Precision::Micro);
+        assert_eq!(capture.path.to_str(), Some("/tmp/client-2001-db8--1.pcap"));
+
+        let packet = OwnedPacket {
+            header: unsafe { std::mem::zeroed::<PacketHeader>() },
+            data: vec![0; 14],
+        };
+        let other = "2001:db8::2".parse().unwrap();
+        let server = "2001:db8::53".parse().unwrap();
+
+        // unrelated traffic starts the clock but is not written
+        capture.observe(start, Linktype::ETHERNET, other, server, &packet);
+        assert!(capture.savefile.is_none());
+        assert_eq!(capture.until, Some(start + Duration::seconds(60)));
+
+        // the client's traffic arrives too late
+        capture.observe(start + Duration::seconds(61), Linktype::ETHERNET, capture.client, server, &packet);
+        assert!(capture.savefile.is_none());
+    }
+}
@@ -0,0 +1,226 @@
+use std::collections::{HashMap, VecDeque};
+
+use chrono::{DateTime, Duration, Utc};
+
+use crate::dns::{DnsHeader, DnsQuestion};
+use crate::stats::SpaceSaving;
+
+
+#[derive(Clone, Copy, Debug, Eq, Hash, Ord, PartialEq, PartialOrd)]
+struct Sightings {
+    first_seen: DateTime<Utc>,
+    query_on_primary: bool,
+    query_on_secondary: bool,
+    response_on_primary: bool,
+    response_on_secondary: bool,
+}
+
+
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct InterfaceComparisonStats {
+    pub query_on_both_count: u64,
+    pub query_only_on_primary_count: u64,
+    pub query_only_on_secondary_count: u64,
+    pub answered_only_on_primary_count: u64,
+    pub answered_only_on_secondary_count: u64,
+    pub name_missing_on_secondary_to_count: SpaceSaving<String>,
+}
+impl InterfaceComparisonStats {
+    fn add(&mut self, name: String, sightings: &Sightings) {
+        match (sightings.query_on_primary, sightings.query_on_secondary) {
+            (true, true) => {
+                self.query_on_both_count += 1;
+                if sightings.response_on_primary && !sightings.response_on_secondary {
+                    self.answered_only_on_primary_count += 1;
+                } else if sightings.response_on_secondary && !sightings.response_on_primary {
+                    self.answered_only_on_secondary_count += 1;
+                }
+            },
+            (true, false) => {
+                self.query_only_on_primary_count += 1;
+                self.name_missing_on_secondary_to_count.add(&name);
+            },
+            (false, true) => {
+                self.query_only_on_secondary_count += 1;
+            },
+            (false, false) => {
+                // only responses; the queries were sent before the sample began
+            },
+        }
+    }
+}
+
+
+/// Compares the DNS traffic seen on two interfaces, e.g. in front of and behind a DNS firewall.
+///
+/// Addresses might be rewritten between the interfaces, so queries are identified by their
+/// transaction ID and name alone. Messages are only matched up within the given window after the
+/// first of them; a query is judged once the window is over.
+#[derive(Clone, Debug)]
+pub struct InterfaceComparison {
+    pub secondary_interface_index: usize,
+    window: Duration,
+    key_to_sightings: HashMap<(u16, String), Sightings>,
+    expiry_queue: VecDeque<(DateTime<Utc>, (u16, String))>,
+    expired_stats: InterfaceComparisonStats,
+}
+impl InterfaceComparison {
+    pub fn new(secondary_interface_index: usize, window: Duration) -> Self {
+        Self {
+            secondary_interface_index,
+            window,
+            key_to_sightings: HashMap::new(),
+            expiry_queue: VecDeque::new(),
+            expired_stats: InterfaceComparisonStats::default(),
+        }
+    }
+
+    /// Judges the queries whose window is over.
+    fn expire(&mut self, now: DateTime<Utc>) {
+        let cutoff = now - self.window;
+        while let Some((timestamp, _key)) = self.expiry_queue.front() {
+            if *timestamp >= cutoff {
+                break;
+            }
+            let (timestamp, key) = self.expiry_queue.pop_front().unwrap();
+            if self.key_to_sightings.get(&key).map(|s| s.first_seen) == Some(timestamp) {
+                let sightings = self.key_to_sightings.remove(&key).unwrap();
+                self.expired_stats.add(key.1, &sightings);
+            }
+        }
+    }
+
+    pub fn observe(&mut self, timestamp: DateTime<Utc>, on_secondary: bool, header: &DnsHeader, questions: &[DnsQuestion]) {
+        self.expire(timestamp);
+
+        for question in questions {
+            let key = (header.id, question.name.as_str().into_owned());
+            let expiry_queue = &mut self.expiry_queue;
+            let sightings = self.key_to_sightings
+                .entry(key)
+                .or_insert_with_key(|key| {
+                    expiry_queue.push_back((timestamp, key.clone()));
+                    Sightings {
+                        first_seen: timestamp,
+                        query_on_primary: false,
+                        query_on_secondary: false,
+                        response_on_primary: false,
+                        response_on_secondary: false,
+                    }
+                });
+            match (header.is_response(), on_secondary) {
+                (false, false) => sightings.query_on_primary = true,
+                (false, true) => sightings.query_on_secondary = true,
+                (true, false) => sightings.response_on_primary = true,
+                (true, true) => sightings.response_on_secondary = true,
+            }
+        }
+    }
+
+    /// Summarizes the differences between the interfaces and forgets all sightings.
+    pub fn take_stats(&mut self) -> InterfaceComparisonStats {
+        let mut stats = std::mem::take(&mut self.expired_stats);
+        for ((_transaction_id, name), sightings) in self.key_to_sightings.drain() {
+            stats.add(name, &sightings);
+        }
+        self.expiry_queue.clear();
+        stats
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use chrono::{DateTime, Duration, TimeZone, Utc};
+
+    use super::InterfaceComparison;
+    use crate::dns::{DnsHeader, DnsQuestion};
+    use crate::stats::HEAVY_HITTER_CAPACITY;
+
+    #[derive(Clone, Copy, Debug, Eq, PartialEq)]
+    enum MessageType {
+        Query,
+        Response,
+    }
+
+    fn make_message(id: u16, name: &str, message_type: MessageType) -> (DnsHeader, Vec<DnsQuestion>) {
+        let header = DnsHeader {
+            id,
+            flags: if message_type == MessageType::Response { 0x8180 } else { 0x0100 },
+            question_count: 1,
+            ..Default::default()
+        };
+        let question = DnsQuestion {
+            name: name.parse().unwrap(),
+            query_type: 1,
+            query_class: 1,
+            mixed_case: false,
+        };
+        (header, vec![question])
+    }
+
+    fn start() -> DateTime<Utc> {
+        Utc.with_ymd_and_hms(2022, 10, 1, 12, 0, 0).unwrap()
+    }
+
+    fn observe(comparison: &mut InterfaceComparison, on_secondary: bool, message: (DnsHeader, Vec<DnsQuestion>)) {
+        comparison.observe(start(), on_secondary, &message.0, &message.1);
+    }
+
+    #[test]
+    fn test_comparison() {
+        let mut comparison = InterfaceComparison::new(1, Duration::seconds(5));
+
+        // passes through the firewall
+        observe(&mut comparison, false, make_message(1, "example.com.", MessageType::Query));
+        observe(&mut comparison, true, make_message(1, "Example.com.", MessageType::Query));
+        observe(&mut comparison, true, make_message(1, "example.com.", MessageType::Response));
+        observe(&mut comparison, false, make_message(1, "example.com.", MessageType::Response));
+
+        // blocked by the firewall
+        observe(&mut comparison, false, make_message(2, "blocked.example.", MessageType::Query));
+
+        // answered by the firewall itself
+        observe(&mut comparison, false, make_message(3, "rewritten.example.", MessageType::Query));
+        observe(&mut comparison, true, make_message(3, "rewritten.example.", MessageType::Query));
+        observe(&mut comparison, false, make_message(3, "rewritten.example.", MessageType::Response));
+
+        let stats = comparison.take_stats();
+        assert_eq!(stats.query_on_both_count, 2);
+        assert_eq!(stats.query_only_on_primary_count, 1);
+        assert_eq!(stats.query_only_on_secondary_count, 0);
+        assert_eq!(stats.answered_only_on_primary_count, 1);
+        assert_eq!(stats.answered_only_on_secondary_count, 0);
+        let missing = stats.name_missing_on_secondary_to_count.top();
+        assert_eq!(missing.len(), 1);
+        assert_eq!((missing[0].0.as_str(), missing[0].1.count), ("blocked.example", 1));
+
+        assert_eq!(comparison.take_stats().query_on_both_count, 0);
+    }
+
+    #[test]
+    fn test_comparison_window() {
+        let mut comparison = InterfaceComparison::new(1, Duration::seconds(5));
+        let (query, questions) = make_message(1, "example.com.", MessageType::Query);
+
+        // the same query too long after is a different one
+        comparison.observe(start(), false, &query, &questions);
+        comparison.observe(start() + Duration::seconds(10), true, &query, &questions);
+        assert_eq!(comparison.key_to_sightings.len(), 1);
+
+        // many names missing on the secondary interface
+        for i in 0..2*HEAVY_HITTER_CAPACITY {
+            let (query, questions) = make_message(2, &format!("blocked{}.example.", i), MessageType::Query);
+            comparison.observe(start() + Duration::seconds(20), false, &query, &questions);
+        }
+        comparison.observe(start() + Duration::seconds(30), false, &query, &questions);
+        assert_eq!(comparison.key_to_sightings.len(), 1);
+        assert_eq!(comparison.expiry_queue.len(), 1);
+
+        let stats = comparison.take_stats();
+        assert_eq!(stats.query_on_both_count, 0);
+        assert_eq!(stats.query_only_on_primary_count, (2*HEAVY_HITTER_CAPACITY + 2) as u64);
+        assert_eq!(stats.query_only_on_secondary_count, 1);
+        assert_eq!(stats.name_missing_on_secondary_to_count.top().len(), HEAVY_HITTER_CAPACITY);
+    }
+}
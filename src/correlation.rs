@@ -0,0 +1,220 @@
+use std::collections::{HashMap, VecDeque};
+use std::net::SocketAddr;
+
+use chrono::{DateTime, Duration, Utc};
+use hickory_proto::rr::Record;
+
+use crate::dns::DnsQuestion;
+
+
+#[derive(Clone, Copy, Debug, Eq, Hash, Ord, PartialEq, PartialOrd)]
+pub struct FlowKey {
+    pub client: SocketAddr,
+    pub server: SocketAddr,
+    pub transaction_id: u16,
+}
+
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct OutstandingQuery {
+    pub timestamp: DateTime<Utc>,
+    pub questions: Vec<DnsQuestion>,
+}
+
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct AnsweredQuery {
+    pub response_timestamp: DateTime<Utc>,
+    pub questions: Vec<DnsQuestion>,
+    pub answers: Vec<Record>,
+}
+
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum CorrelationOutcome {
+    Matched(OutstandingQuery),
+    Duplicate { differing: bool },
+    QuestionMismatch,
+    Unsolicited,
+}
+
+
+/// Checks whether both slices contain the same records, ignoring order and TTLs.
+fn same_records(left: &[Record], right: &[Record]) -> bool {
+    if left.len() != right.len() {
+        return false;
+    }
+    let record_equals = |l: &Record, r: &Record|
+        l.name() == r.name()
+        && l.record_type() == r.record_type()
+        && l.data() == r.data();
+    left.iter().all(|l| right.iter().any(|r| record_equals(l, r)))
+        && right.iter().all(|r| left.iter().any(|l| record_equals(l, r)))
+}
+
+
+/// Pairs responses with the queries they answer.
+///
+/// Answered queries are remembered for another window so that further responses to them can be
+/// recognized as duplicates.
+#[derive(Clone, Debug)]
+pub struct CorrelationTable {
+    window: Duration,
+    outstanding: HashMap<FlowKey, OutstandingQuery>,
+    expiry_queue: VecDeque<(DateTime<Utc>, FlowKey)>,
+    answered: HashMap<FlowKey, AnsweredQuery>,
+    answered_expiry_queue: VecDeque<(DateTime<Utc>, FlowKey)>,
+}
+impl CorrelationTable {
+    pub fn new(window: Duration) -> Self {
+        Self {
+            window,
+            outstanding: HashMap::new(),
+            expiry_queue: VecDeque::new(),
+            answered: HashMap::new(),
+            answered_expiry_queue: VecDeque::new(),
+        }
+    }
+
+    /// Forgets all queries that have been outstanding or answered for longer than the window.
+    fn expire(&mut self, now: DateTime<Utc>) {
+        while let Some((timestamp, key)) = self.expiry_queue.front() {
+            if *timestamp + self.window >= now {
+                break;
+            }
+
+            // the query might have been answered or replaced in the meantime
+            let still_outstanding = self.outstanding.get(key)
+                .map(|q| q.timestamp == *timestamp)
+                .unwrap_or(false);
+            if still_outstanding {
+                self.outstanding.remove(key);
+            }
+            self.expiry_queue.pop_front();
+        }
+
+        while let Some((timestamp, key)) = self.answered_expiry_queue.front() {
+            if *timestamp + self.window >= now {
+                break;
+            }
+
+            let still_answered = self.answered.get(key)
+                .map(|a| a.response_timestamp == *timestamp)
+                .unwrap_or(false);
+            if still_answered {
+                self.answered.remove(key);
+            }
+            self.answered_expiry_queue.pop_front();
+        }
+    }
+
+    pub fn add_query(&mut self, key: FlowKey, timestamp: DateTime<Utc>, questions: Vec<DnsQuestion>) {
+        self.expire(timestamp);
+
+        // the flow key is being reused for a new query
+        self.answered.remove(&key);
+
+        self.outstanding.insert(key, OutstandingQuery {
+            timestamp,
+            questions,
+        });
+        self.expiry_queue.push_back((timestamp, key));
+    }
+
+    /// Removes the given query from the outstanding queries because it is known to have failed.
+    pub fn fail_query(&mut self, key: FlowKey, timestamp: DateTime<Utc>) -> Option<OutstandingQuery> {
+        self.expire(timestamp);
+        self.outstanding.remove(&key)
+    }
+
+    pub fn match_response(&mut self, key: FlowKey, timestamp: DateTime<Utc>, questions: &[DnsQuestion], answers: &[Record]) -> CorrelationOutcome {
+        self.expire(timestamp);
+
+        let query = match self.outstanding.get(&key) {
+            Some(q) => q,
+            None => {
+                // perhaps we have seen a response to this query already
+                return match self.answered.get(&key) {
+                    Some(a) if a.questions == questions => CorrelationOutcome::Duplicate {
+                        differing: !same_records(&a.answers, answers),
+                    },
+                    _ => CorrelationOutcome::Unsolicited,
+                };
+            },
+        };
+        if query.questions != questions {
+            // keep waiting for the genuine response
+            return CorrelationOutcome::QuestionMismatch;
+        }
+
+        let query = self.outstanding.remove(&key).unwrap();
+        self.answered.insert(key, AnsweredQuery {
+            response_timestamp: timestamp,
+            questions: query.questions.clone(),
+            answers: answers.to_vec(),
+        });
+        self.answered_expiry_queue.push_back((timestamp, key));
+        CorrelationOutcome::Matched(query)
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use std::net::Ipv4Addr;
+    use std::str::FromStr;
+
+    use chrono::{Duration, TimeZone, Utc};
+    use hickory_proto::rr::{Name, RData, Record};
+    use hickory_proto::rr::rdata::A;
+
+    use super::{CorrelationOutcome, CorrelationTable, FlowKey};
+    use crate::dns::DnsQuestion;
+
+    #[test]
+    fn test_correlation() {
+        let start = Utc.with_ymd_and_hms(2022, 10, 1, 12, 0, 0).unwrap();
+        let key = FlowKey {
+            client: "192.0.2.1:12345".parse().unwrap(),
+            server: "192.0.2.53:53".parse().unwrap(),
+            transaction_id: 0x1234,
+        };
+        let other_key = FlowKey {
+            transaction_id: 0x4321,
+            ..key
+        };
+        let name = Name::from_str("example.com.").unwrap();
+        let question = DnsQuestion {
+            name: "example.com".parse().unwrap(),
+            query_type: 1,
+            query_class: 1,
+            mixed_case: false,
+        };
+        let other_question = DnsQuestion {
+            name: "example.org".parse().unwrap(),
+            ..question
+        };
+        let answer = Record::from_rdata(name.clone(), 300, RData::A(A(Ipv4Addr::new(192, 0, 2, 80))));
+        let answer_lower_ttl = Record::from_rdata(name.clone(), 299, RData::A(A(Ipv4Addr::new(192, 0, 2, 80))));
+        let other_answer = Record::from_rdata(name.clone(), 300, RData::A(A(Ipv4Addr::new(198, 51, 100, 80))));
+
+        let mut table = CorrelationTable::new(Duration::seconds(5));
+        table.add_query(key, start, vec![question.clone()]);
+
+        assert_eq!(table.match_response(other_key, start, &[question.clone()], &[answer.clone()]), CorrelationOutcome::Unsolicited);
+        assert_eq!(table.match_response(key, start, &[other_question.clone()], &[answer.clone()]), CorrelationOutcome::QuestionMismatch);
+        match table.match_response(key, start + Duration::milliseconds(20), &[question.clone()], &[answer.clone()]) {
+            CorrelationOutcome::Matched(q) => assert_eq!(q.timestamp, start),
+            other => panic!("unexpected outcome {:?}", other),
+        }
+
+        // further responses are duplicates
+        assert_eq!(table.match_response(key, start + Duration::milliseconds(30), &[question.clone()], &[answer_lower_ttl.clone()]), CorrelationOutcome::Duplicate { differing: false });
+        assert_eq!(table.match_response(key, start + Duration::milliseconds(30), &[question.clone()], &[other_answer.clone()]), CorrelationOutcome::Duplicate { differing: true });
+        assert_eq!(table.match_response(key, start + Duration::milliseconds(30), &[other_question.clone()], &[answer.clone()]), CorrelationOutcome::Unsolicited);
+
+        // responses arriving after the window are unsolicited
+        table.add_query(key, start + Duration::seconds(1), vec![question.clone()]);
+        assert_eq!(table.match_response(key, start + Duration::seconds(7), &[question.clone()], &[answer.clone()]), CorrelationOutcome::Unsolicited);
+    }
+}
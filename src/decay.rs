@@ -0,0 +1,142 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::hash::Hash;
+
+use chrono::{DateTime, Duration, Utc};
+
+
+const DEFAULT_CAPACITY: usize = 1024;
+const DEFAULT_EXPORT_COUNT: usize = 20;
+const DEFAULT_DECAY_INTERVAL_SECS: i64 = 60 * 60;
+
+
+/// Counts occurrences of keys in bounded memory, halving all counts whenever the decay interval
+/// elapses so that keys which are no longer being seen eventually disappear.
+///
+/// Once the capacity is reached, a new key replaces the key with the lowest count.
+///
+/// Only the `export_count` keys with the highest counts are output when formatting with `Debug`.
+#[derive(Clone, Eq, PartialEq)]
+pub struct DecayingCounter<K: Eq + Hash> {
+    capacity: usize,
+    export_count: usize,
+    decay_interval: Duration,
+    last_decay: Option<DateTime<Utc>>,
+    key_to_count: HashMap<K, u64>,
+}
+impl<K: Clone + Eq + Hash + Ord> DecayingCounter<K> {
+    pub fn new(capacity: usize, export_count: usize, decay_interval: Duration) -> Self {
+        Self {
+            capacity,
+            export_count,
+            decay_interval,
+            last_decay: None,
+            key_to_count: HashMap::new(),
+        }
+    }
+
+    fn decay(&mut self, timestamp: DateTime<Utc>) {
+        let last_decay = match self.last_decay {
+            Some(ld) => ld,
+            None => {
+                self.last_decay = Some(timestamp);
+                return;
+            },
+        };
+
+        let mut halvings = 0;
+        let mut next_decay = last_decay + self.decay_interval;
+        while next_decay <= timestamp && halvings < 64 {
+            halvings += 1;
+            next_decay = next_decay + self.decay_interval;
+        }
+        if halvings == 0 {
+            return;
+        }
+
+        for count in self.key_to_count.values_mut() {
+            *count = count.checked_shr(halvings).unwrap_or(0);
+        }
+        self.key_to_count.retain(|_key, count| *count > 0);
+        self.last_decay = Some(next_decay - self.decay_interval);
+    }
+
+    pub fn observe(&mut self, key: &K, timestamp: DateTime<Utc>) {
+        self.decay(timestamp);
+
+        if let Some(count) = self.key_to_count.get_mut(key) {
+            *count += 1;
+            return;
+        }
+
+        if self.capacity == 0 {
+            return;
+        }
+        if self.key_to_count.len() >= self.capacity {
+            // make room by forgetting the rarest key
+            let rarest_key = self.key_to_count.iter()
+                .min_by(|(k1, c1), (k2, c2)| c1.cmp(c2).then_with(|| k1.cmp(k2)))
+                .map(|(k, _c)| k.clone())
+                .unwrap();
+            self.key_to_count.remove(&rarest_key);
+        }
+        self.key_to_count.insert(key.clone(), 1);
+    }
+
+    /// Returns the keys that are exported, i.e. the `export_count` most frequent ones.
+    pub fn exported(&self) -> Vec<(K, u64)> {
+        self.top(self.export_count)
+    }
+
+    /// Returns the keys with the highest counts, most frequent first.
+    pub fn top(&self, n: usize) -> Vec<(K, u64)> {
+        let mut entries: Vec<(K, u64)> = self.key_to_count.iter()
+            .map(|(k, c)| (k.clone(), *c))
+            .collect();
+        entries.sort_unstable_by(|(k1, c1), (k2, c2)| c2.cmp(c1).then_with(|| k1.cmp(k2)));
+        entries.truncate(n);
+        entries
+    }
+}
+impl<K: Clone + Eq + Hash + Ord> Default for DecayingCounter<K> {
+    fn default() -> Self {
+        Self::new(DEFAULT_CAPACITY, DEFAULT_EXPORT_COUNT, Duration::seconds(DEFAULT_DECAY_INTERVAL_SECS))
+    }
+}
+impl<K: Clone + Eq + fmt::Debug + Hash + Ord> fmt::Debug for DecayingCounter<K> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_map()
+            .entries(self.exported())
+            .finish()
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use chrono::{Duration, TimeZone, Utc};
+
+    use super::DecayingCounter;
+
+    #[test]
+    fn test_decaying_counter() {
+        let start = Utc.with_ymd_and_hms(2022, 10, 1, 12, 0, 0).unwrap();
+        let mut counter = DecayingCounter::new(2, 1, Duration::minutes(10));
+
+        for _ in 0..4 {
+            counter.observe(&"com".to_owned(), start);
+        }
+        counter.observe(&"net".to_owned(), start);
+        counter.observe(&"net".to_owned(), start);
+        assert_eq!(counter.top(5), vec![("com".to_owned(), 4), ("net".to_owned(), 2)]);
+        assert_eq!(format!("{:?}", counter), r#"{"com": 4}"#);
+
+        // a new key replaces the rarest one
+        counter.observe(&"org".to_owned(), start + Duration::minutes(1));
+        assert_eq!(counter.top(5), vec![("com".to_owned(), 4), ("org".to_owned(), 1)]);
+
+        // two decay intervals later, the counts have been quartered
+        counter.observe(&"com".to_owned(), start + Duration::minutes(21));
+        assert_eq!(counter.top(5), vec![("com".to_owned(), 2)]);
+    }
+}
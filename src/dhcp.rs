@@ -0,0 +1,202 @@
+use std::collections::HashMap;
+use std::net::{IpAddr, Ipv4Addr};
+
+use crate::bytes::TryFromBytes;
+use crate::packet::PacketDissection;
+
+
+pub const DHCP_SERVER_PORT: u16 = 67;
+pub const DHCP_CLIENT_PORT: u16 = 68;
+
+const DHCP_MAGIC_COOKIE: [u8; 4] = [99, 130, 83, 99];
+
+// managed by IANA: https://www.iana.org/assignments/bootp-dhcp-parameters/bootp-dhcp-parameters.xhtml
+const DHCP_OPTION_PAD: u8 = 0;
+const DHCP_OPTION_HOST_NAME: u8 = 12;
+const DHCP_OPTION_MESSAGE_TYPE: u8 = 53;
+const DHCP_OPTION_END: u8 = 255;
+const DHCP_MESSAGE_TYPE_ACK: u8 = 5;
+
+
+#[derive(Clone, Debug, Eq, Hash, Ord, PartialEq, PartialOrd)]
+// as defined in RFC2131 section 2 and RFC2132; only the fields and options we need
+pub struct DhcpMessage {
+    pub your_address: Ipv4Addr,
+    pub client_hardware_address: Vec<u8>,
+    pub message_type: Option<u8>,
+    pub host_name: Option<String>,
+}
+impl DhcpMessage {
+    pub fn try_take(bytes: &[u8]) -> PacketDissection<Self> {
+        // fixed-length part plus magic cookie
+        if bytes.len() < 240 {
+            return PacketDissection::TooShort;
+        }
+        if bytes[236..240] != DHCP_MAGIC_COOKIE {
+            return PacketDissection::WrongType;
+        }
+
+        let hardware_address_length = usize::from(bytes[2]).min(16);
+        let your_address = Ipv4Addr::try_from_bytes(&bytes[16..20]).unwrap();
+        let client_hardware_address = bytes[28..28+hardware_address_length].to_vec();
+
+        let mut message_type = None;
+        let mut host_name = None;
+
+        let mut options = &bytes[240..];
+        loop {
+            if options.len() == 0 {
+                // the end option is mandatory
+                return PacketDissection::TooShort;
+            }
+            match options[0] {
+                DHCP_OPTION_PAD => {
+                    options = &options[1..];
+                    continue;
+                },
+                DHCP_OPTION_END => {
+                    options = &options[1..];
+                    break;
+                },
+                _ => {},
+            }
+
+            if options.len() < 2 || options.len() < 2 + usize::from(options[1]) {
+                return PacketDissection::TooShort;
+            }
+            let option_type = options[0];
+            let value = &options[2..2+usize::from(options[1])];
+            match option_type {
+                DHCP_OPTION_MESSAGE_TYPE => message_type = value.first().map(|t| *t),
+                DHCP_OPTION_HOST_NAME => host_name = Some(String::from_utf8_lossy(value).into_owned()),
+                _ => {},
+            }
+            options = &options[2+value.len()..];
+        }
+
+        let message = Self {
+            your_address,
+            client_hardware_address,
+            message_type,
+            host_name,
+        };
+        PacketDissection::Success { header: message, rest: options }
+    }
+}
+
+
+/// Remembers the host names that DHCP clients announce and the addresses they are assigned.
+///
+/// Clients are told apart by their hardware address, which every message carries; the client
+/// identifier option would be more precise, but servers do not always repeat it in their replies.
+#[derive(Clone, Debug, Default)]
+pub struct DhcpTracker {
+    client_to_host_name: HashMap<Vec<u8>, String>,
+    address_to_client: HashMap<Ipv4Addr, Vec<u8>>,
+}
+impl DhcpTracker {
+    pub fn new() -> Self {
+        Self {
+            client_to_host_name: HashMap::new(),
+            address_to_client: HashMap::new(),
+        }
+    }
+
+    pub fn observe(&mut self, message: &DhcpMessage) {
+        // clients announce their host name when requesting a lease; some servers repeat it
+        if let Some(host_name) = &message.host_name {
+            if host_name.len() > 0 {
+                self.client_to_host_name.insert(message.client_hardware_address.clone(), host_name.clone());
+            }
+        }
+
+        // the acknowledgement tells us which address the client will be using
+        if message.message_type == Some(DHCP_MESSAGE_TYPE_ACK) && !message.your_address.is_unspecified() {
+            self.address_to_client.insert(message.your_address, message.client_hardware_address.clone());
+        }
+    }
+
+    pub fn host_name(&self, address: IpAddr) -> Option<&str> {
+        let address = match address {
+            IpAddr::V4(a) => a,
+            IpAddr::V6(_) => return None,
+        };
+        let client = self.address_to_client.get(&address)?;
+        self.client_to_host_name.get(client)
+            .map(|h| h.as_str())
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use std::net::Ipv4Addr;
+
+    use super::{DhcpMessage, DhcpTracker};
+    use crate::packet::PacketDissection;
+
+    fn make_message(message_type: u8, your_address: Ipv4Addr, host_name: Option<&str>, client_identifier: Option<&[u8]>) -> Vec<u8> {
+        let mut bs = vec![0u8; 240];
+        bs[0] = if message_type == 5 { 2 } else { 1 }; // op
+        bs[1] = 1; // htype: Ethernet
+        bs[2] = 6; // hlen
+        bs[16..20].copy_from_slice(&your_address.octets());
+        bs[28..34].copy_from_slice(&[0x02, 0x00, 0x5E, 0x00, 0x53, 0x01]);
+        bs[236..240].copy_from_slice(&[99, 130, 83, 99]);
+        bs.extend_from_slice(&[53, 1, message_type]);
+        if let Some(hn) = host_name {
+            bs.push(12);
+            bs.push(hn.len().try_into().unwrap());
+            bs.extend_from_slice(hn.as_bytes());
+        }
+        if let Some(ci) = client_identifier {
+            bs.push(61);
+            bs.push(ci.len().try_into().unwrap());
+            bs.extend_from_slice(ci);
+        }
+        bs.push(0); // pad
+        bs.push(255);
+        bs
+    }
+
+    #[test]
+    fn test_host_name_from_request() {
+        let request = make_message(3, Ipv4Addr::UNSPECIFIED, Some("laptop"), None);
+        let ack = make_message(5, Ipv4Addr::new(192, 0, 2, 23), None, None);
+
+        let mut tracker = DhcpTracker::new();
+        for bs in [&request, &ack] {
+            match DhcpMessage::try_take(bs) {
+                PacketDissection::Success { header, .. } => tracker.observe(&header),
+                other => panic!("unexpected dissection {:?}", other),
+            }
+        }
+
+        assert_eq!(tracker.host_name("192.0.2.23".parse().unwrap()), Some("laptop"));
+        assert_eq!(tracker.host_name("192.0.2.24".parse().unwrap()), None);
+
+        // without the end option
+        match DhcpMessage::try_take(&request[..request.len()-1]) {
+            PacketDissection::TooShort => {},
+            other => panic!("unexpected dissection {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_client_identifier_not_repeated() {
+        // the client identifies itself by type (Ethernet) and hardware address; the server does not echo it
+        let client_identifier = [0x01, 0x02, 0x00, 0x5E, 0x00, 0x53, 0x01];
+        let request = make_message(3, Ipv4Addr::UNSPECIFIED, Some("printer"), Some(&client_identifier));
+        let ack = make_message(5, Ipv4Addr::new(192, 0, 2, 42), None, None);
+
+        let mut tracker = DhcpTracker::new();
+        for bs in [&request, &ack] {
+            match DhcpMessage::try_take(bs) {
+                PacketDissection::Success { header, .. } => tracker.observe(&header),
+                other => panic!("unexpected dissection {:?}", other),
+            }
+        }
+
+        assert_eq!(tracker.host_name("192.0.2.42".parse().unwrap()), Some("printer"));
+    }
+}
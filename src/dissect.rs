@@ -0,0 +1,533 @@
+use std::fmt;
+use std::net::{IpAddr, SocketAddr};
+
+use macaddr::MacAddr6;
+use hickory_proto::error::ProtoError;
+use hickory_proto::op::Message;
+
+use crate::arp::ArpPacket;
+use crate::dhcp::{DHCP_CLIENT_PORT, DHCP_SERVER_PORT, DhcpMessage};
+use crate::dns::{
+    DnsHeader, DnsParseError, DnsQuestion, DnsRecordHeader, HickoryDecoder, MessageDecoder,
+    Questions, RecordHeaders,
+};
+use crate::edns::Edns;
+use crate::ethernet::{
+    EthernetHeader, ETHERTYPE_ARP, ETHERTYPE_IPV4, ETHERTYPE_IPV6, ETHERTYPE_VLAN_TAG,
+    VlanTagHeader,
+};
+use crate::icmp::{
+    IcmpFailureReason, IcmpHeader, ICMPV6_TYPE_NEIGHBOR_ADVERTISEMENT,
+    ICMPV6_TYPE_NEIGHBOR_SOLICITATION, NeighborDiscovery,
+};
+use crate::ip::{
+    IpHeader, Ipv4Header, Ipv6Header, PROTO_ICMP, PROTO_ICMPV6, PROTO_IPV6_FRAGMENT, PROTO_TCP,
+    PROTO_UDP,
+};
+use crate::packet::{Linktype, PacketDissection};
+use crate::stage_timer::{PipelineStage, StageTimer};
+use crate::tcp_udp::{TcpHeader, UdpHeader};
+
+
+#[derive(Debug)]
+pub enum DissectError {
+    UnsupportedLinktype(Linktype),
+    TooShort { layer: &'static str },
+    WrongType { layer: &'static str },
+    IncorrectChecksum { layer: &'static str },
+    Malformed { layer: &'static str, reason: &'static str },
+    UnexpectedEthertype(u16),
+    UnexpectedIpVersion(u8),
+    UnexpectedProtocol(u8),
+    DnsStructure(DnsParseError),
+}
+impl DissectError {
+    fn from_dissection<H>(dissection: PacketDissection<H>, layer: &'static str) -> Self {
+        match dissection {
+            PacketDissection::Success { .. } => unreachable!("successful dissection is not an error"),
+            PacketDissection::TooShort => Self::TooShort { layer },
+            PacketDissection::WrongType => Self::WrongType { layer },
+            PacketDissection::IncorrectChecksum => Self::IncorrectChecksum { layer },
+            PacketDissection::Malformed { reason } => Self::Malformed { layer, reason },
+        }
+    }
+
+    /// Returns the layer at which dissection failed and a short description of the reason, for
+    /// keeping statistics.
+    pub fn layer_and_reason(&self) -> (&'static str, &'static str) {
+        match self {
+            Self::UnsupportedLinktype(_) => ("link", "unsupported link type"),
+            Self::TooShort { layer } => (layer, "too short"),
+            Self::WrongType { layer } => (layer, "wrong type"),
+            Self::IncorrectChecksum { layer } => (layer, "incorrect checksum"),
+            Self::Malformed { layer, reason } => (layer, reason),
+            Self::UnexpectedEthertype(_) => ("Ethernet", "unexpected ethertype"),
+            Self::UnexpectedIpVersion(_) => ("IP", "unexpected IP version"),
+            Self::UnexpectedProtocol(_) => ("IP", "unexpected inner protocol"),
+            Self::DnsStructure(_) => ("DNS", "invalid structure"),
+        }
+    }
+}
+impl fmt::Display for DissectError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::UnsupportedLinktype(l)
+                => write!(f, "unsupported link type {:?}", l),
+            Self::TooShort { layer }
+                => write!(f, "{} layer is too short", layer),
+            Self::WrongType { layer }
+                => write!(f, "{} layer is of the wrong type", layer),
+            Self::IncorrectChecksum { layer }
+                => write!(f, "{} layer has an incorrect checksum", layer),
+            Self::Malformed { layer, reason }
+                => write!(f, "{} layer is malformed: {}", layer, reason),
+            Self::UnexpectedEthertype(e)
+                => write!(f, "unexpected ethertype 0x{:04X}", e),
+            Self::UnexpectedIpVersion(v)
+                => write!(f, "unexpected IP version {}", v),
+            Self::UnexpectedProtocol(p)
+                => write!(f, "unexpected inner protocol {}", p),
+            Self::DnsStructure(e)
+                => write!(f, "failed to parse DNS message structure: {}", e),
+        }
+    }
+}
+impl std::error::Error for DissectError {
+}
+
+
+/// A DNS message that the DNS library has failed to decode, kept for debugging.
+#[derive(Clone, Debug)]
+pub struct UndecodableMessage {
+    pub error: ProtoError,
+    pub bytes: Vec<u8>,
+}
+
+
+/// How thoroughly DNS messages are dissected.
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub enum DetailLevel {
+    Full,
+
+    /// Only the header, the questions and the record headers; the DNS library is not consulted.
+    HeadersOnly,
+}
+
+
+/// The information extracted from a single frame.
+#[derive(Clone, Debug)]
+pub enum DnsEvent {
+    Query {
+        source: SocketAddr,
+        destination: SocketAddr,
+        vlan_id: Option<u16>,
+        link_destination: Option<MacAddr6>, // None if not captured on Ethernet
+        ip_header: IpHeader,
+        tcp_header: Option<TcpHeader>, // None if carried over UDP
+        header: DnsHeader,
+        questions: Vec<DnsQuestion>,
+        edns: Option<Edns>,
+    },
+    Response {
+        source: SocketAddr,
+        destination: SocketAddr,
+        vlan_id: Option<u16>,
+        link_destination: Option<MacAddr6>, // None if not captured on Ethernet
+        ip_header: IpHeader,
+        tcp_header: Option<TcpHeader>, // None if carried over UDP
+        header: DnsHeader,
+        questions: Vec<DnsQuestion>,
+        edns: Option<Edns>,
+        answer_headers: Vec<DnsRecordHeader>,
+        message_length: usize,
+        message: Option<Result<Message, UndecodableMessage>>, // None if not decoded at this detail level; records the DNS library fails to decode are still counted
+        #[cfg(feature = "tcp-tracking")]
+        tcp_payload: Vec<u8>, // the whole payload of the segment, which may also hold the start of the next message; empty over UDP
+    },
+    IcmpFailure {
+        reason: IcmpFailureReason,
+        client: SocketAddr,
+        server: SocketAddr,
+        transaction_id: Option<u16>, // if the quote is long enough
+    },
+    NeighborDiscovery {
+        source_address: IpAddr,
+        discovery: NeighborDiscovery,
+    },
+    Arp(ArpPacket),
+    Dhcp(DhcpMessage),
+
+    /// A TCP segment that does not carry a complete DNS message, e.g. part of the handshake.
+    #[cfg(feature = "tcp-tracking")]
+    TcpSegment {
+        source: SocketAddr,
+        destination: SocketAddr,
+        vlan_id: Option<u16>,
+        header: TcpHeader,
+        payload: Vec<u8>,
+    },
+
+    /// A fragment of a UDP datagram; fragments are not reassembled.
+    Fragment {
+        source: IpAddr,
+        destination: IpAddr,
+        vlan_id: Option<u16>,
+        ports: Option<(u16, u16)>, // (source, destination); only known from the first fragment
+    },
+
+    /// The frame is intact but carries nothing of interest, e.g. an unrelated ICMP message.
+    Unrelated,
+}
+
+
+/// Dissects a captured frame down to the DNS message (or the related protocol message) it carries.
+pub fn dissect_frame(frame: &[u8], linktype: Linktype, detail_level: DetailLevel, timer: &mut StageTimer) -> Result<DnsEvent, DissectError> {
+    timer.enter(PipelineStage::Link);
+    let mut vlan_id = None;
+    let mut link_destination = None;
+    let ip_bytes = match linktype {
+        Linktype::ETHERNET => {
+            let (eth, rest) = match EthernetHeader::try_take(frame) {
+                PacketDissection::Success { header, rest } => (header, rest),
+                other => return Err(DissectError::from_dissection(other, "Ethernet")),
+            };
+            link_destination = Some(eth.destination);
+
+            // the VLAN tag sits between the addresses and the actual ethertype
+            let (ethertype, rest) = if eth.ethertype == ETHERTYPE_VLAN_TAG {
+                match VlanTagHeader::try_take(rest) {
+                    PacketDissection::Success { header, rest } => {
+                        vlan_id = Some(header.vlan_id);
+                        (header.ethertype, rest)
+                    },
+                    other => return Err(DissectError::from_dissection(other, "VLAN tag")),
+                }
+            } else {
+                (eth.ethertype, rest)
+            };
+
+            match ethertype {
+                ETHERTYPE_IPV4|ETHERTYPE_IPV6 => rest,
+                ETHERTYPE_ARP => {
+                    return match ArpPacket::try_take(rest) {
+                        PacketDissection::Success { header, .. } => Ok(DnsEvent::Arp(header)),
+                        other => Err(DissectError::from_dissection(other, "ARP")),
+                    };
+                },
+                other => return Err(DissectError::UnexpectedEthertype(other)),
+            }
+        },
+        Linktype::RAW|Linktype::IPV4|Linktype::IPV6 => frame,
+        other => return Err(DissectError::UnsupportedLinktype(other)),
+    };
+
+    // check IP version by peeking
+    timer.enter(PipelineStage::Ip);
+    if ip_bytes.len() < 1 {
+        return Err(DissectError::TooShort { layer: "IP" });
+    }
+    let ip_version = (ip_bytes[0] & 0b1111_0000) >> 4;
+    let (ip_header, rest) = match ip_version {
+        4 => {
+            match Ipv4Header::try_take(ip_bytes) {
+                PacketDissection::Success { header, rest } => (IpHeader::V4(header), rest),
+                other => return Err(DissectError::from_dissection(other, "IPv4")),
+            }
+        },
+        6 => {
+            match Ipv6Header::try_take(ip_bytes) {
+                PacketDissection::Success { header, rest } => (IpHeader::V6(header), rest),
+                other => return Err(DissectError::from_dissection(other, "IPv6")),
+            }
+        },
+        other => return Err(DissectError::UnexpectedIpVersion(other)),
+    };
+
+    timer.enter(PipelineStage::Transport);
+    if let Some(event) = dissect_fragment(&ip_header, rest, vlan_id) {
+        return Ok(event);
+    }
+    if ip_header.inner_protocol() == PROTO_ICMP || ip_header.inner_protocol() == PROTO_ICMPV6 {
+        return dissect_icmp(&ip_header, rest);
+    }
+
+    let (pseudo_header_bytes, pseudo_header_length) = ip_header.to_pseudo_header();
+    let pseudo_header = &pseudo_header_bytes[0..pseudo_header_length];
+    if ip_header.inner_protocol() == PROTO_TCP {
+        let (tcp_header, rest) = match TcpHeader::try_take(rest, pseudo_header) {
+            PacketDissection::Success { header, rest } => (header, rest),
+            other => return Err(DissectError::from_dissection(other, "TCP")),
+        };
+        let source = SocketAddr::new(ip_header.source_address(), tcp_header.source_port);
+        let destination = SocketAddr::new(ip_header.destination_address(), tcp_header.destination_port);
+
+        // DNS messages over TCP are prefixed with their length (RFC1035 § 4.2.2); we do not
+        // reassemble streams, so only messages contained in a single segment are evaluated
+        if rest.len() >= 2 {
+            let message_length = usize::from(u16::from_be_bytes(rest[0..2].try_into().unwrap()));
+            if rest.len() >= 2 + message_length {
+                let message_bytes = &rest[2..2 + message_length];
+                timer.enter(PipelineStage::Dns);
+                if let Ok(event) = dissect_dns(message_bytes, source, destination, vlan_id, link_destination, ip_header, Some(tcp_header), detail_level) {
+                    // zone transfers continue with further messages in the same segment
+                    #[cfg(feature = "tcp-tracking")]
+                    let mut event = event;
+                    #[cfg(feature = "tcp-tracking")]
+                    if let DnsEvent::Response { tcp_payload, .. } = &mut event {
+                        *tcp_payload = rest.to_vec();
+                    }
+                    return Ok(event);
+                }
+            }
+        }
+        #[cfg(feature = "tcp-tracking")]
+        return Ok(DnsEvent::TcpSegment {
+            source,
+            destination,
+            vlan_id,
+            header: tcp_header,
+            payload: rest.to_vec(),
+        });
+        #[cfg(not(feature = "tcp-tracking"))]
+        return Ok(DnsEvent::Unrelated);
+    }
+    if ip_header.inner_protocol() != PROTO_UDP {
+        return Err(DissectError::UnexpectedProtocol(ip_header.inner_protocol()));
+    }
+
+    let (udp_header, rest) = match UdpHeader::try_take(rest, pseudo_header) {
+        PacketDissection::Success { header, rest } => (header, rest),
+        other => return Err(DissectError::from_dissection(other, "UDP")),
+    };
+
+    // relays talk to servers from port 67 to port 67; whatever only shares a port with DHCP is
+    // left to the DNS dissector
+    let is_dhcp = |port| port == DHCP_SERVER_PORT || port == DHCP_CLIENT_PORT;
+    if is_dhcp(udp_header.source_port) || is_dhcp(udp_header.destination_port) {
+        match DhcpMessage::try_take(rest) {
+            PacketDissection::Success { header, .. } => return Ok(DnsEvent::Dhcp(header)),
+            other if is_dhcp(udp_header.source_port) && is_dhcp(udp_header.destination_port)
+                => return Err(DissectError::from_dissection(other, "DHCP")),
+            _ => {},
+        }
+    }
+
+    let source = SocketAddr::new(ip_header.source_address(), udp_header.source_port);
+    let destination = SocketAddr::new(ip_header.destination_address(), udp_header.destination_port);
+    timer.enter(PipelineStage::Dns);
+    dissect_dns(rest, source, destination, vlan_id, link_destination, ip_header, None, detail_level)
+}
+
+
+/// Recognizes fragments of UDP datagrams. Only the first fragment contains the UDP header, so it
+/// alone reveals the ports; its UDP checksum cannot be verified without the other fragments.
+fn dissect_fragment(ip_header: &IpHeader, rest: &[u8], vlan_id: Option<u16>) -> Option<DnsEvent> {
+    let (first, inner_protocol, inner) = match ip_header {
+        IpHeader::V4(h) => {
+            if !h.more_fragments() && h.fragment_offset() == 0 {
+                return None;
+            }
+            (h.fragment_offset() == 0, h.protocol, rest)
+        },
+        IpHeader::V6(h) => {
+            if h.next_header != PROTO_IPV6_FRAGMENT || rest.len() < 8 {
+                return None;
+            }
+            let fragment_offset = u16::from_be_bytes(rest[2..4].try_into().unwrap()) >> 3;
+            (fragment_offset == 0, rest[0], &rest[8..])
+        },
+    };
+    if inner_protocol != PROTO_UDP {
+        return None;
+    }
+
+    let ports = if first && inner.len() >= 4 {
+        Some((
+            u16::from_be_bytes(inner[0..2].try_into().unwrap()),
+            u16::from_be_bytes(inner[2..4].try_into().unwrap()),
+        ))
+    } else {
+        None
+    };
+    Some(DnsEvent::Fragment {
+        source: ip_header.source_address(),
+        destination: ip_header.destination_address(),
+        vlan_id,
+        ports,
+    })
+}
+
+
+/// Dissects a DNS message carried over UDP or TCP.
+fn dissect_dns(rest: &[u8], source: SocketAddr, destination: SocketAddr, vlan_id: Option<u16>, link_destination: Option<MacAddr6>, ip_header: IpHeader, tcp_header: Option<TcpHeader>, detail_level: DetailLevel) -> Result<DnsEvent, DissectError> {
+    // header and questions are enough for queries
+    let header = match DnsHeader::try_take(rest) {
+        PacketDissection::Success { header, .. } => header,
+        other => return Err(DissectError::from_dissection(other, "DNS")),
+    };
+    let mut question_iter = Questions::new(rest, &header);
+    let questions = question_iter.by_ref()
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|e| DissectError::DnsStructure(e))?;
+    let edns = Edns::find(rest, &header, question_iter.offset());
+
+    if !header.is_response() {
+        return Ok(DnsEvent::Query {
+            source,
+            destination,
+            vlan_id,
+            link_destination,
+            ip_header,
+            tcp_header,
+            header,
+            questions,
+            edns,
+        });
+    }
+
+    // the records of responses are evaluated as well
+    let answer_headers = RecordHeaders::new(rest, question_iter.offset(), header.answer_count)
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|e| DissectError::DnsStructure(e))?;
+    let message = match detail_level {
+        DetailLevel::Full => Some(
+            HickoryDecoder::decode(rest)
+                .map_err(|error| UndecodableMessage { error, bytes: rest.to_vec() })
+        ),
+        DetailLevel::HeadersOnly => None,
+    };
+    Ok(DnsEvent::Response {
+        source,
+        destination,
+        vlan_id,
+        link_destination,
+        ip_header,
+        tcp_header,
+        header,
+        questions,
+        edns,
+        answer_headers,
+        message_length: rest.len(),
+        message,
+        #[cfg(feature = "tcp-tracking")]
+        tcp_payload: Vec::new(),
+    })
+}
+
+
+/// Dissects an ICMP or ICMPv6 message, looking for neighbor discovery and for errors reporting the
+/// failure of a DNS query.
+fn dissect_icmp(ip_header: &IpHeader, icmp_bytes: &[u8]) -> Result<DnsEvent, DissectError> {
+    let (pseudo_header_bytes, pseudo_header_length) = ip_header.to_pseudo_header();
+    let pseudo_header = match ip_header {
+        IpHeader::V4(_) => &[][..], // ICMPv4 does not use a pseudo-header
+        IpHeader::V6(_) => &pseudo_header_bytes[0..pseudo_header_length],
+    };
+    let (icmp_header, quoted) = match IcmpHeader::try_take(icmp_bytes, pseudo_header) {
+        PacketDissection::Success { header, rest } => (header, rest),
+        other => return Err(DissectError::from_dissection(other, "ICMP")),
+    };
+
+    // the error message quotes the beginning of the packet that caused it
+    let (reason, quoted_ip_header, quoted_rest) = match ip_header {
+        IpHeader::V4(_) => {
+            let reason = match IcmpFailureReason::from_icmpv4(&icmp_header) {
+                Some(r) => r,
+                None => return Ok(DnsEvent::Unrelated),
+            };
+            match Ipv4Header::try_take(quoted) {
+                PacketDissection::Success { header, rest } => (reason, IpHeader::V4(header), rest),
+                _ => return Ok(DnsEvent::Unrelated),
+            }
+        },
+        IpHeader::V6(_) => {
+            if icmp_header.icmp_type == ICMPV6_TYPE_NEIGHBOR_SOLICITATION || icmp_header.icmp_type == ICMPV6_TYPE_NEIGHBOR_ADVERTISEMENT {
+                return match NeighborDiscovery::try_take(&icmp_header, quoted) {
+                    PacketDissection::Success { header, .. } => Ok(DnsEvent::NeighborDiscovery {
+                        source_address: ip_header.source_address(),
+                        discovery: header,
+                    }),
+                    other => Err(DissectError::from_dissection(other, "neighbor discovery")),
+                };
+            }
+
+            let reason = match IcmpFailureReason::from_icmpv6(&icmp_header) {
+                Some(r) => r,
+                None => return Ok(DnsEvent::Unrelated),
+            };
+            match Ipv6Header::try_take(quoted) {
+                PacketDissection::Success { header, rest } => (reason, IpHeader::V6(header), rest),
+                _ => return Ok(DnsEvent::Unrelated),
+            }
+        },
+    };
+
+    // is it a DNS query? (the quote might be too short for a full UDP header check)
+    if quoted_ip_header.inner_protocol() != PROTO_UDP || quoted_rest.len() < 8 {
+        return Ok(DnsEvent::Unrelated);
+    }
+    let source_port = u16::from_be_bytes(quoted_rest[0..2].try_into().unwrap());
+    let destination_port = u16::from_be_bytes(quoted_rest[2..4].try_into().unwrap());
+    if destination_port != 53 {
+        return Ok(DnsEvent::Unrelated);
+    }
+
+    // if the transaction ID has been quoted too, we can find out which query failed
+    let transaction_id = if quoted_rest.len() >= 10 {
+        Some(u16::from_be_bytes(quoted_rest[8..10].try_into().unwrap()))
+    } else {
+        None
+    };
+
+    Ok(DnsEvent::IcmpFailure {
+        reason,
+        client: SocketAddr::new(quoted_ip_header.source_address(), source_port),
+        server: SocketAddr::new(quoted_ip_header.destination_address(), destination_port),
+        transaction_id,
+    })
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::{DetailLevel, dissect_frame, DissectError, DnsEvent};
+    use crate::packet::Linktype;
+    use crate::stage_timer::StageTimer;
+
+    #[test]
+    fn test_dissect_errors() {
+        // Ethernet frame with an IPv4 header cut short
+        let mut frame = vec![
+            0x02, 0x00, 0x5E, 0x00, 0x53, 0x35, 0x02, 0x00, 0x5E, 0x00, 0x53, 0x01, 0x08, 0x00,
+            0x45, 0x00, 0x00, 0x39,
+        ];
+        match dissect_frame(&frame, Linktype::ETHERNET, DetailLevel::Full, &mut StageTimer::new()) {
+            Err(DissectError::TooShort { layer: "IPv4" }) => {},
+            other => panic!("unexpected result {:?}", other),
+        }
+        match dissect_frame(&frame, Linktype::LINUX_SLL, DetailLevel::Full, &mut StageTimer::new()) {
+            Err(DissectError::UnsupportedLinktype(Linktype::LINUX_SLL)) => {},
+            other => panic!("unexpected result {:?}", other),
+        }
+
+        // IPX
+        frame[12..14].copy_from_slice(&[0x81, 0x37]);
+        match dissect_frame(&frame, Linktype::ETHERNET, DetailLevel::Full, &mut StageTimer::new()) {
+            Err(DissectError::UnexpectedEthertype(0x8137)) => {},
+            other => panic!("unexpected result {:?}", other),
+        }
+
+        // ARP reply: 192.0.2.53 is at 02:00:5e:00:53:35
+        frame[12..14].copy_from_slice(&[0x08, 0x06]);
+        frame.truncate(14);
+        frame.extend_from_slice(&[
+            0x00, 0x01, 0x08, 0x00, 0x06, 0x04, 0x00, 0x02,
+            0x02, 0x00, 0x5E, 0x00, 0x53, 0x35, 0xC0, 0x00, 0x02, 0x35,
+            0x02, 0x00, 0x5E, 0x00, 0x53, 0x01, 0xC0, 0x00, 0x02, 0x01,
+        ]);
+        match dissect_frame(&frame, Linktype::ETHERNET, DetailLevel::Full, &mut StageTimer::new()) {
+            Ok(DnsEvent::Arp(arp)) => assert_eq!(arp.sender_protocol_address, "192.0.2.53".parse::<std::net::Ipv4Addr>().unwrap()),
+            other => panic!("unexpected result {:?}", other),
+        }
+    }
+}
@@ -0,0 +1,573 @@
+use std::borrow::Cow;
+use std::fmt;
+use std::str::FromStr;
+
+use hickory_proto::error::ProtoError;
+use hickory_proto::op::{Message, ResponseCode};
+use hickory_proto::rr::{DNSClass, RecordType};
+use hickory_proto::serialize::binary::BinDecodable;
+
+use crate::packet::PacketDissection;
+
+
+const MAX_NAME_LENGTH: usize = 255;
+
+
+#[derive(Clone, Copy, Debug, Eq, Hash, Ord, PartialEq, PartialOrd)]
+pub enum DnsParseError {
+    TooShort,
+    NameTooLong,
+    PointerLoop,
+    UnsupportedLabelType(u8),
+}
+impl fmt::Display for DnsParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::TooShort
+                => write!(f, "message ends prematurely"),
+            Self::NameTooLong
+                => write!(f, "name is longer than {} bytes", MAX_NAME_LENGTH),
+            Self::PointerLoop
+                => write!(f, "compression pointer does not point backwards"),
+            Self::UnsupportedLabelType(t)
+                => write!(f, "unsupported label type 0b{:02b}", t),
+        }
+    }
+}
+impl std::error::Error for DnsParseError {
+}
+
+
+// managed by IANA: https://www.iana.org/assignments/dns-parameters/dns-parameters.xhtml#dns-parameters-5
+#[derive(Clone, Copy, Debug, Eq, Hash, Ord, PartialEq, PartialOrd)]
+pub enum Opcode {
+    Query,
+    InverseQuery,
+    Status,
+    Notify,
+    Update,
+    StatefulOperations,
+    Other(u8),
+}
+impl Opcode {
+    pub fn from_u8(value: u8) -> Self {
+        match value {
+            0 => Self::Query,
+            1 => Self::InverseQuery,
+            2 => Self::Status,
+            4 => Self::Notify,
+            5 => Self::Update,
+            6 => Self::StatefulOperations,
+            other => Self::Other(other),
+        }
+    }
+}
+
+
+// the single-bit flags of the header besides QR, as defined in RFC1035 section 4.1.1 and RFC4035
+// section 3.2
+const HEADER_FLAGS: [(&str, u16); 6] = [
+    ("AA", 0b0000_0100_0000_0000),
+    ("TC", 0b0000_0010_0000_0000),
+    ("RD", 0b0000_0001_0000_0000),
+    ("RA", 0b0000_0000_1000_0000),
+    ("AD", 0b0000_0000_0010_0000),
+    ("CD", 0b0000_0000_0001_0000),
+];
+
+
+#[derive(Clone, Copy, Debug, Default, Eq, Hash, Ord, PartialEq, PartialOrd)]
+// as defined in RFC1035 section 4.1.1
+pub struct DnsHeader {
+    pub id: u16,
+    pub flags: u16,
+    pub question_count: u16,
+    pub answer_count: u16,
+    pub authority_count: u16,
+    pub additional_count: u16,
+}
+impl DnsHeader {
+    pub fn try_take(bytes: &[u8]) -> PacketDissection<Self> {
+        if bytes.len() < 12 {
+            return PacketDissection::TooShort;
+        }
+
+        let id = u16::from_be_bytes(bytes[0..2].try_into().unwrap());
+        let flags = u16::from_be_bytes(bytes[2..4].try_into().unwrap());
+        let question_count = u16::from_be_bytes(bytes[4..6].try_into().unwrap());
+        let answer_count = u16::from_be_bytes(bytes[6..8].try_into().unwrap());
+        let authority_count = u16::from_be_bytes(bytes[8..10].try_into().unwrap());
+        let additional_count = u16::from_be_bytes(bytes[10..12].try_into().unwrap());
+
+        let header = Self {
+            id,
+            flags,
+            question_count,
+            answer_count,
+            authority_count,
+            additional_count,
+        };
+        PacketDissection::Success { header, rest: &bytes[12..] }
+    }
+
+    pub fn is_response(&self) -> bool {
+        (self.flags & 0b1000_0000_0000_0000) != 0
+    }
+
+    pub fn opcode(&self) -> Opcode {
+        Opcode::from_u8(((self.flags & 0b0111_1000_0000_0000) >> 11).try_into().unwrap())
+    }
+
+    pub fn authoritative(&self) -> bool {
+        (self.flags & 0b0000_0100_0000_0000) != 0
+    }
+
+    pub fn truncated(&self) -> bool {
+        (self.flags & 0b0000_0010_0000_0000) != 0
+    }
+
+    pub fn recursion_available(&self) -> bool {
+        (self.flags & 0b0000_0000_1000_0000) != 0
+    }
+
+    /// The names of the flags set in the header (e.g. `RD`), not including QR.
+    pub fn flag_names(&self) -> impl Iterator<Item = &'static str> {
+        let flags = self.flags;
+        HEADER_FLAGS.iter()
+            .filter(move |(_, bit)| flags & bit != 0)
+            .map(|(name, _)| *name)
+    }
+
+    /// The response code in the header; the upper bits of extended response codes are in the OPT
+    /// record and not considered here.
+    pub fn response_code(&self) -> ResponseCode {
+        ResponseCode::from_low((self.flags & 0b0000_0000_0000_1111).try_into().unwrap())
+    }
+}
+
+
+/// A DNS name in normalized form (lowercase labels separated by dots, without a trailing dot),
+/// stored without allocating.
+#[derive(Clone, Copy, Eq, Hash, Ord, PartialEq, PartialOrd)]
+pub struct DnsName {
+    bytes: [u8; MAX_NAME_LENGTH],
+    length: u8,
+}
+impl DnsName {
+    pub fn root() -> Self {
+        Self {
+            bytes: [0; MAX_NAME_LENGTH],
+            length: 0,
+        }
+    }
+
+    /// Appends a label. The caller ensures that the length limit for names is respected, which
+    /// guarantees that the label fits.
+    fn push_label(&mut self, label: &[u8]) {
+        let mut length = usize::from(self.length);
+        if length > 0 {
+            self.bytes[length] = b'.';
+            length += 1;
+        }
+        for b in label {
+            self.bytes[length] = b.to_ascii_lowercase();
+            length += 1;
+        }
+        self.length = length.try_into().unwrap();
+    }
+
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.bytes[0..usize::from(self.length)]
+    }
+
+    /// Returns the name as a string, only allocating if it is not valid UTF-8.
+    pub fn as_str(&self) -> Cow<str> {
+        String::from_utf8_lossy(self.as_bytes())
+    }
+
+    /// Reads the (possibly compressed) name starting at `offset` within `message`, returning it along
+    /// with the offset just past it.
+    pub fn read(message: &[u8], offset: usize) -> Result<(Self, usize), DnsParseError> {
+        let mut name = Self::root();
+        let mut position = offset;
+        let mut end_offset = None;
+        let mut wire_length = 1; // the terminating root label
+
+        // each pointer must point before the previous one, so we cannot go around in circles
+        let mut pointer_limit = offset;
+
+        loop {
+            let length_byte = *message.get(position).ok_or(DnsParseError::TooShort)?;
+            match length_byte >> 6 {
+                0b00 => {
+                    if length_byte == 0 {
+                        return Ok((name, end_offset.unwrap_or(position + 1)));
+                    }
+
+                    let label_length = usize::from(length_byte);
+                    let label = message.get(position+1..position+1+label_length)
+                        .ok_or(DnsParseError::TooShort)?;
+                    wire_length += 1 + label_length;
+                    if wire_length > MAX_NAME_LENGTH {
+                        return Err(DnsParseError::NameTooLong);
+                    }
+                    name.push_label(label);
+                    position += 1 + label_length;
+                },
+                0b11 => {
+                    let pointer_bytes = message.get(position..position+2)
+                        .ok_or(DnsParseError::TooShort)?;
+                    let target = usize::from(u16::from_be_bytes(pointer_bytes.try_into().unwrap()) & 0x3FFF);
+                    if target >= pointer_limit {
+                        return Err(DnsParseError::PointerLoop);
+                    }
+                    if end_offset.is_none() {
+                        end_offset = Some(position + 2);
+                    }
+                    pointer_limit = target;
+                    position = target;
+                },
+                other => return Err(DnsParseError::UnsupportedLabelType(other)),
+            }
+        }
+    }
+}
+impl FromStr for DnsName {
+    type Err = DnsParseError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut name = Self::root();
+        let mut wire_length = 1;
+        for label in s.trim_end_matches('.').split('.').filter(|l| l.len() > 0) {
+            wire_length += 1 + label.len();
+            if label.len() > 63 || wire_length > MAX_NAME_LENGTH {
+                return Err(DnsParseError::NameTooLong);
+            }
+            name.push_label(label.as_bytes());
+        }
+        Ok(name)
+    }
+}
+impl fmt::Debug for DnsName {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "DnsName({:?})", self.as_str())
+    }
+}
+impl fmt::Display for DnsName {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.as_str())
+    }
+}
+
+
+#[derive(Clone, Copy, Debug, Eq, Hash, Ord, PartialEq, PartialOrd)]
+// as defined in RFC1035 section 4.1.2
+pub struct DnsQuestion {
+    pub name: DnsName,
+    pub query_type: u16,
+    pub query_class: u16,
+    pub mixed_case: bool, // on the wire, as with 0x20 encoding
+}
+impl DnsQuestion {
+    pub fn record_type(&self) -> RecordType {
+        RecordType::from(self.query_type)
+    }
+
+    pub fn record_class(&self) -> DNSClass {
+        DNSClass::from(self.query_class)
+    }
+}
+
+
+/// Returns the bytes of the labels of a name as it appears on the wire, skipping the label lengths
+/// and stopping at a compression pointer, whose second byte may well look like a letter.
+fn wire_label_bytes(wire_name: &[u8]) -> impl Iterator<Item = u8> + '_ {
+    let mut position = 0;
+    std::iter::from_fn(move || {
+        let length = usize::from(*wire_name.get(position)?);
+        if length == 0 || length & 0xC0 != 0 {
+            return None;
+        }
+        let label = wire_name.get(position+1..position+1+length)?;
+        position += 1 + length;
+        Some(label)
+    })
+        .flatten()
+        .copied()
+}
+
+
+/// Iterates over the question section of a DNS message.
+pub struct Questions<'a> {
+    message: &'a [u8],
+    position: usize,
+    remaining: u16,
+}
+impl<'a> Questions<'a> {
+    /// Starts iterating over the questions of the given message, which must begin with the given
+    /// header.
+    pub fn new(message: &'a [u8], header: &DnsHeader) -> Self {
+        Self {
+            message,
+            position: 12,
+            remaining: header.question_count,
+        }
+    }
+
+    /// The offset of the next question, or of the answer section once all questions have been read.
+    pub fn offset(&self) -> usize {
+        self.position
+    }
+}
+impl<'a> Iterator for Questions<'a> {
+    type Item = Result<DnsQuestion, DnsParseError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.remaining == 0 {
+            return None;
+        }
+
+        let result = DnsName::read(self.message, self.position)
+            .and_then(|(name, end_offset)| {
+                let type_and_class = self.message.get(end_offset..end_offset+4)
+                    .ok_or(DnsParseError::TooShort)?;
+
+                // the name has been lowercased, so look at the labels as sent
+                let wire_name = &self.message[self.position..end_offset];
+                let mixed_case = wire_label_bytes(wire_name).any(|b| b.is_ascii_uppercase())
+                    && wire_label_bytes(wire_name).any(|b| b.is_ascii_lowercase());
+
+                self.position = end_offset + 4;
+                Ok(DnsQuestion {
+                    name,
+                    query_type: u16::from_be_bytes(type_and_class[0..2].try_into().unwrap()),
+                    query_class: u16::from_be_bytes(type_and_class[2..4].try_into().unwrap()),
+                    mixed_case,
+                })
+            });
+
+        // nothing sensible follows a broken question
+        self.remaining = if result.is_ok() { self.remaining - 1 } else { 0 };
+        Some(result)
+    }
+}
+
+
+#[derive(Clone, Copy, Debug, Eq, Hash, Ord, PartialEq, PartialOrd)]
+// as defined in RFC1035 section 4.1.3; the data itself is skipped
+pub struct DnsRecordHeader {
+    pub name: DnsName,
+    pub record_type: u16,
+    pub record_class: u16,
+    pub ttl: u32,
+    pub data_length: u16,
+    pub data_offset: usize, // within the message
+}
+
+
+/// Iterates over the headers of the resource records in a DNS message, regardless of whether the
+/// data of the records can be understood.
+pub struct RecordHeaders<'a> {
+    message: &'a [u8],
+    position: usize,
+    remaining: u16,
+}
+impl<'a> RecordHeaders<'a> {
+    /// Starts iterating over `count` records beginning at `offset` within `message`.
+    pub fn new(message: &'a [u8], offset: usize, count: u16) -> Self {
+        Self {
+            message,
+            position: offset,
+            remaining: count,
+        }
+    }
+
+    /// The offset of the next record, or of the following section once all records have been read.
+    pub fn offset(&self) -> usize {
+        self.position
+    }
+}
+impl<'a> Iterator for RecordHeaders<'a> {
+    type Item = Result<DnsRecordHeader, DnsParseError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.remaining == 0 {
+            return None;
+        }
+
+        let result = DnsName::read(self.message, self.position)
+            .and_then(|(name, end_offset)| {
+                let fixed = self.message.get(end_offset..end_offset+10)
+                    .ok_or(DnsParseError::TooShort)?;
+                let data_length = u16::from_be_bytes(fixed[8..10].try_into().unwrap());
+                let data_end = end_offset + 10 + usize::from(data_length);
+                if data_end > self.message.len() {
+                    return Err(DnsParseError::TooShort);
+                }
+                self.position = data_end;
+                Ok(DnsRecordHeader {
+                    name,
+                    record_type: u16::from_be_bytes(fixed[0..2].try_into().unwrap()),
+                    record_class: u16::from_be_bytes(fixed[2..4].try_into().unwrap()),
+                    ttl: u32::from_be_bytes(fixed[4..8].try_into().unwrap()),
+                    data_length,
+                    data_offset: end_offset + 10,
+                })
+            });
+
+        self.remaining = if result.is_ok() { self.remaining - 1 } else { 0 };
+        Some(result)
+    }
+}
+
+
+/// A DNS library that fully decodes messages, including the data of their records.
+pub trait MessageDecoder {
+    type Message;
+    type Error;
+
+    fn decode(bytes: &[u8]) -> Result<Self::Message, Self::Error>;
+}
+
+
+pub struct HickoryDecoder;
+impl MessageDecoder for HickoryDecoder {
+    type Message = Message;
+    type Error = ProtoError;
+
+    fn decode(bytes: &[u8]) -> Result<Self::Message, Self::Error> {
+        Message::from_bytes(bytes)
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::{DnsHeader, DnsName, DnsParseError, Opcode, Questions, RecordHeaders};
+    use crate::packet::PacketDissection;
+
+    #[test]
+    fn test_questions() {
+        // two questions, the second one compressed: Example.COM A IN, www.example.com AAAA IN
+        let bs: [u8; 43] = [
+            0x12, 0x34, 0x01, 0x00, 0x00, 0x02, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+            0x07, b'E', b'x', b'a', b'm', b'p', b'l', b'e', 0x03, b'C', b'O', b'M', 0x00,
+            0x00, 0x01, 0x00, 0x01,
+            0x03, b'w', b'w', b'w', 0xC0, 0x0C,
+            0x00, 0x1C, 0x00, 0x01,
+            0xFF, 0xFF, 0xFF, 0xFF,
+        ];
+        let header = match DnsHeader::try_take(&bs) {
+            PacketDissection::Success { header, .. } => header,
+            other => panic!("unexpected dissection {:?}", other),
+        };
+        assert_eq!(header.id, 0x1234);
+        assert!(!header.is_response());
+        assert_eq!(header.opcode(), Opcode::Query);
+
+        let questions: Vec<_> = Questions::new(&bs, &header)
+            .collect::<Result<_, _>>()
+            .unwrap();
+        assert_eq!(questions.len(), 2);
+        assert_eq!(questions[0].name.as_str(), "example.com");
+        assert_eq!(questions[0].query_type, 1);
+        assert!(questions[0].mixed_case);
+        assert_eq!(questions[1].name.as_str(), "www.example.com");
+        assert_eq!(questions[1].query_type, 28);
+        assert_eq!(questions[1].query_class, 1);
+        assert!(!questions[1].mixed_case);
+    }
+
+    #[test]
+    fn test_mixed_case_pointer() {
+        // a 52-letter label followed by com at offset 0x41, then www pointing to that com, the
+        // pointer offset being an uppercase A
+        let mut bs = vec![0x12, 0x34, 0x01, 0x00, 0x00, 0x02, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 52];
+        bs.extend_from_slice(&[b'x'; 52]);
+        bs.extend_from_slice(&[0x03, b'c', b'o', b'm', 0x00, 0x00, 0x01, 0x00, 0x01]);
+        bs.extend_from_slice(&[0x03, b'w', b'w', b'w', 0xC0, 0x41, 0x00, 0x01, 0x00, 0x01]);
+        let header = match DnsHeader::try_take(&bs) {
+            PacketDissection::Success { header, .. } => header,
+            other => panic!("unexpected dissection {:?}", other),
+        };
+
+        let questions: Vec<_> = Questions::new(&bs, &header)
+            .collect::<Result<_, _>>()
+            .unwrap();
+        assert_eq!(questions[1].name.as_str(), "www.com");
+        assert!(!questions[1].mixed_case);
+    }
+
+    #[test]
+    fn test_malicious_names() {
+        // pointer to itself
+        let bs = [0x00, 0x00, 0x03, b'w', b'w', b'w', 0xC0, 0x02];
+        assert_eq!(DnsName::read(&bs, 2), Err(DnsParseError::PointerLoop));
+
+        // two pointers pointing at each other
+        let bs = [0xC0, 0x02, 0x01, b'a', 0xC0, 0x00];
+        assert_eq!(DnsName::read(&bs, 2), Err(DnsParseError::PointerLoop));
+
+        // chain of labels exceeding 255 bytes
+        let mut bs = Vec::new();
+        for _ in 0..5 {
+            bs.push(63);
+            bs.extend_from_slice(&[b'a'; 63]);
+        }
+        bs.push(0);
+        assert_eq!(DnsName::read(&bs, 0), Err(DnsParseError::NameTooLong));
+
+        // truncated label
+        assert_eq!(DnsName::read(&[0x05, b'a', b'b'], 0), Err(DnsParseError::TooShort));
+
+        // extended label type
+        assert_eq!(DnsName::read(&[0x41, 0x00], 0), Err(DnsParseError::UnsupportedLabelType(0b01)));
+    }
+
+    #[test]
+    fn test_record_headers() {
+        // response with one question and two answers: a private-use type 65280 record and a NULL
+        // record, the owner names compressed
+        let bs: [u8; 56] = [
+            0x12, 0x34, 0x81, 0x80, 0x00, 0x01, 0x00, 0x02, 0x00, 0x00, 0x00, 0x00,
+            0x07, b'e', b'x', b'a', b'm', b'p', b'l', b'e', 0x03, b'c', b'o', b'm', 0x00,
+            0xFF, 0x00, 0x00, 0x01,
+            0xC0, 0x0C, 0xFF, 0x00, 0x00, 0x01, 0x00, 0x00, 0x01, 0x2C, 0x00, 0x03, 0x01, 0x02, 0x03,
+            0xC0, 0x0C, 0x00, 0x0A, 0x00, 0x01, 0x00, 0x00, 0x01, 0x2C, 0x00, 0x00,
+        ];
+        let header = match DnsHeader::try_take(&bs) {
+            PacketDissection::Success { header, .. } => header,
+            other => panic!("unexpected dissection {:?}", other),
+        };
+        assert!(header.is_response());
+        assert!(header.recursion_available());
+        assert!(!header.authoritative());
+
+        let mut questions = Questions::new(&bs, &header);
+        assert_eq!(questions.by_ref().count(), 1);
+        let records: Vec<_> = RecordHeaders::new(&bs, questions.offset(), header.answer_count)
+            .collect::<Result<_, _>>()
+            .unwrap();
+        assert_eq!(records.len(), 2);
+        assert_eq!(records[0].name.as_str(), "example.com");
+        assert_eq!(records[0].record_type, 0xFF00);
+        assert_eq!(records[0].ttl, 300);
+        assert_eq!(records[0].data_length, 3);
+        assert_eq!(bs[records[0].data_offset..records[0].data_offset + 3], [0x01, 0x02, 0x03]);
+        assert_eq!(records[1].record_type, 10);
+
+        // the data of the last record is cut off
+        let mut truncated = bs;
+        truncated[55] = 1;
+        let result: Result<Vec<_>, _> = RecordHeaders::new(&truncated, questions.offset(), header.answer_count).collect();
+        assert_eq!(result, Err(DnsParseError::TooShort));
+    }
+
+    #[test]
+    fn test_flag_names() {
+        // response with AA, RD, RA and CD set
+        let header = DnsHeader { flags: 0b1000_0101_1001_0011, ..DnsHeader::default() };
+        assert_eq!(header.flag_names().collect::<Vec<_>>(), vec!["AA", "RD", "RA", "CD"]);
+        assert_eq!(DnsHeader::default().flag_names().count(), 0);
+    }
+}
@@ -0,0 +1,154 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::net::IpAddr;
+use std::path::PathBuf;
+use std::time::Duration;
+
+use serde_json::Value;
+use tokio::net::UnixStream;
+use tracing::debug;
+
+use crate::http::{get_over, HttpError};
+
+
+const CONTAINERS_PATH: &str = "/containers/json";
+const REQUEST_TIMEOUT_SECS: u64 = 5;
+
+
+#[derive(Debug)]
+pub enum DockerError {
+    Http(HttpError),
+    Timeout,
+    InvalidContainerList(String),
+}
+impl fmt::Display for DockerError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Http(e)
+                => write!(f, "failed to query the Docker daemon: {}", e),
+            Self::Timeout
+                => write!(f, "timed out querying the Docker daemon"),
+            Self::InvalidContainerList(reason)
+                => write!(f, "invalid container list: {}", reason),
+        }
+    }
+}
+impl std::error::Error for DockerError {
+}
+impl From<HttpError> for DockerError {
+    fn from(e: HttpError) -> Self { Self::Http(e) }
+}
+
+
+/// Maps the addresses the running containers have on their networks to the container names.
+/// Containers using the network of the host have no address of their own and are skipped.
+fn parse_container_list(container_list: &Value) -> Result<HashMap<IpAddr, String>, DockerError> {
+    let containers = container_list.as_array()
+        .ok_or_else(|| DockerError::InvalidContainerList("not an array".to_owned()))?;
+    let mut ip_to_container = HashMap::new();
+    for container in containers {
+        // the names are given with a leading slash
+        let name = match container["Names"][0].as_str() {
+            Some(n) => n.trim_start_matches('/'),
+            None => continue,
+        };
+        let networks = match container["NetworkSettings"]["Networks"].as_object() {
+            Some(n) => n,
+            None => continue,
+        };
+        for network in networks.values() {
+            let addresses = [network["IPAddress"].as_str(), network["GlobalIPv6Address"].as_str()];
+            for address in addresses.into_iter().flatten() {
+                if let Ok(ip) = address.parse() {
+                    ip_to_container.insert(ip, name.to_owned());
+                }
+            }
+        }
+    }
+    Ok(ip_to_container)
+}
+
+
+/// Knows which container is using which address, as listed by the Docker daemon.
+///
+/// The addresses of containers that have since exited are remembered so that the clients seen
+/// earlier in a sample can still be named.
+pub struct ContainerDirectory {
+    socket_path: PathBuf,
+    refresh_interval: Duration,
+    ip_to_container: HashMap<IpAddr, String>,
+}
+impl ContainerDirectory {
+    pub fn new(socket_path: PathBuf, refresh_interval: Duration) -> Self {
+        Self {
+            socket_path,
+            refresh_interval,
+            ip_to_container: HashMap::new(),
+        }
+    }
+
+    pub fn refresh_interval(&self) -> Duration {
+        self.refresh_interval
+    }
+
+    async fn list_containers(&self) -> Result<Vec<u8>, DockerError> {
+        let stream = UnixStream::connect(&self.socket_path).await
+            .map_err(|e| DockerError::Http(HttpError::Io(e)))?;
+        Ok(get_over(stream, "localhost", CONTAINERS_PATH, &[]).await?)
+    }
+
+    /// Lists the running containers, adding their addresses to those already known.
+    pub async fn refresh(&mut self) -> Result<(), DockerError> {
+        let timeout = Duration::from_secs(REQUEST_TIMEOUT_SECS);
+        let body = match tokio::time::timeout(timeout, self.list_containers()).await {
+            Ok(b) => b?,
+            Err(_) => return Err(DockerError::Timeout),
+        };
+        let container_list: Value = serde_json::from_slice(&body)
+            .map_err(|e| DockerError::InvalidContainerList(e.to_string()))?;
+        self.ip_to_container.extend(parse_container_list(&container_list)?);
+        debug!("{} container addresses known", self.ip_to_container.len());
+        Ok(())
+    }
+
+    pub fn container(&self, address: IpAddr) -> Option<&str> {
+        self.ip_to_container.get(&address)
+            .map(|n| n.as_str())
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::parse_container_list;
+
+    #[test]
+    fn test_parse_container_list() {
+        let container_list = json!([
+            {
+                "Id": "8dfafdbc3a40",
+                "Names": ["/web"],
+                "NetworkSettings": {"Networks": {
+                    "bridge": {"IPAddress": "172.17.0.2", "GlobalIPv6Address": "2001:db8::242:ac11:2"},
+                    "backend": {"IPAddress": "172.18.0.5", "GlobalIPv6Address": ""},
+                }},
+            },
+            {
+                "Id": "9cd87474be90",
+                "Names": ["/monitoring"],
+                "NetworkSettings": {"Networks": {
+                    "host": {"IPAddress": "", "GlobalIPv6Address": ""},
+                }},
+            },
+        ]);
+        let ip_to_container = parse_container_list(&container_list).unwrap();
+        assert_eq!(ip_to_container.len(), 3);
+        assert_eq!(ip_to_container[&"172.17.0.2".parse().unwrap()], "web");
+        assert_eq!(ip_to_container[&"172.18.0.5".parse().unwrap()], "web");
+        assert_eq!(ip_to_container[&"2001:db8::242:ac11:2".parse().unwrap()], "web");
+
+        assert!(parse_container_list(&json!({"message": "page not found"})).is_err());
+    }
+}
@@ -0,0 +1,310 @@
+use crate::dns::{DnsHeader, DnsParseError, DnsRecordHeader, RecordHeaders};
+
+
+const RECORD_TYPE_OPT: u16 = 41;
+
+// managed by IANA: https://www.iana.org/assignments/dns-parameters/dns-parameters.xhtml#dns-parameters-11
+pub const EDNS_OPTION_NSID: u16 = 3;
+pub const EDNS_OPTION_COOKIE: u16 = 10;
+pub const EDNS_OPTION_EXTENDED_ERROR: u16 = 15;
+
+// managed by IANA: https://www.iana.org/assignments/dns-parameters/dns-parameters.xhtml#extended-dns-error-codes
+const EXTENDED_ERROR_NAMES: [&str; 31] = [
+    "Other Error",
+    "Unsupported DNSKEY Algorithm",
+    "Unsupported DS Digest Type",
+    "Stale Answer",
+    "Forged Answer",
+    "DNSSEC Indeterminate",
+    "DNSSEC Bogus",
+    "Signature Expired",
+    "Signature Not Yet Valid",
+    "DNSKEY Missing",
+    "RRSIGs Missing",
+    "No Zone Key Bit Set",
+    "NSEC Missing",
+    "Cached Error",
+    "Not Ready",
+    "Blocked",
+    "Censored",
+    "Filtered",
+    "Prohibited",
+    "Stale NXDomain Answer",
+    "Not Authoritative",
+    "Not Supported",
+    "No Reachable Authority",
+    "Network Error",
+    "Invalid Data",
+    "Signature Expired before Valid",
+    "Too Early",
+    "Unsupported NSEC3 Iterations Value",
+    "Unable to conform to policy",
+    "Synthesized",
+    "Invalid Query Type",
+];
+
+
+#[derive(Clone, Debug, Eq, Hash, Ord, PartialEq, PartialOrd)]
+pub struct EdnsOption {
+    pub code: u16,
+    pub data: Vec<u8>,
+}
+
+
+#[derive(Clone, Debug, Eq, Hash, Ord, PartialEq, PartialOrd)]
+// the contents of an OPT pseudo-record as defined in RFC6891 section 6.1
+pub struct Edns {
+    pub udp_payload_size: u16,
+    pub extended_rcode_upper_bits: u8,
+    pub version: u8,
+    pub dnssec_ok: bool,
+    pub options: Vec<EdnsOption>,
+}
+impl Edns {
+    /// Parses the OPT pseudo-record with the given header from the message.
+    pub fn parse(message: &[u8], record_header: &DnsRecordHeader) -> Result<Self, DnsParseError> {
+        let data_end = record_header.data_offset + usize::from(record_header.data_length);
+        let mut data = message.get(record_header.data_offset..data_end)
+            .ok_or(DnsParseError::TooShort)?;
+
+        let mut options = Vec::new();
+        while data.len() > 0 {
+            if data.len() < 4 {
+                return Err(DnsParseError::TooShort);
+            }
+            let code = u16::from_be_bytes(data[0..2].try_into().unwrap());
+            let length = usize::from(u16::from_be_bytes(data[2..4].try_into().unwrap()));
+            let option_data = data.get(4..4+length)
+                .ok_or(DnsParseError::TooShort)?;
+            options.push(EdnsOption {
+                code,
+                data: option_data.to_vec(),
+            });
+            data = &data[4+length..];
+        }
+
+        // the TTL field is repurposed
+        let ttl_bytes = record_header.ttl.to_be_bytes();
+        Ok(Self {
+            udp_payload_size: record_header.record_class,
+            extended_rcode_upper_bits: ttl_bytes[0],
+            version: ttl_bytes[1],
+            dnssec_ok: (ttl_bytes[2] & 0x80) != 0,
+            options,
+        })
+    }
+
+    /// Finds and parses the OPT pseudo-record in the additional section of the message, whose
+    /// answer section begins at `answer_offset`.
+    ///
+    /// Returns `None` if the message has no OPT pseudo-record or if it cannot be parsed.
+    pub fn find(message: &[u8], header: &DnsHeader, answer_offset: usize) -> Option<Self> {
+        // skip over the answer and authority sections
+        let mut offset = answer_offset;
+        for count in [header.answer_count, header.authority_count] {
+            let mut records = RecordHeaders::new(message, offset, count);
+            for record in records.by_ref() {
+                if record.is_err() {
+                    return None;
+                }
+            }
+            offset = records.offset();
+        }
+
+        for record in RecordHeaders::new(message, offset, header.additional_count) {
+            let record = record.ok()?;
+            if record.record_type == RECORD_TYPE_OPT {
+                return Self::parse(message, &record).ok();
+            }
+        }
+        None
+    }
+
+    /// The data of the first option with the given code.
+    pub fn option(&self, code: u16) -> Option<&[u8]> {
+        self.options_with_code(code).next()
+    }
+
+    /// The server identity (RFC5001) reported in a response, if any; printable identities are
+    /// returned verbatim, others as hex digits.
+    pub fn name_server_identifier(&self) -> Option<String> {
+        let nsid = self.option(EDNS_OPTION_NSID)?;
+        if nsid.len() == 0 {
+            // an empty NSID option is how a query asks for it
+            return None;
+        }
+        if nsid.iter().all(|b| *b >= 0x20 && *b <= 0x7E) {
+            Some(nsid.iter().map(|b| *b as char).collect())
+        } else {
+            Some(nsid.iter().map(|b| format!("{:02x}", b)).collect())
+        }
+    }
+
+    /// The data of all the options with the given code.
+    pub fn options_with_code(&self, code: u16) -> impl Iterator<Item = &[u8]> {
+        self.options.iter()
+            .filter(move |o| o.code == code)
+            .map(|o| o.data.as_slice())
+    }
+}
+
+
+#[derive(Clone, Debug, Eq, Hash, Ord, PartialEq, PartialOrd)]
+// an Extended DNS Error as defined in RFC8914 section 2
+pub struct ExtendedError {
+    pub info_code: u16,
+    pub extra_text: String,
+}
+impl ExtendedError {
+    /// Collects the well-formed Extended DNS Errors of a message; a message may carry more than
+    /// one.
+    pub fn from_edns(edns: &Edns) -> Vec<Self> {
+        edns.options_with_code(EDNS_OPTION_EXTENDED_ERROR)
+            .filter_map(|data| {
+                let info_code = u16::from_be_bytes(data.get(0..2)?.try_into().unwrap());
+                Some(Self {
+                    info_code,
+                    extra_text: String::from_utf8_lossy(&data[2..]).into_owned(),
+                })
+            })
+            .collect()
+    }
+
+    pub fn name(info_code: u16) -> &'static str {
+        EXTENDED_ERROR_NAMES.get(usize::from(info_code))
+            .copied()
+            .unwrap_or("Unassigned")
+    }
+}
+
+
+/// How a DNS message makes use of DNS cookies (RFC7873).
+#[derive(Clone, Copy, Debug, Eq, Hash, Ord, PartialEq, PartialOrd)]
+pub enum CookieUse {
+    None,
+    ClientOnly,
+    ClientAndServer,
+    Malformed,
+}
+impl CookieUse {
+    pub fn from_edns(edns: Option<&Edns>) -> Self {
+        let cookie = match edns.and_then(|e| e.option(EDNS_OPTION_COOKIE)) {
+            Some(c) => c,
+            None => return Self::None,
+        };
+
+        // an 8-byte client cookie, optionally followed by a server cookie of 8 to 32 bytes
+        match cookie.len() {
+            8 => Self::ClientOnly,
+            16..=40 => Self::ClientAndServer,
+            _ => Self::Malformed,
+        }
+    }
+
+    #[cfg(feature = "http")]
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::None => "none",
+            Self::ClientOnly => "client",
+            Self::ClientAndServer => "client_and_server",
+            Self::Malformed => "malformed",
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        CookieUse, Edns, EDNS_OPTION_COOKIE, EDNS_OPTION_EXTENDED_ERROR, EDNS_OPTION_NSID,
+        EdnsOption, ExtendedError,
+    };
+    use crate::dns::{DnsHeader, Questions};
+    use crate::packet::PacketDissection;
+
+    #[test]
+    fn test_find() {
+        let bs: &[u8] = &[
+            0x45, 0x67, 0x01, 0x00, 0x00, 0x01, 0x00, 0x00, 0x00, 0x00, 0x00, 0x01,
+            // example.com. IN A
+            0x07, b'e', b'x', b'a', b'm', b'p', b'l', b'e', 0x03, b'c', b'o', b'm', 0x00,
+            0x00, 0x01, 0x00, 0x01,
+            // OPT with payload size 1232, DO bit and a client cookie
+            0x00, 0x00, 0x29, 0x04, 0xD0, 0x00, 0x00, 0x80, 0x00, 0x00, 0x0C,
+            0x00, 0x0A, 0x00, 0x08, 0x01, 0x02, 0x03, 0x04, 0x05, 0x06, 0x07, 0x08,
+        ];
+        let header = match DnsHeader::try_take(bs) {
+            PacketDissection::Success { header, .. } => header,
+            other => panic!("unexpected {:?}", other),
+        };
+        let mut questions = Questions::new(bs, &header);
+        for question in questions.by_ref() {
+            question.unwrap();
+        }
+
+        let edns = Edns::find(bs, &header, questions.offset()).unwrap();
+        assert_eq!(edns.udp_payload_size, 1232);
+        assert_eq!(edns.version, 0);
+        assert!(edns.dnssec_ok);
+        assert_eq!(edns.option(EDNS_OPTION_COOKIE), Some(&[0x01, 0x02, 0x03, 0x04, 0x05, 0x06, 0x07, 0x08][..]));
+        assert_eq!(CookieUse::from_edns(Some(&edns)), CookieUse::ClientOnly);
+
+        // an option running past the end of the record
+        let mut truncated = bs.to_vec();
+        truncated[43] = 0x09;
+        assert_eq!(Edns::find(&truncated, &header, questions.offset()), None);
+    }
+
+    #[test]
+    fn test_cookie_use() {
+        let with_cookie = |length| Edns {
+            udp_payload_size: 1232,
+            extended_rcode_upper_bits: 0,
+            version: 0,
+            dnssec_ok: false,
+            options: vec![EdnsOption { code: EDNS_OPTION_COOKIE, data: vec![0; length] }],
+        };
+        assert_eq!(CookieUse::from_edns(None), CookieUse::None);
+        assert_eq!(CookieUse::from_edns(Some(&with_cookie(8))), CookieUse::ClientOnly);
+        assert_eq!(CookieUse::from_edns(Some(&with_cookie(24))), CookieUse::ClientAndServer);
+        assert_eq!(CookieUse::from_edns(Some(&with_cookie(12))), CookieUse::Malformed);
+    }
+
+    #[test]
+    fn test_extended_errors() {
+        let edns = Edns {
+            udp_payload_size: 1232,
+            extended_rcode_upper_bits: 0,
+            version: 0,
+            dnssec_ok: false,
+            options: vec![
+                EdnsOption { code: EDNS_OPTION_EXTENDED_ERROR, data: b"\x00\x0Fads.example".to_vec() },
+                EdnsOption { code: EDNS_OPTION_COOKIE, data: vec![0; 8] },
+                EdnsOption { code: EDNS_OPTION_EXTENDED_ERROR, data: vec![0x00, 0x06] },
+                EdnsOption { code: EDNS_OPTION_EXTENDED_ERROR, data: vec![0x00] },
+            ],
+        };
+        let errors = ExtendedError::from_edns(&edns);
+        assert_eq!(errors, vec![
+            ExtendedError { info_code: 15, extra_text: "ads.example".to_owned() },
+            ExtendedError { info_code: 6, extra_text: String::new() },
+        ]);
+        assert_eq!(ExtendedError::name(15), "Blocked");
+        assert_eq!(ExtendedError::name(6), "DNSSEC Bogus");
+        assert_eq!(ExtendedError::name(1234), "Unassigned");
+    }
+
+    #[test]
+    fn test_name_server_identifier() {
+        let with_nsid = |data: &[u8]| Edns {
+            udp_payload_size: 1232,
+            extended_rcode_upper_bits: 0,
+            version: 0,
+            dnssec_ok: false,
+            options: vec![EdnsOption { code: EDNS_OPTION_NSID, data: data.to_vec() }],
+        };
+        assert_eq!(with_nsid(b"").name_server_identifier(), None);
+        assert_eq!(with_nsid(b"ns1.fra").name_server_identifier(), Some("ns1.fra".to_owned()));
+        assert_eq!(with_nsid(b"\x01\xAB").name_server_identifier(), Some("01ab".to_owned()));
+    }
+}
@@ -33,6 +33,7 @@ impl EthernetHeader {
 
 // managed by IEEE: https://regauth.standards.ieee.org/standards-ra-web/pub/view.html ("Ethertype")
 pub const ETHERTYPE_IPV4: u16 = 0x0800;
+pub const ETHERTYPE_ARP: u16 = 0x0806;
 pub const ETHERTYPE_VLAN_TAG: u16 = 0x8100;
 pub const ETHERTYPE_IPV6: u16 = 0x86DD;
 
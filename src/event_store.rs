@@ -0,0 +1,138 @@
+use std::path::Path;
+
+use chrono::{DateTime, Duration, Utc};
+use rusqlite::{Connection, params};
+use tracing::error;
+
+use crate::sink::{DnsMessageEvent, EventSink};
+
+
+const PRUNE_INTERVAL: u64 = 1024;
+
+
+/// An on-disk log of the queries and responses observed, from which events older than the
+/// retention period are removed.
+pub struct EventStore {
+    connection: Connection,
+    retention: Duration,
+    inserts_since_prune: u64,
+}
+impl EventStore {
+    pub fn open<P: AsRef<Path>>(path: P, retention: Duration) -> Result<Self, rusqlite::Error> {
+        let connection = Connection::open(path)?;
+        connection.execute_batch("
+            PRAGMA journal_mode = WAL;
+            PRAGMA synchronous = NORMAL;
+            CREATE TABLE IF NOT EXISTS dns_events
+            ( time_us INTEGER NOT NULL
+            , source TEXT NOT NULL
+            , source_port INTEGER NOT NULL
+            , destination TEXT NOT NULL
+            , destination_port INTEGER NOT NULL
+            , transaction_id INTEGER NOT NULL
+            , name TEXT NOT NULL
+            , record_type TEXT NOT NULL
+            , response_code TEXT NULL
+            );
+            CREATE INDEX IF NOT EXISTS idx_dns_events_time ON dns_events (time_us);
+            CREATE INDEX IF NOT EXISTS idx_dns_events_source ON dns_events (source, time_us);
+            CREATE INDEX IF NOT EXISTS idx_dns_events_name ON dns_events (name, time_us);
+        ")?;
+        Ok(Self {
+            connection,
+            retention,
+            inserts_since_prune: 0,
+        })
+    }
+
+    pub fn record(&mut self, event: &DnsMessageEvent<'_>) -> Result<(), rusqlite::Error> {
+        self.connection.execute(
+            "
+                INSERT INTO dns_events
+                    (time_us, source, source_port, destination, destination_port, transaction_id, name, record_type, response_code)
+                VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9)
+            ",
+            params![
+                event.timestamp.timestamp_micros(),
+                event.source.ip().to_string(),
+                event.source.port(),
+                event.destination.ip().to_string(),
+                event.destination.port(),
+                event.transaction_id,
+                event.name,
+                event.record_type.to_string(),
+                event.response_code.map(|rc| rc.to_str()),
+            ],
+        )?;
+
+        self.inserts_since_prune += 1;
+        if self.inserts_since_prune >= PRUNE_INTERVAL {
+            self.prune(event.timestamp)?;
+        }
+        Ok(())
+    }
+
+    /// Removes the events that have fallen out of the retention period.
+    pub fn prune(&mut self, now: DateTime<Utc>) -> Result<(), rusqlite::Error> {
+        let cutoff = now - self.retention;
+        self.connection.execute(
+            "DELETE FROM dns_events WHERE time_us < ?1",
+            params![cutoff.timestamp_micros()],
+        )?;
+        self.inserts_since_prune = 0;
+        Ok(())
+    }
+}
+impl EventSink for EventStore {
+    fn emit(&mut self, event: &DnsMessageEvent<'_>) {
+        if let Err(e) = self.record(event) {
+            error!("failed to store DNS event: {}", e);
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+
+    use chrono::{Duration, TimeZone, Utc};
+    use hickory_proto::op::ResponseCode;
+    use hickory_proto::rr::RecordType;
+
+    use super::EventStore;
+    use crate::sink::DnsMessageEvent;
+
+    #[test]
+    fn test_record_and_prune() {
+        let mut store = EventStore::open(":memory:", Duration::hours(1)).unwrap();
+        let start = Utc.with_ymd_and_hms(2022, 10, 1, 12, 0, 0).unwrap();
+
+        let query = DnsMessageEvent {
+            timestamp: start,
+            source: "192.0.2.1:54321".parse().unwrap(),
+            destination: "192.0.2.53:53".parse().unwrap(),
+            transaction_id: 0x1234,
+            name: "example.com",
+            record_type: RecordType::A,
+            over_tcp: false,
+            response_code: None,
+            labels: &BTreeMap::new(),
+        };
+        store.record(&query).unwrap();
+        store.record(&DnsMessageEvent {
+            timestamp: start + Duration::minutes(90),
+            source: query.destination,
+            destination: query.source,
+            response_code: Some(ResponseCode::NoError),
+            ..query.clone()
+        }).unwrap();
+
+        store.prune(start + Duration::minutes(90)).unwrap();
+        let remaining: Vec<(i64, Option<String>)> = store.connection
+            .prepare("SELECT time_us, response_code FROM dns_events").unwrap()
+            .query_map([], |row| Ok((row.get(0)?, row.get(1)?))).unwrap()
+            .collect::<Result<_, _>>().unwrap();
+        assert_eq!(remaining, vec![((start + Duration::minutes(90)).timestamp_micros(), Some("No Error".to_owned()))]);
+    }
+}
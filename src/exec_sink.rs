@@ -0,0 +1,152 @@
+use std::io;
+use std::process::Stdio;
+use std::time::Duration;
+
+use tokio::io::AsyncWriteExt;
+use tokio::process::{Child, ChildStdin, Command};
+use tokio::sync::mpsc;
+use tokio::sync::mpsc::error::TrySendError;
+use tracing::{debug, info, warn};
+
+use crate::json_log::{format_event, JsonLogFormat};
+use crate::sink::{DnsMessageEvent, EventSink};
+
+
+const QUEUE_LENGTH: usize = 4096;
+const MIN_RESTART_DELAY_SECS: u64 = 1;
+const MAX_RESTART_DELAY_SECS: u64 = 60;
+
+
+/// Returns how long to wait before starting the process again after it has exited the given
+/// number of times in a row; the delay doubles each time up to a limit.
+fn restart_delay(consecutive_failures: u32) -> Duration {
+    let factor = 1u64.checked_shl(consecutive_failures.saturating_sub(1)).unwrap_or(u64::MAX);
+    let secs = MIN_RESTART_DELAY_SECS.saturating_mul(factor).min(MAX_RESTART_DELAY_SECS);
+    Duration::from_secs(secs)
+}
+
+
+fn spawn_process(command: &str) -> Result<(Child, ChildStdin), io::Error> {
+    let mut child = Command::new("/bin/sh")
+        .arg("-c")
+        .arg(command)
+        .stdin(Stdio::piped())
+        .kill_on_drop(true)
+        .spawn()?;
+    let stdin = child.stdin.take().unwrap();
+    Ok((child, stdin))
+}
+
+
+async fn run_sink(command: String, mut receiver: mpsc::Receiver<String>) {
+    let mut process: Option<(Child, ChildStdin)> = None;
+    let mut consecutive_failures = 0;
+    while let Some(first_line) = receiver.recv().await {
+        // write everything that is already queued in one go
+        let mut buf = first_line.into_bytes();
+        while let Ok(line) = receiver.try_recv() {
+            buf.extend_from_slice(line.as_bytes());
+        }
+
+        if process.is_none() {
+            if consecutive_failures > 0 {
+                tokio::time::sleep(restart_delay(consecutive_failures)).await;
+            }
+            match spawn_process(&command) {
+                Ok(p) => {
+                    info!("started event process {:?}", command);
+                    process = Some(p);
+                },
+                Err(e) => {
+                    warn!("failed to start event process {:?}; dropping events: {}", command, e);
+                    consecutive_failures += 1;
+                    continue;
+                },
+            }
+        }
+        if let Some((child, stdin)) = process.as_mut() {
+            match stdin.write_all(&buf).await {
+                Ok(()) => consecutive_failures = 0,
+                Err(e) => {
+                    // most likely, the process has exited
+                    let status = child.try_wait().ok().flatten();
+                    warn!("failed to pass events to event process {:?} (exit status {:?}); restarting it: {}", command, status, e);
+                    process = None;
+                    consecutive_failures += 1;
+                },
+            }
+        }
+    }
+
+    // closing standard input tells the process to finish up
+    if let Some((mut child, stdin)) = process {
+        drop(stdin);
+        if let Err(e) = child.wait().await {
+            warn!("failed to wait for event process {:?}: {}", command, e);
+        }
+    }
+}
+
+
+/// Passes every query and response as a line of JSON to the standard input of a process, which is
+/// restarted if it exits.
+///
+/// The lines are written in the background; if the process cannot keep up, events are dropped
+/// instead of holding up the capture.
+pub struct ExecSink {
+    format: JsonLogFormat,
+    sender: mpsc::Sender<String>,
+    task: tokio::task::JoinHandle<()>,
+    dropped_count: u64,
+}
+impl ExecSink {
+    /// Starts the sink, running the command using the shell. Must be called from within the Tokio
+    /// runtime.
+    pub fn new(command: String, format: JsonLogFormat) -> Self {
+        let (sender, receiver) = mpsc::channel(QUEUE_LENGTH);
+        let task = tokio::spawn(run_sink(command, receiver));
+        Self {
+            format,
+            sender,
+            task,
+            dropped_count: 0,
+        }
+    }
+}
+impl EventSink for ExecSink {
+    fn emit(&mut self, event: &DnsMessageEvent<'_>) {
+        let line = format!("{}\n", format_event(event, self.format));
+        match self.sender.try_send(line) {
+            Ok(()) => {},
+            Err(TrySendError::Full(_)) => {
+                debug!("event process queue full; dropping event");
+                self.dropped_count += 1;
+            },
+            Err(TrySendError::Closed(_)) => {},
+        }
+    }
+
+    fn close(self: Box<Self>) -> Option<tokio::task::JoinHandle<()>> {
+        if self.dropped_count > 0 {
+            warn!("dropped {} events because the event process could not keep up", self.dropped_count);
+        }
+        Some(self.task)
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::restart_delay;
+
+    #[test]
+    fn test_restart_delay() {
+        assert_eq!(restart_delay(1), Duration::from_secs(1));
+        assert_eq!(restart_delay(2), Duration::from_secs(2));
+        assert_eq!(restart_delay(4), Duration::from_secs(8));
+        assert_eq!(restart_delay(7), Duration::from_secs(60));
+        assert_eq!(restart_delay(100), Duration::from_secs(60));
+    }
+}
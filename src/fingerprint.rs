@@ -0,0 +1,328 @@
+use chrono::{DateTime, Duration, Utc};
+
+use crate::dns::{DnsName, DnsQuestion};
+use crate::edns::Edns;
+
+
+const MIN_QUERIES: u64 = 4;
+const RECENT_QUERY_COUNT: usize = 4;
+const PAIR_WINDOW_MS: i64 = 100; // A and AAAA queries sent this close together form a pair
+const ADDRESS_PAIR_WINDOW_MS: i64 = 1000; // ... which is stretched when measuring the gap
+const RETRANSMIT_WINDOW_SECS: i64 = 10;
+const MUSL_MAX_RETRANSMIT_MS: i64 = 4000; // musl retries every 2.5 s by default, glibc every 5 s
+const LARGE_PAYLOAD_SIZE: u16 = 1232;
+
+const RECORD_TYPE_A: u16 = 1;
+const RECORD_TYPE_AAAA: u16 = 28;
+const RECORD_TYPE_HTTPS: u16 = 65;
+
+
+/// The kind of DNS client software a source appears to be running.
+#[derive(Clone, Copy, Debug, Default, Eq, Hash, Ord, PartialEq, PartialOrd)]
+pub enum Fingerprint {
+    #[default]
+    Unknown,
+    Glibc,
+    Musl,
+    SystemdResolved,
+    Windows,
+    MacOs,
+    Resolver, // a recursive resolver rather than a stub
+}
+impl Fingerprint {
+    #[cfg(feature = "http")]
+    pub const ALL: [Self; 7] = [
+        Self::Unknown, Self::Glibc, Self::Musl, Self::SystemdResolved, Self::Windows, Self::MacOs,
+        Self::Resolver,
+    ];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::Unknown => "unknown",
+            Self::Glibc => "glibc",
+            Self::Musl => "musl",
+            Self::SystemdResolved => "systemd-resolved",
+            Self::Windows => "windows",
+            Self::MacOs => "macos",
+            Self::Resolver => "resolver",
+        }
+    }
+}
+
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+struct RecentQuery {
+    timestamp: DateTime<Utc>,
+    name: DnsName,
+    query_type: u16,
+    paired: bool,
+}
+
+
+/// An A query and an AAAA query for the same name sent in quick succession, as in Happy Eyeballs
+/// (RFC8305), which recommends asking for the AAAA records first.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct AddressQueryPair {
+    pub aaaa_first: bool,
+    pub gap: Duration,
+}
+
+
+/// The traits of the queries of a single source from which the client software can be guessed.
+///
+/// The guess is a heuristic: stub resolvers differ in whether they use EDNS (and with which
+/// buffer size and flags), in the order in which they ask for IPv4 and IPv6 addresses, in how
+/// quickly they retransmit unanswered queries and in the record types they ask for.
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct ClientTraits {
+    query_count: u64,
+    edns_count: u64,
+    dnssec_ok_count: u64,
+    large_payload_count: u64,
+    mixed_case_count: u64,
+    https_count: u64,
+    a_first_count: u64,
+    aaaa_first_count: u64,
+    retransmit_count: u64,
+    retransmit_interval_ms_sum: i64,
+    recent_queries: Vec<RecentQuery>,
+}
+impl ClientTraits {
+    /// Processes a query of the source, returning the pair it completes if it is the second of an A
+    /// and an AAAA query for the same name.
+    pub fn observe(&mut self, timestamp: DateTime<Utc>, question: &DnsQuestion, edns: Option<&Edns>) -> Option<AddressQueryPair> {
+        self.query_count += 1;
+        if let Some(e) = edns {
+            self.edns_count += 1;
+            if e.dnssec_ok {
+                self.dnssec_ok_count += 1;
+            }
+            if e.udp_payload_size >= LARGE_PAYLOAD_SIZE {
+                self.large_payload_count += 1;
+            }
+        }
+        if question.mixed_case {
+            self.mixed_case_count += 1;
+        }
+        if question.query_type == RECORD_TYPE_HTTPS {
+            self.https_count += 1;
+        }
+
+        let pair_window = Duration::milliseconds(PAIR_WINDOW_MS);
+        let address_pair_window = Duration::milliseconds(ADDRESS_PAIR_WINDOW_MS);
+        let retransmit_window = Duration::seconds(RETRANSMIT_WINDOW_SECS);
+        let mut address_pair = None;
+        let mut paired = false;
+        for recent in self.recent_queries.iter_mut().rev() {
+            if recent.name != question.name {
+                continue;
+            }
+            let interval = timestamp - recent.timestamp;
+            if recent.query_type == question.query_type {
+                if interval > pair_window && interval <= retransmit_window {
+                    self.retransmit_count += 1;
+                    self.retransmit_interval_ms_sum += interval.num_milliseconds();
+                }
+                break;
+            }
+
+            let aaaa_first = match (recent.query_type, question.query_type) {
+                (RECORD_TYPE_A, RECORD_TYPE_AAAA) => Some(false),
+                (RECORD_TYPE_AAAA, RECORD_TYPE_A) => Some(true),
+                _ => None,
+            };
+            if let Some(af) = aaaa_first {
+                // each query only belongs to one pair
+                if !recent.paired && interval <= address_pair_window {
+                    recent.paired = true;
+                    paired = true;
+                    address_pair = Some(AddressQueryPair {
+                        aaaa_first: af,
+                        gap: interval,
+                    });
+                }
+            }
+            if interval <= pair_window {
+                match aaaa_first {
+                    Some(true) => self.aaaa_first_count += 1,
+                    Some(false) => self.a_first_count += 1,
+                    None => {},
+                }
+                break;
+            }
+            if address_pair.is_some() {
+                break;
+            }
+        }
+
+        if self.recent_queries.len() >= RECENT_QUERY_COUNT {
+            self.recent_queries.remove(0);
+        }
+        self.recent_queries.push(RecentQuery {
+            timestamp,
+            name: question.name,
+            query_type: question.query_type,
+            paired,
+        });
+        address_pair
+    }
+
+    /// Guesses the client software from the traits observed so far.
+    pub fn classify(&self) -> Fingerprint {
+        if self.query_count < MIN_QUERIES {
+            return Fingerprint::Unknown;
+        }
+        let mostly = |count: u64| count * 2 > self.query_count;
+
+        // stubs do not randomize the case of names (0x20 encoding), recursive resolvers might
+        if mostly(self.mixed_case_count) {
+            return Fingerprint::Resolver;
+        }
+
+        // Apple's mDNSResponder asks for HTTPS records alongside the addresses
+        if self.https_count > 0 {
+            return Fingerprint::MacOs;
+        }
+
+        if mostly(self.edns_count) {
+            if mostly(self.dnssec_ok_count) && mostly(self.large_payload_count) {
+                return Fingerprint::SystemdResolved;
+            }
+            return Fingerprint::Windows;
+        }
+
+        // the classic stubs without EDNS differ in how quickly they retry
+        if self.retransmit_count > 0 {
+            let retransmit_interval_ms = self.retransmit_interval_ms_sum / i64::try_from(self.retransmit_count).unwrap();
+            if retransmit_interval_ms <= MUSL_MAX_RETRANSMIT_MS {
+                return Fingerprint::Musl;
+            }
+            return Fingerprint::Glibc;
+        }
+        if self.a_first_count > 0 && self.a_first_count >= self.aaaa_first_count {
+            return Fingerprint::Glibc;
+        }
+        Fingerprint::Unknown
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use chrono::{Duration, TimeZone, Utc};
+
+    use super::{AddressQueryPair, ClientTraits, Fingerprint};
+    use crate::dns::DnsQuestion;
+    use crate::edns::Edns;
+
+    // (milliseconds since start, name, type, EDNS payload size and DO bit, mixed case)
+    type TraceEntry = (i64, &'static str, u16, Option<(u16, bool)>, bool);
+
+    const CORPUS: [(&str, Fingerprint, &[TraceEntry]); 7] = [
+        ("glibc resolving two names", Fingerprint::Glibc, &[
+            (0, "example.com", 1, None, false),
+            (1, "example.com", 28, None, false),
+            (500, "www.example.org", 1, None, false),
+            (501, "www.example.org", 28, None, false),
+        ]),
+        ("glibc retransmitting after the timeout", Fingerprint::Glibc, &[
+            (0, "example.com", 1, None, false),
+            (1, "example.com", 28, None, false),
+            (5001, "example.com", 1, None, false),
+            (5002, "example.com", 28, None, false),
+        ]),
+        ("musl retransmitting quickly", Fingerprint::Musl, &[
+            (0, "example.com", 1, None, false),
+            (0, "example.com", 28, None, false),
+            (2500, "example.com", 1, None, false),
+            (2500, "example.com", 28, None, false),
+        ]),
+        ("systemd-resolved with DNSSEC", Fingerprint::SystemdResolved, &[
+            (0, "example.com", 1, Some((1232, true)), false),
+            (0, "example.com", 28, Some((1232, true)), false),
+            (300, "example.net", 1, Some((1232, true)), false),
+            (300, "example.net", 28, Some((1232, true)), false),
+        ]),
+        ("Windows with EDNS", Fingerprint::Windows, &[
+            (0, "example.com", 28, Some((1232, false)), false),
+            (2, "example.com", 1, Some((1232, false)), false),
+            (700, "login.example.net", 28, Some((1232, false)), false),
+            (702, "login.example.net", 1, Some((1232, false)), false),
+        ]),
+        ("macOS asking for HTTPS records", Fingerprint::MacOs, &[
+            (0, "example.com", 65, Some((512, false)), false),
+            (0, "example.com", 28, Some((512, false)), false),
+            (1, "example.com", 1, Some((512, false)), false),
+            (400, "example.org", 1, Some((512, false)), false),
+        ]),
+        ("resolver using 0x20 encoding", Fingerprint::Resolver, &[
+            (0, "example.com", 1, Some((1232, true)), true),
+            (10, "example.net", 1, Some((1232, true)), true),
+            (20, "example.org", 28, Some((1232, true)), true),
+            (30, "example.com", 2, Some((1232, true)), true),
+        ]),
+    ];
+
+    #[test]
+    fn test_corpus() {
+        let start = Utc.with_ymd_and_hms(2022, 10, 1, 12, 0, 0).unwrap();
+        for (description, expected, trace) in CORPUS {
+            let mut traits = ClientTraits::default();
+            for (ms, name, query_type, edns_params, mixed_case) in trace {
+                let question = DnsQuestion {
+                    name: name.parse().unwrap(),
+                    query_type: *query_type,
+                    query_class: 1,
+                    mixed_case: *mixed_case,
+                };
+                let edns = edns_params.map(|(udp_payload_size, dnssec_ok)| Edns {
+                    udp_payload_size,
+                    extended_rcode_upper_bits: 0,
+                    version: 0,
+                    dnssec_ok,
+                    options: Vec::new(),
+                });
+                traits.observe(start + Duration::milliseconds(*ms), &question, edns.as_ref());
+            }
+            assert_eq!(traits.classify(), expected, "{}", description);
+        }
+
+        // too few queries to tell
+        let mut traits = ClientTraits::default();
+        let question = DnsQuestion { name: "example.com".parse().unwrap(), query_type: 65, query_class: 1, mixed_case: false };
+        traits.observe(start, &question, None);
+        assert_eq!(traits.classify(), Fingerprint::Unknown);
+    }
+
+    #[test]
+    fn test_address_query_pairs() {
+        let start = Utc.with_ymd_and_hms(2022, 10, 1, 12, 0, 0).unwrap();
+        let question = |name: &str, query_type| DnsQuestion {
+            name: name.parse().unwrap(),
+            query_type,
+            query_class: 1,
+            mixed_case: false,
+        };
+        let ms = |n| start + Duration::milliseconds(n);
+
+        let mut traits = ClientTraits::default();
+        assert_eq!(traits.observe(ms(0), &question("example.com", 28), None), None);
+        assert_eq!(
+            traits.observe(ms(50), &question("example.com", 1), None),
+            Some(AddressQueryPair { aaaa_first: true, gap: Duration::milliseconds(50) }),
+        );
+
+        // the A query has already been paired
+        assert_eq!(traits.observe(ms(70), &question("example.com", 28), None), None);
+
+        assert_eq!(traits.observe(ms(1000), &question("example.net", 1), None), None);
+        assert_eq!(
+            traits.observe(ms(1400), &question("example.net", 28), None),
+            Some(AddressQueryPair { aaaa_first: false, gap: Duration::milliseconds(400) }),
+        );
+
+        // too far apart
+        assert_eq!(traits.observe(ms(3000), &question("example.org", 28), None), None);
+        assert_eq!(traits.observe(ms(5000), &question("example.org", 1), None), None);
+    }
+}
@@ -0,0 +1,223 @@
+use std::collections::VecDeque;
+use std::fs::File;
+use std::io::BufWriter;
+use std::path::PathBuf;
+
+use chrono::{DateTime, Duration, Utc};
+use tracing::{error, info, warn};
+
+use crate::packet::{Linktype, OwnedPacket, Precision};
+use crate::savefile::SavefileWriter;
+
+
+// a burst within the window must not exhaust the memory
+const MAX_BUFFERED_PACKETS: usize = 100_000;
+const MAX_BUFFERED_BYTES: usize = 64 * 1024 * 1024;
+
+
+struct ActiveDump {
+    path: PathBuf,
+    savefile: SavefileWriter<BufWriter<File>>,
+    linktype: Linktype,
+    until: DateTime<Utc>,
+}
+
+
+/// Keeps the most recent packets in memory and, once triggered, writes them and the packets of
+/// the following period to a pcap file.
+///
+/// A pcap file can only contain packets of one link type; packets of other link types (e.g. from
+/// other interfaces) are left out of the dump.
+///
+/// If more packets arrive within the window than the buffer may hold, the oldest are dropped early.
+pub struct FlightRecorder {
+    window: Duration,
+    post_trigger: Duration,
+    directory: PathBuf,
+    precision: Precision,
+    max_packets: usize,
+    max_bytes: usize,
+    buffer: VecDeque<(DateTime<Utc>, Linktype, OwnedPacket)>,
+    buffered_bytes: usize,
+    dropped_count: u64,
+    dump: Option<ActiveDump>,
+}
+impl FlightRecorder {
+    pub fn new(window: Duration, post_trigger: Duration, directory: PathBuf, precision: Precision) -> Self {
+        Self {
+            window,
+            post_trigger,
+            directory,
+            precision,
+            max_packets: MAX_BUFFERED_PACKETS,
+            max_bytes: MAX_BUFFERED_BYTES,
+            buffer: VecDeque::new(),
+            buffered_bytes: 0,
+            dropped_count: 0,
+            dump: None,
+        }
+    }
+
+    /// Records a packet, writing it to the current dump if one is active.
+    pub fn record(&mut self, timestamp: DateTime<Utc>, linktype: Linktype, packet: OwnedPacket) {
+        let dump_finished = match self.dump.as_mut() {
+            Some(dump) => {
+                if timestamp > dump.until {
+                    true
+                } else if linktype == dump.linktype {
+                    match dump.savefile.write(&packet) {
+                        Ok(()) => false,
+                        Err(e) => {
+                            error!("failed to write flight recorder dump {}: {}", dump.path.display(), e);
+                            true
+                        },
+                    }
+                } else {
+                    false
+                }
+            },
+            None => false,
+        };
+        if dump_finished {
+            self.finish_dump();
+        }
+
+        self.buffered_bytes += packet.data.len();
+        self.buffer.push_back((timestamp, linktype, packet));
+        self.trim(timestamp);
+    }
+
+    fn trim(&mut self, now: DateTime<Utc>) {
+        let cutoff = now - self.window;
+        while let Some((oldest_timestamp, _linktype, _packet)) = self.buffer.front() {
+            let expired = *oldest_timestamp < cutoff;
+            let over_limit = self.buffer.len() > self.max_packets || self.buffered_bytes > self.max_bytes;
+            if !expired && !over_limit {
+                break;
+            }
+            if !expired {
+                self.dropped_count += 1;
+            }
+            let (_timestamp, _linktype, packet) = self.buffer.pop_front().unwrap();
+            self.buffered_bytes -= packet.data.len();
+        }
+    }
+
+    fn finish_dump(&mut self) {
+        if let Some(mut dump) = self.dump.take() {
+            if let Err(e) = dump.savefile.flush() {
+                error!("failed to flush flight recorder dump {}: {}", dump.path.display(), e);
+            }
+            info!("flight recorder dump {} finished", dump.path.display());
+        }
+    }
+
+    /// Starts a dump of the buffered packets, or extends the current one.
+    pub fn trigger(&mut self, timestamp: DateTime<Utc>, linktype: Linktype, reason: &str) {
+        let until = timestamp + self.post_trigger;
+        if let Some(dump) = self.dump.as_mut() {
+            if dump.until < until {
+                dump.until = until;
+            }
+            return;
+        }
+
+        let file_name = format!("flight-{}-{}.pcap", timestamp.format("%Y%m%dT%H%M%S%.6fZ"), reason);
+        let path = self.directory.join(file_name);
+        let mut savefile = match SavefileWriter::create(&path, linktype, self.precision) {
+            Ok(sf) => sf,
+            Err(e) => {
+                error!("failed to create flight recorder dump {}: {}", path.display(), e);
+                return;
+            },
+        };
+
+        let mut skipped_count: usize = 0;
+        for (_timestamp, packet_linktype, packet) in &self.buffer {
+            if *packet_linktype != linktype {
+                skipped_count += 1;
+            } else if let Err(e) = savefile.write(packet) {
+                error!("failed to write flight recorder dump {}: {}", path.display(), e);
+                return;
+            }
+        }
+        if skipped_count > 0 {
+            warn!("flight recorder dump {} omits {} packets of a different link type", path.display(), skipped_count);
+        }
+        info!("flight recorder dump {} started ({})", path.display(), reason);
+
+        self.dump = Some(ActiveDump {
+            path,
+            savefile,
+            linktype,
+            until,
+        });
+    }
+
+    /// Finishes the current dump, if any.
+    pub fn close(&mut self) {
+        self.finish_dump();
+        if self.dropped_count > 0 {
+            warn!("flight recorder dropped {} packets before the end of its window to stay within its buffer limits", self.dropped_count);
+            self.dropped_count = 0;
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use chrono::{Duration, TimeZone, Utc};
+    use crate::packet::{Linktype, PacketHeader, Precision};
+
+    use super::FlightRecorder;
+    use crate::packet::OwnedPacket;
+
+    fn packet() -> OwnedPacket {
+        OwnedPacket {
+            header: unsafe { std::mem::zeroed::<PacketHeader>() },
+            data: vec![0; 14],
+        }
+    }
+
+    #[test]
+    fn test_window() {
+        let start = Utc.with_ymd_and_hms(2022, 10, 1, 12, 0, 0).unwrap();
+        let mut recorder = FlightRecorder::new(Duration::seconds(10), Duration::seconds(5), "/nonexistent".into(), Precision::Micro);
+
+        for second in 0..30 {
+            recorder.record(start + Duration::seconds(second), Linktype::ETHERNET, packet());
+        }
+
+        // seconds 19 to 29 are kept
+        assert_eq!(recorder.buffer.len(), 11);
+        assert_eq!(recorder.buffer.front().unwrap().0, start + Duration::seconds(19));
+        assert!(recorder.dump.is_none());
+    }
+
+    #[test]
+    fn test_limits() {
+        let start = Utc.with_ymd_and_hms(2022, 10, 1, 12, 0, 0).unwrap();
+        let mut recorder = FlightRecorder::new(Duration::seconds(10), Duration::seconds(5), "/nonexistent".into(), Precision::Micro);
+        recorder.max_packets = 5;
+        recorder.max_bytes = 14 * 3;
+
+        for millisecond in 0..10 {
+            recorder.record(start + Duration::milliseconds(millisecond), Linktype::ETHERNET, packet());
+        }
+
+        // the byte limit is the tighter one
+        assert_eq!(recorder.buffer.len(), 3);
+        assert_eq!(recorder.buffered_bytes, 14 * 3);
+        assert_eq!(recorder.buffer.front().unwrap().0, start + Duration::milliseconds(7));
+        assert_eq!(recorder.dropped_count, 7);
+
+        // packets leaving the window are not drops
+        recorder.max_bytes = usize::MAX;
+        for second in 20..30 {
+            recorder.record(start + Duration::seconds(second), Linktype::ETHERNET, packet());
+        }
+        assert_eq!(recorder.buffer.len(), 5);
+        assert_eq!(recorder.dropped_count, 7 + 5);
+    }
+}
@@ -0,0 +1,264 @@
+use std::io;
+use std::time::{Duration, Instant};
+
+use serde_json::{json, Value};
+use tokio::io::AsyncWriteExt;
+use tokio::net::{TcpStream, UdpSocket};
+use tokio::sync::mpsc;
+use tokio::sync::mpsc::error::TrySendError;
+use tracing::{debug, warn};
+
+use crate::log_limit::WarningLimiter;
+use crate::sink::{DnsMessageEvent, EventSink};
+
+
+const QUEUE_LENGTH: usize = 4096;
+const CONNECT_TIMEOUT_SECS: u64 = 5;
+const MIN_RECONNECT_DELAY_SECS: u64 = 1;
+const MAX_RECONNECT_DELAY_SECS: u64 = 60;
+const WARNING_SUMMARY_SECS: u64 = 60;
+
+// the fields set by the sink itself, which labels may not overwrite; "_id" is reserved by GELF
+const RESERVED_FIELD_NAMES: [&str; 7] = [
+    "client_ip", "id", "query_name", "query_type", "response_code", "server_ip", "transaction_id",
+];
+
+// the chunk size recommended by Graylog for networks with an unknown MTU
+const MAX_CHUNK_SIZE: usize = 1420;
+const CHUNK_HEADER_SIZE: usize = 12;
+const MAX_CHUNK_COUNT: usize = 128;
+
+// syslog severity "informational"
+const LEVEL_INFO: u8 = 6;
+
+
+/// Splits a message into GELF chunks if it does not fit into a single datagram.
+///
+/// Returns `None` if the message would need more chunks than GELF allows.
+fn chunk_message(message_id: u64, message: &[u8]) -> Option<Vec<Vec<u8>>> {
+    if message.len() <= MAX_CHUNK_SIZE {
+        return Some(vec![message.to_vec()]);
+    }
+
+    let data_size = MAX_CHUNK_SIZE - CHUNK_HEADER_SIZE;
+    let chunk_count = (message.len() + data_size - 1) / data_size;
+    if chunk_count > MAX_CHUNK_COUNT {
+        return None;
+    }
+    let chunks = message.chunks(data_size)
+        .enumerate()
+        .map(|(i, data)| {
+            let mut chunk = Vec::with_capacity(CHUNK_HEADER_SIZE + data.len());
+            chunk.extend_from_slice(&[0x1E, 0x0F]);
+            chunk.extend_from_slice(&message_id.to_be_bytes());
+            chunk.push(u8::try_from(i).unwrap());
+            chunk.push(u8::try_from(chunk_count).unwrap());
+            chunk.extend_from_slice(data);
+            chunk
+        })
+        .collect();
+    Some(chunks)
+}
+
+
+/// Returns how long to wait before connecting again after the given number of failed attempts in a
+/// row; the delay doubles each time up to a limit.
+fn reconnect_delay(consecutive_failures: u32) -> Duration {
+    let factor = 1u64.checked_shl(consecutive_failures.saturating_sub(1)).unwrap_or(u64::MAX);
+    let secs = MIN_RECONNECT_DELAY_SECS.saturating_mul(factor).min(MAX_RECONNECT_DELAY_SECS);
+    Duration::from_secs(secs)
+}
+
+
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub enum GelfTransport {
+    Udp,
+    Tcp,
+}
+
+
+enum GelfConnection {
+    Udp(UdpSocket),
+    Tcp(TcpStream),
+}
+impl GelfConnection {
+    async fn open(address: &str, transport: GelfTransport) -> Result<Self, io::Error> {
+        match transport {
+            GelfTransport::Udp => {
+                let remote_address = tokio::net::lookup_host(address).await?
+                    .next()
+                    .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "GELF host not found"))?;
+                let local_address = if remote_address.is_ipv4() { "0.0.0.0:0" } else { "[::]:0" };
+                let socket = UdpSocket::bind(local_address).await?;
+                socket.connect(remote_address).await?;
+                Ok(Self::Udp(socket))
+            },
+            GelfTransport::Tcp => {
+                let stream = TcpStream::connect(address).await?;
+                Ok(Self::Tcp(stream))
+            },
+        }
+    }
+
+    async fn send(&mut self, message_id: u64, message: &[u8]) -> Result<(), io::Error> {
+        match self {
+            Self::Udp(socket) => {
+                let chunks = match chunk_message(message_id, message) {
+                    Some(c) => c,
+                    None => {
+                        warn!("dropping GELF message of {} bytes as it is too long", message.len());
+                        return Ok(());
+                    },
+                };
+                for chunk in chunks {
+                    socket.send(&chunk).await?;
+                }
+            },
+            Self::Tcp(stream) => {
+                // messages are delimited by null bytes, which cannot appear in JSON
+                stream.write_all(message).await?;
+                stream.write_all(&[0]).await?;
+            },
+        }
+        Ok(())
+    }
+}
+
+
+async fn run_sender(address: String, transport: GelfTransport, mut receiver: mpsc::Receiver<Vec<u8>>) {
+    let mut connection = None;
+    let mut message_id: u64 = u64::from(std::process::id()) << 32;
+    let mut consecutive_failures = 0;
+    let mut next_connect_attempt = Instant::now();
+    let mut warning_limiter = WarningLimiter::new(Duration::from_secs(WARNING_SUMMARY_SECS));
+    while let Some(message) = receiver.recv().await {
+        if connection.is_none() {
+            // messages arriving while waiting to reconnect are dropped
+            let now = Instant::now();
+            if now < next_connect_attempt {
+                continue;
+            }
+
+            let connect_timeout = Duration::from_secs(CONNECT_TIMEOUT_SECS);
+            let connect_error = match tokio::time::timeout(connect_timeout, GelfConnection::open(&address, transport)).await {
+                Ok(Ok(c)) => {
+                    connection = Some(c);
+                    consecutive_failures = 0;
+                    None
+                },
+                Ok(Err(e)) => Some(e.to_string()),
+                Err(_) => Some("timed out".to_owned()),
+            };
+            if let Some(e) = connect_error {
+                consecutive_failures += 1;
+                let delay = reconnect_delay(consecutive_failures);
+                next_connect_attempt = Instant::now() + delay;
+                if warning_limiter.admit("failed to connect to GELF endpoint", now) {
+                    warn!("failed to connect to GELF endpoint {}; dropping messages for {:?}: {}", address, delay, e);
+                }
+                continue;
+            }
+        }
+        if let Some(conn) = connection.as_mut() {
+            message_id = message_id.wrapping_add(1);
+            if let Err(e) = conn.send(message_id, &message).await {
+                if warning_limiter.admit("failed to send GELF message", Instant::now()) {
+                    warn!("failed to send GELF message; reconnecting: {}", e);
+                }
+                connection = None;
+            }
+        }
+    }
+    warning_limiter.summarize();
+}
+
+
+/// Sends every query and response to Graylog as a GELF message.
+pub struct GelfSink {
+    host: String,
+    sender: mpsc::Sender<Vec<u8>>,
+    task: tokio::task::JoinHandle<()>,
+}
+impl GelfSink {
+    /// Starts the sink. Must be called from within the Tokio runtime.
+    ///
+    /// `host` is the name under which the messages appear in Graylog.
+    pub fn new(address: String, transport: GelfTransport, host: String) -> Self {
+        let (sender, receiver) = mpsc::channel(QUEUE_LENGTH);
+        let task = tokio::spawn(run_sender(address, transport, receiver));
+        Self {
+            host,
+            sender,
+            task,
+        }
+    }
+}
+impl EventSink for GelfSink {
+    fn emit(&mut self, event: &DnsMessageEvent<'_>) {
+        let short_message = match event.response_code {
+            None => format!("{} queried {} {}", event.client().ip(), event.record_type, event.name),
+            Some(rc) => format!("{} answered {} {} with {}", event.server().ip(), event.record_type, event.name, rc.to_str()),
+        };
+        // additional fields start with an underscore
+        let mut message = json!({
+            "version": "1.1",
+            "host": self.host,
+            "short_message": short_message,
+            "timestamp": event.timestamp.timestamp_micros() as f64 / 1_000_000.0,
+            "level": LEVEL_INFO,
+            "_client_ip": event.client().ip().to_string(),
+            "_server_ip": event.server().ip().to_string(),
+            "_transaction_id": event.transaction_id,
+            "_query_name": event.name,
+            "_query_type": event.record_type.to_string(),
+        });
+        if let Some(rc) = event.response_code {
+            // GELF does not allow null values
+            message["_response_code"] = Value::from(rc.to_str());
+        }
+        for (key, value) in event.labels {
+            if !RESERVED_FIELD_NAMES.contains(&key.as_str()) {
+                message[format!("_{}", key)] = Value::from(value.as_str());
+            }
+        }
+        match self.sender.try_send(message.to_string().into_bytes()) {
+            Ok(()) => {},
+            Err(TrySendError::Full(_)) => debug!("GELF queue full; dropping event"),
+            Err(TrySendError::Closed(_)) => {},
+        }
+    }
+
+    fn close(self: Box<Self>) -> Option<tokio::task::JoinHandle<()>> {
+        Some(self.task)
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::{chunk_message, MAX_CHUNK_SIZE, reconnect_delay};
+
+    #[test]
+    fn test_chunk_message() {
+        let short = vec![b'x'; MAX_CHUNK_SIZE];
+        assert_eq!(chunk_message(1, &short), Some(vec![short.clone()]));
+
+        let long = vec![b'x'; 3000];
+        let chunks = chunk_message(0x0102030405060708, &long).unwrap();
+        assert_eq!(chunks.len(), 3);
+        assert_eq!(&chunks[1][..12], &[0x1E, 0x0F, 0x01, 0x02, 0x03, 0x04, 0x05, 0x06, 0x07, 0x08, 1, 3]);
+        assert_eq!(chunks[0].len(), MAX_CHUNK_SIZE);
+        assert_eq!(chunks.iter().map(|c| c.len() - 12).sum::<usize>(), 3000);
+
+        assert_eq!(chunk_message(1, &vec![b'x'; 200_000]), None);
+    }
+    #[test]
+    fn test_reconnect_delay() {
+        assert_eq!(reconnect_delay(1), Duration::from_secs(1));
+        assert_eq!(reconnect_delay(3), Duration::from_secs(4));
+        assert_eq!(reconnect_delay(7), Duration::from_secs(60));
+        assert_eq!(reconnect_delay(100), Duration::from_secs(60));
+    }
+}
@@ -0,0 +1,411 @@
+use std::fmt;
+use std::io;
+#[cfg(feature = "tls")] use std::sync::Arc;
+
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+#[cfg(feature = "kubernetes")] use tokio::io::{AsyncBufReadExt, BufReader};
+use tokio::net::TcpStream;
+#[cfg(feature = "tls")] use tokio_rustls::TlsConnector;
+#[cfg(feature = "tls")] use tokio_rustls::rustls::{ClientConfig, RootCertStore};
+#[cfg(feature = "tls")] use tokio_rustls::rustls::pki_types::{CertificateDer, ServerName};
+#[cfg(feature = "tls")] use tokio_rustls::rustls::pki_types::pem::PemObject;
+use tracing::warn;
+
+
+const MAX_ATTEMPTS: u32 = 4;
+const INITIAL_RETRY_DELAY_MS: u64 = 500;
+const REQUEST_TIMEOUT_SECS: u64 = 10;
+
+
+#[derive(Debug)]
+pub enum HttpError {
+    UnsupportedScheme(String),
+    InvalidUrl(String),
+    Io(io::Error),
+    Timeout,
+    UnexpectedResponse(String),
+    #[cfg(feature = "tls")] Tls(tokio_rustls::rustls::Error),
+    #[cfg(feature = "tls")] InvalidCaCertificates(String),
+}
+impl fmt::Display for HttpError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::UnsupportedScheme(url) if cfg!(feature = "tls")
+                => write!(f, "URL {:?} does not start with http:// or https://", url),
+            Self::UnsupportedScheme(url)
+                => write!(f, "URL {:?} does not start with http:// (https:// requires the tls feature)", url),
+            Self::InvalidUrl(url)
+                => write!(f, "invalid URL {:?}", url),
+            Self::Io(e)
+                => write!(f, "I/O error: {}", e),
+            Self::Timeout
+                => write!(f, "timed out"),
+            Self::UnexpectedResponse(status_line)
+                => write!(f, "unexpected response {:?}", status_line),
+            #[cfg(feature = "tls")]
+            Self::Tls(e)
+                => write!(f, "TLS error: {}", e),
+            #[cfg(feature = "tls")]
+            Self::InvalidCaCertificates(reason)
+                => write!(f, "invalid CA certificates: {}", reason),
+        }
+    }
+}
+impl std::error::Error for HttpError {
+}
+impl From<io::Error> for HttpError {
+    fn from(e: io::Error) -> Self { Self::Io(e) }
+}
+#[cfg(feature = "tls")]
+impl From<tokio_rustls::rustls::Error> for HttpError {
+    fn from(e: tokio_rustls::rustls::Error) -> Self { Self::Tls(e) }
+}
+
+
+/// A connection to an HTTP server, with or without TLS.
+trait Connection: AsyncRead + AsyncWrite + Unpin + Send {}
+impl<T: AsyncRead + AsyncWrite + Unpin + Send> Connection for T {}
+
+
+#[derive(Clone, Debug, Eq, Hash, PartialEq)]
+pub struct HttpUrl {
+    pub tls: bool,
+    pub host: String,
+    pub port: u16,
+    pub path: String,
+}
+impl HttpUrl {
+    pub fn parse(url: &str) -> Result<Self, HttpError> {
+        let (rest, tls) = match (url.strip_prefix("http://"), url.strip_prefix("https://")) {
+            (Some(rest), _) => (rest, false),
+            (None, Some(rest)) if cfg!(feature = "tls") => (rest, true),
+            _ => return Err(HttpError::UnsupportedScheme(url.to_owned())),
+        };
+        let (authority, path) = match rest.find('/') {
+            Some(slash_index) => (&rest[..slash_index], &rest[slash_index..]),
+            None => (rest, "/"),
+        };
+
+        // IPv6 addresses are enclosed in brackets as they contain colons themselves
+        let port_colon_index = if authority.starts_with('[') {
+            let closing_index = authority.find(']')
+                .ok_or_else(|| HttpError::InvalidUrl(url.to_owned()))?;
+            authority[closing_index..].find(':').map(|i| closing_index + i)
+        } else {
+            authority.rfind(':')
+        };
+        let (host, port) = match port_colon_index {
+            Some(i) => {
+                let port = authority[i+1..].parse()
+                    .map_err(|_| HttpError::InvalidUrl(url.to_owned()))?;
+                (&authority[..i], port)
+            },
+            None => (authority, if tls { 443 } else { 80 }),
+        };
+        if host.len() == 0 {
+            return Err(HttpError::InvalidUrl(url.to_owned()));
+        }
+
+        Ok(Self {
+            tls,
+            host: host.to_owned(),
+            port,
+            path: path.to_owned(),
+        })
+    }
+
+    fn connect_host(&self) -> &str {
+        self.host.trim_start_matches('[').trim_end_matches(']')
+    }
+
+    /// The value of the `Host` header, which only leaves out the port if it is the default one.
+    fn host_header(&self) -> String {
+        let default_port = if self.tls { 443 } else { 80 };
+        if self.port == default_port {
+            self.host.clone()
+        } else {
+            format!("{}:{}", self.host, self.port)
+        }
+    }
+}
+
+
+/// Encodes the bytes in base64 with padding, as specified in RFC4648.
+fn base64(bytes: &[u8]) -> String {
+    const ALPHABET: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+    let mut encoded = String::with_capacity(bytes.len().div_ceil(3) * 4);
+    for chunk in bytes.chunks(3) {
+        let group = (u32::from(chunk[0]) << 16)
+            | (u32::from(*chunk.get(1).unwrap_or(&0)) << 8)
+            | u32::from(*chunk.get(2).unwrap_or(&0));
+        for i in 0..4 {
+            if i <= chunk.len() {
+                encoded.push(char::from(ALPHABET[((group >> (18 - 6 * i)) & 0b11_1111) as usize]));
+            } else {
+                encoded.push('=');
+            }
+        }
+    }
+    encoded
+}
+
+
+/// Returns the value of an `Authorization` header for HTTP basic authentication (RFC7617).
+pub fn basic_authorization(username: &str, password: &str) -> String {
+    format!("Basic {}", base64(format!("{}:{}", username, password).as_bytes()))
+}
+
+
+/// Wraps the connection to the server in TLS, verifying its certificate against the given
+/// PEM-encoded CA certificates or, if there are none, the web PKI roots.
+#[cfg(feature = "tls")]
+async fn tls_connect(stream: TcpStream, url: &HttpUrl, ca_certificates: Option<&[u8]>) -> Result<Box<dyn Connection>, HttpError> {
+    let roots = match ca_certificates {
+        Some(pem) => {
+            let mut roots = RootCertStore::empty();
+            for certificate in CertificateDer::pem_slice_iter(pem) {
+                let certificate = certificate
+                    .map_err(|e| HttpError::InvalidCaCertificates(e.to_string()))?;
+                roots.add(certificate)?;
+            }
+            if roots.is_empty() {
+                return Err(HttpError::InvalidCaCertificates("no certificates found".to_owned()));
+            }
+            roots
+        },
+        None => RootCertStore { roots: webpki_roots::TLS_SERVER_ROOTS.to_vec() },
+    };
+    let config = ClientConfig::builder_with_provider(Arc::new(tokio_rustls::rustls::crypto::ring::default_provider()))
+        .with_safe_default_protocol_versions()?
+        .with_root_certificates(roots)
+        .with_no_client_auth();
+    let server_name = ServerName::try_from(url.connect_host().to_owned())
+        .map_err(|_| HttpError::InvalidUrl(url.host.clone()))?;
+    let stream = TlsConnector::from(Arc::new(config))
+        .connect(server_name, stream).await?;
+    Ok(Box::new(stream))
+}
+
+#[cfg(not(feature = "tls"))]
+async fn tls_connect(_stream: TcpStream, url: &HttpUrl, _ca_certificates: Option<&[u8]>) -> Result<Box<dyn Connection>, HttpError> {
+    Err(HttpError::UnsupportedScheme(format!("https://{}:{}{}", url.host, url.port, url.path)))
+}
+
+
+/// Connects to the server of the URL, using TLS for https:// URLs.
+async fn connect(url: &HttpUrl, ca_certificates: Option<&[u8]>) -> Result<Box<dyn Connection>, HttpError> {
+    let stream = TcpStream::connect((url.connect_host(), url.port)).await?;
+    if url.tls {
+        return tls_connect(stream, url, ca_certificates).await;
+    }
+    Ok(Box::new(stream))
+}
+
+
+/// Sends a POST request and checks that the server responds with a success status.
+async fn post_once(url: &HttpUrl, headers: &[(&str, &str)], body: &[u8]) -> Result<(), HttpError> {
+    let mut stream = connect(url, None).await?;
+    let mut request = format!("POST {} HTTP/1.1\r\nHost: {}\r\n", url.path, url.host_header());
+    for (name, value) in headers {
+        request.push_str(&format!("{}: {}\r\n", name, value));
+    }
+    request.push_str(&format!("Content-Length: {}\r\nConnection: close\r\n\r\n", body.len()));
+    stream.write_all(request.as_bytes()).await?;
+    stream.write_all(body).await?;
+
+    // the status line is all we care about
+    let mut response = Vec::new();
+    let mut buf = [0u8; 512];
+    while !response.contains(&b'\n') {
+        let read_count = stream.read(&mut buf).await?;
+        if read_count == 0 {
+            break;
+        }
+        response.extend_from_slice(&buf[..read_count]);
+    }
+    let response_text = String::from_utf8_lossy(&response);
+    check_status(response_text.lines().next().unwrap_or(""))
+}
+
+
+/// Checks that the status line of a response has a success status.
+fn check_status(status_line: &str) -> Result<(), HttpError> {
+    let status_code = status_line.split(' ').nth(1).unwrap_or("");
+    if status_code.starts_with('2') && status_code.len() == 3 {
+        Ok(())
+    } else {
+        Err(HttpError::UnexpectedResponse(status_line.to_owned()))
+    }
+}
+
+
+/// Formats a GET request. HTTP/1.0 is used so that the body is not chunked and ends with the
+/// connection.
+fn get_request(host: &str, path: &str, headers: &[(&str, &str)]) -> String {
+    let mut request = format!("GET {} HTTP/1.0\r\nHost: {}\r\nAccept: application/json\r\n", path, host);
+    for (name, value) in headers {
+        request.push_str(&format!("{}: {}\r\n", name, value));
+    }
+    request.push_str("\r\n");
+    request
+}
+
+
+/// Sends a GET request over an established connection and returns the body of the response.
+pub async fn get_over<S: tokio::io::AsyncRead + tokio::io::AsyncWrite + Unpin>(mut stream: S, host: &str, path: &str, headers: &[(&str, &str)]) -> Result<Vec<u8>, HttpError> {
+    stream.write_all(get_request(host, path, headers).as_bytes()).await?;
+
+    let mut response = Vec::new();
+    stream.read_to_end(&mut response).await?;
+    let header_end = response.windows(4).position(|w| w == b"\r\n\r\n")
+        .ok_or_else(|| HttpError::UnexpectedResponse(String::from_utf8_lossy(&response).into_owned()))?;
+    let header_text = String::from_utf8_lossy(&response[..header_end]);
+    check_status(header_text.lines().next().unwrap_or(""))?;
+    Ok(response[header_end + 4..].to_vec())
+}
+
+
+async fn get_once(url: &HttpUrl, headers: &[(&str, &str)], ca_certificates: Option<&[u8]>) -> Result<Vec<u8>, HttpError> {
+    let stream = connect(url, ca_certificates).await?;
+    get_over(stream, &url.host_header(), &url.path, headers).await
+}
+
+
+/// Sends a GET request, giving up after the request timeout.
+///
+/// For https:// URLs, the server's certificate is verified against the given PEM-encoded CA
+/// certificates or, if there are none, the web PKI roots.
+pub async fn get(url: &HttpUrl, headers: &[(&str, &str)], ca_certificates: Option<&[u8]>) -> Result<Vec<u8>, HttpError> {
+    let timeout = std::time::Duration::from_secs(REQUEST_TIMEOUT_SECS);
+    match tokio::time::timeout(timeout, get_once(url, headers, ca_certificates)).await {
+        Ok(r) => r,
+        Err(_) => Err(HttpError::Timeout),
+    }
+}
+
+
+/// The body of a response which is read line by line as it arrives, such as a stream of events.
+#[cfg(feature = "kubernetes")]
+pub struct ResponseLines {
+    reader: BufReader<Box<dyn Connection>>,
+}
+#[cfg(feature = "kubernetes")]
+impl ResponseLines {
+    /// Returns the next line without its line ending, or `None` once the server has closed the
+    /// connection.
+    pub async fn next_line(&mut self) -> Result<Option<Vec<u8>>, HttpError> {
+        let mut line = Vec::new();
+        if self.reader.read_until(b'\n', &mut line).await? == 0 {
+            return Ok(None);
+        }
+        while line.last() == Some(&b'\n') || line.last() == Some(&b'\r') {
+            line.pop();
+        }
+        Ok(Some(line))
+    }
+}
+
+
+#[cfg(feature = "kubernetes")]
+async fn get_lines_once(url: &HttpUrl, headers: &[(&str, &str)], ca_certificates: Option<&[u8]>) -> Result<ResponseLines, HttpError> {
+    let mut stream = connect(url, ca_certificates).await?;
+    stream.write_all(get_request(&url.host_header(), &url.path, headers).as_bytes()).await?;
+
+    let mut reader = BufReader::new(stream);
+    let mut status_line = Vec::new();
+    reader.read_until(b'\n', &mut status_line).await?;
+    check_status(String::from_utf8_lossy(&status_line).trim_end())?;
+    loop {
+        let mut header_line = Vec::new();
+        if reader.read_until(b'\n', &mut header_line).await? == 0 {
+            return Err(HttpError::UnexpectedResponse(String::from_utf8_lossy(&status_line).trim_end().to_owned()));
+        }
+        if header_line == b"\r\n" || header_line == b"\n" {
+            break;
+        }
+    }
+    Ok(ResponseLines { reader })
+}
+
+
+/// Sends a GET request and returns the body of the response for reading as it arrives; only
+/// connecting and receiving the headers are subject to the request timeout.
+#[cfg(feature = "kubernetes")]
+pub async fn get_lines(url: &HttpUrl, headers: &[(&str, &str)], ca_certificates: Option<&[u8]>) -> Result<ResponseLines, HttpError> {
+    let timeout = std::time::Duration::from_secs(REQUEST_TIMEOUT_SECS);
+    match tokio::time::timeout(timeout, get_lines_once(url, headers, ca_certificates)).await {
+        Ok(r) => r,
+        Err(_) => Err(HttpError::Timeout),
+    }
+}
+
+
+/// Sends a POST request, retrying with exponential backoff if it fails.
+///
+/// This is a minimal HTTP/1.1 client; HTTPS is only supported when built with the tls feature.
+pub async fn post(url: &HttpUrl, headers: &[(&str, &str)], body: &[u8]) -> Result<(), HttpError> {
+    let mut delay = std::time::Duration::from_millis(INITIAL_RETRY_DELAY_MS);
+    let mut attempt = 1;
+    loop {
+        let timeout = std::time::Duration::from_secs(REQUEST_TIMEOUT_SECS);
+        let result = match tokio::time::timeout(timeout, post_once(url, headers, body)).await {
+            Ok(r) => r,
+            Err(_) => Err(HttpError::Timeout),
+        };
+        match result {
+            Ok(()) => return Ok(()),
+            Err(e) if attempt < MAX_ATTEMPTS => {
+                warn!("POST to {}:{}{} failed (attempt {} of {}): {}", url.host, url.port, url.path, attempt, MAX_ATTEMPTS, e);
+                tokio::time::sleep(delay).await;
+                delay *= 2;
+                attempt += 1;
+            },
+            Err(e) => return Err(e),
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::{base64, basic_authorization, HttpUrl};
+
+    #[test]
+    fn test_parse_url() {
+        let url = HttpUrl::parse("http://alerts.example:9093/api/v2/alerts").unwrap();
+        assert_eq!(url.host, "alerts.example");
+        assert_eq!(url.port, 9093);
+        assert_eq!(url.path, "/api/v2/alerts");
+        assert_eq!(url.host_header(), "alerts.example:9093");
+
+        let url = HttpUrl::parse("http://[2001:db8::1]").unwrap();
+        assert_eq!(url.host, "[2001:db8::1]");
+        assert_eq!(url.connect_host(), "2001:db8::1");
+        assert_eq!(url.port, 80);
+        assert_eq!(url.path, "/");
+        assert_eq!(url.host_header(), "[2001:db8::1]");
+
+        let url = HttpUrl::parse("https://prometheus.example/api/prom/push");
+        if cfg!(feature = "tls") {
+            let url = url.unwrap();
+            assert!(url.tls);
+            assert_eq!(url.port, 443);
+        } else {
+            assert!(url.is_err());
+        }
+        assert!(HttpUrl::parse("ftp://hooks.example/").is_err());
+        assert!(HttpUrl::parse("http://:80/").is_err());
+        assert!(HttpUrl::parse("http://hooks.example:http/").is_err());
+    }
+
+    #[test]
+    fn test_basic_authorization() {
+        assert_eq!(base64(b""), "");
+        assert_eq!(base64(b"f"), "Zg==");
+        assert_eq!(base64(b"fo"), "Zm8=");
+        assert_eq!(base64(b"foo"), "Zm9v");
+        assert_eq!(base64(b"foob"), "Zm9vYg==");
+        assert_eq!(base64(&[0xfb, 0xff]), "+/8=");
+        assert_eq!(basic_authorization("Aladdin", "open sesame"), "Basic QWxhZGRpbjpvcGVuIHNlc2FtZQ==");
+    }
+}
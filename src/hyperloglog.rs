@@ -0,0 +1,97 @@
+use std::collections::hash_map::DefaultHasher;
+use std::fmt;
+use std::hash::{Hash, Hasher};
+
+
+// 2**12 registers of one byte each; the standard error is about 1.04/sqrt(2**12) = 1.6%
+const PRECISION: u32 = 12;
+const REGISTER_COUNT: usize = 1 << PRECISION;
+
+
+/// Estimates the number of distinct items in constant memory.
+///
+/// See Flajolet et al., "HyperLogLog: the analysis of a near-optimal cardinality estimation
+/// algorithm" (2007).
+#[derive(Clone, Eq, PartialEq)]
+pub struct HyperLogLog {
+    registers: Vec<u8>,
+}
+impl HyperLogLog {
+    pub fn new() -> Self {
+        Self {
+            registers: vec![0; REGISTER_COUNT],
+        }
+    }
+
+    pub fn add<T: Hash + ?Sized>(&mut self, item: &T) {
+        // the sketch is not persisted, so the hash does not have to be stable across builds
+        let mut hasher = DefaultHasher::new();
+        item.hash(&mut hasher);
+        let hash = hasher.finish();
+
+        // the top bits choose the register, the position of the first 1 bit in the rest is the rank
+        let index = usize::try_from(hash >> (64 - PRECISION)).unwrap();
+        let rest = (hash << PRECISION) | (1 << (PRECISION - 1));
+        let rank = u8::try_from(rest.leading_zeros() + 1).unwrap();
+        if self.registers[index] < rank {
+            self.registers[index] = rank;
+        }
+    }
+
+    pub fn estimate(&self) -> u64 {
+        let m = REGISTER_COUNT as f64;
+        let alpha = 0.7213 / (1.0 + 1.079 / m);
+        let harmonic_sum: f64 = self.registers.iter()
+            .map(|r| 2.0f64.powi(-i32::from(*r)))
+            .sum();
+        let raw_estimate = alpha * m * m / harmonic_sum;
+
+        // small cardinalities are estimated more precisely by counting the empty registers
+        let zero_registers = self.registers.iter().filter(|r| **r == 0).count();
+        let estimate = if raw_estimate <= 2.5 * m && zero_registers > 0 {
+            m * (m / zero_registers as f64).ln()
+        } else {
+            raw_estimate
+        };
+        estimate.round() as u64
+    }
+}
+impl Default for HyperLogLog {
+    fn default() -> Self { Self::new() }
+}
+impl fmt::Debug for HyperLogLog {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("HyperLogLog")
+            .field("estimate", &self.estimate())
+            .finish()
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::HyperLogLog;
+
+    #[test]
+    fn test_estimate() {
+        let mut sketch = HyperLogLog::new();
+        assert_eq!(sketch.estimate(), 0);
+
+        for i in 0..100 {
+            sketch.add(&format!("host{}.example.com", i));
+        }
+        assert!((98..=102).contains(&sketch.estimate()), "estimate {}", sketch.estimate());
+
+        // repetitions do not count
+        for i in 0..100 {
+            sketch.add(&format!("host{}.example.com", i));
+        }
+        assert!((98..=102).contains(&sketch.estimate()), "estimate {}", sketch.estimate());
+
+        for i in 100..50_000 {
+            sketch.add(&format!("host{}.example.com", i));
+        }
+        let estimate = sketch.estimate();
+        assert!(estimate > 47_500 && estimate < 52_500, "estimate {}", estimate);
+    }
+}
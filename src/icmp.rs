@@ -0,0 +1,216 @@
+use std::net::Ipv6Addr;
+
+use macaddr::MacAddr6;
+
+use crate::bytes::TryFromBytes;
+use crate::ip::internet_checksum;
+use crate::packet::PacketDissection;
+
+
+// managed by IANA: https://www.iana.org/assignments/icmp-parameters/icmp-parameters.xhtml
+pub const ICMPV4_TYPE_DESTINATION_UNREACHABLE: u8 = 3;
+// managed by IANA: https://www.iana.org/assignments/icmpv6-parameters/icmpv6-parameters.xhtml
+pub const ICMPV6_TYPE_DESTINATION_UNREACHABLE: u8 = 1;
+pub const ICMPV6_TYPE_PACKET_TOO_BIG: u8 = 2;
+pub const ICMPV6_TYPE_NEIGHBOR_SOLICITATION: u8 = 135;
+pub const ICMPV6_TYPE_NEIGHBOR_ADVERTISEMENT: u8 = 136;
+
+// managed by IANA: https://www.iana.org/assignments/icmpv6-parameters/icmpv6-parameters.xhtml#icmpv6-parameters-5
+const NDP_OPTION_SOURCE_LINK_LAYER_ADDRESS: u8 = 1;
+const NDP_OPTION_TARGET_LINK_LAYER_ADDRESS: u8 = 2;
+
+
+#[derive(Clone, Copy, Debug, Default, Eq, Hash, Ord, PartialEq, PartialOrd)]
+// as defined in RFC792 (ICMPv4) and RFC4443 section 2.1 (ICMPv6)
+pub struct IcmpHeader {
+    pub icmp_type: u8,
+    pub code: u8,
+    pub checksum: u16,
+    pub rest_of_header: u32,
+}
+impl IcmpHeader {
+    /// Extracts an ICMP header from the given bytes.
+    ///
+    /// For ICMPv4, `pseudo_header` must be empty; for ICMPv6, it must be the IPv6 pseudo-header.
+    pub fn try_take<'b, 'h>(bytes: &'b [u8], pseudo_header: &'h [u8]) -> PacketDissection<'b, Self> {
+        if bytes.len() < 8 {
+            return PacketDissection::TooShort;
+        }
+
+        let full_checksum = internet_checksum(
+            pseudo_header.iter().map(|b| *b)
+                .chain(bytes.iter().map(|b| *b))
+        );
+        if full_checksum != 0xFFFF {
+            return PacketDissection::IncorrectChecksum;
+        }
+
+        let icmp_type = bytes[0];
+        let code = bytes[1];
+        let checksum = u16::from_be_bytes(bytes[2..4].try_into().unwrap());
+        let rest_of_header = u32::from_be_bytes(bytes[4..8].try_into().unwrap());
+
+        let header = Self {
+            icmp_type,
+            code,
+            checksum,
+            rest_of_header,
+        };
+        PacketDissection::Success { header, rest: &bytes[8..] }
+    }
+}
+
+
+#[derive(Clone, Copy, Debug, Eq, Hash, Ord, PartialEq, PartialOrd)]
+pub enum IcmpFailureReason {
+    NetworkUnreachable,
+    HostUnreachable,
+    PortUnreachable,
+    AdministrativelyProhibited,
+    PacketTooBig,
+    OtherUnreachable,
+}
+impl IcmpFailureReason {
+    pub fn from_icmpv4(header: &IcmpHeader) -> Option<Self> {
+        if header.icmp_type != ICMPV4_TYPE_DESTINATION_UNREACHABLE {
+            return None;
+        }
+        let reason = match header.code {
+            0 => Self::NetworkUnreachable,
+            1 => Self::HostUnreachable,
+            3 => Self::PortUnreachable,
+            4 => Self::PacketTooBig, // "fragmentation needed and DF set"
+            9|10|13 => Self::AdministrativelyProhibited,
+            _ => Self::OtherUnreachable,
+        };
+        Some(reason)
+    }
+
+    pub fn from_icmpv6(header: &IcmpHeader) -> Option<Self> {
+        let reason = match (header.icmp_type, header.code) {
+            (ICMPV6_TYPE_DESTINATION_UNREACHABLE, 0) => Self::NetworkUnreachable,
+            (ICMPV6_TYPE_DESTINATION_UNREACHABLE, 1) => Self::AdministrativelyProhibited,
+            (ICMPV6_TYPE_DESTINATION_UNREACHABLE, 3) => Self::HostUnreachable,
+            (ICMPV6_TYPE_DESTINATION_UNREACHABLE, 4) => Self::PortUnreachable,
+            (ICMPV6_TYPE_DESTINATION_UNREACHABLE, _) => Self::OtherUnreachable,
+            (ICMPV6_TYPE_PACKET_TOO_BIG, _) => Self::PacketTooBig,
+            _ => return None,
+        };
+        Some(reason)
+    }
+}
+
+
+#[derive(Clone, Copy, Debug, Eq, Hash, Ord, PartialEq, PartialOrd)]
+// as defined in RFC4861 sections 4.3 (solicitation), 4.4 (advertisement) and 4.6.1 (options)
+pub struct NeighborDiscovery {
+    pub target_address: Ipv6Addr,
+    pub source_link_layer_address: Option<MacAddr6>,
+    pub target_link_layer_address: Option<MacAddr6>,
+}
+impl NeighborDiscovery {
+    /// Extracts a neighbor solicitation or advertisement from the bytes following the ICMPv6 header.
+    pub fn try_take<'b>(header: &IcmpHeader, bytes: &'b [u8]) -> PacketDissection<'b, Self> {
+        if header.icmp_type != ICMPV6_TYPE_NEIGHBOR_SOLICITATION && header.icmp_type != ICMPV6_TYPE_NEIGHBOR_ADVERTISEMENT {
+            return PacketDissection::WrongType;
+        }
+        if bytes.len() < 16 {
+            return PacketDissection::TooShort;
+        }
+
+        let target_address = Ipv6Addr::try_from_bytes(&bytes[0..16]).unwrap();
+        let mut source_link_layer_address = None;
+        let mut target_link_layer_address = None;
+
+        let mut options = &bytes[16..];
+        while options.len() >= 2 {
+            // the length is given in units of 8 bytes and includes type and length
+            let option_type = options[0];
+            let option_length = usize::from(options[1]) * 8;
+            if option_length == 0 || option_length > options.len() {
+                return PacketDissection::TooShort;
+            }
+
+            // we only understand Ethernet link-layer addresses
+            if option_length == 8 {
+                let address = MacAddr6::try_from_bytes(&options[2..8]);
+                match option_type {
+                    NDP_OPTION_SOURCE_LINK_LAYER_ADDRESS => source_link_layer_address = address,
+                    NDP_OPTION_TARGET_LINK_LAYER_ADDRESS => target_link_layer_address = address,
+                    _ => {},
+                }
+            }
+
+            options = &options[option_length..];
+        }
+
+        let header = Self {
+            target_address,
+            source_link_layer_address,
+            target_link_layer_address,
+        };
+        PacketDissection::Success { header, rest: options }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use std::net::Ipv6Addr;
+
+    use macaddr::MacAddr6;
+
+    use super::{
+        IcmpFailureReason, IcmpHeader, ICMPV6_TYPE_NEIGHBOR_ADVERTISEMENT, NeighborDiscovery,
+    };
+    use crate::packet::PacketDissection;
+
+    #[test]
+    fn test_port_unreachable() {
+        // ICMPv4 port unreachable quoting an IPv4/UDP DNS query from 192.0.2.1:54321 to 192.0.2.53:53
+        let bs: [u8; 36] = [
+            0x03, 0x03, 0x28, 0x81, 0x00, 0x00, 0x00, 0x00,
+            0x45, 0x00, 0x00, 0x29, 0x00, 0x00, 0x00, 0x00,
+            0x40, 0x11, 0xF6, 0x8D, 0xC0, 0x00, 0x02, 0x01,
+            0xC0, 0x00, 0x02, 0x35, 0xD4, 0x31, 0x00, 0x35,
+            0x00, 0x15, 0x00, 0x00,
+        ];
+        let (header, rest) = match IcmpHeader::try_take(&bs, &[]) {
+            PacketDissection::Success { header, rest } => (header, rest),
+            other => panic!("unexpected dissection {:?}", other),
+        };
+        assert_eq!(IcmpFailureReason::from_icmpv4(&header), Some(IcmpFailureReason::PortUnreachable));
+        assert_eq!(rest.len(), 28);
+    }
+
+    #[test]
+    fn test_neighbor_advertisement() {
+        // advertisement for 2001:db8::1 with a target link-layer address option
+        let header = IcmpHeader {
+            icmp_type: ICMPV6_TYPE_NEIGHBOR_ADVERTISEMENT,
+            code: 0,
+            checksum: 0,
+            rest_of_header: 0x6000_0000,
+        };
+        let bs: [u8; 24] = [
+            0x20, 0x01, 0x0D, 0xB8, 0x00, 0x00, 0x00, 0x00,
+            0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x01,
+            0x02, 0x01, 0x02, 0x00, 0x5E, 0x00, 0x53, 0x01,
+        ];
+        let discovery = match NeighborDiscovery::try_take(&header, &bs) {
+            PacketDissection::Success { header, .. } => header,
+            other => panic!("unexpected dissection {:?}", other),
+        };
+        assert_eq!(discovery.target_address, "2001:db8::1".parse::<Ipv6Addr>().unwrap());
+        assert_eq!(discovery.source_link_layer_address, None);
+        assert_eq!(discovery.target_link_layer_address, Some(MacAddr6::new(0x02, 0x00, 0x5E, 0x00, 0x53, 0x01)));
+
+        // an option claiming to be longer than the message
+        let mut truncated = bs;
+        truncated[17] = 2;
+        match NeighborDiscovery::try_take(&header, &truncated) {
+            PacketDissection::TooShort => {},
+            other => panic!("unexpected dissection {:?}", other),
+        }
+    }
+}
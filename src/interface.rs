@@ -0,0 +1,257 @@
+use std::fmt;
+use std::io;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, UdpSocket};
+#[cfg(target_os = "linux")] use std::path::Path;
+use std::str::FromStr;
+
+
+// connecting a UDP socket only consults the routing table; nothing is sent to these addresses
+const ROUTE_PROBE_ADDRESSES: [IpAddr; 2] = [
+    IpAddr::V4(Ipv4Addr::new(192, 0, 2, 1)),
+    IpAddr::V6(Ipv6Addr::new(0x2001, 0xdb8, 0, 0, 0, 0, 0, 1)),
+];
+
+
+/// A network interface that can be captured on, along with its addresses if they are known.
+#[derive(Clone, Debug)]
+pub struct Device {
+    pub name: String,
+    pub desc: Option<String>,
+    pub addresses: Vec<IpAddr>,
+}
+impl From<&str> for Device {
+    fn from(name: &str) -> Self {
+        Self {
+            name: name.to_owned(),
+            desc: None,
+            addresses: Vec::new(),
+        }
+    }
+}
+#[cfg(feature = "libpcap")]
+impl From<pcap::Device> for Device {
+    fn from(d: pcap::Device) -> Self {
+        Self {
+            addresses: d.addresses.iter().map(|a| a.addr).collect(),
+            name: d.name,
+            desc: d.desc,
+        }
+    }
+}
+
+
+#[derive(Debug)]
+pub enum InterfaceError {
+    Io(io::Error),
+    NoDefaultRoute,
+    NoInterfaceWithAddress(IpAddr),
+    NoInterfaceNamed(String),
+}
+impl fmt::Display for InterfaceError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Io(e)
+                => write!(f, "failed to look up interface: {}", e),
+            Self::NoDefaultRoute
+                => write!(f, "no default route"),
+            Self::NoInterfaceWithAddress(address)
+                => write!(f, "no interface has the address {}", address),
+            Self::NoInterfaceNamed(name)
+                => write!(f, "no interface is named {:?}", name),
+        }
+    }
+}
+impl std::error::Error for InterfaceError {
+}
+impl From<io::Error> for InterfaceError {
+    fn from(e: io::Error) -> Self { Self::Io(e) }
+}
+
+
+/// How to choose the interface to capture on without knowing its position in the device list.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum InterfaceSelector {
+    DefaultRoute, // given as "auto"
+    Address(IpAddr),
+    Name(String),
+}
+impl FromStr for InterfaceSelector {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if s == "auto" {
+            Ok(Self::DefaultRoute)
+        } else if let Ok(address) = s.parse() {
+            Ok(Self::Address(address))
+        } else if s.len() > 0 {
+            Ok(Self::Name(s.to_owned()))
+        } else {
+            Err("interface must be \"auto\", an address or a name".to_owned())
+        }
+    }
+}
+
+
+/// Returns the local address the default route sends packets from, preferring IPv4.
+fn default_route_address() -> Result<IpAddr, InterfaceError> {
+    for probe_address in ROUTE_PROBE_ADDRESSES {
+        let bind_address: IpAddr = match probe_address {
+            IpAddr::V4(_) => Ipv4Addr::UNSPECIFIED.into(),
+            IpAddr::V6(_) => Ipv6Addr::UNSPECIFIED.into(),
+        };
+        let socket = match UdpSocket::bind((bind_address, 0)) {
+            Ok(s) => s,
+            Err(_) => continue, // address family not available
+        };
+        if socket.connect((probe_address, 53)).is_ok() {
+            return Ok(socket.local_addr()?.ip());
+        }
+    }
+    Err(InterfaceError::NoDefaultRoute)
+}
+
+
+/// Returns the name of the interface that has the given address, asking the operating system.
+#[cfg(unix)]
+fn interface_with_address(address: IpAddr) -> Result<Option<String>, io::Error> {
+    let mut interface_addresses: *mut libc::ifaddrs = std::ptr::null_mut();
+    if unsafe { libc::getifaddrs(&mut interface_addresses) } < 0 {
+        return Err(io::Error::last_os_error());
+    }
+
+    let mut found = None;
+    let mut entry = interface_addresses;
+    unsafe {
+        while !entry.is_null() {
+            let socket_address = (*entry).ifa_addr;
+            let entry_address = if socket_address.is_null() {
+                None
+            } else if i32::from((*socket_address).sa_family) == libc::AF_INET {
+                let v4 = &*(socket_address as *const libc::sockaddr_in);
+                Some(IpAddr::V4(Ipv4Addr::from(u32::from_be(v4.sin_addr.s_addr))))
+            } else if i32::from((*socket_address).sa_family) == libc::AF_INET6 {
+                let v6 = &*(socket_address as *const libc::sockaddr_in6);
+                Some(IpAddr::V6(Ipv6Addr::from(v6.sin6_addr.s6_addr)))
+            } else {
+                None
+            };
+            if entry_address == Some(address) {
+                found = Some(std::ffi::CStr::from_ptr((*entry).ifa_name).to_string_lossy().into_owned());
+                break;
+            }
+            entry = (*entry).ifa_next;
+        }
+        libc::freeifaddrs(interface_addresses);
+    }
+    Ok(found)
+}
+
+#[cfg(not(unix))]
+fn interface_with_address(_address: IpAddr) -> Result<Option<String>, io::Error> {
+    Ok(None)
+}
+
+
+/// Finds the index of the selected interface in the device list.
+pub fn select_interface(selector: &InterfaceSelector, devices: &[Device]) -> Result<usize, InterfaceError> {
+    let address = match selector {
+        InterfaceSelector::Name(name) => {
+            return devices.iter()
+                .position(|d| &d.name == name)
+                .ok_or_else(|| InterfaceError::NoInterfaceNamed(name.clone()));
+        },
+        InterfaceSelector::Address(a) => *a,
+        InterfaceSelector::DefaultRoute => default_route_address()?,
+    };
+
+    // libpcap knows the addresses of its devices; the raw socket backend does not
+    if let Some(index) = devices.iter().position(|d| d.addresses.contains(&address)) {
+        return Ok(index);
+    }
+    interface_with_address(address)?
+        .and_then(|name| devices.iter().position(|d| d.name == name))
+        .ok_or(InterfaceError::NoInterfaceWithAddress(address))
+}
+
+
+/// Returns the member ports of a bond or bridge as listed in the given sysfs network class
+/// directory, or nothing if the interface is neither.
+#[cfg(target_os = "linux")]
+fn members_in_sysfs(class_dir: &Path, interface: &str) -> Result<Vec<String>, io::Error> {
+    let interface_dir = class_dir.join(interface);
+
+    // bonds list their members in a file, bridges as a directory of links
+    match std::fs::read_to_string(interface_dir.join("bonding").join("slaves")) {
+        Ok(s) => return Ok(s.split_whitespace().map(|m| m.to_owned()).collect()),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => {},
+        Err(e) => return Err(e),
+    }
+    let entries = match std::fs::read_dir(interface_dir.join("brif")) {
+        Ok(e) => e,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => return Err(e),
+    };
+    let mut members = Vec::new();
+    for entry in entries {
+        members.push(entry?.file_name().to_string_lossy().into_owned());
+    }
+    members.sort_unstable();
+    Ok(members)
+}
+
+
+/// Returns the member ports of a bond or bridge, or nothing if the interface is neither.
+///
+/// The members are looked up in sysfs, which shows the interfaces of the network namespace it was
+/// mounted in.
+#[cfg(target_os = "linux")]
+pub fn interface_members(interface: &str) -> Result<Vec<String>, io::Error> {
+    members_in_sysfs(Path::new("/sys/class/net"), interface)
+}
+
+#[cfg(not(target_os = "linux"))]
+pub fn interface_members(_interface: &str) -> Result<Vec<String>, io::Error> {
+    Ok(Vec::new())
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::{Device, InterfaceSelector, select_interface};
+
+    #[test]
+    fn test_parse_selector() {
+        assert_eq!("auto".parse(), Ok(InterfaceSelector::DefaultRoute));
+        assert_eq!("192.0.2.10".parse(), Ok(InterfaceSelector::Address("192.0.2.10".parse().unwrap())));
+        assert_eq!("eth0".parse(), Ok(InterfaceSelector::Name("eth0".to_owned())));
+        assert!("".parse::<InterfaceSelector>().is_err());
+    }
+
+    #[test]
+    fn test_select_interface() {
+        let mut devices = vec![Device::from("no-such-interface0"), Device::from("no-such-interface1")];
+        devices[1].addresses.push("192.0.2.10".parse().unwrap());
+
+        assert_eq!(select_interface(&InterfaceSelector::Address("192.0.2.10".parse().unwrap()), &devices).unwrap(), 1);
+        assert_eq!(select_interface(&InterfaceSelector::Name("no-such-interface0".to_owned()), &devices).unwrap(), 0);
+        assert!(select_interface(&InterfaceSelector::Name("no-such-interface2".to_owned()), &devices).is_err());
+        assert!(select_interface(&InterfaceSelector::Address("192.0.2.254".parse().unwrap()), &devices).is_err());
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn test_members_in_sysfs() {
+        let class_dir = std::env::temp_dir().join(format!("dns-sniff-exporter-sysfs-{}", std::process::id()));
+        std::fs::create_dir_all(class_dir.join("bond0").join("bonding")).unwrap();
+        std::fs::write(class_dir.join("bond0").join("bonding").join("slaves"), "eth1 eth0\n").unwrap();
+        std::fs::create_dir_all(class_dir.join("br0").join("brif").join("veth2")).unwrap();
+        std::fs::create_dir_all(class_dir.join("br0").join("brif").join("veth1")).unwrap();
+        std::fs::create_dir_all(class_dir.join("eth0")).unwrap();
+
+        assert_eq!(super::members_in_sysfs(&class_dir, "bond0").unwrap(), vec!["eth1", "eth0"]);
+        assert_eq!(super::members_in_sysfs(&class_dir, "br0").unwrap(), vec!["veth1", "veth2"]);
+        assert_eq!(super::members_in_sysfs(&class_dir, "eth0").unwrap(), Vec::<String>::new());
+
+        std::fs::remove_dir_all(&class_dir).unwrap();
+    }
+}
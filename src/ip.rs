@@ -4,6 +4,89 @@ use crate::bytes::TryFromBytes;
 use crate::packet::PacketDissection;
 
 
+// IPv4 option types (copied flag, class and number)
+pub const IPV4_OPTION_END_OF_LIST: u8 = 0;
+pub const IPV4_OPTION_NO_OPERATION: u8 = 1;
+pub const IPV4_OPTION_RECORD_ROUTE: u8 = 7;
+pub const IPV4_OPTION_TIMESTAMP: u8 = 68;
+pub const IPV4_OPTION_ROUTER_ALERT: u8 = 148;
+
+
+/// An option of an IPv4 header, as defined in RFC791 section 3.1 and RFC2113.
+#[derive(Clone, Debug, Eq, Hash, Ord, PartialEq, PartialOrd)]
+pub enum Ipv4Option {
+    RecordRoute {
+        pointer: u8,
+        route: Vec<Ipv4Addr>, // only the addresses recorded so far
+    },
+    Timestamp {
+        pointer: u8,
+        overflow: u8,
+        flag: u8,
+        entries: Vec<(Option<Ipv4Addr>, u32)>, // only the entries recorded so far
+    },
+    RouterAlert {
+        value: u16,
+    },
+    Other {
+        option_type: u8,
+        data: Vec<u8>,
+    },
+}
+impl Ipv4Option {
+    /// Parses a single option from its data (the bytes following the type and length).
+    fn from_type_and_data(option_type: u8, data: &[u8]) -> Option<Self> {
+        match option_type {
+            IPV4_OPTION_RECORD_ROUTE => {
+                let pointer = *data.get(0)?;
+                // the pointer counts from the type byte and starts at 4
+                let recorded_length = usize::from(pointer).checked_sub(4)?;
+                let recorded = data.get(1..1+recorded_length)?;
+                let route = recorded.chunks_exact(4)
+                    .map(|c| Ipv4Addr::new(c[0], c[1], c[2], c[3]))
+                    .collect();
+                Some(Self::RecordRoute { pointer, route })
+            },
+            IPV4_OPTION_TIMESTAMP => {
+                let pointer = *data.get(0)?;
+                let overflow = (*data.get(1)? & 0b1111_0000) >> 4;
+                let flag = data[1] & 0b0000_1111;
+                // the pointer counts from the type byte and starts at 5
+                let recorded_length = usize::from(pointer).checked_sub(5)?;
+                let recorded = data.get(2..2+recorded_length)?;
+                let entries = match flag {
+                    0 => recorded.chunks_exact(4)
+                        .map(|c| (None, u32::from_be_bytes(c.try_into().unwrap())))
+                        .collect(),
+                    1|3 => recorded.chunks_exact(8)
+                        .map(|c| (Some(Ipv4Addr::new(c[0], c[1], c[2], c[3])), u32::from_be_bytes(c[4..8].try_into().unwrap())))
+                        .collect(),
+                    _ => return None,
+                };
+                Some(Self::Timestamp { pointer, overflow, flag, entries })
+            },
+            IPV4_OPTION_ROUTER_ALERT => {
+                if data.len() != 2 {
+                    return None;
+                }
+                Some(Self::RouterAlert { value: u16::from_be_bytes(data.try_into().unwrap()) })
+            },
+            other => Some(Self::Other { option_type: other, data: data.to_vec() }),
+        }
+    }
+
+    /// A short name of the kind of option, suitable as a metric label.
+    pub fn kind_name(&self) -> &'static str {
+        match self {
+            Self::RecordRoute { .. } => "record_route",
+            Self::Timestamp { .. } => "timestamp",
+            Self::RouterAlert { .. } => "router_alert",
+            Self::Other { .. } => "other",
+        }
+    }
+}
+
+
 #[derive(Clone, Copy, Debug, Eq, Hash, Ord, PartialEq, PartialOrd)]
 // as defined in RFC791 section 3.1
 pub struct Ipv4Header {
@@ -35,7 +118,7 @@ impl Ipv4Header {
         let header_length_w32 = bytes[0] & 0b0000_1111;
         let header_length_bytes = usize::from(header_length_w32) * (32 / 8);
         if header_length_bytes < 20 {
-            return PacketDissection::TooShort;
+            return PacketDissection::Malformed { reason: "header length smaller than the header" };
         }
         if bytes.len() < header_length_bytes {
             return PacketDissection::TooShort;
@@ -48,6 +131,9 @@ impl Ipv4Header {
 
         let type_of_service = bytes[1];
         let total_length = u16::from_be_bytes(bytes[2..4].try_into().unwrap());
+        if usize::from(total_length) < header_length_bytes {
+            return PacketDissection::Malformed { reason: "total length smaller than the header" };
+        }
         let identification = u16::from_be_bytes(bytes[4..6].try_into().unwrap());
         let flags_and_fragment_offset = u16::from_be_bytes(bytes[6..8].try_into().unwrap());
         let time_to_live = bytes[8];
@@ -87,12 +173,14 @@ impl Ipv4Header {
         let src_addr_bytes = self.source_address.octets();
         let dest_addr_bytes = self.destination_address.octets();
 
-        let mut l4_length = self.total_length - 20;
+        // try_take rejects total lengths smaller than the header; take no payload if one slips by
+        let mut l4_length = self.total_length.checked_sub(20);
         for option in &self.options {
             if option.is_some() {
-                l4_length -= 4;
+                l4_length = l4_length.and_then(|l| l.checked_sub(4));
             }
         }
+        let l4_length = l4_length.unwrap_or(0);
         let l4_length_bytes = l4_length.to_be_bytes();
 
         let mut pseudo_header = [0u8; 12];
@@ -104,6 +192,53 @@ impl Ipv4Header {
 
         pseudo_header
     }
+
+    /// Whether the sender has forbidden fragmenting this packet (the DF bit).
+    pub fn dont_fragment(&self) -> bool {
+        (self.flags_and_fragment_offset & 0b0100_0000_0000_0000) != 0
+    }
+
+    /// Whether further fragments of the same datagram follow this one (the MF bit).
+    pub fn more_fragments(&self) -> bool {
+        (self.flags_and_fragment_offset & 0b0010_0000_0000_0000) != 0
+    }
+
+    /// The offset of this fragment within the datagram, in units of 8 bytes.
+    pub fn fragment_offset(&self) -> u16 {
+        self.flags_and_fragment_offset & 0b0001_1111_1111_1111
+    }
+
+    /// Parses the options of this header.
+    ///
+    /// The options end at the end of the header or at the end-of-list option, whichever comes
+    /// first; anything following the end-of-list option is padding. Returns `None` if the options
+    /// are malformed, e.g. if an option claims to extend beyond the end of the header.
+    pub fn parsed_options(&self) -> Option<Vec<Ipv4Option>> {
+        let bytes: Vec<u8> = self.options.iter()
+            .flatten()
+            .flat_map(|word| word.iter().copied())
+            .collect();
+
+        let mut options = Vec::new();
+        let mut i = 0;
+        while i < bytes.len() {
+            match bytes[i] {
+                IPV4_OPTION_END_OF_LIST => break,
+                IPV4_OPTION_NO_OPERATION => {
+                    i += 1;
+                },
+                option_type => {
+                    let length = usize::from(*bytes.get(i + 1)?);
+                    if length < 2 || i + length > bytes.len() {
+                        return None;
+                    }
+                    options.push(Ipv4Option::from_type_and_data(option_type, &bytes[i+2..i+length])?);
+                    i += length;
+                },
+            }
+        }
+        Some(options)
+    }
 }
 impl Default for Ipv4Header {
     fn default() -> Self {
@@ -182,7 +317,8 @@ impl Ipv6Header {
         let src_addr_bytes = self.source_address.octets();
         let dest_addr_bytes = self.destination_address.octets();
 
-        let l4_length: u32 = (self.payload_length - 40).into();
+        // payload_length already excludes the fixed header
+        let l4_length: u32 = self.payload_length.into();
         let l4_length_bytes = l4_length.to_be_bytes();
 
         let mut pseudo_header = [0u8; 40];
@@ -238,6 +374,30 @@ impl IpHeader {
         }
     }
 
+    /// The type of service (IPv4) or traffic class (IPv6), consisting of the DSCP and the ECN bits.
+    pub fn traffic_class(&self) -> u8 {
+        match self {
+            Self::V4(h) => h.type_of_service,
+            Self::V6(h) => h.traffic_class,
+        }
+    }
+
+    pub fn dscp(&self) -> u8 {
+        (self.traffic_class() & 0b1111_1100) >> 2
+    }
+
+    pub fn ecn(&self) -> u8 {
+        self.traffic_class() & 0b0000_0011
+    }
+
+    /// The remaining time-to-live (IPv4) or hop limit (IPv6).
+    pub fn hop_limit(&self) -> u8 {
+        match self {
+            Self::V4(h) => h.time_to_live,
+            Self::V6(h) => h.hop_limit,
+        }
+    }
+
     pub fn destination_address(&self) -> IpAddr {
         match self {
             Self::V4(h) => IpAddr::V4(h.destination_address),
@@ -255,8 +415,75 @@ impl IpHeader {
 
 
 // managed by IANA: https://www.iana.org/assignments/protocol-numbers/protocol-numbers.xhtml#protocol-numbers-1
+pub const PROTO_ICMP: u8 = 1;
 pub const PROTO_TCP: u8 = 6;
 pub const PROTO_UDP: u8 = 17;
+pub const PROTO_IPV6_FRAGMENT: u8 = 44;
+pub const PROTO_ICMPV6: u8 = 58;
+
+
+/// Returns the name of a Differentiated Services code point (RFC2474, RFC2597, RFC3246, RFC5865,
+/// RFC8622), or its number if it has none.
+#[cfg(any(test, feature = "http"))]
+pub fn dscp_name(dscp: u8) -> String {
+    let name = match dscp {
+        0 => "CS0",
+        1 => "LE",
+        8 => "CS1",
+        10 => "AF11",
+        12 => "AF12",
+        14 => "AF13",
+        16 => "CS2",
+        18 => "AF21",
+        20 => "AF22",
+        22 => "AF23",
+        24 => "CS3",
+        26 => "AF31",
+        28 => "AF32",
+        30 => "AF33",
+        32 => "CS4",
+        34 => "AF41",
+        36 => "AF42",
+        38 => "AF43",
+        40 => "CS5",
+        44 => "VOICE-ADMIT",
+        46 => "EF",
+        48 => "CS6",
+        56 => "CS7",
+        other => return other.to_string(),
+    };
+    name.to_owned()
+}
+
+
+/// Returns the name of an Explicit Congestion Notification code point (RFC3168).
+#[cfg(any(test, feature = "http"))]
+pub fn ecn_name(ecn: u8) -> &'static str {
+    match ecn & 0b11 {
+        0b00 => "not_ect",
+        0b01 => "ect1",
+        0b10 => "ect0",
+        _ => "ce",
+    }
+}
+
+
+/// Zeroes out all but the first `prefix_length` bits of the given address, yielding the address of
+/// the network containing it.
+pub fn mask_address(address: IpAddr, prefix_length: u8) -> IpAddr {
+    match address {
+        IpAddr::V4(a) => {
+            let bits = u32::from(a);
+            let mask = u32::MAX.checked_shl(32 - u32::from(prefix_length.min(32))).unwrap_or(0);
+            IpAddr::V4(Ipv4Addr::from(bits & mask))
+        },
+        IpAddr::V6(a) => {
+            let bits = u128::from(a);
+            let mask = u128::MAX.checked_shl(128 - u32::from(prefix_length.min(128))).unwrap_or(0);
+            IpAddr::V6(Ipv6Addr::from(bits & mask))
+        },
+    }
+}
 
 
 /// Performs ones' complement addition on two u16s.
@@ -310,7 +537,13 @@ pub fn internet_checksum<I: IntoIterator<Item = u8>>(bytes: I) -> u16 {
 
 #[cfg(test)]
 mod tests {
-    use super::{internet_checksum, ones_complement_add};
+    use std::net::{IpAddr, Ipv4Addr};
+
+    use super::{
+        dscp_name, ecn_name, internet_checksum, IpHeader, Ipv4Header, Ipv4Option, mask_address,
+        ones_complement_add,
+    };
+    use crate::packet::PacketDissection;
 
     #[test]
     fn test_ones_complement_add() {
@@ -326,4 +559,82 @@ mod tests {
         ];
         assert_eq!(internet_checksum(bs), 0xFFFF);
     }
+
+    #[test]
+    fn test_mask_address() {
+        let v4: IpAddr = "192.0.2.123".parse().unwrap();
+        assert_eq!(mask_address(v4, 24), "192.0.2.0".parse::<IpAddr>().unwrap());
+        assert_eq!(mask_address(v4, 32), v4);
+        assert_eq!(mask_address(v4, 0), "0.0.0.0".parse::<IpAddr>().unwrap());
+
+        let v6: IpAddr = "2001:db8:1234:5678::1".parse().unwrap();
+        assert_eq!(mask_address(v6, 48), "2001:db8:1234::".parse::<IpAddr>().unwrap());
+        assert_eq!(mask_address(v6, 128), v6);
+    }
+
+    #[test]
+    fn test_parsed_options() {
+        let header_with_options = |words: &[[u8; 4]]| {
+            let mut header = Ipv4Header::default();
+            for (slot, word) in header.options.iter_mut().zip(words) {
+                *slot = Some(*word);
+            }
+            header
+        };
+
+        // router alert, no-op, then end of list followed by padding
+        let header = header_with_options(&[[148, 4, 0, 0], [1, 0, 0xAA, 0xAA]]);
+        assert_eq!(header.parsed_options(), Some(vec![Ipv4Option::RouterAlert { value: 0 }]));
+
+        // record route with room for two addresses, one of which has been recorded
+        let header = header_with_options(&[[1, 7, 11, 8], [192, 0, 2, 1], [0, 0, 0, 0]]);
+        assert_eq!(
+            header.parsed_options(),
+            Some(vec![Ipv4Option::RecordRoute { pointer: 8, route: vec![Ipv4Addr::new(192, 0, 2, 1)] }]),
+        );
+
+        // timestamps only, one recorded
+        let header = header_with_options(&[[68, 12, 9, 0], [0, 0, 0x12, 0x34], [0, 0, 0, 0]]);
+        assert_eq!(
+            header.parsed_options(),
+            Some(vec![Ipv4Option::Timestamp { pointer: 9, overflow: 0, flag: 0, entries: vec![(None, 0x1234)] }]),
+        );
+
+        // option extending beyond the header
+        let header = header_with_options(&[[7, 39, 4, 0]]);
+        assert_eq!(header.parsed_options(), None);
+        assert_eq!(Ipv4Header::default().parsed_options(), Some(vec![]));
+    }
+
+    #[test]
+    fn test_traffic_class() {
+        let header = IpHeader::V4(Ipv4Header { type_of_service: 0b1011_1001, ..Default::default() });
+        assert_eq!(header.dscp(), 46);
+        assert_eq!(header.ecn(), 1);
+        assert_eq!(dscp_name(header.dscp()), "EF");
+        assert_eq!(dscp_name(5), "5");
+        assert_eq!(ecn_name(header.ecn()), "ect1");
+        assert_eq!(ecn_name(3), "ce");
+    }
+
+    #[test]
+    fn test_total_length_malformed() {
+        // a header of six words (one option word) claiming a total length of 20 bytes
+        let mut packet = [0u8; 24];
+        packet[0] = 0x46;
+        packet[3] = 20;
+        let checksum = internet_checksum(packet.iter().map(|b| *b));
+        packet[10..12].copy_from_slice(&checksum.to_be_bytes());
+        assert!(matches!(
+            Ipv4Header::try_take(&packet),
+            PacketDissection::Malformed { reason: "total length smaller than the header" },
+        ));
+
+        // headers built by hand are not checked; their pseudo-header claims no payload
+        let mut header = Ipv4Header { total_length: 20, ..Default::default() };
+        header.options[0] = Some([1, 1, 1, 1]);
+        assert_eq!(header.to_pseudo_header()[10..12], [0, 0]);
+        let header = Ipv4Header { total_length: 10, ..Default::default() };
+        assert_eq!(header.to_pseudo_header()[10..12], [0, 0]);
+    }
 }
@@ -0,0 +1,309 @@
+use std::io;
+use std::net::IpAddr;
+
+use tokio::net::UdpSocket;
+use tokio::sync::mpsc;
+use tokio::sync::mpsc::error::TrySendError;
+use tracing::{debug, warn};
+
+use crate::sink::{DnsMessageEvent, EventSink};
+
+
+const QUEUE_LENGTH: usize = 4096;
+const CONNECT_TIMEOUT_SECS: u64 = 5;
+
+// keeps the messages below the usual path MTU, leaving room for the headers and templates
+const MAX_RECORDS_SIZE: usize = 1000;
+
+const IPFIX_VERSION: u16 = 10;
+const TEMPLATE_SET_ID: u16 = 2;
+const FIRST_TEMPLATE_ID: u16 = 256;
+const ENTERPRISE_BIT: u16 = 0x8000;
+const VARIABLE_LENGTH: u16 = 0xFFFF;
+const PROTOCOL_TCP: u8 = 6;
+const PROTOCOL_UDP: u8 = 17;
+
+// IANA information elements
+const IE_FLOW_START_MILLISECONDS: u16 = 152;
+const IE_SOURCE_IPV4_ADDRESS: u16 = 8;
+const IE_SOURCE_IPV6_ADDRESS: u16 = 27;
+const IE_SOURCE_TRANSPORT_PORT: u16 = 7;
+const IE_DESTINATION_IPV4_ADDRESS: u16 = 12;
+const IE_DESTINATION_IPV6_ADDRESS: u16 = 28;
+const IE_DESTINATION_TRANSPORT_PORT: u16 = 11;
+const IE_PROTOCOL_IDENTIFIER: u16 = 4;
+
+// enterprise-specific information elements
+const IE_DNS_TRANSACTION_ID: u16 = 1;
+const IE_DNS_QUERY_TYPE: u16 = 2;
+const IE_DNS_RESPONSE_CODE: u16 = 3;
+const IE_DNS_QUERY_NAME: u16 = 4;
+
+
+/// Returns the ID of the template matching the address family and message direction.
+fn template_id(ipv6: bool, response: bool) -> u16 {
+    FIRST_TEMPLATE_ID + if ipv6 { 2 } else { 0 } + if response { 1 } else { 0 }
+}
+
+
+/// Encodes the template record for the template with the given ID.
+fn encode_template(template_id: u16, enterprise_number: u32) -> Vec<u8> {
+    let index = template_id - FIRST_TEMPLATE_ID;
+    let ipv6 = index & 2 != 0;
+    let response = index & 1 != 0;
+
+    let mut fields = vec![
+        (IE_FLOW_START_MILLISECONDS, 8, false),
+        (if ipv6 { IE_SOURCE_IPV6_ADDRESS } else { IE_SOURCE_IPV4_ADDRESS }, if ipv6 { 16 } else { 4 }, false),
+        (IE_SOURCE_TRANSPORT_PORT, 2, false),
+        (if ipv6 { IE_DESTINATION_IPV6_ADDRESS } else { IE_DESTINATION_IPV4_ADDRESS }, if ipv6 { 16 } else { 4 }, false),
+        (IE_DESTINATION_TRANSPORT_PORT, 2, false),
+        (IE_PROTOCOL_IDENTIFIER, 1, false),
+        (IE_DNS_TRANSACTION_ID, 2, true),
+        (IE_DNS_QUERY_TYPE, 2, true),
+    ];
+    if response {
+        fields.push((IE_DNS_RESPONSE_CODE, 2, true));
+    }
+    fields.push((IE_DNS_QUERY_NAME, VARIABLE_LENGTH, true));
+
+    let mut buf = Vec::new();
+    buf.extend_from_slice(&template_id.to_be_bytes());
+    buf.extend_from_slice(&u16::try_from(fields.len()).unwrap().to_be_bytes());
+    for (element_id, length, enterprise) in fields {
+        if enterprise {
+            buf.extend_from_slice(&(element_id | ENTERPRISE_BIT).to_be_bytes());
+            buf.extend_from_slice(&length.to_be_bytes());
+            buf.extend_from_slice(&enterprise_number.to_be_bytes());
+        } else {
+            buf.extend_from_slice(&element_id.to_be_bytes());
+            buf.extend_from_slice(&length.to_be_bytes());
+        }
+    }
+    buf
+}
+
+
+fn encode_address(buf: &mut Vec<u8>, address: IpAddr) {
+    match address {
+        IpAddr::V4(a) => buf.extend_from_slice(&a.octets()),
+        IpAddr::V6(a) => buf.extend_from_slice(&a.octets()),
+    }
+}
+
+
+/// Encodes an event as a data record, returning the ID of its template and the record.
+fn encode_record(event: &DnsMessageEvent<'_>) -> (u16, Vec<u8>) {
+    let mut buf = Vec::new();
+    buf.extend_from_slice(&event.timestamp.timestamp_millis().to_be_bytes());
+    encode_address(&mut buf, event.source.ip());
+    buf.extend_from_slice(&event.source.port().to_be_bytes());
+    encode_address(&mut buf, event.destination.ip());
+    buf.extend_from_slice(&event.destination.port().to_be_bytes());
+    buf.push(if event.over_tcp { PROTOCOL_TCP } else { PROTOCOL_UDP });
+    buf.extend_from_slice(&event.transaction_id.to_be_bytes());
+    buf.extend_from_slice(&u16::from(event.record_type).to_be_bytes());
+    if let Some(rc) = event.response_code {
+        buf.extend_from_slice(&u16::from(rc).to_be_bytes());
+    }
+
+    // a DNS name is at most 255 bytes long, but its textual form might not be
+    let name = event.name.as_bytes();
+    if name.len() < 255 {
+        buf.push(u8::try_from(name.len()).unwrap());
+    } else {
+        let length = u16::try_from(name.len()).unwrap_or(u16::MAX);
+        buf.push(255);
+        buf.extend_from_slice(&length.to_be_bytes());
+    }
+    buf.extend_from_slice(&name[..usize::from(u16::MAX).min(name.len())]);
+
+    (template_id(event.source.is_ipv6(), !event.is_query()), buf)
+}
+
+
+fn encode_set(buf: &mut Vec<u8>, set_id: u16, records: &[&[u8]]) {
+    let length: usize = 4 + records.iter().map(|r| r.len()).sum::<usize>();
+    buf.extend_from_slice(&set_id.to_be_bytes());
+    buf.extend_from_slice(&u16::try_from(length).unwrap().to_be_bytes());
+    for record in records {
+        buf.extend_from_slice(record);
+    }
+}
+
+
+/// Encodes a message containing the given data records, preceded by their templates.
+///
+/// `sequence_number` is the number of data records sent before this message.
+fn encode_message(export_time: u32, sequence_number: u32, observation_domain_id: u32, enterprise_number: u32, records: &[(u16, Vec<u8>)]) -> Vec<u8> {
+    let mut buf = Vec::new();
+    buf.extend_from_slice(&IPFIX_VERSION.to_be_bytes());
+    buf.extend_from_slice(&[0, 0]); // length, filled in later
+    buf.extend_from_slice(&export_time.to_be_bytes());
+    buf.extend_from_slice(&sequence_number.to_be_bytes());
+    buf.extend_from_slice(&observation_domain_id.to_be_bytes());
+
+    // UDP collectors may have missed earlier templates, so always send the ones we use
+    let mut template_ids: Vec<u16> = records.iter().map(|(t, _)| *t).collect();
+    template_ids.sort_unstable();
+    template_ids.dedup();
+    let templates: Vec<Vec<u8>> = template_ids.iter()
+        .map(|t| encode_template(*t, enterprise_number))
+        .collect();
+    let template_refs: Vec<&[u8]> = templates.iter().map(|t| t.as_slice()).collect();
+    encode_set(&mut buf, TEMPLATE_SET_ID, &template_refs);
+
+    // one data set per run of records with the same template
+    let mut start = 0;
+    while start < records.len() {
+        let set_id = records[start].0;
+        let end = records[start..].iter()
+            .position(|(t, _)| *t != set_id)
+            .map(|p| start + p)
+            .unwrap_or(records.len());
+        let record_refs: Vec<&[u8]> = records[start..end].iter().map(|(_, r)| r.as_slice()).collect();
+        encode_set(&mut buf, set_id, &record_refs);
+        start = end;
+    }
+
+    let length = u16::try_from(buf.len()).unwrap();
+    buf[2..4].copy_from_slice(&length.to_be_bytes());
+    buf
+}
+
+
+async fn connect(address: &str) -> Result<UdpSocket, io::Error> {
+    let remote_address = tokio::net::lookup_host(address).await?
+        .next()
+        .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "IPFIX collector not found"))?;
+    let local_address = if remote_address.is_ipv4() { "0.0.0.0:0" } else { "[::]:0" };
+    let socket = UdpSocket::bind(local_address).await?;
+    socket.connect(remote_address).await?;
+    Ok(socket)
+}
+
+
+async fn run_exporter(address: String, observation_domain_id: u32, enterprise_number: u32, mut receiver: mpsc::Receiver<(u16, Vec<u8>)>) {
+    let mut socket = None;
+    let mut sequence_number: u32 = 0;
+    while let Some(first_record) = receiver.recv().await {
+        let mut records = vec![first_record];
+        while let Ok(record) = receiver.try_recv() {
+            records.push(record);
+        }
+
+        if socket.is_none() {
+            let connect_timeout = std::time::Duration::from_secs(CONNECT_TIMEOUT_SECS);
+            match tokio::time::timeout(connect_timeout, connect(&address)).await {
+                Ok(Ok(s)) => socket = Some(s),
+                Ok(Err(e)) => {
+                    warn!("failed to connect to IPFIX collector {}; dropping {} records: {}", address, records.len(), e);
+                    continue;
+                },
+                Err(_) => {
+                    warn!("timed out connecting to IPFIX collector {}; dropping {} records", address, records.len());
+                    continue;
+                },
+            }
+        }
+
+        // split the records across messages of limited size
+        let export_time = u32::try_from(chrono::Utc::now().timestamp()).unwrap_or(u32::MAX);
+        let mut batch: Vec<(u16, Vec<u8>)> = Vec::new();
+        let mut batch_size = 0;
+        let mut records_iter = records.into_iter().peekable();
+        while let Some(record) = records_iter.next() {
+            batch_size += record.1.len();
+            batch.push(record);
+            let next_size = records_iter.peek().map(|r| r.1.len()).unwrap_or(0);
+            if records_iter.peek().is_some() && batch_size + next_size <= MAX_RECORDS_SIZE {
+                continue;
+            }
+
+            let message = encode_message(export_time, sequence_number, observation_domain_id, enterprise_number, &batch);
+            sequence_number = sequence_number.wrapping_add(u32::try_from(batch.len()).unwrap());
+            batch.clear();
+            batch_size = 0;
+            if let Some(s) = socket.as_ref() {
+                if let Err(e) = s.send(&message).await {
+                    warn!("failed to send IPFIX message; reconnecting: {}", e);
+                    socket = None;
+                }
+            }
+        }
+    }
+}
+
+
+/// Exports every query and response as an IPFIX data record to a collector.
+///
+/// The DNS-specific information elements (transaction ID, query type, response code and query
+/// name, numbered 1 to 4) are defined under the given private enterprise number.
+pub struct IpfixExporter {
+    sender: mpsc::Sender<(u16, Vec<u8>)>,
+    task: tokio::task::JoinHandle<()>,
+}
+impl IpfixExporter {
+    /// Starts the exporter. Must be called from within the Tokio runtime.
+    pub fn new(address: String, observation_domain_id: u32, enterprise_number: u32) -> Self {
+        let (sender, receiver) = mpsc::channel(QUEUE_LENGTH);
+        let task = tokio::spawn(run_exporter(address, observation_domain_id, enterprise_number, receiver));
+        Self {
+            sender,
+            task,
+        }
+    }
+}
+impl EventSink for IpfixExporter {
+    fn emit(&mut self, event: &DnsMessageEvent<'_>) {
+        match self.sender.try_send(encode_record(event)) {
+            Ok(()) => {},
+            Err(TrySendError::Full(_)) => debug!("IPFIX queue full; dropping record"),
+            Err(TrySendError::Closed(_)) => {},
+        }
+    }
+
+    fn close(self: Box<Self>) -> Option<tokio::task::JoinHandle<()>> {
+        Some(self.task)
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+
+    use chrono::{TimeZone, Utc};
+    use hickory_proto::op::ResponseCode;
+    use hickory_proto::rr::RecordType;
+
+    use super::{encode_message, encode_record};
+    use crate::sink::DnsMessageEvent;
+
+    #[test]
+    fn test_encode_message() {
+        let response = DnsMessageEvent {
+            timestamp: Utc.with_ymd_and_hms(2022, 10, 1, 12, 0, 0).unwrap(),
+            source: "192.0.2.53:53".parse().unwrap(),
+            destination: "192.0.2.1:54321".parse().unwrap(),
+            transaction_id: 0x1234,
+            name: "example.com",
+            record_type: RecordType::A,
+            over_tcp: false,
+            response_code: Some(ResponseCode::NXDomain),
+            labels: &BTreeMap::new(),
+        };
+        let (template_id, record) = encode_record(&response);
+        assert_eq!(template_id, 257);
+        assert_eq!(record.len(), 8 + 4 + 2 + 4 + 2 + 1 + 2 + 2 + 2 + 1 + 11);
+        assert_eq!(&record[21..30], &[0x12, 0x34, 0x00, 0x01, 0x00, 0x03, 11, b'e', b'x']);
+
+        let message = encode_message(1664625600, 7, 1, 32473, &[(template_id, record.clone()), (template_id, record)]);
+        // message header
+        assert_eq!(&message[0..16], &[0, 10, 0, 162, 0x63, 0x38, 0x2B, 0xC0, 0, 0, 0, 7, 0, 0, 0, 1]);
+        // template set with one template of 10 fields, 4 of which are enterprise-specific
+        assert_eq!(&message[16..24], &[0, 2, 0, 64, 1, 1, 0, 10]);
+        // data set with both records
+        assert_eq!(&message[80..84], &[1, 1, 0, 82]);
+    }
+}
@@ -0,0 +1,252 @@
+use std::fs::{File, OpenOptions};
+use std::io::{self, BufWriter, Write};
+use std::path::Path;
+
+use clap::ValueEnum;
+use hickory_proto::op::ResponseCode;
+use serde_json::{json, Value};
+use tracing::error;
+
+use crate::sink::{DnsMessageEvent, EventSink};
+
+
+const ECS_VERSION: &str = "8.11.0";
+
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq, ValueEnum)]
+pub enum JsonLogFormat {
+    Plain,
+    Ecs,
+    Eve,
+}
+
+
+/// Returns the mnemonic of a response code as used by ECS and Suricata (e.g. `NXDOMAIN`).
+pub fn response_code_mnemonic(response_code: ResponseCode) -> String {
+    let value = u16::from(response_code);
+    let mnemonic = match value {
+        0 => "NOERROR",
+        1 => "FORMERR",
+        2 => "SERVFAIL",
+        3 => "NXDOMAIN",
+        4 => "NOTIMP",
+        5 => "REFUSED",
+        6 => "YXDOMAIN",
+        7 => "YXRRSET",
+        8 => "NXRRSET",
+        9 => "NOTAUTH",
+        10 => "NOTZONE",
+        16 => "BADVERS",
+        17 => "BADKEY",
+        18 => "BADTIME",
+        19 => "BADMODE",
+        20 => "BADNAME",
+        21 => "BADALG",
+        22 => "BADTRUNC",
+        23 => "BADCOOKIE",
+        other => return other.to_string(),
+    };
+    mnemonic.to_owned()
+}
+
+
+/// Adds the global labels of the event to an entry, using the `labels` field as ECS does.
+fn add_labels(entry: &mut Value, event: &DnsMessageEvent<'_>) {
+    if event.labels.len() > 0 {
+        entry["labels"] = json!(event.labels);
+    }
+}
+
+
+fn format_plain(event: &DnsMessageEvent<'_>) -> Value {
+    let mut entry = json!({
+        "timestamp": event.timestamp.to_rfc3339(),
+        "source": event.source.to_string(),
+        "destination": event.destination.to_string(),
+        "transaction_id": event.transaction_id,
+        "name": event.name,
+        "type": event.record_type.to_string(),
+        "response_code": event.response_code.map(|rc| rc.to_str()),
+    });
+    add_labels(&mut entry, event);
+    entry
+}
+
+
+fn format_ecs(event: &DnsMessageEvent<'_>) -> Value {
+    let client = event.client();
+    let server = event.server();
+    let mut dns = json!({
+        "type": if event.is_query() { "query" } else { "answer" },
+        "id": event.transaction_id.to_string(),
+        "question": {
+            "name": event.name.trim_end_matches('.'),
+            "type": event.record_type.to_string(),
+        },
+    });
+    if let Some(rc) = event.response_code {
+        dns["response_code"] = Value::from(response_code_mnemonic(rc));
+    }
+    let mut entry = json!({
+        "@timestamp": event.timestamp.to_rfc3339_opts(chrono::SecondsFormat::Micros, true),
+        "ecs": {"version": ECS_VERSION},
+        "event": {
+            "kind": "event",
+            "category": ["network"],
+            "type": ["protocol"],
+            "dataset": "dns",
+        },
+        "network": {
+            "protocol": "dns",
+            "transport": if event.over_tcp { "tcp" } else { "udp" },
+        },
+        "source": {"ip": event.source.ip().to_string(), "port": event.source.port()},
+        "destination": {"ip": event.destination.ip().to_string(), "port": event.destination.port()},
+        "client": {"ip": client.ip().to_string(), "port": client.port()},
+        "server": {"ip": server.ip().to_string(), "port": server.port()},
+        "dns": dns,
+    });
+    add_labels(&mut entry, event);
+    entry
+}
+
+
+fn format_eve(event: &DnsMessageEvent<'_>) -> Value {
+    let mut dns = json!({
+        "type": if event.is_query() { "query" } else { "answer" },
+        "id": event.transaction_id,
+        "rrname": event.name.trim_end_matches('.'),
+        "rrtype": event.record_type.to_string(),
+    });
+    if let Some(rc) = event.response_code {
+        dns["version"] = Value::from(2);
+        dns["rcode"] = Value::from(response_code_mnemonic(rc));
+    }
+    let mut entry = json!({
+        "timestamp": event.timestamp.format("%Y-%m-%dT%H:%M:%S%.6f%z").to_string(),
+        // Suricata's flow IDs fit into the integer range of JavaScript numbers
+        "flow_id": event.flow_id() & 0x0000_FFFF_FFFF_FFFF,
+        "event_type": "dns",
+        "src_ip": event.source.ip().to_string(),
+        "src_port": event.source.port(),
+        "dest_ip": event.destination.ip().to_string(),
+        "dest_port": event.destination.port(),
+        "proto": if event.over_tcp { "TCP" } else { "UDP" },
+        "dns": dns,
+    });
+    add_labels(&mut entry, event);
+    entry
+}
+
+
+pub fn format_event(event: &DnsMessageEvent<'_>, format: JsonLogFormat) -> Value {
+    match format {
+        JsonLogFormat::Plain => format_plain(event),
+        JsonLogFormat::Ecs => format_ecs(event),
+        JsonLogFormat::Eve => format_eve(event),
+    }
+}
+
+
+
+/// Appends every query and response as a line of JSON to a file.
+pub struct JsonLogSink {
+    writer: BufWriter<File>,
+    format: JsonLogFormat,
+}
+impl JsonLogSink {
+    pub fn open<P: AsRef<Path>>(path: P, format: JsonLogFormat) -> Result<Self, io::Error> {
+        let file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)?;
+        Ok(Self {
+            writer: BufWriter::new(file),
+            format,
+        })
+    }
+}
+impl EventSink for JsonLogSink {
+    fn emit(&mut self, event: &DnsMessageEvent<'_>) {
+        let entry = format_event(event, self.format);
+        if let Err(e) = writeln!(self.writer, "{}", entry) {
+            error!("failed to write JSON event log: {}", e);
+        }
+    }
+
+    fn close(mut self: Box<Self>) -> Option<tokio::task::JoinHandle<()>> {
+        if let Err(e) = self.writer.flush() {
+            error!("failed to write JSON event log: {}", e);
+        }
+        None
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+
+    use chrono::{TimeZone, Utc};
+    use hickory_proto::op::ResponseCode;
+    use hickory_proto::rr::RecordType;
+    use serde_json::json;
+
+    use super::{format_ecs, format_eve};
+    use crate::sink::DnsMessageEvent;
+
+    #[test]
+    fn test_format_ecs() {
+        let labels = BTreeMap::from([("node_name".to_owned(), "worker-1".to_owned())]);
+        let response = DnsMessageEvent {
+            timestamp: Utc.with_ymd_and_hms(2022, 10, 1, 12, 0, 0).unwrap(),
+            source: "192.0.2.53:53".parse().unwrap(),
+            destination: "192.0.2.1:54321".parse().unwrap(),
+            transaction_id: 0x1234,
+            name: "example.com.",
+            record_type: RecordType::AAAA,
+            over_tcp: false,
+            response_code: Some(ResponseCode::NXDomain),
+            labels: &labels,
+        };
+        let entry = format_ecs(&response);
+        assert_eq!(entry["@timestamp"], json!("2022-10-01T12:00:00.000000Z"));
+        assert_eq!(entry["client"], json!({"ip": "192.0.2.1", "port": 54321}));
+        assert_eq!(entry["server"], json!({"ip": "192.0.2.53", "port": 53}));
+        assert_eq!(entry["dns"], json!({
+            "type": "answer",
+            "id": "4660",
+            "question": {"name": "example.com", "type": "AAAA"},
+            "response_code": "NXDOMAIN",
+        }));
+        assert_eq!(entry["labels"], json!({"node_name": "worker-1"}));
+    }
+
+    #[test]
+    fn test_format_eve() {
+        let query = DnsMessageEvent {
+            timestamp: Utc.with_ymd_and_hms(2022, 10, 1, 12, 0, 0).unwrap(),
+            source: "192.0.2.1:54321".parse().unwrap(),
+            destination: "192.0.2.53:53".parse().unwrap(),
+            transaction_id: 0x1234,
+            name: "example.com.",
+            record_type: RecordType::A,
+            over_tcp: false,
+            response_code: None,
+            labels: &BTreeMap::new(),
+        };
+        let response = DnsMessageEvent {
+            source: query.destination,
+            destination: query.source,
+            response_code: Some(ResponseCode::NoError),
+            ..query.clone()
+        };
+        let query_entry = format_eve(&query);
+        let response_entry = format_eve(&response);
+        assert_eq!(query_entry["timestamp"], json!("2022-10-01T12:00:00.000000+0000"));
+        assert_eq!(query_entry["dns"], json!({"type": "query", "id": 4660, "rrname": "example.com", "rrtype": "A"}));
+        assert_eq!(response_entry["dns"], json!({"version": 2, "type": "answer", "id": 4660, "rrname": "example.com", "rrtype": "A", "rcode": "NOERROR"}));
+        assert_eq!(query_entry["flow_id"], response_entry["flow_id"]);
+        assert_eq!(response_entry["src_port"], json!(53));
+    }
+}
@@ -0,0 +1,652 @@
+use std::collections::{HashMap, HashSet};
+use std::env;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::net::IpAddr;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use chrono::{DateTime, Utc};
+use serde_json::Value;
+use tracing::{debug, error, warn};
+
+use crate::http::{get, get_lines, HttpError, HttpUrl, ResponseLines};
+
+
+const PODS_PATH: &str = "/api/v1/pods";
+const PODS_PER_PAGE: usize = 500;
+
+// the server ends a watch after this long, upon which we resume it from the last resource version
+const WATCH_TIMEOUT_SECS: u64 = 300;
+const WATCH_IDLE_TIMEOUT_SECS: u64 = WATCH_TIMEOUT_SECS + 60;
+const MIN_RETRY_DELAY_SECS: u64 = 1;
+const MAX_RETRY_DELAY_SECS: u64 = 60;
+
+// the status of a watch whose resource version is too old to resume from
+const STATUS_GONE: i64 = 410;
+
+const SERVICE_ACCOUNT_TOKEN_PATH: &str = "/var/run/secrets/kubernetes.io/serviceaccount/token";
+const SERVICE_ACCOUNT_CA_PATH: &str = "/var/run/secrets/kubernetes.io/serviceaccount/ca.crt";
+
+
+#[derive(Debug)]
+pub enum KubernetesError {
+    Http(HttpError),
+    InvalidPodList(String),
+    InvalidWatchEvent(String),
+    WatchFailed(String),
+    NotInCluster,
+    ServiceAccount(io::Error),
+}
+impl fmt::Display for KubernetesError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Http(e)
+                => write!(f, "failed to query the Kubernetes API: {}", e),
+            Self::InvalidPodList(reason)
+                => write!(f, "invalid pod list: {}", reason),
+            Self::InvalidWatchEvent(reason)
+                => write!(f, "invalid watch event: {}", reason),
+            Self::WatchFailed(message)
+                => write!(f, "the watch failed: {}", message),
+            Self::NotInCluster
+                => write!(f, "KUBERNETES_SERVICE_HOST and KUBERNETES_SERVICE_PORT are not set; not running in a pod?"),
+            Self::ServiceAccount(e)
+                => write!(f, "failed to read the service account credentials: {}", e),
+        }
+    }
+}
+impl std::error::Error for KubernetesError {
+}
+impl From<HttpError> for KubernetesError {
+    fn from(e: HttpError) -> Self { Self::Http(e) }
+}
+
+
+/// The pod using an address, and the workload (such as a deployment) managing it.
+#[derive(Clone, Debug, Eq, Hash, PartialEq)]
+pub struct PodInfo {
+    pub namespace: String,
+    pub name: String,
+    pub workload: Option<String>,
+}
+
+
+/// A pod as described by the API, along with the addresses it uses.
+#[derive(Clone, Debug, Eq, PartialEq)]
+struct PodState {
+    pub info: PodInfo,
+    pub addresses: Vec<IpAddr>,
+    pub start_time: Option<DateTime<Utc>>,
+    pub finished: bool, // pods which have run to completion have given up their addresses
+}
+
+
+/// Derives the name of the workload managing a pod from its owner. Pods of a deployment are owned
+/// by a replica set named after the deployment and the hash of the pod template.
+fn workload_name(pod: &Value) -> Option<String> {
+    let owner = pod["metadata"]["ownerReferences"].as_array()?
+        .iter()
+        .find(|o| o["controller"].as_bool().unwrap_or(false))?;
+    let owner_name = owner["name"].as_str()?;
+    if owner["kind"].as_str() == Some("ReplicaSet") {
+        if let Some(hash) = pod["metadata"]["labels"]["pod-template-hash"].as_str() {
+            if let Some(deployment) = owner_name.strip_suffix(hash).and_then(|n| n.strip_suffix('-')) {
+                return Some(deployment.to_owned());
+            }
+        }
+    }
+    Some(owner_name.to_owned())
+}
+
+
+/// Reads the state of a pod. Pods sharing the network of their node are skipped, as their address
+/// is that of the node.
+fn parse_pod(pod: &Value) -> Option<PodState> {
+    if pod["spec"]["hostNetwork"].as_bool().unwrap_or(false) {
+        return None;
+    }
+    let (namespace, name) = match (pod["metadata"]["namespace"].as_str(), pod["metadata"]["name"].as_str()) {
+        (Some(ns), Some(n)) => (ns, n),
+        _ => return None,
+    };
+
+    // dual-stack pods have one address per family; the first one is also the pod IP
+    let mut addresses = Vec::new();
+    let address_strings = pod["status"]["podIPs"].as_array().into_iter().flatten()
+        .filter_map(|entry| entry["ip"].as_str())
+        .chain(pod["status"]["podIP"].as_str());
+    for address in address_strings {
+        if let Ok(ip) = address.parse() {
+            if !addresses.contains(&ip) {
+                addresses.push(ip);
+            }
+        }
+    }
+
+    let start_time = pod["status"]["startTime"].as_str()
+        .and_then(|t| DateTime::parse_from_rfc3339(t).ok())
+        .map(|t| t.with_timezone(&Utc));
+    let phase = pod["status"]["phase"].as_str();
+    Some(PodState {
+        info: PodInfo {
+            namespace: namespace.to_owned(),
+            name: name.to_owned(),
+            workload: workload_name(pod),
+        },
+        addresses,
+        start_time,
+        finished: phase == Some("Succeeded") || phase == Some("Failed"),
+    })
+}
+
+
+fn parse_pod_list(pod_list: &Value) -> Result<Vec<PodState>, KubernetesError> {
+    let pods = pod_list["items"].as_array()
+        .ok_or_else(|| KubernetesError::InvalidPodList("no items".to_owned()))?;
+    Ok(pods.iter().filter_map(parse_pod).collect())
+}
+
+
+/// Returns the resource version of a list or an object, from which changes can be watched.
+fn resource_version(object: &Value) -> Option<&str> {
+    object["metadata"]["resourceVersion"].as_str()
+        .filter(|v| v.len() > 0)
+}
+
+
+/// Returns the token for fetching the next page of a pod list, if there are more pods.
+fn continue_token(pod_list: &Value) -> Option<&str> {
+    pod_list["metadata"]["continue"].as_str()
+        .filter(|t| t.len() > 0)
+}
+
+
+/// Percent-encodes a value for use in a query string.
+fn encode_query_value(value: &str) -> String {
+    let mut encoded = String::with_capacity(value.len());
+    for b in value.bytes() {
+        if b.is_ascii_alphanumeric() || b"-._~".contains(&b) {
+            encoded.push(char::from(b));
+        } else {
+            encoded.push_str(&format!("%{:02X}", b));
+        }
+    }
+    encoded
+}
+
+
+/// Returns how long to wait before trying again after the given number of failures in a row; the
+/// delay doubles each time up to a limit.
+fn retry_delay(consecutive_failures: u32) -> Duration {
+    let factor = 1u64.checked_shl(consecutive_failures.saturating_sub(1)).unwrap_or(u64::MAX);
+    let secs = MIN_RETRY_DELAY_SECS.saturating_mul(factor).min(MAX_RETRY_DELAY_SECS);
+    Duration::from_secs(secs)
+}
+
+
+/// The time during which a pod used an address; a lease without an end is still in use.
+#[derive(Clone, Debug, Eq, PartialEq)]
+struct Lease {
+    pub pod: PodInfo,
+    pub start: DateTime<Utc>,
+    pub end: Option<DateTime<Utc>>,
+}
+impl Lease {
+    fn is_current_for(&self, pod: &PodInfo) -> bool {
+        self.end.is_none() && self.pod.namespace == pod.namespace && self.pod.name == pod.name
+    }
+}
+
+
+/// Knows which pod used which address when.
+///
+/// Addresses are handed on to new pods once a pod has ended, so the leases of every pod which has
+/// used an address are kept, ordered by their start.
+#[derive(Debug, Default)]
+pub struct PodDirectory {
+    ip_to_leases: HashMap<IpAddr, Vec<Lease>>,
+}
+impl PodDirectory {
+    pub fn new() -> Self {
+        Self {
+            ip_to_leases: HashMap::new(),
+        }
+    }
+
+    /// Records the addresses a pod uses as of the given time; pods which have been deleted or have
+    /// finished no longer use any.
+    fn update(&mut self, pod: &PodState, deleted: bool, now: DateTime<Utc>) {
+        for address in &pod.addresses {
+            if deleted || pod.finished {
+                let current_lease = self.ip_to_leases.get_mut(address)
+                    .and_then(|leases| leases.iter_mut().find(|l| l.is_current_for(&pod.info)));
+                if let Some(lease) = current_lease {
+                    lease.end = Some(now);
+                }
+                continue;
+            }
+
+            let leases = self.ip_to_leases.entry(*address).or_default();
+            if leases.iter().any(|l| l.is_current_for(&pod.info)) {
+                continue;
+            }
+
+            // a pod may have been running for a while by the time we learn about it
+            let start = pod.start_time
+                .map(|t| t.min(now))
+                .unwrap_or(now);
+
+            // whichever pod used the address before has given it up
+            for lease in leases.iter_mut().filter(|l| l.end.is_none()) {
+                lease.end = Some(start.max(lease.start));
+            }
+            let index = leases.partition_point(|l| l.start <= start);
+            leases.insert(index, Lease {
+                pod: pod.info.clone(),
+                start,
+                end: None,
+            });
+        }
+    }
+
+    /// Records the pods of a complete listing as of the given time; pods which are no longer
+    /// listed have been deleted since the previous listing or watch.
+    fn update_all(&mut self, pods: &[PodState], now: DateTime<Utc>) {
+        for pod in pods {
+            self.update(pod, false, now);
+        }
+
+        let listed: HashSet<(IpAddr, &str, &str)> = pods.iter()
+            .filter(|p| !p.finished)
+            .flat_map(|p| p.addresses.iter().map(move |a| (*a, p.info.namespace.as_str(), p.info.name.as_str())))
+            .collect();
+        for (address, leases) in &mut self.ip_to_leases {
+            for lease in leases.iter_mut().filter(|l| l.end.is_none()) {
+                let still_listed = listed.contains(&(*address, lease.pod.namespace.as_str(), lease.pod.name.as_str()));
+                if !still_listed {
+                    lease.end = Some(now);
+                }
+            }
+        }
+    }
+
+    /// Returns the pod which used the address at the given time.
+    ///
+    /// When the address was unused at the time, this is the pod which used it last before then or,
+    /// if the time is before any known pod used it (as when replaying a capture), the first one.
+    pub fn pod_at(&self, address: IpAddr, timestamp: DateTime<Utc>) -> Option<&PodInfo> {
+        let leases = self.ip_to_leases.get(&address)?;
+
+        // leases may overlap while the address is handed on, in which case the newer pod wins
+        leases.iter().rev()
+            .find(|l| l.start <= timestamp)
+            .or(leases.first())
+            .map(|l| &l.pod)
+    }
+
+    /// Applies a watch event, returning the resource version from which to continue watching or
+    /// `None` if the watch has expired and the pods must be listed again.
+    fn apply_event(&mut self, event: &Value, now: DateTime<Utc>) -> Result<Option<String>, KubernetesError> {
+        let object = &event["object"];
+        match event["type"].as_str() {
+            Some(event_type @ ("ADDED" | "MODIFIED" | "DELETED")) => {
+                if let Some(pod) = parse_pod(object) {
+                    self.update(&pod, event_type == "DELETED", now);
+                }
+            },
+            Some("BOOKMARK") => {},
+            Some("ERROR") if object["code"].as_i64() == Some(STATUS_GONE) => return Ok(None),
+            Some("ERROR") => {
+                let message = object["message"].as_str().unwrap_or("unknown error");
+                return Err(KubernetesError::WatchFailed(message.to_owned()));
+            },
+            _ => return Err(KubernetesError::InvalidWatchEvent(format!("unknown type {}", event["type"]))),
+        }
+        match resource_version(object) {
+            Some(version) => Ok(Some(version.to_owned())),
+            None => Err(KubernetesError::InvalidWatchEvent("no resource version".to_owned())),
+        }
+    }
+}
+
+
+/// Where the Kubernetes API is and how to authenticate to it.
+#[derive(Clone, Debug)]
+pub struct KubernetesApi {
+    url: HttpUrl,
+    service_account: bool,
+}
+impl KubernetesApi {
+    /// Accesses the API without authenticating, such as through `kubectl proxy`, which takes care
+    /// of it.
+    pub fn new(url: HttpUrl) -> Self {
+        Self {
+            url,
+            service_account: false,
+        }
+    }
+
+    /// Accesses the API of the cluster we are running in as the service account of our pod,
+    /// verifying the server against the cluster's CA. Unless a URL is given, the API is found
+    /// using the environment variables set in every pod.
+    pub fn in_cluster(url: Option<HttpUrl>) -> Result<Self, KubernetesError> {
+        let url = match url {
+            Some(u) => u,
+            None => {
+                let (host, port) = match (env::var("KUBERNETES_SERVICE_HOST"), env::var("KUBERNETES_SERVICE_PORT")) {
+                    (Ok(h), Ok(p)) => (h, p),
+                    _ => return Err(KubernetesError::NotInCluster),
+                };
+                let host = if host.contains(':') { format!("[{}]", host) } else { host };
+                HttpUrl::parse(&format!("https://{}:{}/", host, port))?
+            },
+        };
+        if !url.tls {
+            warn!("the Kubernetes service account token will be sent unencrypted");
+        }
+        Ok(Self {
+            url,
+            service_account: true,
+        })
+    }
+
+    fn url(&self, path_and_query: &str) -> HttpUrl {
+        HttpUrl {
+            path: format!("{}{}", self.url.path.trim_end_matches('/'), path_and_query),
+            ..self.url.clone()
+        }
+    }
+
+    /// Returns the `Authorization` header and the CA certificates to use, if any. The files are read
+    /// for every request as the token is rotated regularly.
+    fn credentials(&self) -> Result<(Option<String>, Option<Vec<u8>>), KubernetesError> {
+        if !self.service_account {
+            return Ok((None, None));
+        }
+        let token = fs::read_to_string(SERVICE_ACCOUNT_TOKEN_PATH)
+            .map_err(KubernetesError::ServiceAccount)?;
+        let ca_certificates = fs::read(SERVICE_ACCOUNT_CA_PATH)
+            .map_err(KubernetesError::ServiceAccount)?;
+        Ok((Some(format!("Bearer {}", token.trim())), Some(ca_certificates)))
+    }
+
+    async fn request(&self, path_and_query: &str) -> Result<Vec<u8>, KubernetesError> {
+        let (authorization, ca_certificates) = self.credentials()?;
+        let headers: Vec<(&str, &str)> = authorization.iter()
+            .map(|a| ("Authorization", a.as_str()))
+            .collect();
+        Ok(get(&self.url(path_and_query), &headers, ca_certificates.as_deref()).await?)
+    }
+
+    async fn watch(&self, path_and_query: &str) -> Result<ResponseLines, KubernetesError> {
+        let (authorization, ca_certificates) = self.credentials()?;
+        let headers: Vec<(&str, &str)> = authorization.iter()
+            .map(|a| ("Authorization", a.as_str()))
+            .collect();
+        Ok(get_lines(&self.url(path_and_query), &headers, ca_certificates.as_deref()).await?)
+    }
+}
+
+
+/// Lists the pods of all namespaces page by page, returning them along with the resource version
+/// of the listing.
+async fn list_pods(api: &KubernetesApi) -> Result<(Vec<PodState>, String), KubernetesError> {
+    let list_path = format!("{}?limit={}", PODS_PATH, PODS_PER_PAGE);
+    let mut path = list_path.clone();
+    let mut pods = Vec::new();
+    let mut version = None;
+    loop {
+        let body = api.request(&path).await?;
+        let pod_list: Value = serde_json::from_slice(&body)
+            .map_err(|e| KubernetesError::InvalidPodList(e.to_string()))?;
+        pods.extend(parse_pod_list(&pod_list)?);
+
+        // the pages are all taken from the version of the first one
+        if version.is_none() {
+            version = resource_version(&pod_list).map(|v| v.to_owned());
+        }
+
+        // the token expires after a few minutes, in which case the listing fails as a whole
+        match continue_token(&pod_list) {
+            Some(token) => path = format!("{}&continue={}", list_path, encode_query_value(token)),
+            None => break,
+        }
+    }
+    match version {
+        Some(v) => Ok((pods, v)),
+        None => Err(KubernetesError::InvalidPodList("no resource version".to_owned())),
+    }
+}
+
+
+/// Watches the pods for changes from the given resource version until the server ends the watch.
+///
+/// Returns the resource version from which to resume watching, or `None` if the pods must be
+/// listed again.
+async fn watch_pods(api: &KubernetesApi, directory: &Mutex<PodDirectory>, mut version: String) -> Result<Option<String>, KubernetesError> {
+    let path = format!(
+        "{}?watch=1&allowWatchBookmarks=true&timeoutSeconds={}&resourceVersion={}",
+        PODS_PATH, WATCH_TIMEOUT_SECS, encode_query_value(&version),
+    );
+    let mut lines = api.watch(&path).await?;
+    let idle_timeout = Duration::from_secs(WATCH_IDLE_TIMEOUT_SECS);
+    loop {
+        let line = match tokio::time::timeout(idle_timeout, lines.next_line()).await {
+            Ok(Ok(Some(l))) => l,
+            Ok(Ok(None)) => return Ok(Some(version)),
+            Ok(Err(e)) => return Err(e.into()),
+            Err(_) => return Err(HttpError::Timeout.into()),
+        };
+        if line.len() == 0 {
+            continue;
+        }
+        let event: Value = serde_json::from_slice(&line)
+            .map_err(|e| KubernetesError::InvalidWatchEvent(e.to_string()))?;
+        match directory.lock().unwrap().apply_event(&event, Utc::now())? {
+            Some(v) => version = v,
+            None => return Ok(None),
+        }
+    }
+}
+
+
+/// Watches the pods, listing them again whenever the watch cannot be resumed, such as after a
+/// connection failure.
+async fn run_watcher(api: KubernetesApi, directory: Arc<Mutex<PodDirectory>>, mut version: Option<String>) {
+    let mut consecutive_failures = 0;
+    loop {
+        let result = match version.take() {
+            Some(v) => watch_pods(&api, &directory, v).await,
+            None => list_pods(&api).await
+                .map(|(pods, v)| {
+                    directory.lock().unwrap().update_all(&pods, Utc::now());
+                    Some(v)
+                }),
+        };
+        match result {
+            Ok(v) => {
+                version = v;
+                consecutive_failures = 0;
+            },
+            Err(e) => {
+                consecutive_failures += 1;
+                warn!("failed to watch Kubernetes pods: {}", e);
+                tokio::time::sleep(retry_delay(consecutive_failures)).await;
+            },
+        }
+    }
+}
+
+
+/// Keeps track of the pods and the addresses they use during the sample by watching them through
+/// the Kubernetes API.
+pub struct PodWatcher {
+    directory: Arc<Mutex<PodDirectory>>,
+    task: tokio::task::JoinHandle<()>,
+}
+impl PodWatcher {
+    /// Lists the pods and starts watching them for changes. Must be called from within the Tokio
+    /// runtime.
+    pub async fn start(api: KubernetesApi) -> Self {
+        let directory = Arc::new(Mutex::new(PodDirectory::new()));
+        let version = match list_pods(&api).await {
+            Ok((pods, version)) => {
+                directory.lock().unwrap().update_all(&pods, Utc::now());
+                Some(version)
+            },
+            Err(e) => {
+                error!("failed to list Kubernetes pods: {}", e);
+                None
+            },
+        };
+        let task = tokio::spawn(run_watcher(api, Arc::clone(&directory), version));
+        Self {
+            directory,
+            task,
+        }
+    }
+
+    /// Stops watching and returns what has been learned about the pods.
+    pub async fn finish(self) -> PodDirectory {
+        self.task.abort();
+        let _ = self.task.await;
+        let directory = Arc::try_unwrap(self.directory)
+            .expect("pod directory still in use")
+            .into_inner()
+            .unwrap();
+        debug!("{} pod addresses known", directory.ip_to_leases.len());
+        directory
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use std::net::IpAddr;
+
+    use chrono::{Duration, TimeZone, Utc};
+    use serde_json::json;
+
+    use super::{continue_token, encode_query_value, parse_pod, parse_pod_list, PodDirectory, PodInfo};
+
+    #[test]
+    fn test_parse_pod_list() {
+        let pod_list = json!({
+            "kind": "PodList",
+            "items": [
+                {
+                    "metadata": {
+                        "name": "web-5d4f8c7b9-x2x7q",
+                        "namespace": "shop",
+                        "labels": {"app": "web", "pod-template-hash": "5d4f8c7b9"},
+                        "ownerReferences": [{"kind": "ReplicaSet", "name": "web-5d4f8c7b9", "controller": true}],
+                    },
+                    "spec": {},
+                    "status": {"podIP": "10.244.1.7", "podIPs": [{"ip": "10.244.1.7"}, {"ip": "fd00:10:244:1::7"}]},
+                },
+                {
+                    "metadata": {
+                        "name": "db-0",
+                        "namespace": "shop",
+                        "ownerReferences": [{"kind": "StatefulSet", "name": "db", "controller": true}],
+                    },
+                    "spec": {},
+                    "status": {"phase": "Running", "podIP": "10.244.2.3", "startTime": "2022-10-01T12:00:00Z"},
+                },
+                {
+                    "metadata": {"name": "kube-proxy-abcde", "namespace": "kube-system"},
+                    "spec": {"hostNetwork": true},
+                    "status": {"podIP": "192.0.2.10"},
+                },
+                {
+                    "metadata": {"name": "pending", "namespace": "default"},
+                    "spec": {},
+                    "status": {},
+                },
+            ],
+        });
+        let pods = parse_pod_list(&pod_list).unwrap();
+        assert_eq!(pods.len(), 3);
+
+        let web = PodInfo { namespace: "shop".to_owned(), name: "web-5d4f8c7b9-x2x7q".to_owned(), workload: Some("web".to_owned()) };
+        assert_eq!(pods[0].info, web);
+        let web_addresses: Vec<IpAddr> = vec!["10.244.1.7".parse().unwrap(), "fd00:10:244:1::7".parse().unwrap()];
+        assert_eq!(pods[0].addresses, web_addresses);
+        assert_eq!(pods[1].info.workload.as_deref(), Some("db"));
+        assert_eq!(pods[1].start_time, Some(Utc.with_ymd_and_hms(2022, 10, 1, 12, 0, 0).unwrap()));
+        assert!(!pods[1].finished);
+
+        // the pending pod has no address yet
+        assert_eq!(pods[2].info.name, "pending");
+        assert_eq!(pods[2].addresses.len(), 0);
+
+        assert!(parse_pod_list(&json!({})).is_err());
+    }
+
+    #[test]
+    fn test_continue_token() {
+        assert_eq!(continue_token(&json!({"metadata": {"continue": "eyJ2IjoibWV0YS5rOHMuaW8vdjEifQ=="}})), Some("eyJ2IjoibWV0YS5rOHMuaW8vdjEifQ=="));
+        assert_eq!(continue_token(&json!({"metadata": {"continue": ""}})), None);
+        assert_eq!(continue_token(&json!({"metadata": {}})), None);
+        assert_eq!(encode_query_value("eyJ2I+o/k=="), "eyJ2I%2Bo%2Fk%3D%3D");
+    }
+
+    #[test]
+    fn test_address_reuse() {
+        let address = "10.244.1.7".parse().unwrap();
+        let start = Utc.with_ymd_and_hms(2022, 10, 1, 12, 0, 0).unwrap();
+        let pod = |name: &str, phase: &str| parse_pod(&json!({
+            "metadata": {"name": name, "namespace": "shop"},
+            "spec": {},
+            "status": {"phase": phase, "podIP": "10.244.1.7"},
+        })).unwrap();
+
+        let mut directory = PodDirectory::new();
+        directory.update_all(&[pod("first", "Running")], start);
+        directory.update(&pod("first", "Running"), true, start + Duration::seconds(10));
+        directory.update(&pod("second", "Running"), false, start + Duration::seconds(20));
+
+        // the second pod does not take over what the first one did
+        let pod_name_at = |directory: &PodDirectory, secs| directory.pod_at(address, start + Duration::seconds(secs))
+            .map(|p| p.name.clone());
+        assert_eq!(pod_name_at(&directory, -5).as_deref(), Some("first"));
+        assert_eq!(pod_name_at(&directory, 5).as_deref(), Some("first"));
+        assert_eq!(pod_name_at(&directory, 15).as_deref(), Some("first"));
+        assert_eq!(pod_name_at(&directory, 25).as_deref(), Some("second"));
+
+        // a pod missing from a listing has ended, and a finished pod has given up its address
+        directory.update_all(&[], start + Duration::seconds(30));
+        directory.update(&pod("third", "Running"), false, start + Duration::seconds(40));
+        directory.update(&pod("third", "Succeeded"), false, start + Duration::seconds(50));
+        directory.update(&pod("fourth", "Running"), false, start + Duration::seconds(60));
+        assert_eq!(directory.ip_to_leases[&address].iter().filter(|l| l.end.is_none()).count(), 1);
+        assert_eq!(pod_name_at(&directory, 55).as_deref(), Some("third"));
+        assert_eq!(pod_name_at(&directory, 65).as_deref(), Some("fourth"));
+    }
+
+    #[test]
+    fn test_apply_event() {
+        let now = Utc.with_ymd_and_hms(2022, 10, 1, 12, 0, 0).unwrap();
+        let mut directory = PodDirectory::new();
+        let added = json!({
+            "type": "ADDED",
+            "object": {
+                "metadata": {"name": "db-0", "namespace": "shop", "resourceVersion": "1001"},
+                "spec": {},
+                "status": {"podIP": "10.244.2.3"},
+            },
+        });
+        assert_eq!(directory.apply_event(&added, now).unwrap().as_deref(), Some("1001"));
+        assert_eq!(directory.pod_at("10.244.2.3".parse().unwrap(), now).unwrap().name, "db-0");
+
+        let bookmark = json!({"type": "BOOKMARK", "object": {"metadata": {"resourceVersion": "1005"}}});
+        assert_eq!(directory.apply_event(&bookmark, now).unwrap().as_deref(), Some("1005"));
+
+        let expired = json!({"type": "ERROR", "object": {"kind": "Status", "code": 410, "message": "too old resource version"}});
+        assert_eq!(directory.apply_event(&expired, now).unwrap(), None);
+        let forbidden = json!({"type": "ERROR", "object": {"kind": "Status", "code": 403, "message": "forbidden"}});
+        assert!(directory.apply_event(&forbidden, now).is_err());
+    }
+}
@@ -0,0 +1,86 @@
+use std::collections::BTreeMap;
+use std::time::{Duration, Instant};
+
+use tracing::warn;
+
+
+/// Keeps repetitive warnings from flooding the log.
+///
+/// Only the first warning of each kind is logged within an interval; the repetitions are counted
+/// and logged as a summary once the interval is over.
+pub struct WarningLimiter {
+    interval: Duration,
+    interval_start: Option<Instant>,
+    kind_to_suppressed_count: BTreeMap<String, u64>,
+}
+impl WarningLimiter {
+    pub fn new(interval: Duration) -> Self {
+        Self {
+            interval,
+            interval_start: None,
+            kind_to_suppressed_count: BTreeMap::new(),
+        }
+    }
+
+    pub fn interval(&self) -> Duration {
+        self.interval
+    }
+
+    /// Returns whether a warning of the given kind should be logged now. If not, it is counted
+    /// towards the summary.
+    pub fn admit(&mut self, kind: &str, now: Instant) -> bool {
+        match self.interval_start {
+            Some(start) if now.duration_since(start) < self.interval => {},
+            _ => {
+                self.summarize();
+                self.interval_start = Some(now);
+            },
+        }
+
+        match self.kind_to_suppressed_count.get_mut(kind) {
+            Some(count) => {
+                *count += 1;
+                false
+            },
+            None => {
+                self.kind_to_suppressed_count.insert(kind.to_owned(), 0);
+                true
+            },
+        }
+    }
+
+    /// Logs how many warnings of each kind have been suppressed and starts over.
+    pub fn summarize(&mut self) {
+        for (kind, count) in &self.kind_to_suppressed_count {
+            if *count > 0 {
+                warn!("suppressed {} more warnings of the same kind: {}", count, kind);
+            }
+        }
+        self.kind_to_suppressed_count.clear();
+        self.interval_start = None;
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, Instant};
+
+    use super::WarningLimiter;
+
+    #[test]
+    fn test_admit() {
+        let start = Instant::now();
+        let mut limiter = WarningLimiter::new(Duration::from_secs(60));
+        assert!(limiter.admit("IPv4: too short", start));
+        assert!(!limiter.admit("IPv4: too short", start + Duration::from_secs(1)));
+        assert!(limiter.admit("UDP: incorrect checksum", start + Duration::from_secs(2)));
+        assert!(!limiter.admit("IPv4: too short", start + Duration::from_secs(3)));
+        assert_eq!(limiter.kind_to_suppressed_count["IPv4: too short"], 2);
+
+        // a new interval
+        assert!(limiter.admit("IPv4: too short", start + Duration::from_secs(61)));
+        assert_eq!(limiter.kind_to_suppressed_count["IPv4: too short"], 0);
+        assert!(!limiter.kind_to_suppressed_count.contains_key("UDP: incorrect checksum"));
+    }
+}
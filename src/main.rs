@@ -1,63 +1,344 @@
+mod anomaly;
+mod answer_watch;
+mod app_category;
+mod arp;
+mod blocklist;
 mod bytes;
+mod cast;
+mod capture;
+mod client_capture;
+mod comparison;
+mod correlation;
+mod decay;
+mod dhcp;
+mod dissect;
+mod dns;
+#[cfg(feature = "docker")] mod docker;
+mod edns;
 mod ethernet;
+#[cfg(feature = "event-store")] mod event_store;
+#[cfg(feature = "sinks")] mod exec_sink;
+mod fingerprint;
+mod flight_recorder;
+#[cfg(feature = "sinks")] mod gelf;
+#[cfg(feature = "http")] mod http;
+mod hyperloglog;
+mod icmp;
+mod interface;
 mod ip;
+#[cfg(feature = "sinks")] mod ipfix;
+mod json_log;
+#[cfg(feature = "kubernetes")] mod kubernetes;
+mod log_limit;
+#[cfg(feature = "http")] mod metrics;
+mod name_tree;
+#[cfg(feature = "nats")] mod nats;
+mod netns;
+mod nod;
 mod packet;
+mod packet_filter;
+#[cfg(feature = "passive-dns")] mod passive_dns;
+mod profile;
+mod qname_min;
+mod quantile;
+mod quarantine;
+mod record_type;
+#[cfg(feature = "sinks")] mod redis;
+#[cfg(feature = "http")] mod remote_write;
+mod report;
 mod sampling;
+mod savefile;
+mod scanner;
+#[cfg(feature = "scripting")] mod script;
+mod sink;
+mod stage_timer;
 mod stats;
+#[cfg(feature = "tcp-tracking")] mod tcp_connection;
 mod tcp_udp;
+mod tenant;
+#[cfg(feature = "http")] mod webhook;
+#[cfg(feature = "sinks")] mod zeek_log;
+#[cfg(feature = "tcp-tracking")] mod zone_transfer;
 
 
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::BufReader;
+use std::net::IpAddr;
+use std::path::{Path, PathBuf};
 use std::time::Duration;
 
-use clap::Parser;
-use pcap::Device;
+use clap::{Parser, Subcommand};
+use tracing::{error, info};
+#[cfg(feature = "http")] use tracing::warn;
 
-use crate::sampling::collect_sample;
+use crate::anomaly::AnomalyDetector;
+use crate::answer_watch::AnswerWatchlist;
+use crate::app_category::AppCategories;
+use crate::blocklist::{Blocklist, BlocklistSource};
+use crate::capture::{CaptureBackendKind, check_filter};
+use crate::client_capture::ClientCapture;
+use crate::comparison::InterfaceComparison;
+use crate::dhcp::DhcpTracker;
+use crate::dissect::DetailLevel;
+#[cfg(feature = "docker")] use crate::docker::ContainerDirectory;
+#[cfg(feature = "event-store")] use crate::event_store::EventStore;
+#[cfg(feature = "sinks")] use crate::exec_sink::ExecSink;
+use crate::flight_recorder::FlightRecorder;
+#[cfg(feature = "sinks")] use crate::gelf::{GelfSink, GelfTransport};
+#[cfg(feature = "http")] use crate::http::{basic_authorization, HttpUrl};
+#[cfg(feature = "sinks")] use crate::ipfix::IpfixExporter;
+use crate::interface::{InterfaceSelector, select_interface};
+use crate::json_log::{JsonLogFormat, JsonLogSink};
+#[cfg(feature = "kubernetes")] use crate::kubernetes::{KubernetesApi, PodWatcher};
+use crate::log_limit::WarningLimiter;
+#[cfg(feature = "http")] use crate::metrics::collect_samples;
+#[cfg(feature = "nats")] use crate::nats::NatsPublisher;
+use crate::netns::enter_netns;
+use crate::nod::NodTracker;
+use crate::packet::Precision;
+#[cfg(feature = "passive-dns")] use crate::passive_dns::PassiveDnsStore;
+use crate::profile::ProfileSpec;
+use crate::qname_min::QnameMinimizationTracker;
+use crate::quarantine::MalformedQuarantine;
+use crate::record_type::parse_record_type;
+#[cfg(feature = "sinks")] use crate::redis::RedisSink;
+#[cfg(feature = "http")] use crate::remote_write::push_remote_write;
+use crate::report::{DEFAULT_REPORT_QUANTILES, ReportCounts, ReportFormat, write_csv_tables, write_diff, write_report};
+use crate::sampling::{collect_sample, DEFAULT_LARGE_RESPONSE_BYTES, list_devices, replay_file, SampleContext};
+#[cfg(feature = "scripting")] use crate::script::ScriptHook;
+#[cfg(feature = "http")] use crate::webhook::WebhookNotifier;
+#[cfg(feature = "sinks")] use crate::zeek_log::ZeekLogSink;
+
+
+// DNS plus the ICMP/ICMPv6 errors that might concern it; like the dissector, the filter expects the
+// transport header right after the fixed IPv6 header, so packets with extension headers are missed
+const CAPTURE_FILTER: &str = "udp port 53 or tcp port 53 or icmp[icmptype] == icmp-unreach or (icmp6 and (ip6[40] == 1 or ip6[40] == 2))";
+const NEIGHBOR_CAPTURE_FILTER: &str = "arp or (icmp6 and (ip6[40] == 135 or ip6[40] == 136))";
+const DHCP_CAPTURE_FILTER: &str = "udp port 67 or udp port 68";
+
+// the labels the exporter gives to the metrics itself, which a global label would duplicate
+const RESERVED_LABEL_NAMES: [&str; 46] = [
+    "aaaa", "cast", "category", "class", "client", "code", "container", "cookie", "df", "dropped_by",
+    "dscp", "ecn", "error", "fingerprint", "first", "flag", "interface", "kind", "label", "layer",
+    "le", "member", "message", "metric", "name", "namespace", "nsid", "opcode", "option", "outcome",
+    "pod", "profile", "qtype", "quantile", "rcode", "reason", "server", "stage", "tenant", "tld",
+    "transport", "type", "use", "value", "workload", "zone",
+];
+
+// environment variables usually filled from the Kubernetes downward API, with the labels they
+// become; POD_NAMESPACE takes precedence over NAMESPACE
+const ENVIRONMENT_LABELS: [(&str, &str); 4] = [
+    ("NODE_NAME", "node_name"),
+    ("POD_NAME", "pod_name"),
+    ("POD_NAMESPACE", "pod_namespace"),
+    ("NAMESPACE", "pod_namespace"),
+];
 
 
 #[derive(Parser)]
+#[clap(args_conflicts_with_subcommands = true)]
 struct Opts {
+    #[clap(subcommand)] command: Option<Command>,
     interface_index: Option<usize>,
+    #[clap(long, value_parser, conflicts_with = "interface-index")] interface: Option<InterfaceSelector>,
     #[clap(default_value = "32")] buffer_size: usize,
     #[clap(default_value = "60")] sample_secs: u64,
+    #[clap(long = "sanctioned-resolver")] sanctioned_resolvers: Vec<IpAddr>,
+    /// Loads a blocklist from a file or, with the http feature, from an http:// or https:// URL.
+    #[clap(long = "blocklist", value_parser)] blocklists: Vec<BlocklistSource>,
+    /// How often the blocklists are loaded again during a sample if any of them is fetched from a URL.
+    #[cfg(feature = "http")] #[clap(long, value_parser = clap::value_parser!(u64).range(1..), default_value = "3600")] blocklist_refresh_secs: u64,
+    #[clap(long)] answer_watchlist: Option<PathBuf>,
+    #[clap(long = "app-categories")] app_category_files: Vec<PathBuf>,
+    #[clap(long)] no_default_app_categories: bool,
+    #[clap(long = "watch-zone")] watched_zones: Vec<String>,
+    #[clap(long = "tenant")] tenants: Vec<String>,
+    #[clap(long = "profile")] profiles: Vec<String>,
+    #[clap(long)] nod_days: Option<u32>,
+    #[clap(long)] nod_state: Option<PathBuf>,
+    #[clap(long)] nod_log: Option<PathBuf>,
+    #[clap(long)] qname_minimization_window_secs: Option<i64>,
+    #[clap(long)] aggregate_answer_addresses: bool,
+    #[clap(long, default_value = "5")] correlation_window_secs: i64,
+    #[clap(long)] nanosecond_timestamps: bool,
+    #[clap(long, value_enum, default_value_t = CaptureBackendKind::DEFAULT)] backend: CaptureBackendKind,
+    #[clap(long)] netns: Option<String>,
+    #[clap(long)] track_neighbors: bool,
+    #[clap(long)] track_dhcp: bool,
+    #[clap(long)] compare_interface: Option<usize>,
+    #[clap(long = "extra-interface")] extra_interface_indexes: Vec<usize>,
+    /// Reads a libpcap capture filter expression (as taken by tcpdump) from the given file, which
+    /// may span several lines and contain comment lines starting with `#`.
+    ///
+    /// Replaces the default capture filter, also when replaying capture files for a diff. Neither the default filter nor the dissector look past
+    /// IPv6 extension headers, so IPv6 DNS and ICMPv6 packets carrying them (including fragments)
+    /// are not counted.
+    #[clap(long = "filter-file", value_parser = parse_filter_file, global = true)] dns_filter: Option<String>,
+    #[clap(long)] merge_interfaces: bool,
+    #[clap(long)] expand_members: bool,
+    #[clap(long = "label", value_parser = parse_label)] global_labels: Vec<(String, String)>,
+    #[clap(long)] labels_from_env: bool,
+    #[clap(long = "quantile", value_parser = parse_quantile)] quantiles: Vec<f64>,
+    #[clap(long = "server-latency-bound-ms")] server_latency_bounds_ms: Vec<u64>,
+    #[clap(long, default_value = "50")] server_latency_label_sets: usize,
+    #[clap(long, default_value_t = DEFAULT_LARGE_RESPONSE_BYTES)] large_response_bytes: usize,
+    #[clap(long = "deprecated-type", value_parser = parse_deprecated_type)] deprecated_types: Vec<u16>,
+    #[clap(long, value_parser = clap::value_parser!(u32).range(1..))] anomaly_interval_secs: Option<u32>,
+    #[clap(long, default_value = "4")] anomaly_threshold: f64,
+    #[cfg(feature = "http")] #[clap(long)] webhook_url: Option<String>,
+    #[cfg(feature = "http")] #[clap(long, default_value = "300")] webhook_dedup_secs: u32,
+    #[cfg(feature = "http")] #[clap(long)] remote_write_url: Option<String>,
+    #[cfg(feature = "http")] #[clap(long, requires = "remote-write-url")] remote_write_username: Option<String>,
+    #[cfg(feature = "http")] #[clap(long = "remote-write-password-file", value_parser = parse_secret_file, requires = "remote-write-username")] remote_write_password: Option<String>,
+    #[cfg(feature = "http")] #[clap(long = "remote-write-bearer-token-file", value_parser = parse_secret_file, requires = "remote-write-url", conflicts_with = "remote-write-username")] remote_write_bearer_token: Option<String>,
+    #[cfg(feature = "sinks")] #[clap(long)] redis_address: Option<String>,
+    #[cfg(feature = "sinks")] #[clap(long, default_value = "dns")] redis_key_prefix: String,
+    #[cfg(feature = "sinks")] #[clap(long, default_value = "3600")] redis_ttl_secs: u64,
+    #[cfg(feature = "sinks")] #[clap(long)] gelf_address: Option<String>,
+    #[cfg(feature = "sinks")] #[clap(long)] gelf_tcp: bool,
+    #[cfg(feature = "sinks")] #[clap(long, default_value = "dns-sniff-exporter")] gelf_host: String,
+    #[clap(long)] json_log: Option<PathBuf>,
+    #[clap(long, value_enum, default_value = "plain")] json_log_format: JsonLogFormat,
+    #[cfg(feature = "sinks")] #[clap(long)] exec_sink: Option<String>,
+    #[cfg(feature = "sinks")] #[clap(long, value_enum, default_value = "plain")] exec_sink_format: JsonLogFormat,
+    #[cfg(feature = "sinks")] #[clap(long)] zeek_log: Option<PathBuf>,
+    #[cfg(feature = "sinks")] #[clap(long)] ipfix_collector: Option<String>,
+    #[cfg(feature = "sinks")] #[clap(long, default_value = "0")] ipfix_observation_domain: u32,
+    #[cfg(feature = "sinks")] #[clap(long, default_value = "32473")] ipfix_enterprise_number: u32,
+    #[clap(long)] flight_recorder_dir: Option<PathBuf>,
+    #[clap(long, default_value = "30")] flight_recorder_window_secs: u32,
+    #[clap(long, default_value = "30")] flight_recorder_post_secs: u32,
+    #[clap(long)] malformed_quarantine_dir: Option<PathBuf>,
+    #[clap(long, default_value = "10")] malformed_quarantine_per_minute: u32,
+    #[clap(long, default_value = "60")] warning_summary_secs: u64,
+    #[clap(long)] no_adaptive_detail: bool,
+    #[clap(long = "client-capture", value_parser = parse_client_capture)] client_captures: Vec<(IpAddr, u32)>,
+    #[clap(long, default_value = ".")] client_capture_dir: PathBuf,
+    #[clap(long)] read_file: Option<PathBuf>,
+    #[clap(long, value_enum)] report: Option<ReportFormat>,
+    #[clap(long)] csv_out: Option<PathBuf>,
+    #[cfg(feature = "event-store")] #[clap(long)] event_store: Option<PathBuf>,
+    #[cfg(feature = "nats")] #[clap(long)] nats_address: Option<String>,
+    #[cfg(feature = "nats")] #[clap(long, default_value = "dns")] nats_subject_prefix: String,
+    #[cfg(feature = "event-store")] #[clap(long, default_value = "168")] event_retention_hours: u32,
+    #[cfg(feature = "passive-dns")] #[clap(long)] pdns_store: Option<PathBuf>,
+    #[cfg(feature = "passive-dns")] #[clap(long, default_value = "1000000")] pdns_max_records: u64,
+    #[cfg(feature = "passive-dns")] #[clap(long, requires = "pdns-store")] pdns_lookup: Option<String>,
+    #[cfg(feature = "scripting")] #[clap(long)] classify_script: Option<PathBuf>,
+    #[cfg(feature = "docker")] #[clap(long)] docker: bool,
+    #[cfg(feature = "docker")] #[clap(long, default_value = "/var/run/docker.sock")] docker_socket: PathBuf,
+    #[cfg(feature = "docker")] #[clap(long, value_parser = clap::value_parser!(u64).range(1..), default_value = "30")] docker_refresh_secs: u64,
+    /// The Kubernetes API, e.g. as served by a proxy such as `kubectl proxy`, which authenticates the requests.
+    #[cfg(feature = "kubernetes")] #[clap(long)] kubernetes_api: Option<String>,
+    /// Authenticate to the Kubernetes API as the service account of the pod we are running in, trusting the cluster's CA.
+    /// Unless --kubernetes-api is given, the API is found through the environment.
+    #[cfg(feature = "kubernetes")] #[clap(long)] kubernetes_in_cluster: bool,
 }
 
 
-fn hexdump(bs: &[u8]) {
-    let mut i = 0;
+#[derive(Subcommand)]
+enum Command {
+    /// Compares two capture files or JSON reports.
+    Diff {
+        before: PathBuf,
+        after: PathBuf,
+        #[clap(long, default_value = "5")] correlation_window_secs: i64,
+        #[clap(long)] nanosecond_timestamps: bool,
+    },
+}
 
-    while i < bs.len() {
-        print!("{:08x}  ", i);
-        for j in 0..16 {
-            if i + j < bs.len() {
-                print!("{:02x} ", bs[i + j]);
-            } else {
-                print!("   ");
-            }
 
-            if j == 8 {
-                print!(" ");
-            }
-        }
+/// Parses a global label given as `key=value`.
+fn parse_label(s: &str) -> Result<(String, String), String> {
+    let (key, value) = s.split_once('=')
+        .ok_or_else(|| format!("label {:?} is not of the form key=value", s))?;
+    let key_valid =
+        key.len() > 0
+        && key.chars().all(|c| c.is_ascii_alphanumeric() || c == '_')
+        && !key.starts_with(|c: char| c.is_ascii_digit());
+    if !key_valid {
+        return Err(format!("label name {:?} may only consist of letters, digits and underscores and may not start with a digit", key));
+    }
+    if key.starts_with("__") || RESERVED_LABEL_NAMES.contains(&key) {
+        return Err(format!("label name {:?} is reserved", key));
+    }
+    Ok((key.to_owned(), value.to_owned()))
+}
 
-        print!(" |");
 
-        for j in 0..16 {
-            if i + j >= bs.len() {
-                break;
-            }
+fn parse_quantile(s: &str) -> Result<f64, String> {
+    let quantile: f64 = s.parse()
+        .map_err(|e| format!("failed to parse quantile {:?}: {}", s, e))?;
+    if !(quantile > 0.0 && quantile < 1.0) {
+        return Err(format!("quantile {} is not between 0 and 1", quantile));
+    }
+    Ok(quantile)
+}
 
-            let b = bs[i + j];
-            if b >= 0x20 && b <= 0x7E {
-                print!("{}", b as char);
-            } else {
-                print!(".");
-            }
-        }
 
-        println!("|");
+fn parse_deprecated_type(s: &str) -> Result<u16, String> {
+    parse_record_type(s)
+        .ok_or_else(|| format!("unknown record type {:?}", s))
+}
+
+
+/// Parses a client capture request given as `address=seconds`.
+fn parse_client_capture(s: &str) -> Result<(IpAddr, u32), String> {
+    let (address_str, seconds_str) = s.split_once('=')
+        .ok_or_else(|| format!("client capture {:?} is not of the form address=seconds", s))?;
+    let address: IpAddr = address_str.parse()
+        .map_err(|e| format!("failed to parse client address {:?}: {}", address_str, e))?;
+    let seconds: u32 = seconds_str.parse()
+        .map_err(|e| format!("failed to parse capture duration {:?}: {}", seconds_str, e))?;
+    Ok((address, seconds))
+}
+
+
+/// Reads a capture filter from a file, skipping comment lines starting with `#`.
+fn parse_filter_file(s: &str) -> Result<String, String> {
+    let text = std::fs::read_to_string(s)
+        .map_err(|e| format!("failed to read capture filter file {:?}: {}", s, e))?;
+    let filter = text.lines()
+        .filter(|l| !l.trim_start().starts_with('#'))
+        .flat_map(|l| l.split_whitespace())
+        .collect::<Vec<&str>>()
+        .join(" ");
+    if filter.len() == 0 {
+        return Err(format!("capture filter file {:?} contains no filter", s));
+    }
+    check_filter(&filter)
+        .map_err(|e| format!("failed to compile capture filter from {:?}: {}", s, e))?;
+    Ok(filter)
+}
 
-        i += 16;
+
+/// Reads a password or token from a file, dropping the line break at its end.
+#[cfg(feature = "http")]
+fn parse_secret_file(s: &str) -> Result<String, String> {
+    let text = std::fs::read_to_string(s)
+        .map_err(|e| format!("failed to read secret file {:?}: {}", s, e))?;
+    Ok(text.trim_end_matches(['\r', '\n']).to_owned())
+}
+
+
+/// Loads the counts to compare from a JSON report or by replaying a capture file.
+fn load_report_counts(path: &Path, dns_filter: Option<&str>, correlation_window: chrono::Duration, precision: Precision) -> ReportCounts {
+    if path.extension().map(|e| e == "json").unwrap_or(false) {
+        let file = File::open(path)
+            .expect("failed to open report");
+        let reports: serde_json::Value = serde_json::from_reader(BufReader::new(file))
+            .expect("failed to parse report");
+        // compare the first report in the file, which is the one without a tenant
+        let report = reports.get(0)
+            .expect("report file contains no reports");
+        ReportCounts::from_json(report)
+            .expect("failed to read counts from report")
+    } else {
+        let mut context = SampleContext::new(correlation_window);
+        let samples = replay_file(path, Some(dns_filter.unwrap_or(CAPTURE_FILTER)), precision, &mut context)
+            .expect("failed to replay capture file");
+        ReportCounts::from_stats(&samples[0])
     }
 }
 
@@ -68,19 +349,73 @@ async fn main() {
     let (stdout_non_blocking, _guard) = tracing_appender::non_blocking::NonBlockingBuilder::default()
         .lossy(false)
         .finish(std::io::stdout());
+    #[cfg(not(feature = "tokio-console"))]
     tracing_subscriber::fmt()
         .with_env_filter(tracing_subscriber::EnvFilter::from_default_env())
         .with_writer(stdout_non_blocking)
         .init();
+    #[cfg(feature = "tokio-console")]
+    {
+        // the console needs the runtime's tracing events regardless of the log level
+        use tracing_subscriber::Layer;
+        use tracing_subscriber::layer::SubscriberExt;
+        use tracing_subscriber::util::SubscriberInitExt;
+        let fmt_layer = tracing_subscriber::fmt::layer()
+            .with_writer(stdout_non_blocking)
+            .with_filter(tracing_subscriber::EnvFilter::from_default_env());
+        tracing_subscriber::registry()
+            .with(console_subscriber::spawn())
+            .with(fmt_layer)
+            .init();
+    }
 
     // parse options
     let opts = Opts::parse();
 
+    if let Some(Command::Diff { before, after, correlation_window_secs, nanosecond_timestamps }) = &opts.command {
+        let correlation_window = chrono::Duration::seconds(*correlation_window_secs);
+        let precision = if *nanosecond_timestamps { Precision::Nano } else { Precision::Micro };
+        let before_counts = load_report_counts(before, opts.dns_filter.as_deref(), correlation_window, precision);
+        let after_counts = load_report_counts(after, opts.dns_filter.as_deref(), correlation_window, precision);
+        write_diff(&before_counts, &after_counts, &mut std::io::stdout().lock())
+            .expect("failed to write diff");
+        return;
+    }
+
+    // only answer the passive DNS lookup
+    #[cfg(feature = "passive-dns")]
+    if let (Some(store_path), Some(name)) = (&opts.pdns_store, &opts.pdns_lookup) {
+        let store = PassiveDnsStore::open(store_path, opts.pdns_max_records)
+            .expect("failed to open passive DNS store");
+        let records = store.lookup(name)
+            .expect("failed to look up name in passive DNS store");
+        for record in records {
+            println!("{}\t{}\t{}\t{}\t{}\t{}", record.name, record.record_type, record.rdata, record.first_seen, record.last_seen, record.count);
+        }
+        return;
+    }
+
     let interface_index = match opts.interface_index {
-        Some(ii) => ii,
+        Some(ii) => Some(ii),
+        None if opts.interface.is_some() => {
+            // the routes and addresses are those of the namespace being captured in
+            let netns_guard = enter_netns(opts.netns.as_deref())
+                .expect("failed to enter network namespace");
+            let device_list = list_devices(opts.backend)
+                .expect("failed to obtain device list");
+            let ii = select_interface(opts.interface.as_ref().unwrap(), &device_list)
+                .expect("failed to select interface");
+            drop(netns_guard);
+            info!("capturing on interface {}: {}", ii, device_list[ii].name);
+            Some(ii)
+        },
+        None if opts.read_file.is_some() => None,
         None => {
-            let device_list = Device::list()
+            let netns_guard = enter_netns(opts.netns.as_deref())
+                .expect("failed to enter network namespace");
+            let device_list = list_devices(opts.backend)
                 .expect("failed to obtain device list");
+            drop(netns_guard);
             for (i, device) in device_list.into_iter().enumerate() {
                 println!("{}: {}", i, device.desc.as_ref().map(|d| d.as_str()).unwrap_or(device.name.as_str()));
             }
@@ -88,13 +423,372 @@ async fn main() {
         },
     };
 
-    // run a single sniffing session
-    let sample = collect_sample(
-        interface_index,
-        Duration::from_secs(opts.sample_secs),
-        Some("udp port 53"),
-        Some(opts.buffer_size),
-    ).await
-        .expect("failed to collect sample");
-    println!("{:#?}", sample);
+    let mut context = SampleContext::new(chrono::Duration::seconds(opts.correlation_window_secs));
+    context.global_labels = opts.global_labels.iter().cloned().collect();
+    if opts.labels_from_env {
+        // labels given explicitly win
+        for (variable, label) in ENVIRONMENT_LABELS {
+            if let Some(value) = std::env::var(variable).ok().filter(|v| v.len() > 0) {
+                context.global_labels.entry(label.to_owned()).or_insert(value);
+            }
+        }
+    }
+    context.quantiles = opts.quantiles.clone();
+    if opts.report.is_some() && context.quantiles.len() == 0 {
+        context.quantiles = DEFAULT_REPORT_QUANTILES.to_vec();
+    }
+    let mut server_latency_bounds_ms = opts.server_latency_bounds_ms.clone();
+    server_latency_bounds_ms.sort_unstable();
+    server_latency_bounds_ms.dedup();
+    context.server_latency_bounds = server_latency_bounds_ms.into_iter()
+        .map(|ms| Duration::from_millis(ms))
+        .collect();
+    context.server_latency_label_sets = opts.server_latency_label_sets;
+    context.large_response_bytes = opts.large_response_bytes;
+    if opts.deprecated_types.len() > 0 {
+        context.deprecated_record_types = opts.deprecated_types.clone();
+    }
+    context.sanctioned_resolvers = opts.sanctioned_resolvers.clone();
+    context.watched_zones = opts.watched_zones.iter()
+        .map(|z| z.trim_end_matches('.').to_ascii_lowercase())
+        .collect();
+    for tenant in &opts.tenants {
+        context.tenants.add_rule(tenant)
+            .expect("failed to parse tenant");
+    }
+    context.aggregate_answer_addresses = opts.aggregate_answer_addresses;
+    if opts.track_neighbors {
+        context.neighbors = Some(HashMap::new());
+    }
+    if opts.track_dhcp {
+        context.dhcp_tracker = Some(DhcpTracker::new());
+    }
+    #[cfg(feature = "docker")]
+    if opts.docker {
+        context.container_directory = Some(ContainerDirectory::new(opts.docker_socket.clone(), Duration::from_secs(opts.docker_refresh_secs)));
+    }
+    context.interface_comparison = opts.compare_interface
+        .map(|i| InterfaceComparison::new(i, chrono::Duration::seconds(opts.correlation_window_secs)));
+    context.anomaly_detector = opts.anomaly_interval_secs
+        .map(|secs| AnomalyDetector::new(chrono::Duration::seconds(secs.into()), opts.anomaly_threshold));
+    #[cfg(feature = "http")]
+    if let Some(webhook_url) = &opts.webhook_url {
+        let url = HttpUrl::parse(webhook_url)
+            .expect("failed to parse webhook URL");
+        context.webhook = Some(WebhookNotifier::new(url, chrono::Duration::seconds(opts.webhook_dedup_secs.into())));
+    }
+
+    // set up the sinks for individual queries and responses
+    #[cfg(feature = "sinks")]
+    if let Some(address) = &opts.redis_address {
+        context.event_sinks.push(Box::new(RedisSink::new(address.clone(), opts.redis_key_prefix.clone(), opts.redis_ttl_secs)));
+    }
+    #[cfg(feature = "sinks")]
+    if let Some(address) = &opts.gelf_address {
+        let transport = if opts.gelf_tcp { GelfTransport::Tcp } else { GelfTransport::Udp };
+        context.event_sinks.push(Box::new(GelfSink::new(address.clone(), transport, opts.gelf_host.clone())));
+    }
+    if let Some(log_path) = &opts.json_log {
+        let sink = JsonLogSink::open(log_path, opts.json_log_format)
+            .expect("failed to open JSON event log");
+        context.event_sinks.push(Box::new(sink));
+    }
+    #[cfg(feature = "sinks")]
+    if let Some(command) = &opts.exec_sink {
+        context.event_sinks.push(Box::new(ExecSink::new(command.clone(), opts.exec_sink_format)));
+    }
+    #[cfg(feature = "sinks")]
+    if let Some(log_path) = &opts.zeek_log {
+        let sink = ZeekLogSink::open(log_path, chrono::Duration::seconds(opts.correlation_window_secs))
+            .expect("failed to open Zeek DNS log");
+        context.event_sinks.push(Box::new(sink));
+    }
+    #[cfg(feature = "sinks")]
+    if let Some(address) = &opts.ipfix_collector {
+        context.event_sinks.push(Box::new(IpfixExporter::new(address.clone(), opts.ipfix_observation_domain, opts.ipfix_enterprise_number)));
+    }
+    #[cfg(feature = "nats")]
+    if let Some(address) = &opts.nats_address {
+        context.event_sinks.push(Box::new(NatsPublisher::new(address.clone(), opts.nats_subject_prefix.clone())));
+    }
+    #[cfg(feature = "event-store")]
+    if let Some(store_path) = &opts.event_store {
+        let store = EventStore::open(store_path, chrono::Duration::hours(opts.event_retention_hours.into()))
+            .expect("failed to open event store");
+        context.event_sinks.push(Box::new(store));
+    }
+
+    // open the passive DNS store
+    #[cfg(feature = "passive-dns")]
+    if let Some(store_path) = &opts.pdns_store {
+        let store = PassiveDnsStore::open(store_path, opts.pdns_max_records)
+            .expect("failed to open passive DNS store");
+        context.passive_dns_store = Some(store);
+    }
+
+    // load the classification script
+    #[cfg(feature = "scripting")]
+    if let Some(script_path) = &opts.classify_script {
+        let hook = ScriptHook::load(script_path)
+            .expect("failed to load classification script");
+        context.script_hook = Some(hook);
+    }
+
+    // load blocklists
+    context.blocklist = Blocklist::load(&opts.blocklists).await
+        .expect("failed to load blocklists");
+    context.blocklist_sources = opts.blocklists.clone();
+    #[cfg(feature = "http")]
+    if opts.blocklists.iter().any(|s| s.is_url()) {
+        context.blocklist_refresh_interval = Some(Duration::from_secs(opts.blocklist_refresh_secs));
+    }
+
+    if let Some(path) = &opts.answer_watchlist {
+        let mut watchlist = AnswerWatchlist::new();
+        watchlist.load_file(path)
+            .expect("failed to load answer watchlist");
+        context.answer_watchlist = watchlist;
+    }
+
+    // files with application categories amend (or replace) the built-in ones
+    context.app_categories = if opts.no_default_app_categories { AppCategories::new() } else { AppCategories::with_defaults() };
+    for path in &opts.app_category_files {
+        context.app_categories.load_file(path)
+            .expect("failed to load application categories");
+    }
+
+    // prepare newly-observed-domain tracking
+    context.nod_tracker = opts.nod_days.map(|days| NodTracker::new(days));
+    if let Some(nt) = context.nod_tracker.as_mut() {
+        if let Some(state_path) = &opts.nod_state {
+            if state_path.exists() {
+                nt.load_state(state_path)
+                    .expect("failed to load newly-observed-domain state");
+            }
+        }
+        if let Some(log_path) = &opts.nod_log {
+            nt.open_log(log_path)
+                .expect("failed to open newly-observed-domain log");
+        }
+    }
+
+    // QNAME minimization is only recognizable on the resolvers' side facing the authoritative servers
+    context.qname_minimization = opts.qname_minimization_window_secs
+        .map(|secs| QnameMinimizationTracker::new(chrono::Duration::seconds(secs)));
+
+    context.warning_limiter = WarningLimiter::new(Duration::from_secs(opts.warning_summary_secs));
+    context.adaptive_detail = !opts.no_adaptive_detail;
+
+    // profiles share the capture and the configuration but keep their own statistics
+    for profile in &opts.profiles {
+        let spec: ProfileSpec = profile.parse()
+            .expect("failed to parse profile");
+        let mut profile_context = context.new_profile(spec.name.clone(), chrono::Duration::seconds(opts.correlation_window_secs));
+        profile_context.profile_selector = spec.selector;
+        if let Some(filter) = &spec.filter {
+            check_filter(filter)
+                .expect("failed to compile capture filter of profile");
+            profile_context.profile_filter = Some(filter.clone());
+        }
+        #[cfg(feature = "scripting")]
+        if let Some(script_path) = &opts.classify_script {
+            // each profile runs its own instance of the script
+            let hook = ScriptHook::load(script_path)
+                .expect("failed to load classification script");
+            profile_context.script_hook = Some(hook);
+        }
+        profile_context.aggregate_answer_addresses |= spec.aggregate_answer_addresses;
+        if spec.headers_only {
+            profile_context.detail_level = DetailLevel::HeadersOnly;
+            profile_context.adaptive_detail = false;
+        }
+        if let Some(log_path) = &spec.json_log {
+            let sink = JsonLogSink::open(log_path, opts.json_log_format)
+                .expect("failed to open JSON event log of profile");
+            profile_context.event_sinks.push(Box::new(sink));
+        }
+        context.profiles.push(profile_context);
+    }
+
+
+    // a custom filter replaces the one for DNS traffic, but not those for the auxiliary protocols
+    let mut capture_filter = match &opts.dns_filter {
+        Some(f) => format!("({})", f),
+        None => CAPTURE_FILTER.to_owned(),
+    };
+    if opts.track_neighbors {
+        capture_filter = format!("{} or {}", capture_filter, NEIGHBOR_CAPTURE_FILTER);
+    }
+    if opts.track_dhcp {
+        capture_filter = format!("{} or ({})", capture_filter, DHCP_CAPTURE_FILTER);
+    }
+
+    #[cfg(feature = "http")]
+    let remote_write_url = opts.remote_write_url.as_ref()
+        .map(|u| HttpUrl::parse(u).expect("failed to parse remote-write URL"));
+    #[cfg(feature = "http")]
+    let remote_write_authorization = match (&opts.remote_write_username, &opts.remote_write_bearer_token) {
+        (Some(username), _) => Some(basic_authorization(username, opts.remote_write_password.as_deref().unwrap_or(""))),
+        (None, Some(token)) => Some(format!("Bearer {}", token)),
+        (None, None) => None,
+    };
+    #[cfg(feature = "http")]
+    if remote_write_authorization.is_some() && remote_write_url.as_ref().map(|u| !u.tls).unwrap_or(false) {
+        warn!("remote-write credentials will be sent unencrypted");
+    }
+    #[cfg(feature = "kubernetes")]
+    let kubernetes_api_url = opts.kubernetes_api.as_ref()
+        .map(|u| HttpUrl::parse(u).expect("failed to parse Kubernetes API URL"));
+    #[cfg(feature = "kubernetes")]
+    let kubernetes_api = if opts.kubernetes_in_cluster {
+        Some(KubernetesApi::in_cluster(kubernetes_api_url).expect("failed to find the Kubernetes API"))
+    } else {
+        kubernetes_api_url.map(KubernetesApi::new)
+    };
+
+    let precision = if opts.nanosecond_timestamps { Precision::Nano } else { Precision::Micro };
+    context.flight_recorder = opts.flight_recorder_dir.as_ref()
+        .map(|dir| FlightRecorder::new(
+            chrono::Duration::seconds(opts.flight_recorder_window_secs.into()),
+            chrono::Duration::seconds(opts.flight_recorder_post_secs.into()),
+            dir.clone(),
+            precision,
+        ));
+    context.malformed_quarantine = opts.malformed_quarantine_dir.as_ref()
+        .map(|dir| MalformedQuarantine::new(dir.clone(), opts.malformed_quarantine_per_minute));
+    context.client_captures = opts.client_captures.iter()
+        .map(|(client, secs)| ClientCapture::new(
+            *client,
+            chrono::Duration::seconds((*secs).into()),
+            opts.client_capture_dir.clone(),
+            precision,
+        ))
+        .collect();
+
+    // watch the pods during the sample so that each address is attributed to the pod using it at the time
+    #[cfg(feature = "kubernetes")]
+    let pod_watcher = match kubernetes_api {
+        Some(api) => Some(PodWatcher::start(api).await),
+        None => None,
+    };
+
+    let samples = match interface_index {
+        Some(ii) => {
+            let mut interface_indexes = vec![ii];
+            interface_indexes.extend_from_slice(&opts.extra_interface_indexes);
+
+            // run a single sniffing session
+            collect_sample(
+                &interface_indexes,
+                opts.merge_interfaces,
+                opts.expand_members,
+                Duration::from_secs(opts.sample_secs),
+                Some(&capture_filter),
+                Some(opts.buffer_size),
+                precision,
+                opts.backend,
+                opts.netns.as_deref(),
+                &mut context,
+            ).await
+                .expect("failed to collect sample")
+        },
+        None => {
+            // analyze a capture file instead
+            let path = opts.read_file.as_ref().unwrap();
+            replay_file(path, Some(&capture_filter), precision, &mut context)
+                .expect("failed to replay capture file")
+        },
+    };
+
+    #[cfg(feature = "kubernetes")]
+    let samples = {
+        let mut samples = samples;
+        if let Some(pw) = pod_watcher {
+            let pod_directory = pw.finish().await;
+            for statistics in &mut samples {
+                statistics.set_source_pods(&pod_directory);
+            }
+        }
+        samples
+    };
+
+    #[cfg(feature = "passive-dns")]
+    if let Some(store) = context.passive_dns_store.as_mut() {
+        store.flush()
+            .expect("failed to write passive DNS records");
+        store.prune()
+            .expect("failed to prune passive DNS store");
+    }
+
+    if let (Some(nt), Some(state_path)) = (&context.nod_tracker, &opts.nod_state) {
+        nt.save_state(state_path)
+            .expect("failed to save newly-observed-domain state");
+    }
+
+    // let the sinks send the events they have queued
+    for sink in context.event_sinks.drain(..) {
+        if let Some(task) = sink.close() {
+            if let Err(e) = task.await {
+                error!("event sink panicked: {}", e);
+            }
+        }
+    }
+
+    #[cfg(feature = "http")]
+    if let Some(url) = &remote_write_url {
+        let timestamp_ms = chrono::Utc::now().timestamp_millis();
+        let metric_samples: Vec<_> = samples.iter()
+            .flat_map(|s| collect_samples(s))
+            .collect();
+        if let Err(e) = push_remote_write(url, remote_write_authorization.as_deref(), &metric_samples, timestamp_ms).await {
+            error!("failed to push metrics via remote write: {}", e);
+        }
+    }
+
+    if let Some(csv_dir) = &opts.csv_out {
+        write_csv_tables(&samples, csv_dir)
+            .expect("failed to write CSV tables");
+    }
+
+    match opts.report {
+        Some(format) => {
+            write_report(&samples, format, &mut std::io::stdout().lock())
+                .expect("failed to write report");
+        },
+        None => println!("{:#?}", samples),
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::{parse_filter_file, parse_label};
+
+    #[test]
+    fn test_parse_label() {
+        assert_eq!(parse_label("site=vie").unwrap(), ("site".to_owned(), "vie".to_owned()));
+        assert_eq!(parse_label("site=a=b").unwrap(), ("site".to_owned(), "a=b".to_owned()));
+        assert!(parse_label("site").is_err());
+        assert!(parse_label("1site=vie").is_err());
+        assert!(parse_label("interface=eth0").is_err());
+        assert!(parse_label("__name__=dns").is_err());
+    }
+
+    #[test]
+    fn test_parse_filter_file() {
+        let path = std::env::temp_dir().join(format!("dns-sniff-exporter-filter-{}", std::process::id()));
+        let path_str = path.to_str().unwrap();
+
+        std::fs::write(&path, "# resolvers only\n\n  udp port 53\n    # and their upstreams\nand (host 192.0.2.53\n\tor host 192.0.2.54)\n").unwrap();
+        assert_eq!(parse_filter_file(path_str).unwrap(), "udp port 53 and (host 192.0.2.53 or host 192.0.2.54)");
+
+        std::fs::write(&path, "# nothing but comments\n\n").unwrap();
+        assert!(parse_filter_file(path_str).is_err());
+
+        std::fs::write(&path, "udp port 53 and (host 192.0.2.53\n").unwrap();
+        assert!(parse_filter_file(path_str).is_err());
+
+        std::fs::remove_file(&path).unwrap();
+        assert!(parse_filter_file(path_str).is_err());
+    }
 }
@@ -0,0 +1,509 @@
+#[cfg(any(feature = "docker", feature = "kubernetes"))] use std::collections::BTreeMap;
+use std::net::IpAddr;
+
+use hickory_proto::rr::RecordType;
+
+use crate::dissect::DetailLevel;
+use crate::edns::ExtendedError;
+use crate::fingerprint::Fingerprint;
+use crate::ip::{dscp_name, ecn_name};
+use crate::quantile::QuantileSummary;
+use crate::record_type::record_type_name;
+use crate::scanner::ScannerKind;
+use crate::stats::{DnsStats, DurationHistogram, HOP_LIMIT_BOUNDS, PerSourceStats};
+
+
+/// A single value of a metric with its labels, in the data model of Prometheus.
+#[derive(Clone, Debug, PartialEq)]
+pub struct MetricSample {
+    pub name: String,
+    pub labels: Vec<(String, String)>,
+    pub value: f64,
+}
+
+
+/// Collects the metric samples with their labels, adding the labels common to all samples.
+struct SampleCollector<'a> {
+    common_labels: Vec<(String, String)>,
+    samples: &'a mut Vec<MetricSample>,
+}
+impl<'a> SampleCollector<'a> {
+    fn add(&mut self, name: &str, labels: &[(&str, String)], value: f64) {
+        let mut all_labels = self.common_labels.clone();
+        all_labels.extend(labels.iter().map(|(k, v)| ((*k).to_owned(), v.clone())));
+        self.samples.push(MetricSample {
+            name: name.to_owned(),
+            labels: all_labels,
+            value,
+        });
+    }
+
+    fn add_histogram(&mut self, name: &str, labels: &[(&str, String)], histogram: &DurationHistogram) {
+        // Prometheus buckets are cumulative
+        let mut cumulative_count = 0;
+        for (i, bucket_count) in histogram.bucket_counts.iter().enumerate() {
+            cumulative_count += *bucket_count;
+            let upper_bound = match histogram.upper_bounds.get(i) {
+                Some(ub) => ub.as_secs_f64().to_string(),
+                None => "+Inf".to_owned(),
+            };
+            let mut bucket_labels = labels.to_vec();
+            bucket_labels.push(("le", upper_bound));
+            self.add(&format!("{}_bucket", name), &bucket_labels, cumulative_count as f64);
+        }
+        self.add(&format!("{}_sum", name), labels, histogram.sum.as_secs_f64());
+        self.add(&format!("{}_count", name), labels, histogram.count as f64);
+    }
+
+    fn add_summary(&mut self, name: &str, labels: &[(&str, String)], summary: &QuantileSummary) {
+        for (p, estimate) in summary.quantiles() {
+            // a quantile cannot be estimated before anything has been observed
+            if let Some(e) = estimate {
+                let mut quantile_labels = labels.to_vec();
+                quantile_labels.push(("quantile", p.to_string()));
+                self.add(name, &quantile_labels, e);
+            }
+        }
+        self.add(&format!("{}_sum", name), labels, summary.sum);
+        self.add(&format!("{}_count", name), labels, summary.count as f64);
+    }
+}
+
+
+fn record_type_label(record_type: RecordType) -> String {
+    format!("{}", record_type)
+}
+
+
+fn message_label(is_response: bool) -> String {
+    if is_response { "response".to_owned() } else { "query".to_owned() }
+}
+
+
+/// Labels a client by its address and, if known, the container or Kubernetes pod using it.
+#[cfg(any(feature = "docker", feature = "kubernetes"))]
+fn client_labels(source: &IpAddr, per_source_stats: &PerSourceStats) -> Vec<(&'static str, String)> {
+    let mut labels = vec![("client", source.to_string())];
+    #[cfg(feature = "docker")]
+    if let Some(container) = &per_source_stats.container {
+        labels.push(("container", container.clone()));
+    }
+    #[cfg(feature = "kubernetes")]
+    if let Some(pod) = &per_source_stats.pod {
+        labels.push(("namespace", pod.namespace.clone()));
+        labels.push(("pod", pod.name.clone()));
+        labels.push(("workload", pod.workload.clone().unwrap_or_default()));
+    }
+    labels
+}
+
+#[cfg(not(any(feature = "docker", feature = "kubernetes")))]
+fn client_labels(source: &IpAddr, _per_source_stats: &PerSourceStats) -> Vec<(&'static str, String)> {
+    vec![("client", source.to_string())]
+}
+
+
+/// Converts the statistics into metric samples, each labelled with the interface and the global
+/// labels.
+pub fn collect_samples(stats: &DnsStats) -> Vec<MetricSample> {
+    let mut common_labels: Vec<(String, String)> = stats.global_labels.iter()
+        .map(|(k, v)| (k.clone(), v.clone()))
+        .collect();
+    if let Some(interface) = &stats.interface {
+        common_labels.push(("interface".to_owned(), interface.clone()));
+    }
+    if let Some(tenant) = &stats.tenant {
+        common_labels.push(("tenant".to_owned(), tenant.clone()));
+    }
+    if let Some(profile) = &stats.profile {
+        common_labels.push(("profile".to_owned(), profile.clone()));
+    }
+
+    let mut samples = Vec::new();
+    let mut collector = SampleCollector {
+        common_labels,
+        samples: &mut samples,
+    };
+
+    collector.add("dns_queries_total", &[], stats.total_count as f64);
+    for ((opcode, class, record_type), count) in &stats.query_kind_to_count {
+        collector.add(
+            "dns_queries_by_kind_total",
+            &[("opcode", format!("{:?}", opcode)), ("class", format!("{}", class)), ("type", record_type_label(*record_type))],
+            *count as f64,
+        );
+    }
+    for (record_type, count) in &stats.deprecated_type_to_query_count {
+        collector.add("dns_deprecated_type_queries_total", &[("type", record_type_name(*record_type))], *count as f64);
+    }
+    for (tld, count) in stats.top_level_domain_to_count.exported() {
+        collector.add("dns_queries_by_tld_total", &[("tld", tld)], count as f64);
+    }
+    for (zone, count) in &stats.watched_zone_to_query_count {
+        collector.add("dns_queries_by_zone_total", &[("zone", zone.clone())], *count as f64);
+    }
+    for (zone, counts) in &stats.watched_zone_to_response_counts {
+        collector.add("dns_responses_by_zone_total", &[("zone", zone.clone())], counts.response_count as f64);
+        for (kind, count) in [("nodata", counts.nodata_count), ("nxdomain", counts.nxdomain_count)] {
+            collector.add("dns_negative_responses_by_zone_total", &[("zone", zone.clone()), ("kind", kind.to_owned())], count as f64);
+            collector.add(
+                "dns_negative_response_ratio_by_zone",
+                &[("zone", zone.clone()), ("kind", kind.to_owned())],
+                count as f64 / counts.response_count as f64,
+            );
+        }
+    }
+    collector.add("dns_distinct_query_names", &[], stats.distinct_query_names.estimate() as f64);
+    collector.add("dns_distinct_clients", &[], stats.distinct_clients.estimate() as f64);
+
+    collector.add("dns_responses_total", &[], stats.response_count as f64);
+    for (response_code, count) in &stats.response_code_to_count {
+        collector.add("dns_responses_by_rcode_total", &[("rcode", response_code.to_str().to_owned())], *count as f64);
+    }
+    collector.add("dns_responses_untracked_zone_total", &[], stats.untracked_zone_response_count as f64);
+    for (record_type, count) in &stats.answer_record_type_to_count {
+        collector.add("dns_answer_records_total", &[("type", record_type_label(*record_type))], *count as f64);
+    }
+    collector.add("dns_matched_responses_total", &[], stats.matched_response_count as f64);
+    for ((server, is_response), count) in &stats.server_message_to_count {
+        collector.add(
+            "dns_messages_by_server_total",
+            &[("server", server.to_string()), ("message", message_label(*is_response))],
+            *count as f64,
+        );
+    }
+    for ((server, is_response, flag), count) in &stats.server_flag_to_count {
+        collector.add(
+            "dns_header_flags_total",
+            &[("server", server.to_string()), ("message", message_label(*is_response)), ("flag", (*flag).to_owned())],
+            *count as f64,
+        );
+    }
+    for (cookie_use, count) in &stats.query_cookie_to_count {
+        collector.add("dns_cookie_messages_total", &[("message", message_label(false)), ("cookie", cookie_use.name().to_owned())], *count as f64);
+    }
+    for (cookie_use, count) in &stats.response_cookie_to_count {
+        collector.add("dns_cookie_messages_total", &[("message", message_label(true)), ("cookie", cookie_use.name().to_owned())], *count as f64);
+    }
+    for (server, per_server_stats) in &stats.server_to_stats {
+        for (instance, count) in &per_server_stats.instance_to_response_count {
+            collector.add("dns_responses_by_instance_total", &[("server", server.to_string()), ("nsid", instance.clone())], *count as f64);
+        }
+
+        let hop_limit_count: u64 = per_server_stats.hop_limit_bucket_counts.iter().sum();
+        if hop_limit_count > 0 {
+            let mut cumulative_count = 0;
+            for (bound, bucket_count) in HOP_LIMIT_BOUNDS.iter().zip(per_server_stats.hop_limit_bucket_counts.iter()) {
+                cumulative_count += *bucket_count;
+                collector.add("dns_server_hop_limit_bucket", &[("server", server.to_string()), ("le", bound.to_string())], cumulative_count as f64);
+            }
+            collector.add("dns_server_hop_limit_bucket", &[("server", server.to_string()), ("le", "+Inf".to_owned())], cumulative_count as f64);
+            collector.add("dns_server_hop_limit_sum", &[("server", server.to_string())], per_server_stats.hop_limit_sum as f64);
+            collector.add("dns_server_hop_limit_count", &[("server", server.to_string())], hop_limit_count as f64);
+        }
+    }
+    for (kind, count) in &stats.fragment_kind_to_count {
+        collector.add("dns_fragments_total", &[("kind", (*kind).to_owned())], *count as f64);
+    }
+    for ((is_response, dont_fragment), count) in &stats.dont_fragment_message_to_count {
+        collector.add("dns_ipv4_messages_by_df_total", &[("message", message_label(*is_response)), ("df", dont_fragment.to_string())], *count as f64);
+    }
+    for (over_tcp, count) in &stats.large_response_transport_to_count {
+        let transport = if *over_tcp { "tcp" } else { "udp" };
+        collector.add("dns_large_responses_total", &[("transport", transport.to_owned())], *count as f64);
+    }
+    for ((is_response, dscp), count) in &stats.dscp_message_to_count {
+        collector.add("dns_messages_by_dscp_total", &[("message", message_label(*is_response)), ("dscp", dscp_name(*dscp))], *count as f64);
+    }
+    for ((is_response, ecn), count) in &stats.ecn_message_to_count {
+        collector.add("dns_messages_by_ecn_total", &[("message", message_label(*is_response)), ("ecn", ecn_name(*ecn).to_owned())], *count as f64);
+    }
+    for (is_response, count) in &stats.low_hop_limit_message_to_count {
+        collector.add("dns_low_hop_limit_messages_total", &[("message", message_label(*is_response))], *count as f64);
+    }
+    for (info_code, count) in &stats.extended_error_to_count {
+        collector.add(
+            "dns_extended_errors_total",
+            &[("code", info_code.to_string()), ("error", ExtendedError::name(*info_code).to_owned())],
+            *count as f64,
+        );
+    }
+    let mut cookie_client_counts = [0u64; 3]; // always, sometimes, never
+    for per_source_stats in stats.source_to_stats.values() {
+        if per_source_stats.query_message_count == 0 {
+            continue;
+        }
+        let index = if per_source_stats.cookie_query_count == per_source_stats.query_message_count {
+            0
+        } else if per_source_stats.cookie_query_count > 0 {
+            1
+        } else {
+            2
+        };
+        cookie_client_counts[index] += 1;
+    }
+    for (usage, count) in ["always", "sometimes", "never"].iter().zip(cookie_client_counts.iter()) {
+        collector.add("dns_clients_by_cookie_use", &[("use", (*usage).to_owned())], *count as f64);
+    }
+    for (name, count) in &stats.watched_name_to_unexpected_count {
+        collector.add("dns_watched_name_unexpected_answers_total", &[("name", name.clone())], *count as f64);
+    }
+    for (name, count) in &stats.watched_name_to_address_count {
+        collector.add("dns_watched_name_addresses", &[("name", name.clone())], *count as f64);
+    }
+    for (category, count) in &stats.app_category_to_query_count {
+        collector.add("dns_queries_by_app_category_total", &[("category", category.clone())], *count as f64);
+    }
+    for ((label, value), count) in &stats.script_label_to_count {
+        collector.add("dns_messages_by_script_label_total", &[("label", label.clone()), ("value", value.clone())], *count as f64);
+    }
+    collector.add("dns_messages_dropped_by_script_total", &[], stats.script_dropped_count as f64);
+    let (mut aaaa_answered_count, mut aaaa_empty_count, mut aaaa_unqueried_count) = (0u64, 0u64, 0u64);
+    for usage in stats.name_to_address_family_usage.values() {
+        if usage.a_query_count == 0 {
+            continue;
+        }
+        if usage.aaaa_answered_count > 0 {
+            aaaa_answered_count += 1;
+        } else if usage.aaaa_query_count > 0 {
+            aaaa_empty_count += 1;
+        } else {
+            aaaa_unqueried_count += 1;
+        }
+    }
+    collector.add("dns_ipv4_names_by_aaaa", &[("aaaa", "answered".to_owned())], aaaa_answered_count as f64);
+    collector.add("dns_ipv4_names_by_aaaa", &[("aaaa", "empty".to_owned())], aaaa_empty_count as f64);
+    collector.add("dns_ipv4_names_by_aaaa", &[("aaaa", "not_queried".to_owned())], aaaa_unqueried_count as f64);
+    for fingerprint in Fingerprint::ALL {
+        let (client_count, query_count) = stats.source_to_stats.values()
+            .filter(|s| s.fingerprint == fingerprint)
+            .fold((0, 0), |(clients, queries), s| (clients + 1, queries + s.count));
+        collector.add("dns_clients_by_fingerprint", &[("fingerprint", fingerprint.name().to_owned())], client_count as f64);
+        collector.add("dns_queries_by_fingerprint_total", &[("fingerprint", fingerprint.name().to_owned())], query_count as f64);
+    }
+    for kind in ScannerKind::ALL {
+        let scanner_count = stats.suspected_scanners.values()
+            .filter(|kinds| kinds.contains(&kind))
+            .count();
+        collector.add("dns_scanner_suspected", &[("kind", kind.name().to_owned())], scanner_count as f64);
+    }
+    for (source, per_source_stats) in &stats.source_to_stats {
+        let labels = client_labels(source, per_source_stats);
+        collector.add("dns_client_query_type_entropy_bits", &labels, per_source_stats.query_type_entropy);
+        collector.add("dns_client_query_name_entropy_bits", &labels, per_source_stats.names.entropy());
+        if per_source_stats.minimizable_query_count > 0 {
+            let ratio = per_source_stats.minimized_query_count as f64 / per_source_stats.minimizable_query_count as f64;
+            collector.add("dns_qname_minimization_ratio", &labels, ratio);
+        }
+    }
+    #[cfg(feature = "docker")]
+    {
+        let mut container_to_query_count: BTreeMap<&str, u64> = BTreeMap::new();
+        for per_source_stats in stats.source_to_stats.values() {
+            if let Some(container) = &per_source_stats.container {
+                let query_count = container_to_query_count.entry(container.as_str()).or_insert(0);
+                *query_count += per_source_stats.count;
+            }
+        }
+        for (container, count) in container_to_query_count {
+            collector.add("dns_queries_by_container_total", &[("container", container.to_owned())], count as f64);
+        }
+    }
+    #[cfg(feature = "kubernetes")]
+    {
+        let mut pod_to_query_count: BTreeMap<(&str, &str, &str), u64> = BTreeMap::new();
+        for per_source_stats in stats.source_to_stats.values() {
+            if let Some(pod) = &per_source_stats.pod {
+                let key = (pod.namespace.as_str(), pod.name.as_str(), pod.workload.as_deref().unwrap_or(""));
+                let query_count = pod_to_query_count.entry(key).or_insert(0);
+                *query_count += per_source_stats.count;
+            }
+        }
+        for ((namespace, pod, workload), count) in pod_to_query_count {
+            collector.add(
+                "dns_queries_by_pod_total",
+                &[("namespace", namespace.to_owned()), ("pod", pod.to_owned()), ("workload", workload.to_owned())],
+                count as f64,
+            );
+        }
+    }
+    collector.add_histogram("dns_latency_seconds", &[], &stats.latency);
+    if let Some(lq) = &stats.latency_quantiles {
+        collector.add_summary("dns_latency_summary_seconds", &[], lq);
+    }
+    if let Some(rsq) = &stats.response_size_quantiles {
+        collector.add_summary("dns_response_size_bytes", &[], rsq);
+    }
+    if let Some(stl) = &stats.server_type_latency {
+        for (label_set, histogram) in &stl.label_set_to_histogram {
+            let (server, query_type) = match label_set {
+                Some((s, t)) => (s.to_string(), t.to_string()),
+                None => ("other".to_owned(), "other".to_owned()),
+            };
+            collector.add_histogram("dns_server_latency_seconds", &[("server", server), ("qtype", query_type)], histogram);
+        }
+    }
+    collector.add_histogram("dns_address_query_pair_gap_seconds", &[("first", "aaaa".to_owned())], &stats.aaaa_first_pair_gap);
+    collector.add_histogram("dns_address_query_pair_gap_seconds", &[("first", "a".to_owned())], &stats.a_first_pair_gap);
+    collector.add_histogram("dns_tcp_connection_setup_seconds", &[], &stats.tcp_connection_setup);
+    collector.add_histogram("dns_tcp_first_response_seconds", &[], &stats.tcp_first_response);
+    collector.add("dns_duplicate_responses_total", &[], stats.duplicate_response_count as f64);
+
+    collector.add("dns_capture_packets_total", &[], stats.capture_loss.packets_captured as f64);
+    collector.add("dns_capture_dropped_packets_total", &[("dropped_by", "buffer".to_owned())], stats.capture_loss.packets_dropped as f64);
+    collector.add("dns_capture_dropped_packets_total", &[("dropped_by", "interface".to_owned())], stats.capture_loss.packets_dropped_by_interface as f64);
+    collector.add("dns_capture_undissectable_packets_total", &[], stats.capture_loss.packets_undissectable as f64);
+    collector.add("dns_capture_interface_restarts_total", &[], stats.capture_loss.interface_restarts as f64);
+    for (member, count) in &stats.capture_loss.member_to_packet_count {
+        collector.add("dns_capture_member_packets_total", &[("member", member.clone())], *count as f64);
+    }
+    for ((layer, reason), count) in &stats.capture_loss.failure_to_count {
+        collector.add("dns_capture_dissection_failures_total", &[("layer", (*layer).to_owned()), ("reason", (*reason).to_owned())], *count as f64);
+    }
+    collector.add("dns_capture_completeness", &[], stats.capture_loss.completeness());
+    collector.add("dns_reduced_detail_packets_total", &[], stats.reduced_detail_packet_count as f64);
+    if let Some(runtime) = &stats.runtime {
+        collector.add("dns_runtime_workers", &[], runtime.worker_count as f64);
+        collector.add("dns_runtime_alive_tasks", &[], runtime.alive_task_count as f64);
+        collector.add("dns_runtime_global_queue_depth", &[], runtime.global_queue_depth as f64);
+        if let Some(count) = runtime.blocking_thread_count {
+            collector.add("dns_runtime_blocking_threads", &[], count as f64);
+        }
+        if let Some(count) = runtime.idle_blocking_thread_count {
+            collector.add("dns_runtime_idle_blocking_threads", &[], count as f64);
+        }
+        if let Some(depth) = runtime.blocking_queue_depth {
+            collector.add("dns_runtime_blocking_queue_depth", &[], depth as f64);
+        }
+        collector.add("dns_packet_queue_capacity", &[], runtime.packet_queue_capacity as f64);
+        collector.add("dns_packet_queue_max_depth", &[], runtime.packet_queue_max_depth as f64);
+        collector.add("dns_reduced_detail", &[], if runtime.detail_level == DetailLevel::Full { 0.0 } else { 1.0 });
+    }
+    for (stage, histogram) in &stats.stage_to_processing_time {
+        collector.add_histogram("dns_pipeline_stage_seconds", &[("stage", stage.name().to_owned())], histogram);
+    }
+    for ((layer, cast_kind), count) in &stats.destination_cast_to_count {
+        collector.add(
+            "dns_messages_by_destination_cast_total",
+            &[("layer", (*layer).to_owned()), ("cast", cast_kind.name().to_owned())],
+            *count as f64,
+        );
+    }
+    for (option, count) in &stats.ipv4_option_to_count {
+        collector.add("dns_ipv4_options_total", &[("option", (*option).to_owned())], *count as f64);
+    }
+
+    collector.add("dns_blocklist_hits_total", &[], stats.blocklist_hit_count as f64);
+    collector.add("dns_newly_observed_domains_total", &[], stats.newly_observed_domain_count as f64);
+    collector.add("dns_updates_total", &[], stats.update_count as f64);
+    collector.add("dns_notifies_total", &[], stats.notify_count as f64);
+    for ((client, server), transfer_stats) in &stats.zone_transfer_pair_to_stats {
+        let labels = [("client", client.to_string()), ("server", server.to_string())];
+        collector.add("dns_zone_transfer_requests_total", &labels, transfer_stats.request_count as f64);
+        for (outcome, count) in [("complete", transfer_stats.complete_response_count), ("incomplete", transfer_stats.response_count - transfer_stats.complete_response_count)] {
+            let mut outcome_labels = labels.to_vec();
+            outcome_labels.push(("outcome", outcome.to_owned()));
+            collector.add("dns_zone_transfer_responses_total", &outcome_labels, count as f64);
+        }
+        collector.add("dns_zone_transfer_messages_total", &labels, transfer_stats.response_message_count as f64);
+        collector.add("dns_zone_transfer_bytes_total", &labels, transfer_stats.response_byte_count as f64);
+    }
+    for (metric, active) in &stats.anomaly_active {
+        collector.add("dns_anomaly_active", &[("metric", format!("{:?}", metric))], if *active { 1.0 } else { 0.0 });
+    }
+
+    samples
+}
+
+
+#[cfg(test)]
+mod tests {
+    use std::net::IpAddr;
+    use std::time::Duration;
+
+    use chrono::Utc;
+    use hickory_proto::op::ResponseCode;
+    use hickory_proto::rr::{DNSClass, RecordType};
+
+    use super::{collect_samples, MetricSample};
+    use crate::dns::Opcode;
+    use crate::stats::DnsStats;
+
+    #[test]
+    fn test_labels_and_histogram() {
+        let mut stats = DnsStats::new();
+        stats.interface = Some("eth0".to_owned());
+        stats.global_labels.insert("site".to_owned(), "vie".to_owned());
+        stats.total_count = 3;
+        stats.add_matched_response(Some(Duration::from_millis(3)));
+        stats.add_matched_response(Some(Duration::from_millis(30)));
+
+        let samples = collect_samples(&stats);
+        let labels = vec![("site".to_owned(), "vie".to_owned()), ("interface".to_owned(), "eth0".to_owned())];
+        assert_eq!(samples[0], MetricSample { name: "dns_queries_total".to_owned(), labels: labels.clone(), value: 3.0 });
+
+        let bucket_value = |le: &str| samples.iter()
+            .find(|s| s.name == "dns_latency_seconds_bucket" && s.labels.last().unwrap().1 == le)
+            .map(|s| s.value);
+        assert_eq!(bucket_value("0.002"), Some(0.0));
+        assert_eq!(bucket_value("0.005"), Some(1.0));
+        assert_eq!(bucket_value("0.05"), Some(2.0));
+        assert_eq!(bucket_value("+Inf"), Some(2.0));
+        assert!(samples.iter().all(|s| s.labels.starts_with(&labels)));
+    }
+
+    #[test]
+    fn test_summary() {
+        let mut stats = DnsStats::new();
+        stats.enable_quantiles(&[0.5, 0.9]);
+        stats.add_matched_response(Some(Duration::from_millis(250)));
+        stats.add_matched_response(Some(Duration::from_millis(500)));
+        stats.add_matched_response(Some(Duration::from_millis(1000)));
+
+        let samples = collect_samples(&stats);
+        let value = |name: &str, quantile: Option<&str>| samples.iter()
+            .find(|s| s.name == name && s.labels.iter().find(|(k, _v)| k == "quantile").map(|(_k, v)| v.as_str()) == quantile)
+            .map(|s| s.value);
+        assert_eq!(value("dns_latency_summary_seconds", Some("0.5")), Some(0.5));
+        assert_eq!(value("dns_latency_summary_seconds", Some("0.9")), Some(1.0));
+        assert_eq!(value("dns_latency_summary_seconds_count", None), Some(3.0));
+        assert_eq!(value("dns_latency_summary_seconds_sum", None), Some(1.75));
+
+        // no responses, so no quantiles, but a count of zero
+        assert_eq!(value("dns_response_size_bytes", Some("0.5")), None);
+        assert_eq!(value("dns_response_size_bytes_count", None), Some(0.0));
+    }
+
+    #[test]
+    fn test_negative_response_ratio() {
+        let mut stats = DnsStats::new();
+        stats.add_watched_zone_response("example.com", ResponseCode::NoError, 1);
+        stats.add_watched_zone_response("example.com", ResponseCode::NoError, 0);
+        stats.add_watched_zone_response("example.com", ResponseCode::NXDomain, 0);
+        stats.add_watched_zone_response("example.com", ResponseCode::NXDomain, 0);
+
+        let samples = collect_samples(&stats);
+        let ratio = |kind: &str| samples.iter()
+            .find(|s| s.name == "dns_negative_response_ratio_by_zone" && s.labels.last().unwrap().1 == kind)
+            .map(|s| s.value);
+        assert_eq!(ratio("nodata"), Some(0.25));
+        assert_eq!(ratio("nxdomain"), Some(0.5));
+    }
+
+    #[test]
+    fn test_client_entropy() {
+        let mut stats = DnsStats::new();
+        let client: IpAddr = "192.0.2.1".parse().unwrap();
+        for (record_type, name) in [(RecordType::A, "a.example.com"), (RecordType::AAAA, "b.example.com")] {
+            stats.add_query(Utc::now(), client, Opcode::Query, DNSClass::IN, record_type, name);
+        }
+        stats.summarize_query_type_entropy();
+
+        let samples = collect_samples(&stats);
+        let value = |name: &str| samples.iter()
+            .find(|s| s.name == name && s.labels.contains(&("client".to_owned(), "192.0.2.1".to_owned())))
+            .map(|s| s.value);
+        assert_eq!(value("dns_client_query_type_entropy_bits"), Some(1.0));
+        assert!(value("dns_client_query_name_entropy_bits").unwrap() > 0.0);
+    }
+}
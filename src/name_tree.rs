@@ -0,0 +1,288 @@
+use std::collections::BTreeMap;
+use std::fmt;
+
+
+// each node costs a few hundred bytes
+const DEFAULT_MAX_NODES: usize = 100_000;
+
+
+/// Splits a normalized name into its labels, starting with the top-level domain.
+fn reversed_labels(normalized_name: &str) -> Vec<&str> {
+    if normalized_name.len() == 0 {
+        // the root
+        return Vec::new();
+    }
+    normalized_name.rsplit('.').collect()
+}
+
+
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+struct Node {
+    // the labels leading from the parent to this node, closest to the root first; only the root's
+    // edge is empty, as chains of nodes with a single child and no queries of their own are merged
+    edge: Vec<String>,
+    own_count: u64,
+    total_count: u64,
+    first_label_to_child: BTreeMap<String, Node>,
+}
+impl Node {
+    fn new(edge: Vec<String>) -> Self {
+        Self {
+            edge,
+            own_count: 0,
+            total_count: 0,
+            first_label_to_child: BTreeMap::new(),
+        }
+    }
+
+    /// Splits this node's edge after the given number of labels, moving its contents into a new
+    /// child.
+    fn split(&mut self, at: usize) {
+        let child_edge = self.edge.split_off(at);
+        let mut child = Node::new(child_edge);
+        child.own_count = self.own_count;
+        child.total_count = self.total_count;
+        std::mem::swap(&mut child.first_label_to_child, &mut self.first_label_to_child);
+
+        self.own_count = 0;
+        self.first_label_to_child.insert(child.edge[0].clone(), child);
+    }
+
+    fn collect_zones(&self, path: &mut Vec<String>, depth: usize, zones: &mut Vec<(String, u64)>) {
+        let path_length = path.len();
+        path.extend(self.edge.iter().cloned());
+        if path.len() >= depth {
+            // every name in this subtree is within the same zone of the given depth
+            let zone_labels: Vec<&str> = path[..depth].iter().rev().map(|l| l.as_str()).collect();
+            zones.push((zone_labels.join("."), self.total_count));
+        } else {
+            for child in self.first_label_to_child.values() {
+                child.collect_zones(path, depth, zones);
+            }
+        }
+        path.truncate(path_length);
+    }
+}
+
+
+/// Counts queried names along the domain hierarchy, so that the traffic under any zone can be
+/// summed up without scanning all the names.
+///
+/// The number of nodes is limited; once the tree is full, queries for names that would need new
+/// nodes are only counted towards the deepest zone above them that is already in the tree.
+#[derive(Clone, Eq, PartialEq)]
+pub struct NameTree {
+    root: Node,
+    name_count: usize,
+    node_count: usize, // not counting the root
+    max_nodes: usize,
+}
+impl NameTree {
+    pub fn new() -> Self {
+        Self::with_max_nodes(DEFAULT_MAX_NODES)
+    }
+
+    pub fn with_max_nodes(max_nodes: usize) -> Self {
+        Self {
+            root: Node::new(Vec::new()),
+            name_count: 0,
+            node_count: 0,
+            max_nodes,
+        }
+    }
+
+    pub fn node_count(&self) -> usize {
+        self.node_count
+    }
+
+    pub fn add(&mut self, normalized_name: &str, count: u64) {
+        let labels = reversed_labels(normalized_name);
+        let mut node = &mut self.root;
+        let mut position = 0;
+        node.total_count += count;
+        loop {
+            if position == labels.len() {
+                if node.own_count == 0 {
+                    self.name_count += 1;
+                }
+                node.own_count += count;
+                return;
+            }
+
+            if !node.first_label_to_child.contains_key(labels[position]) {
+                if self.node_count >= self.max_nodes {
+                    return;
+                }
+                let edge = labels[position..].iter().map(|l| (*l).to_owned()).collect();
+                let mut child = Node::new(edge);
+                child.own_count = count;
+                child.total_count = count;
+                node.first_label_to_child.insert(labels[position].to_owned(), child);
+                self.node_count += 1;
+                self.name_count += 1;
+                return;
+            }
+            let child = node.first_label_to_child.get_mut(labels[position]).unwrap();
+
+            // how far does the name follow the child's edge?
+            let common_length = child.edge.iter()
+                .zip(&labels[position..])
+                .take_while(|(e, l)| e.as_str() == **l)
+                .count();
+            if common_length < child.edge.len() {
+                // the name ends within the edge or branches off from it
+                let new_nodes = if position + common_length == labels.len() { 1 } else { 2 };
+                if self.node_count + new_nodes > self.max_nodes {
+                    return;
+                }
+                child.split(common_length);
+                self.node_count += 1;
+            }
+
+            node = child;
+            node.total_count += count;
+            position += common_length;
+        }
+    }
+
+    /// Returns the number of queries for the given zone and all names below it.
+    pub fn zone_count(&self, normalized_zone: &str) -> u64 {
+        let labels = reversed_labels(normalized_zone);
+        let mut node = &self.root;
+        let mut position = 0;
+        while position < labels.len() {
+            let child = match node.first_label_to_child.get(labels[position]) {
+                Some(c) => c,
+                None => return 0,
+            };
+            for edge_label in &child.edge {
+                if position == labels.len() {
+                    // the zone ends within the edge; the whole subtree is within the zone
+                    return child.total_count;
+                }
+                if edge_label != labels[position] {
+                    return 0;
+                }
+                position += 1;
+            }
+            node = child;
+        }
+        node.total_count
+    }
+
+    /// Returns the zones with the given number of labels that received the most queries, most
+    /// queried first. Names with fewer labels are not considered.
+    pub fn top_zones(&self, depth: usize, n: usize) -> Vec<(String, u64)> {
+        let mut zones = Vec::new();
+        if depth == 0 {
+            zones.push((String::new(), self.root.total_count));
+        } else {
+            let mut path = Vec::new();
+            self.root.collect_zones(&mut path, depth, &mut zones);
+        }
+        zones.sort_unstable_by(|(z1, c1), (z2, c2)| c2.cmp(c1).then_with(|| z1.cmp(z2)));
+        zones.truncate(n);
+        zones
+    }
+}
+impl Default for NameTree {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+impl fmt::Debug for NameTree {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        // the full tree is far too large to output
+        f.debug_struct("NameTree")
+            .field("name_count", &self.name_count)
+            .field("node_count", &self.node_count)
+            .field("query_count", &self.root.total_count)
+            .finish()
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::NameTree;
+
+    #[test]
+    fn test_name_tree() {
+        let mut tree = NameTree::new();
+        tree.add("www.example.com", 1);
+        tree.add("mail.corp.example.com", 2);
+        tree.add("vpn.corp.example.com", 1);
+        tree.add("example.com", 1);
+        tree.add("www.example.com", 1);
+        tree.add("example.org", 4);
+        tree.add("", 1);
+
+        assert_eq!(tree.name_count, 6);
+        assert_eq!(tree.zone_count(""), 11);
+        assert_eq!(tree.zone_count("com"), 6);
+        assert_eq!(tree.zone_count("example.com"), 6);
+        assert_eq!(tree.zone_count("corp.example.com"), 3);
+        assert_eq!(tree.zone_count("vpn.corp.example.com"), 1);
+        assert_eq!(tree.zone_count("ample.com"), 0);
+        assert_eq!(tree.zone_count("www.corp.example.com"), 0);
+        assert_eq!(tree.zone_count("org"), 4);
+
+        assert_eq!(
+            tree.top_zones(2, 5),
+            vec![("example.com".to_owned(), 6), ("example.org".to_owned(), 4)],
+        );
+        assert_eq!(
+            tree.top_zones(3, 2),
+            vec![("corp.example.com".to_owned(), 3), ("www.example.com".to_owned(), 2)],
+        );
+    }
+
+    #[test]
+    fn test_name_tree_compression() {
+        let mut tree = NameTree::new();
+
+        // a single long name is stored as a single edge
+        tree.add("a.b.c.d.example", 1);
+        assert_eq!(tree.root.first_label_to_child.len(), 1);
+        assert_eq!(tree.root.first_label_to_child["example"].edge.len(), 5);
+
+        // which is split where another name branches off
+        tree.add("x.c.d.example", 1);
+        let split_node = &tree.root.first_label_to_child["example"];
+        assert_eq!(split_node.edge, vec!["example", "d", "c"]);
+        assert_eq!(split_node.own_count, 0);
+        assert_eq!(split_node.total_count, 2);
+        assert_eq!(split_node.first_label_to_child.len(), 2);
+        assert_eq!(tree.zone_count("d.example"), 2);
+        assert_eq!(tree.zone_count("b.c.d.example"), 1);
+    }
+    #[test]
+    fn test_name_tree_limit() {
+        let mut tree = NameTree::with_max_nodes(4);
+        tree.add("a.b.example.com", 1);
+        tree.add("c.example.com", 1);
+        assert_eq!(tree.node_count, 3);
+
+        // branching off within an edge would take two more nodes
+        tree.add("d.b.example.com", 1);
+        tree.add("example.com", 1);
+        assert_eq!(tree.node_count, 3);
+        assert_eq!(tree.zone_count("example.com"), 4);
+        assert_eq!(tree.zone_count("b.example.com"), 1);
+        assert_eq!(tree.zone_count("d.b.example.com"), 0);
+
+        // ending within an edge takes only one, the last; further names are counted towards their zones
+        tree.add("b.example.com", 1);
+        for i in 0..100 {
+            tree.add(&format!("host{}.example.com", i), 1);
+        }
+        tree.add("example.org", 1);
+        assert_eq!(tree.node_count, 4);
+        assert_eq!(tree.name_count, 4);
+        assert_eq!(tree.zone_count(""), 106);
+        assert_eq!(tree.zone_count("example.com"), 105);
+        assert_eq!(tree.zone_count("b.example.com"), 2);
+        assert_eq!(tree.zone_count("host1.example.com"), 0);
+        assert_eq!(tree.zone_count("example.org"), 0);
+    }
+}
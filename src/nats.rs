@@ -0,0 +1,158 @@
+use std::io;
+use std::sync::Arc;
+
+use serde_json::json;
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+use tokio::net::TcpStream;
+use tokio::net::tcp::OwnedWriteHalf;
+use tokio::sync::{mpsc, Mutex};
+use tokio::sync::mpsc::error::TrySendError;
+use tracing::{debug, warn};
+
+use crate::sink::{DnsMessageEvent, EventSink};
+
+
+const QUEUE_LENGTH: usize = 4096;
+const CONNECT_TIMEOUT_SECS: u64 = 5;
+
+
+/// Encodes a message in the NATS client protocol.
+fn encode_publish(buf: &mut Vec<u8>, subject: &str, payload: &[u8]) {
+    buf.extend_from_slice(format!("PUB {} {}\r\n", subject, payload.len()).as_bytes());
+    buf.extend_from_slice(payload);
+    buf.extend_from_slice(b"\r\n");
+}
+
+
+/// Replaces the characters that have a special meaning in NATS subjects.
+fn subject_token(s: &str) -> String {
+    s.replace(|c: char| c == '.' || c == '*' || c == '>' || c.is_whitespace(), "_")
+}
+
+
+async fn connect(address: &str) -> Result<Arc<Mutex<OwnedWriteHalf>>, io::Error> {
+    let stream = TcpStream::connect(address).await?;
+    let (read_half, mut write_half) = stream.into_split();
+    write_half.write_all(b"CONNECT {\"verbose\":false,\"pedantic\":false,\"name\":\"dns-sniff-exporter\"}\r\n").await?;
+    let write_half = Arc::new(Mutex::new(write_half));
+
+    // the server expects us to answer its keepalive pings
+    let pong_write_half = Arc::clone(&write_half);
+    tokio::spawn(async move {
+        let mut reader = BufReader::new(read_half);
+        let mut line = String::new();
+        loop {
+            line.clear();
+            match reader.read_line(&mut line).await {
+                Ok(0)|Err(_) => break,
+                Ok(_) => {},
+            }
+            if line.starts_with("PING") {
+                if pong_write_half.lock().await.write_all(b"PONG\r\n").await.is_err() {
+                    break;
+                }
+            } else if line.starts_with("-ERR") {
+                warn!("NATS server reported an error: {}", line.trim_end());
+            }
+        }
+    });
+    Ok(write_half)
+}
+
+
+async fn run_publisher(address: String, mut receiver: mpsc::Receiver<(String, Vec<u8>)>) {
+    let mut connection: Option<Arc<Mutex<OwnedWriteHalf>>> = None;
+    while let Some((first_subject, first_payload)) = receiver.recv().await {
+        let mut buf = Vec::new();
+        encode_publish(&mut buf, &first_subject, &first_payload);
+        while let Ok((subject, payload)) = receiver.try_recv() {
+            encode_publish(&mut buf, &subject, &payload);
+        }
+
+        if connection.is_none() {
+            let connect_timeout = std::time::Duration::from_secs(CONNECT_TIMEOUT_SECS);
+            match tokio::time::timeout(connect_timeout, connect(&address)).await {
+                Ok(Ok(c)) => connection = Some(c),
+                Ok(Err(e)) => {
+                    warn!("failed to connect to NATS at {}; dropping messages: {}", address, e);
+                    continue;
+                },
+                Err(_) => {
+                    warn!("timed out connecting to NATS at {}; dropping messages", address);
+                    continue;
+                },
+            }
+        }
+        if let Some(conn) = &connection {
+            let write_result = conn.lock().await.write_all(&buf).await;
+            if let Err(e) = write_result {
+                warn!("failed to publish to NATS; reconnecting: {}", e);
+                connection = None;
+            }
+        }
+    }
+}
+
+
+/// Publishes an event for every query to NATS, on the subject `<prefix>.<query type>`.
+///
+/// Messages are published in the background; if the server cannot keep up, they are dropped.
+pub struct NatsPublisher {
+    subject_prefix: String,
+    sender: mpsc::Sender<(String, Vec<u8>)>,
+    task: tokio::task::JoinHandle<()>,
+}
+impl NatsPublisher {
+    /// Starts the publisher. Must be called from within the Tokio runtime.
+    pub fn new(address: String, subject_prefix: String) -> Self {
+        let (sender, receiver) = mpsc::channel(QUEUE_LENGTH);
+        let task = tokio::spawn(run_publisher(address, receiver));
+        Self {
+            subject_prefix,
+            sender,
+            task,
+        }
+    }
+}
+impl EventSink for NatsPublisher {
+    fn emit(&mut self, event: &DnsMessageEvent<'_>) {
+        if !event.is_query() {
+            return;
+        }
+        let subject = format!("{}.{}", self.subject_prefix, subject_token(&event.record_type.to_string()));
+        let mut payload = json!({
+            "timestamp": event.timestamp.to_rfc3339(),
+            "source": event.source.to_string(),
+            "destination": event.destination.to_string(),
+            "name": event.name,
+            "type": event.record_type.to_string(),
+        });
+        if event.labels.len() > 0 {
+            payload["labels"] = json!(event.labels);
+        }
+        let payload = payload.to_string();
+        match self.sender.try_send((subject, payload.into_bytes())) {
+            Ok(()) => {},
+            Err(TrySendError::Full(_)) => debug!("NATS queue full; dropping query event"),
+            Err(TrySendError::Closed(_)) => {},
+        }
+    }
+
+    fn close(self: Box<Self>) -> Option<tokio::task::JoinHandle<()>> {
+        Some(self.task)
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::{encode_publish, subject_token};
+
+    #[test]
+    fn test_encode_publish() {
+        let mut buf = Vec::new();
+        encode_publish(&mut buf, &format!("dns.vie.{}", subject_token("TYPE65534")), b"{}");
+        assert_eq!(buf, b"PUB dns.vie.TYPE65534 2\r\n{}\r\n");
+        assert_eq!(subject_token("a.b*c>d e"), "a_b_c_d_e");
+    }
+}
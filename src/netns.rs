@@ -0,0 +1,101 @@
+use std::io;
+use std::path::{Path, PathBuf};
+
+
+// where `ip netns` keeps the named network namespaces
+const NAMED_NETNS_DIR: &str = "/run/netns";
+
+
+/// Resolves a network namespace given either as a path (such as `/proc/1234/ns/net`) or as the
+/// name of a namespace created by `ip netns`.
+pub fn netns_path(namespace: &str) -> PathBuf {
+    if namespace.contains('/') {
+        PathBuf::from(namespace)
+    } else {
+        Path::new(NAMED_NETNS_DIR).join(namespace)
+    }
+}
+
+
+#[cfg(target_os = "linux")]
+pub use self::linux::NetnsGuard;
+
+
+#[cfg(target_os = "linux")]
+mod linux {
+    use std::fs::File;
+    use std::io;
+    use std::os::fd::AsRawFd;
+    use std::path::Path;
+
+    use tracing::error;
+
+
+    fn set_namespace(file: &File) -> Result<(), io::Error> {
+        if unsafe { libc::setns(file.as_raw_fd(), libc::CLONE_NEWNET) } < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(())
+    }
+
+
+    /// Moves the current thread into another network namespace, returning it to its original
+    /// namespace when dropped.
+    ///
+    /// Sockets remain in the namespace they were created in, so captures opened while the guard is
+    /// alive keep capturing in the other namespace.
+    pub struct NetnsGuard {
+        original: File,
+    }
+    impl NetnsGuard {
+        pub fn enter(path: &Path) -> Result<Self, io::Error> {
+            let original = File::open("/proc/thread-self/ns/net")?;
+            let target = File::open(path)?;
+            set_namespace(&target)?;
+            Ok(Self {
+                original,
+            })
+        }
+    }
+    impl Drop for NetnsGuard {
+        fn drop(&mut self) {
+            if let Err(e) = set_namespace(&self.original) {
+                error!("failed to return to the original network namespace: {}", e);
+            }
+        }
+    }
+}
+
+
+#[cfg(not(target_os = "linux"))]
+pub struct NetnsGuard;
+
+#[cfg(not(target_os = "linux"))]
+impl NetnsGuard {
+    pub fn enter(_path: &Path) -> Result<Self, io::Error> {
+        Err(io::Error::new(io::ErrorKind::Unsupported, "network namespaces are only supported on Linux"))
+    }
+}
+
+
+/// Enters the given network namespace, if any.
+pub fn enter_netns(namespace: Option<&str>) -> Result<Option<NetnsGuard>, io::Error> {
+    match namespace {
+        Some(ns) => Ok(Some(NetnsGuard::enter(&netns_path(ns))?)),
+        None => Ok(None),
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use super::netns_path;
+
+    #[test]
+    fn test_netns_path() {
+        assert_eq!(netns_path("/proc/1234/ns/net"), PathBuf::from("/proc/1234/ns/net"));
+        assert_eq!(netns_path("blue"), PathBuf::from("/run/netns/blue"));
+    }
+}
@@ -0,0 +1,276 @@
+use std::collections::VecDeque;
+use std::fmt;
+use std::fs::{File, OpenOptions};
+use std::io::{self, BufReader, BufWriter, Read, Write};
+use std::net::IpAddr;
+use std::path::Path;
+
+use chrono::{DateTime, Duration, TimeZone, Utc};
+use serde_json::json;
+use tracing::error;
+
+
+const STATE_MAGIC: &[u8; 4] = b"NOD1";
+const FILTER_BITS: u64 = 1 << 23; // 1 MiB per generation
+const FILTER_HASHES: u64 = 7;
+
+
+/// Calculates the 64-bit FNV-1a hash of the given bytes, starting from the given offset basis.
+///
+/// We implement our own hash instead of using `DefaultHasher` because the filters are persisted
+/// and must hash identically across builds.
+pub fn fnv1a(bytes: &[u8], offset_basis: u64) -> u64 {
+    let mut hash = offset_basis;
+    for b in bytes {
+        hash ^= u64::from(*b);
+        hash = hash.wrapping_mul(0x0000_0100_0000_01B3);
+    }
+    hash
+}
+
+
+/// Returns the registered domain of a normalized name.
+///
+/// Without the Public Suffix List, this is approximated as the last two labels.
+pub fn registered_domain(normalized_name: &str) -> &str {
+    let mut dots = normalized_name.rmatch_indices('.');
+    dots.next();
+    match dots.next() {
+        Some((dot_index, _)) => &normalized_name[dot_index+1..],
+        None => normalized_name,
+    }
+}
+
+
+#[derive(Debug)]
+pub enum NodError {
+    Io(io::Error),
+    InvalidStateFile,
+}
+impl fmt::Display for NodError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Io(e)
+                => write!(f, "I/O error: {}", e),
+            Self::InvalidStateFile
+                => write!(f, "invalid newly-observed-domain state file"),
+        }
+    }
+}
+impl std::error::Error for NodError {
+}
+impl From<io::Error> for NodError {
+    fn from(e: io::Error) -> Self { Self::Io(e) }
+}
+
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct BloomFilter {
+    bits: Vec<u8>,
+}
+impl BloomFilter {
+    pub fn new() -> Self {
+        Self {
+            bits: vec![0u8; (FILTER_BITS / 8) as usize],
+        }
+    }
+
+    fn bit_indexes(item: &[u8]) -> impl Iterator<Item = u64> {
+        // double hashing as proposed by Kirsch and Mitzenmacher
+        let h1 = fnv1a(item, 0xCBF2_9CE4_8422_2325);
+        let h2 = fnv1a(item, 0x8422_2325_CBF2_9CE4) | 1; // odd to cycle through all bits
+        (0..FILTER_HASHES)
+            .map(move |i| h1.wrapping_add(i.wrapping_mul(h2)) % FILTER_BITS)
+    }
+
+    pub fn contains(&self, item: &[u8]) -> bool {
+        Self::bit_indexes(item)
+            .all(|i| self.bits[(i / 8) as usize] & (1 << (i % 8)) != 0)
+    }
+
+    pub fn insert(&mut self, item: &[u8]) {
+        for i in Self::bit_indexes(item) {
+            self.bits[(i / 8) as usize] |= 1 << (i % 8);
+        }
+    }
+}
+
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+struct Generation {
+    pub start: DateTime<Utc>,
+    pub filter: BloomFilter,
+}
+
+
+/// Keeps track of which registered domains have been seen within the last few days.
+///
+/// Each day is stored in its own Bloom filter ("generation"); the oldest generation is dropped once
+/// it falls out of the tracking window.
+#[derive(Debug)]
+pub struct NodTracker {
+    days: u32,
+    generations: VecDeque<Generation>,
+    log_writer: Option<BufWriter<File>>,
+}
+impl NodTracker {
+    pub fn new(days: u32) -> Self {
+        Self {
+            days,
+            generations: VecDeque::new(),
+            log_writer: None,
+        }
+    }
+
+    /// Returns a tracker that starts out knowing the same domains, but does not write to the log.
+    pub fn fork(&self) -> Self {
+        Self {
+            days: self.days,
+            generations: self.generations.clone(),
+            log_writer: None,
+        }
+    }
+
+    pub fn open_log<P: AsRef<Path>>(&mut self, path: P) -> Result<(), NodError> {
+        let file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)?;
+        self.log_writer = Some(BufWriter::new(file));
+        Ok(())
+    }
+
+    fn rotate(&mut self, now: DateTime<Utc>) {
+        let needs_new_generation = match self.generations.back() {
+            Some(g) => g.start + Duration::days(1) <= now,
+            None => true,
+        };
+        if needs_new_generation {
+            self.generations.push_back(Generation {
+                start: now,
+                filter: BloomFilter::new(),
+            });
+        }
+
+        let window_start = now - Duration::days(self.days.into());
+        while self.generations.len() > 1 && self.generations[0].start + Duration::days(1) <= window_start {
+            self.generations.pop_front();
+        }
+    }
+
+    /// Records that the given normalized name has been queried and returns whether its registered
+    /// domain has not been seen within the tracking window.
+    pub fn observe(&mut self, timestamp: DateTime<Utc>, source: IpAddr, normalized_name: &str) -> bool {
+        self.rotate(timestamp);
+
+        let domain = registered_domain(normalized_name);
+        if domain.len() == 0 {
+            return false;
+        }
+
+        let seen = self.generations.iter()
+            .any(|g| g.filter.contains(domain.as_bytes()));
+        let current = self.generations.back_mut().unwrap();
+        if !current.filter.contains(domain.as_bytes()) {
+            current.filter.insert(domain.as_bytes());
+        }
+        if seen {
+            return false;
+        }
+
+        if let Some(log_writer) = &mut self.log_writer {
+            let entry = json!({
+                "timestamp": timestamp.to_rfc3339(),
+                "source": source.to_string(),
+                "name": normalized_name,
+                "domain": domain,
+            });
+            let write_result = writeln!(log_writer, "{}", entry)
+                .and_then(|_| log_writer.flush());
+            if let Err(e) = write_result {
+                error!("failed to write newly observed domain log: {}", e);
+            }
+        }
+        true
+    }
+
+    pub fn load_state<P: AsRef<Path>>(&mut self, path: P) -> Result<(), NodError> {
+        let mut reader = BufReader::new(File::open(path)?);
+
+        let mut magic = [0u8; 4];
+        reader.read_exact(&mut magic)?;
+        if &magic != STATE_MAGIC {
+            return Err(NodError::InvalidStateFile);
+        }
+
+        let mut count_bytes = [0u8; 4];
+        reader.read_exact(&mut count_bytes)?;
+        let count = u32::from_be_bytes(count_bytes);
+
+        let mut generations = VecDeque::new();
+        for _ in 0..count {
+            let mut start_bytes = [0u8; 8];
+            reader.read_exact(&mut start_bytes)?;
+            let start = Utc.timestamp_opt(i64::from_be_bytes(start_bytes), 0)
+                .single()
+                .ok_or(NodError::InvalidStateFile)?;
+
+            let mut filter = BloomFilter::new();
+            reader.read_exact(&mut filter.bits)?;
+            generations.push_back(Generation {
+                start,
+                filter,
+            });
+        }
+
+        self.generations = generations;
+        Ok(())
+    }
+
+    pub fn save_state<P: AsRef<Path>>(&self, path: P) -> Result<(), NodError> {
+        let mut writer = BufWriter::new(File::create(path)?);
+        writer.write_all(STATE_MAGIC)?;
+        let count: u32 = self.generations.len().try_into().unwrap();
+        writer.write_all(&count.to_be_bytes())?;
+        for generation in &self.generations {
+            writer.write_all(&generation.start.timestamp().to_be_bytes())?;
+            writer.write_all(&generation.filter.bits)?;
+        }
+        writer.flush()?;
+        Ok(())
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use std::net::{IpAddr, Ipv4Addr};
+
+    use chrono::{Duration, TimeZone, Utc};
+
+    use super::{NodTracker, registered_domain};
+
+    #[test]
+    fn test_registered_domain() {
+        assert_eq!(registered_domain("www.example.com"), "example.com");
+        assert_eq!(registered_domain("example.com"), "example.com");
+        assert_eq!(registered_domain("com"), "com");
+    }
+
+    #[test]
+    fn test_rotation() {
+        let source = IpAddr::V4(Ipv4Addr::LOCALHOST);
+        let start = Utc.with_ymd_and_hms(2022, 10, 1, 12, 0, 0).unwrap();
+        let mut tracker = NodTracker::new(2);
+
+        assert!(tracker.observe(start, source, "www.example.com"));
+        assert!(!tracker.observe(start, source, "mail.example.com"));
+        assert!(tracker.observe(start, source, "example.org"));
+
+        // still within the window
+        assert!(!tracker.observe(start + Duration::days(1), source, "example.com"));
+
+        // the generation in which we last saw it has expired
+        assert!(tracker.observe(start + Duration::days(4), source, "example.com"));
+    }
+}
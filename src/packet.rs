@@ -1,14 +1,109 @@
-use pcap::{Packet, PacketHeader};
+use chrono::{DateTime, TimeZone, Utc};
+
+
+/// The type of the link-layer header of captured packets, numbered as in pcap files.
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub struct Linktype(pub i32);
+impl Linktype {
+    pub const NULL: Self = Linktype(0);
+    pub const ETHERNET: Self = Linktype(1);
+    pub const RAW: Self = Linktype(101);
+    pub const LOOP: Self = Linktype(108);
+    pub const LINUX_SLL: Self = Linktype(113);
+    pub const IPV4: Self = Linktype(228);
+    pub const IPV6: Self = Linktype(229);
+    pub const LINUX_SLL2: Self = Linktype(276);
+
+    /// The name libpcap knows the link type by.
+    pub fn name(&self) -> Option<&'static str> {
+        match *self {
+            Self::NULL => Some("NULL"),
+            Self::ETHERNET => Some("EN10MB"),
+            Self::RAW => Some("RAW"),
+            Self::LOOP => Some("LOOP"),
+            Self::LINUX_SLL => Some("LINUX_SLL"),
+            Self::IPV4 => Some("IPV4"),
+            Self::IPV6 => Some("IPV6"),
+            Self::LINUX_SLL2 => Some("LINUX_SLL2"),
+            _ => None,
+        }
+    }
+}
+#[cfg(feature = "libpcap")]
+impl From<pcap::Linktype> for Linktype {
+    fn from(l: pcap::Linktype) -> Self { Self(l.0) }
+}
+#[cfg(feature = "libpcap")]
+impl From<Linktype> for pcap::Linktype {
+    fn from(l: Linktype) -> Self { Self(l.0) }
+}
+
+
+/// Whether the sub-second part of packet timestamps counts microseconds or nanoseconds.
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub enum Precision {
+    Micro,
+    Nano,
+}
+#[cfg(feature = "libpcap")]
+impl From<Precision> for pcap::Precision {
+    fn from(p: Precision) -> Self {
+        match p {
+            Precision::Micro => Self::Micro,
+            Precision::Nano => Self::Nano,
+        }
+    }
+}
+
+
+/// The timestamp and lengths of a captured packet, laid out like libpcap's `struct pcap_pkthdr`.
+#[derive(Clone, Copy)]
+#[repr(C)]
+pub struct PacketHeader {
+    pub ts: libc::timeval,
+    pub caplen: u32,
+    pub len: u32,
+}
+
+
+/// Converts the timestamp of a captured packet into a `DateTime`.
+///
+/// Depending on the precision requested when opening the capture, the sub-second part of the
+/// timestamp counts either microseconds or nanoseconds. Out-of-range sub-second values (which some
+/// broken capture files contain) are carried over into the seconds. Returns `None` if the
+/// timestamp cannot be represented.
+pub fn timestamp_to_datetime(header: &PacketHeader, precision: Precision) -> Option<DateTime<Utc>> {
+    let seconds = i64::from(header.ts.tv_sec);
+    let subseconds = i64::from(header.ts.tv_usec);
+    let subseconds_per_second = match precision {
+        Precision::Micro => 1_000_000,
+        Precision::Nano => 1_000_000_000,
+    };
+
+    let total_seconds = seconds.checked_add(subseconds.div_euclid(subseconds_per_second))?;
+    let nanoseconds = subseconds.rem_euclid(subseconds_per_second) * (1_000_000_000 / subseconds_per_second);
+    Utc.timestamp_opt(total_seconds, nanoseconds.try_into().unwrap()).single()
+}
 
 
 pub struct OwnedPacket {
     pub header: PacketHeader,
     pub data: Vec<u8>,
 }
-impl<'a> From<Packet<'a>> for OwnedPacket {
-    fn from(p: Packet<'a>) -> Self {
+impl OwnedPacket {
+    pub fn timestamp(&self, precision: Precision) -> Option<DateTime<Utc>> {
+        timestamp_to_datetime(&self.header, precision)
+    }
+}
+#[cfg(feature = "libpcap")]
+impl<'a> From<pcap::Packet<'a>> for OwnedPacket {
+    fn from(p: pcap::Packet<'a>) -> Self {
         OwnedPacket {
-            header: p.header.clone(),
+            header: PacketHeader {
+                ts: p.header.ts,
+                caplen: p.header.caplen,
+                len: p.header.len,
+            },
             data: p.data.into(),
         }
     }
@@ -21,4 +116,40 @@ pub enum PacketDissection<'a, H> {
     TooShort,
     WrongType,
     IncorrectChecksum,
+    Malformed { reason: &'static str },
+}
+
+
+#[cfg(test)]
+mod tests {
+    use chrono::{TimeZone, Utc};
+
+    use super::{PacketHeader, Precision, timestamp_to_datetime};
+
+    fn header(tv_sec: i64, tv_usec: i64) -> PacketHeader {
+        let mut header: PacketHeader = unsafe { std::mem::zeroed() };
+        header.ts.tv_sec = tv_sec.try_into().unwrap();
+        header.ts.tv_usec = tv_usec.try_into().unwrap();
+        header
+    }
+
+    #[test]
+    fn test_timestamp_to_datetime() {
+        let base = Utc.with_ymd_and_hms(2022, 10, 1, 12, 0, 0).unwrap();
+
+        assert_eq!(
+            timestamp_to_datetime(&header(base.timestamp(), 123_456), Precision::Micro),
+            Some(base + chrono::Duration::microseconds(123_456)),
+        );
+        assert_eq!(
+            timestamp_to_datetime(&header(base.timestamp(), 123_456_789), Precision::Nano),
+            Some(base + chrono::Duration::nanoseconds(123_456_789)),
+        );
+
+        // overflowing sub-second values are carried over
+        assert_eq!(
+            timestamp_to_datetime(&header(base.timestamp(), 1_500_000), Precision::Micro),
+            Some(base + chrono::Duration::microseconds(1_500_000)),
+        );
+    }
 }
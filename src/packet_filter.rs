@@ -0,0 +1,1096 @@
+use std::fmt;
+use std::net::IpAddr;
+
+use crate::packet::Linktype;
+
+
+// instruction classes, sizes, addressing modes and operations from net/bpf.h
+const BPF_LD: u16 = 0x00;
+const BPF_LDX: u16 = 0x01;
+const BPF_ST: u16 = 0x02;
+const BPF_STX: u16 = 0x03;
+const BPF_ALU: u16 = 0x04;
+const BPF_JMP: u16 = 0x05;
+const BPF_RET: u16 = 0x06;
+const BPF_MISC: u16 = 0x07;
+
+const BPF_W: u16 = 0x00;
+const BPF_H: u16 = 0x08;
+const BPF_B: u16 = 0x10;
+
+const BPF_IMM: u16 = 0x00;
+const BPF_ABS: u16 = 0x20;
+const BPF_IND: u16 = 0x40;
+const BPF_MEM: u16 = 0x60;
+const BPF_LEN: u16 = 0x80;
+const BPF_MSH: u16 = 0xa0;
+
+const BPF_AND: u16 = 0x50;
+const BPF_JA: u16 = 0x00;
+const BPF_JEQ: u16 = 0x10;
+const BPF_JGT: u16 = 0x20;
+const BPF_JGE: u16 = 0x30;
+const BPF_JSET: u16 = 0x40;
+const BPF_K: u16 = 0x00;
+const BPF_X: u16 = 0x08;
+
+// the number of bytes of a matching packet that are kept, as returned by libpcap's filters
+const ACCEPT_LENGTH: u32 = 262144;
+
+const ETHERTYPE_IPV4: u32 = 0x0800;
+const ETHERTYPE_IPV6: u32 = 0x86dd;
+const ETHERTYPE_ARP: u32 = 0x0806;
+
+const PROTOCOL_ICMP: u32 = 1;
+const PROTOCOL_TCP: u32 = 6;
+const PROTOCOL_UDP: u32 = 17;
+const PROTOCOL_ICMPV6: u32 = 58;
+
+// the address family values for IPv6 used by the BSDs, any of which may be found in a capture file
+const LOOPBACK_AF_INET: u32 = 2;
+const LOOPBACK_AF_INET6: [u32; 3] = [24, 28, 30];
+
+const IPV6_HEADER_LENGTH: u32 = 40;
+
+
+/// A classic BPF instruction, laid out like `struct sock_filter` on Linux and `struct bpf_insn` on
+/// the BSDs.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+#[repr(C)]
+pub struct FilterInstruction {
+    pub code: u16,
+    pub jt: u8,
+    pub jf: u8,
+    pub k: u32,
+}
+
+
+/// Parses a BPF instruction as formatted by the pcap crate: the opcode, both jump offsets and the
+/// operand in decimal, as output by `tcpdump -ddd`.
+#[cfg(any(test, feature = "libpcap"))]
+pub fn parse_filter_instruction(s: &str) -> Option<FilterInstruction> {
+    let mut fields = s.split(' ');
+    let instruction = FilterInstruction {
+        code: fields.next()?.parse().ok()?,
+        jt: fields.next()?.parse().ok()?,
+        jf: fields.next()?.parse().ok()?,
+        k: fields.next()?.parse().ok()?,
+    };
+    if fields.next().is_some() {
+        return None;
+    }
+    Some(instruction)
+}
+
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum FilterError {
+    UnexpectedEnd,
+    UnexpectedToken(String),
+    InvalidValue(String),
+    Unsupported(String),
+    UnsupportedLinktype(Linktype),
+    TooComplex,
+}
+impl fmt::Display for FilterError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::UnexpectedEnd
+                => write!(f, "unexpected end of filter expression"),
+            Self::UnexpectedToken(t)
+                => write!(f, "unexpected {:?} in filter expression", t),
+            Self::InvalidValue(v)
+                => write!(f, "invalid value {:?} in filter expression", v),
+            Self::Unsupported(what)
+                => write!(f, "{} is not supported by the built-in filter compiler", what),
+            Self::UnsupportedLinktype(linktype)
+                => write!(f, "the built-in filter compiler does not support link type {}", linktype.0),
+            Self::TooComplex
+                => write!(f, "filter expression is too complex"),
+        }
+    }
+}
+impl std::error::Error for FilterError {
+}
+
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+enum Token {
+    Word(String),
+    Symbol(&'static str),
+}
+
+
+// longer symbols first, so that they take precedence over their prefixes
+const SYMBOLS: [&str; 16] = ["&&", "||", "==", "!=", ">=", "<=", "(", ")", "[", "]", ":", "&", "!", "=", ">", "<"];
+
+
+fn tokenize(filter: &str) -> Result<Vec<Token>, FilterError> {
+    let mut tokens = Vec::new();
+    let mut rest = filter;
+    let mut bracket_depth = 0;
+    while let Some(c) = rest.chars().next() {
+        if c.is_whitespace() {
+            rest = &rest[c.len_utf8()..];
+            continue;
+        }
+
+        // colons separate offset and size within brackets but are part of IPv6 addresses outside
+        let is_word_char = |c: char| c.is_ascii_alphanumeric()
+            || c == '.' || c == '_' || c == '-' || c == '/'
+            || (c == ':' && bracket_depth == 0);
+        if is_word_char(c) {
+            let end = rest.find(|c: char| !is_word_char(c)).unwrap_or(rest.len());
+            tokens.push(Token::Word(rest[..end].to_owned()));
+            rest = &rest[end..];
+            continue;
+        }
+
+        let symbol = SYMBOLS.iter()
+            .find(|s| rest.starts_with(**s))
+            .ok_or_else(|| FilterError::UnexpectedToken(c.to_string()))?;
+        match *symbol {
+            "[" => bracket_depth += 1,
+            "]" => bracket_depth -= 1,
+            _ => {},
+        }
+        tokens.push(Token::Symbol(symbol));
+        rest = &rest[symbol.len()..];
+    }
+    Ok(tokens)
+}
+
+
+/// Where a value is loaded from.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+enum Load {
+    /// At a fixed offset from the start of the packet.
+    Absolute { offset: u32, size: u16 },
+
+    /// At an offset from the end of the IPv4 header, whose length varies.
+    AfterIpv4 { network_offset: u32, offset: u32, size: u16 },
+
+    /// The length of the packet on the wire.
+    Length,
+}
+
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+enum Test {
+    Equal,
+    Greater,
+    GreaterOrEqual,
+    AnyBitSet,
+}
+
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+enum Node {
+    Constant(bool),
+    Check { load: Load, mask: Option<u32>, test: Test, value: u32 },
+    Not(Box<Node>),
+    And(Box<Node>, Box<Node>),
+    Or(Box<Node>, Box<Node>),
+}
+impl Node {
+    fn check(load: Load, test: Test, value: u32) -> Self {
+        Self::Check { load, mask: None, test, value }
+    }
+
+    fn not(self) -> Self {
+        Self::Not(Box::new(self))
+    }
+
+    fn and(self, other: Self) -> Self {
+        Self::And(Box::new(self), Box::new(other))
+    }
+
+    fn or(self, other: Self) -> Self {
+        Self::Or(Box::new(self), Box::new(other))
+    }
+
+    fn any(nodes: Vec<Self>) -> Self {
+        nodes.into_iter()
+            .reduce(|a, b| a.or(b))
+            .unwrap_or(Self::Constant(false))
+    }
+
+    fn all(nodes: Vec<Self>) -> Self {
+        nodes.into_iter()
+            .reduce(|a, b| a.and(b))
+            .unwrap_or(Self::Constant(true))
+    }
+}
+
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+enum Protocol {
+    Ether,
+    Ip,
+    Ip6,
+    Arp,
+    Tcp,
+    Udp,
+    Icmp,
+    Icmp6,
+}
+impl Protocol {
+    fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "ether" => Some(Self::Ether),
+            "ip" => Some(Self::Ip),
+            "ip6" => Some(Self::Ip6),
+            "arp" => Some(Self::Arp),
+            "tcp" => Some(Self::Tcp),
+            "udp" => Some(Self::Udp),
+            "icmp" => Some(Self::Icmp),
+            "icmp6" => Some(Self::Icmp6),
+            _ => None,
+        }
+    }
+}
+
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+enum Side {
+    Source,
+    Destination,
+    Either,
+}
+
+
+/// The link-layer header of the packets a filter is compiled for.
+struct LinkLayer {
+    linktype: Linktype,
+    network_offset: u32,
+}
+impl LinkLayer {
+    fn new(linktype: Linktype) -> Result<Self, FilterError> {
+        let network_offset = match linktype {
+            Linktype::ETHERNET => 14,
+            Linktype::LINUX_SLL => 16,
+            Linktype::NULL|Linktype::LOOP => 4,
+            Linktype::RAW|Linktype::IPV4|Linktype::IPV6 => 0,
+            other => return Err(FilterError::UnsupportedLinktype(other)),
+        };
+        Ok(Self { linktype, network_offset })
+    }
+
+    fn ethertype(&self, ethertype: u32) -> Node {
+        match self.linktype {
+            Linktype::ETHERNET => Node::check(Load::Absolute { offset: 12, size: BPF_H }, Test::Equal, ethertype),
+            Linktype::LINUX_SLL => Node::check(Load::Absolute { offset: 14, size: BPF_H }, Test::Equal, ethertype),
+            _ => Node::Constant(false),
+        }
+    }
+
+    fn loopback_family(&self, family: u32) -> Node {
+        // DLT_NULL stores the address family in the byte order of the capturing host
+        let value = if self.linktype == Linktype::NULL {
+            u32::from_be_bytes(family.to_ne_bytes())
+        } else {
+            family
+        };
+        Node::check(Load::Absolute { offset: 0, size: BPF_W }, Test::Equal, value)
+    }
+
+    fn ip_version(&self, version: u32) -> Node {
+        Node::Check {
+            load: Load::Absolute { offset: 0, size: BPF_B },
+            mask: Some(0xf0),
+            test: Test::Equal,
+            value: version << 4,
+        }
+    }
+
+    fn is_ipv4(&self) -> Node {
+        match self.linktype {
+            Linktype::RAW => self.ip_version(4),
+            Linktype::IPV4 => Node::Constant(true),
+            Linktype::IPV6 => Node::Constant(false),
+            Linktype::NULL|Linktype::LOOP => self.loopback_family(LOOPBACK_AF_INET),
+            _ => self.ethertype(ETHERTYPE_IPV4),
+        }
+    }
+
+    fn is_ipv6(&self) -> Node {
+        match self.linktype {
+            Linktype::RAW => self.ip_version(6),
+            Linktype::IPV4 => Node::Constant(false),
+            Linktype::IPV6 => Node::Constant(true),
+            Linktype::NULL|Linktype::LOOP => Node::any(
+                LOOPBACK_AF_INET6.iter().map(|family| self.loopback_family(*family)).collect()
+            ),
+            _ => self.ethertype(ETHERTYPE_IPV6),
+        }
+    }
+
+    fn is_arp(&self) -> Node {
+        self.ethertype(ETHERTYPE_ARP)
+    }
+
+    fn network(&self, offset: u32, size: u16) -> Load {
+        Load::Absolute { offset: self.network_offset + offset, size }
+    }
+
+    fn ipv4_protocol(&self, protocol: u32) -> Node {
+        Node::check(self.network(9, BPF_B), Test::Equal, protocol)
+    }
+
+    fn ipv6_protocol(&self, protocol: u32) -> Node {
+        Node::check(self.network(6, BPF_B), Test::Equal, protocol)
+    }
+
+    /// Matches IPv4 packets that are not fragments following the first, whose transport header
+    /// is missing.
+    fn ipv4_first_fragment(&self) -> Node {
+        Node::Check { load: self.network(6, BPF_H), mask: None, test: Test::AnyBitSet, value: 0x1fff }.not()
+    }
+
+    fn after_ipv4(&self, offset: u32, size: u16) -> Load {
+        Load::AfterIpv4 { network_offset: self.network_offset, offset, size }
+    }
+
+    fn transport(&self, protocol: Protocol) -> Node {
+        match protocol {
+            Protocol::Tcp|Protocol::Udp => {
+                let number = transport_protocol_number(protocol);
+                self.is_ipv4().and(self.ipv4_protocol(number))
+                    .or(self.is_ipv6().and(self.ipv6_protocol(number)))
+            },
+            Protocol::Icmp => self.is_ipv4().and(self.ipv4_protocol(PROTOCOL_ICMP)),
+            Protocol::Icmp6 => self.is_ipv6().and(self.ipv6_protocol(PROTOCOL_ICMPV6)),
+            Protocol::Ip => self.is_ipv4(),
+            Protocol::Ip6 => self.is_ipv6(),
+            Protocol::Arp => self.is_arp(),
+            Protocol::Ether => Node::Constant(true),
+        }
+    }
+
+    fn port(&self, protocol: Option<Protocol>, side: Side, port: u32) -> Result<Node, FilterError> {
+        let (ipv4, ipv6) = match protocol {
+            None|Some(Protocol::Tcp)|Some(Protocol::Udp) => (true, true),
+            Some(Protocol::Ip) => (true, false),
+            Some(Protocol::Ip6) => (false, true),
+            Some(_) => return Err(FilterError::Unsupported("this protocol with a port".to_owned())),
+        };
+        let transports = match protocol {
+            Some(Protocol::Tcp) => vec![PROTOCOL_TCP],
+            Some(Protocol::Udp) => vec![PROTOCOL_UDP],
+            _ => vec![PROTOCOL_TCP, PROTOCOL_UDP],
+        };
+        let ports = |source: Load, destination: Load| match side {
+            Side::Source => Node::check(source, Test::Equal, port),
+            Side::Destination => Node::check(destination, Test::Equal, port),
+            Side::Either => Node::check(source, Test::Equal, port).or(Node::check(destination, Test::Equal, port)),
+        };
+
+        let mut alternatives = Vec::new();
+        if ipv6 {
+            let transport = Node::any(transports.iter().map(|t| self.ipv6_protocol(*t)).collect());
+            let ports = ports(self.network(IPV6_HEADER_LENGTH, BPF_H), self.network(IPV6_HEADER_LENGTH + 2, BPF_H));
+            alternatives.push(self.is_ipv6().and(transport).and(ports));
+        }
+        if ipv4 {
+            let transport = Node::any(transports.iter().map(|t| self.ipv4_protocol(*t)).collect());
+            let ports = ports(self.after_ipv4(0, BPF_H), self.after_ipv4(2, BPF_H));
+            alternatives.push(self.is_ipv4().and(transport).and(self.ipv4_first_fragment()).and(ports));
+        }
+        Ok(Node::any(alternatives))
+    }
+
+    fn addresses(&self, protocol: Option<Protocol>, side: Side, address: IpAddr, prefix_length: u32) -> Result<Node, FilterError> {
+        let (family, words, source_offset, destination_offset) = match address {
+            IpAddr::V4(a) => {
+                if !matches!(protocol, None|Some(Protocol::Ip)) {
+                    return Err(FilterError::Unsupported("this protocol with an IPv4 address".to_owned()));
+                }
+                (self.is_ipv4(), vec![u32::from(a)], 12, 16)
+            },
+            IpAddr::V6(a) => {
+                if !matches!(protocol, None|Some(Protocol::Ip6)) {
+                    return Err(FilterError::Unsupported("this protocol with an IPv6 address".to_owned()));
+                }
+                let octets = a.octets();
+                let words = octets.chunks(4)
+                    .map(|c| u32::from_be_bytes(c.try_into().unwrap()))
+                    .collect();
+                (self.is_ipv6(), words, 8, 24)
+            },
+        };
+
+        // compare each 32-bit word of the address under its part of the prefix
+        let matches_at = |offset: u32| {
+            let mut checks = Vec::new();
+            for (i, word) in words.iter().enumerate() {
+                let word_prefix_length = prefix_length.saturating_sub(32 * i as u32).min(32);
+                if word_prefix_length == 0 {
+                    continue;
+                }
+                let mask = u32::MAX << (32 - word_prefix_length);
+                checks.push(Node::Check {
+                    load: self.network(offset + 4 * i as u32, BPF_W),
+                    mask: if mask == u32::MAX { None } else { Some(mask) },
+                    test: Test::Equal,
+                    value: *word & mask,
+                });
+            }
+            Node::all(checks)
+        };
+        let addresses = match side {
+            Side::Source => matches_at(source_offset),
+            Side::Destination => matches_at(destination_offset),
+            Side::Either => matches_at(source_offset).or(matches_at(destination_offset)),
+        };
+        Ok(family.and(addresses))
+    }
+
+    /// Returns the condition for the given protocol header being present and the location of the
+    /// given field within it.
+    fn field(&self, protocol: Protocol, offset: u32, size: u16) -> (Node, Load) {
+        match protocol {
+            Protocol::Ether => (Node::Constant(true), Load::Absolute { offset, size }),
+            Protocol::Ip => (self.is_ipv4(), self.network(offset, size)),
+            Protocol::Ip6 => (self.is_ipv6(), self.network(offset, size)),
+            Protocol::Arp => (self.is_arp(), self.network(offset, size)),
+            Protocol::Tcp|Protocol::Udp|Protocol::Icmp => {
+                let present = self.is_ipv4()
+                    .and(self.ipv4_protocol(transport_protocol_number(protocol)))
+                    .and(self.ipv4_first_fragment());
+                (present, self.after_ipv4(offset, size))
+            },
+            Protocol::Icmp6 => (
+                self.is_ipv6().and(self.ipv6_protocol(PROTOCOL_ICMPV6)),
+                self.network(IPV6_HEADER_LENGTH + offset, size),
+            ),
+        }
+    }
+}
+
+
+fn transport_protocol_number(protocol: Protocol) -> u32 {
+    match protocol {
+        Protocol::Tcp => PROTOCOL_TCP,
+        Protocol::Udp => PROTOCOL_UDP,
+        Protocol::Icmp => PROTOCOL_ICMP,
+        Protocol::Icmp6 => PROTOCOL_ICMPV6,
+        _ => unreachable!(),
+    }
+}
+
+
+/// The names tcpdump knows for header fields and their values.
+fn named_value(name: &str) -> Option<u32> {
+    let value = match name {
+        "icmptype"|"icmp6type" => 0,
+        "icmpcode"|"icmp6code" => 1,
+        "tcpflags" => 13,
+        "icmp-echoreply" => 0,
+        "icmp-unreach" => 3,
+        "icmp-sourcequench" => 4,
+        "icmp-redirect" => 5,
+        "icmp-echo" => 8,
+        "icmp-timxceed" => 11,
+        "icmp-paramprob" => 12,
+        "icmp6-destinationunreach" => 1,
+        "icmp6-packettoobig" => 2,
+        "icmp6-timeexceeded" => 3,
+        "icmp6-parameterproblem" => 4,
+        "icmp6-echo" => 128,
+        "icmp6-echoreply" => 129,
+        "icmp6-neighborsolicit" => 135,
+        "icmp6-neighboradvert" => 136,
+        "tcp-fin" => 0x01,
+        "tcp-syn" => 0x02,
+        "tcp-rst" => 0x04,
+        "tcp-push" => 0x08,
+        "tcp-ack" => 0x10,
+        "tcp-urg" => 0x20,
+        _ => return None,
+    };
+    Some(value)
+}
+
+
+fn parse_value(word: &str) -> Result<u32, FilterError> {
+    let parsed = if let Some(hex) = word.strip_prefix("0x").or_else(|| word.strip_prefix("0X")) {
+        u32::from_str_radix(hex, 16).ok()
+    } else if word.len() > 1 && word.starts_with('0') {
+        u32::from_str_radix(&word[1..], 8).ok()
+    } else if word.starts_with(|c: char| c.is_ascii_digit()) {
+        word.parse().ok()
+    } else {
+        named_value(word)
+    };
+    parsed.ok_or_else(|| FilterError::InvalidValue(word.to_owned()))
+}
+
+
+struct Parser<'a> {
+    tokens: Vec<Token>,
+    position: usize,
+    link_layer: &'a LinkLayer,
+}
+impl Parser<'_> {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.position)
+    }
+
+    fn next(&mut self) -> Result<Token, FilterError> {
+        let token = self.tokens.get(self.position)
+            .cloned()
+            .ok_or(FilterError::UnexpectedEnd)?;
+        self.position += 1;
+        Ok(token)
+    }
+
+    fn next_word(&mut self) -> Result<String, FilterError> {
+        match self.next()? {
+            Token::Word(w) => Ok(w),
+            Token::Symbol(s) => Err(FilterError::UnexpectedToken(s.to_owned())),
+        }
+    }
+
+    fn expect_symbol(&mut self, symbol: &str) -> Result<(), FilterError> {
+        match self.next()? {
+            Token::Symbol(s) if s == symbol => Ok(()),
+            Token::Symbol(s) => Err(FilterError::UnexpectedToken(s.to_owned())),
+            Token::Word(w) => Err(FilterError::UnexpectedToken(w)),
+        }
+    }
+
+    fn peek_is(&self, word_or_symbol: &str) -> bool {
+        match self.peek() {
+            Some(Token::Word(w)) => w == word_or_symbol,
+            Some(Token::Symbol(s)) => *s == word_or_symbol,
+            None => false,
+        }
+    }
+
+    /// Parses an expression; as in libpcap, "and" and "or" have the same precedence and group from
+    /// the left.
+    fn expression(&mut self) -> Result<Node, FilterError> {
+        let mut node = self.unary()?;
+        loop {
+            if self.peek_is("and") || self.peek_is("&&") {
+                self.position += 1;
+                node = node.and(self.unary()?);
+            } else if self.peek_is("or") || self.peek_is("||") {
+                self.position += 1;
+                node = node.or(self.unary()?);
+            } else {
+                return Ok(node);
+            }
+        }
+    }
+
+    fn unary(&mut self) -> Result<Node, FilterError> {
+        if self.peek_is("not") || self.peek_is("!") {
+            self.position += 1;
+            return Ok(self.unary()?.not());
+        }
+        if self.peek_is("(") {
+            self.position += 1;
+            let node = self.expression()?;
+            self.expect_symbol(")")?;
+            return Ok(node);
+        }
+        self.primitive()
+    }
+
+    fn primitive(&mut self) -> Result<Node, FilterError> {
+        let mut word = self.next_word()?;
+        if word == "len" {
+            return self.comparison(Node::Constant(true), Load::Length);
+        }
+
+        let protocol = Protocol::from_name(&word);
+        if let Some(p) = protocol {
+            if self.peek_is("[") {
+                return self.field_comparison(p);
+            }
+            let qualified = ["src", "dst", "host", "net", "port"].iter().any(|q| self.peek_is(q));
+            if !qualified {
+                if p == Protocol::Ether {
+                    return Err(FilterError::Unsupported("ether without a field".to_owned()));
+                }
+                return Ok(self.link_layer.transport(p));
+            }
+            word = self.next_word()?;
+        }
+
+        let side = match word.as_str() {
+            "src" => Side::Source,
+            "dst" => Side::Destination,
+            _ => Side::Either,
+        };
+        if side != Side::Either {
+            word = self.next_word()?;
+        }
+
+        let value = self.next_word()?;
+        match word.as_str() {
+            "port" => {
+                let port = value.parse::<u16>()
+                    .map_err(|_| FilterError::InvalidValue(value.clone()))?;
+                self.link_layer.port(protocol, side, port.into())
+            },
+            "host" => {
+                let address: IpAddr = value.parse()
+                    .map_err(|_| FilterError::Unsupported(format!("host name {:?}", value)))?;
+                let prefix_length = if address.is_ipv4() { 32 } else { 128 };
+                self.link_layer.addresses(protocol, side, address, prefix_length)
+            },
+            "net" => {
+                let (address, prefix_length) = parse_network(&value)?;
+                self.link_layer.addresses(protocol, side, address, prefix_length)
+            },
+            _ => Err(FilterError::UnexpectedToken(word)),
+        }
+    }
+
+    /// Parses the rest of a comparison like `ip[6:2] & 0x1fff == 0` after its protocol.
+    fn field_comparison(&mut self, protocol: Protocol) -> Result<Node, FilterError> {
+        self.expect_symbol("[")?;
+        let offset = parse_value(&self.next_word()?)?;
+        let size = if self.peek_is(":") {
+            self.position += 1;
+            match self.next_word()?.as_str() {
+                "1" => BPF_B,
+                "2" => BPF_H,
+                "4" => BPF_W,
+                other => return Err(FilterError::InvalidValue(other.to_owned())),
+            }
+        } else {
+            BPF_B
+        };
+        self.expect_symbol("]")?;
+
+        let (present, load) = self.link_layer.field(protocol, offset, size);
+        self.comparison(present, load)
+    }
+
+    fn comparison(&mut self, present: Node, load: Load) -> Result<Node, FilterError> {
+        let mask = if self.peek_is("&") {
+            self.position += 1;
+            Some(parse_value(&self.next_word()?)?)
+        } else {
+            None
+        };
+        let operator = match self.next()? {
+            Token::Symbol(s) => s,
+            Token::Word(w) => return Err(FilterError::UnexpectedToken(w)),
+        };
+        let value = parse_value(&self.next_word()?)?;
+
+        let check = |test| Node::Check { load, mask, test, value };
+        let comparison = match operator {
+            "="|"==" => check(Test::Equal),
+            "!=" => check(Test::Equal).not(),
+            ">" => check(Test::Greater),
+            ">=" => check(Test::GreaterOrEqual),
+            "<" => check(Test::GreaterOrEqual).not(),
+            "<=" => check(Test::Greater).not(),
+            other => return Err(FilterError::UnexpectedToken(other.to_owned())),
+        };
+        Ok(present.and(comparison))
+    }
+}
+
+
+fn parse_network(value: &str) -> Result<(IpAddr, u32), FilterError> {
+    let invalid = || FilterError::InvalidValue(value.to_owned());
+    let (address, prefix_length) = match value.split_once('/') {
+        Some((a, p)) => (a, Some(p)),
+        None => (value, None),
+    };
+    let address: IpAddr = address.parse().map_err(|_| invalid())?;
+    let bits = if address.is_ipv4() { 32 } else { 128 };
+    let prefix_length = match prefix_length {
+        Some(p) => p.parse().ok().filter(|p| *p <= bits).ok_or_else(invalid)?,
+        None => bits,
+    };
+
+    // as in libpcap, the host part of the network must be zero
+    let host_bits_set = match address {
+        IpAddr::V4(a) => prefix_length < 32 && u32::from(a) << prefix_length != 0,
+        IpAddr::V6(a) => prefix_length < 128 && u128::from(a) << prefix_length != 0,
+    };
+    if host_bits_set {
+        return Err(invalid());
+    }
+    Ok((address, prefix_length))
+}
+
+
+/// The instructions of a program before the jump targets have been resolved.
+enum Emitted {
+    Instruction(FilterInstruction),
+    Jump { code: u16, k: u32, on_true: usize, on_false: usize },
+    Goto(usize),
+}
+
+
+#[derive(Default)]
+struct Emitter {
+    emitted: Vec<Emitted>,
+    label_positions: Vec<Option<usize>>,
+}
+impl Emitter {
+    fn new_label(&mut self) -> usize {
+        self.label_positions.push(None);
+        self.label_positions.len() - 1
+    }
+
+    fn place_label(&mut self, label: usize) {
+        self.label_positions[label] = Some(self.emitted.len());
+    }
+
+    fn instruction(&mut self, code: u16, k: u32) {
+        self.emitted.push(Emitted::Instruction(FilterInstruction { code, jt: 0, jf: 0, k }));
+    }
+
+    fn node(&mut self, node: &Node, on_true: usize, on_false: usize) {
+        match node {
+            Node::Constant(value) => {
+                self.emitted.push(Emitted::Goto(if *value { on_true } else { on_false }));
+            },
+            Node::Check { load, mask, test, value } => {
+                match load {
+                    Load::Absolute { offset, size } => self.instruction(BPF_LD | size | BPF_ABS, *offset),
+                    Load::AfterIpv4 { network_offset, offset, size } => {
+                        self.instruction(BPF_LDX | BPF_B | BPF_MSH, *network_offset);
+                        self.instruction(BPF_LD | size | BPF_IND, network_offset + offset);
+                    },
+                    Load::Length => self.instruction(BPF_LD | BPF_W | BPF_LEN, 0),
+                }
+                if let Some(m) = mask {
+                    self.instruction(BPF_ALU | BPF_AND | BPF_K, *m);
+                }
+                let operation = match test {
+                    Test::Equal => BPF_JEQ,
+                    Test::Greater => BPF_JGT,
+                    Test::GreaterOrEqual => BPF_JGE,
+                    Test::AnyBitSet => BPF_JSET,
+                };
+                self.emitted.push(Emitted::Jump { code: BPF_JMP | operation | BPF_K, k: *value, on_true, on_false });
+            },
+            Node::Not(inner) => self.node(inner, on_false, on_true),
+            Node::And(first, second) => {
+                let second_label = self.new_label();
+                self.node(first, second_label, on_false);
+                self.place_label(second_label);
+                self.node(second, on_true, on_false);
+            },
+            Node::Or(first, second) => {
+                let second_label = self.new_label();
+                self.node(first, on_true, second_label);
+                self.place_label(second_label);
+                self.node(second, on_true, on_false);
+            },
+        }
+    }
+
+    /// Replaces the labels by the relative jump offsets. All jumps go forward, but the conditional
+    /// ones can only skip up to 255 instructions.
+    fn resolve(self) -> Result<Vec<FilterInstruction>, FilterError> {
+        let offset_to = |position: usize, label: usize| {
+            self.label_positions[label].unwrap() - (position + 1)
+        };
+        self.emitted.iter()
+            .enumerate()
+            .map(|(position, emitted)| match emitted {
+                Emitted::Instruction(instruction) => Ok(*instruction),
+                Emitted::Jump { code, k, on_true, on_false } => Ok(FilterInstruction {
+                    code: *code,
+                    jt: offset_to(position, *on_true).try_into().map_err(|_| FilterError::TooComplex)?,
+                    jf: offset_to(position, *on_false).try_into().map_err(|_| FilterError::TooComplex)?,
+                    k: *k,
+                }),
+                Emitted::Goto(label) => Ok(FilterInstruction {
+                    code: BPF_JMP | BPF_JA,
+                    jt: 0,
+                    jf: 0,
+                    k: offset_to(position, *label).try_into().map_err(|_| FilterError::TooComplex)?,
+                }),
+            })
+            .collect()
+    }
+}
+
+
+/// Compiles a capture filter for packets of the given link type into BPF instructions without
+/// libpcap.
+///
+/// Only part of the tcpdump filter syntax is understood: the protocols `ip`, `ip6`, `arp`, `tcp`,
+/// `udp`, `icmp` and `icmp6`, optionally qualified `host`, `net` and `port` primitives with
+/// numeric addresses, comparisons of header bytes such as `icmp[icmptype] == icmp-unreach` or of
+/// `len`, and `and`, `or`, `not` and parentheses. As in libpcap, the transport protocol of IPv6
+/// packets is only found if it directly follows the IPv6 header.
+pub fn compile(linktype: Linktype, filter: &str) -> Result<Vec<FilterInstruction>, FilterError> {
+    let link_layer = LinkLayer::new(linktype)?;
+    let mut parser = Parser {
+        tokens: tokenize(filter)?,
+        position: 0,
+        link_layer: &link_layer,
+    };
+    let root = parser.expression()?;
+    if let Some(token) = parser.peek() {
+        return Err(FilterError::UnexpectedToken(match token {
+            Token::Word(w) => w.clone(),
+            Token::Symbol(s) => (*s).to_owned(),
+        }));
+    }
+
+    let mut emitter = Emitter::default();
+    let accept = emitter.new_label();
+    let reject = emitter.new_label();
+    emitter.node(&root, accept, reject);
+    emitter.place_label(accept);
+    emitter.instruction(BPF_RET | BPF_K, ACCEPT_LENGTH);
+    emitter.place_label(reject);
+    emitter.instruction(BPF_RET | BPF_K, 0);
+    emitter.resolve()
+}
+
+
+fn load_bytes(packet: &[u8], offset: u32, size: u16) -> Option<u32> {
+    let start = usize::try_from(offset).ok()?;
+    let length = match size {
+        BPF_W => 4,
+        BPF_H => 2,
+        BPF_B => 1,
+        _ => return None,
+    };
+    let bytes = packet.get(start..start.checked_add(length)?)?;
+    Some(bytes.iter().fold(0, |value, byte| (value << 8) | u32::from(*byte)))
+}
+
+
+/// Runs a filter program on a packet the way the kernel does, returning whether the packet passes.
+/// `length` is the length of the packet on the wire, which may exceed the captured bytes.
+pub fn filter_accepts(program: &[FilterInstruction], packet: &[u8], length: u32) -> bool {
+    let mut a: u32 = 0;
+    let mut x: u32 = 0;
+    let mut memory = [0u32; 16];
+    let mut position = 0;
+    while let Some(instruction) = program.get(position) {
+        position += 1;
+        let k = instruction.k;
+        match instruction.code & 0x07 {
+            class @ (BPF_LD|BPF_LDX) => {
+                let size = instruction.code & 0x18;
+                let value = match instruction.code & 0xe0 {
+                    BPF_IMM => Some(k),
+                    BPF_LEN => Some(length),
+                    BPF_MEM => memory.get(k as usize).copied(),
+                    BPF_ABS => load_bytes(packet, k, size),
+                    BPF_IND => x.checked_add(k).and_then(|offset| load_bytes(packet, offset, size)),
+                    BPF_MSH => load_bytes(packet, k, BPF_B).map(|b| 4 * (b & 0x0f)),
+                    _ => None,
+                };
+                match (value, class) {
+                    (None, _) => return false,
+                    (Some(v), BPF_LD) => a = v,
+                    (Some(v), _) => x = v,
+                }
+            },
+            class @ (BPF_ST|BPF_STX) => {
+                match memory.get_mut(k as usize) {
+                    Some(slot) => *slot = if class == BPF_ST { a } else { x },
+                    None => return false,
+                }
+            },
+            BPF_ALU => {
+                let operand = if instruction.code & BPF_X != 0 { x } else { k };
+                a = match instruction.code & 0xf0 {
+                    0x00 => a.wrapping_add(operand),
+                    0x10 => a.wrapping_sub(operand),
+                    0x20 => a.wrapping_mul(operand),
+                    0x30 => match a.checked_div(operand) {
+                        Some(v) => v,
+                        None => return false,
+                    },
+                    0x40 => a | operand,
+                    BPF_AND => a & operand,
+                    0x60 => a.checked_shl(operand).unwrap_or(0),
+                    0x70 => a.checked_shr(operand).unwrap_or(0),
+                    0x80 => a.wrapping_neg(),
+                    0x90 => match a.checked_rem(operand) {
+                        Some(v) => v,
+                        None => return false,
+                    },
+                    0xa0 => a ^ operand,
+                    _ => return false,
+                };
+            },
+            BPF_JMP => {
+                let operand = if instruction.code & BPF_X != 0 { x } else { k };
+                let condition = match instruction.code & 0xf0 {
+                    BPF_JA => {
+                        position += k as usize;
+                        continue;
+                    },
+                    BPF_JEQ => a == operand,
+                    BPF_JGT => a > operand,
+                    BPF_JGE => a >= operand,
+                    BPF_JSET => a & operand != 0,
+                    _ => return false,
+                };
+                position += usize::from(if condition { instruction.jt } else { instruction.jf });
+            },
+            BPF_RET => {
+                let value = if instruction.code & 0x18 == 0x10 { a } else { k };
+                return value > 0;
+            },
+            BPF_MISC => {
+                if instruction.code & 0xf8 == 0 {
+                    x = a;
+                } else {
+                    a = x;
+                }
+            },
+            _ => return false,
+        }
+    }
+    false
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::{compile, filter_accepts, FilterError, FilterInstruction, parse_filter_instruction};
+    use crate::packet::Linktype;
+
+    const CAPTURE_FILTER: &str = "udp port 53 or tcp port 53 or icmp[icmptype] == icmp-unreach or (icmp6 and (ip6[40] == 1 or ip6[40] == 2))";
+    const NEIGHBOR_CAPTURE_FILTER: &str = "arp or (icmp6 and (ip6[40] == 135 or ip6[40] == 136))";
+
+    fn ethernet(ethertype: u16, payload: &[u8]) -> Vec<u8> {
+        let mut frame = vec![0x02, 0, 0, 0, 0, 1, 0x02, 0, 0, 0, 0, 2];
+        frame.extend_from_slice(&ethertype.to_be_bytes());
+        frame.extend_from_slice(payload);
+        frame
+    }
+
+    fn ipv4(protocol: u8, fragment_offset: u16, source: [u8; 4], payload: &[u8]) -> Vec<u8> {
+        // with a four-byte option, so that the transport header does not follow at a fixed offset
+        let mut packet = vec![0x46, 0, 0, 0, 0, 0];
+        packet.extend_from_slice(&fragment_offset.to_be_bytes());
+        packet.extend_from_slice(&[64, protocol, 0, 0]);
+        packet.extend_from_slice(&source);
+        packet.extend_from_slice(&[192, 0, 2, 53]);
+        packet.extend_from_slice(&[1, 1, 1, 1]);
+        packet.extend_from_slice(payload);
+        packet
+    }
+
+    fn ipv6(next_header: u8, payload: &[u8]) -> Vec<u8> {
+        let mut packet = vec![0x60, 0, 0, 0, 0, 0, next_header, 64];
+        packet.extend_from_slice(&"2001:db8::1".parse::<std::net::Ipv6Addr>().unwrap().octets());
+        packet.extend_from_slice(&"2001:db8::53".parse::<std::net::Ipv6Addr>().unwrap().octets());
+        packet.extend_from_slice(payload);
+        packet
+    }
+
+    fn ports(source: u16, destination: u16) -> Vec<u8> {
+        let mut header = Vec::new();
+        header.extend_from_slice(&source.to_be_bytes());
+        header.extend_from_slice(&destination.to_be_bytes());
+        header.extend_from_slice(&[0; 16]);
+        header
+    }
+
+    fn accepts(linktype: Linktype, filter: &str, packet: &[u8]) -> bool {
+        let program = compile(linktype, filter).unwrap();
+        filter_accepts(&program, packet, packet.len().try_into().unwrap())
+    }
+
+    #[test]
+    fn test_parse_filter_instruction() {
+        // ldh [12], then jeq #0x86dd jt 0 jf 7
+        assert_eq!(parse_filter_instruction("40 0 0 12"), Some(FilterInstruction { code: 0x28, jt: 0, jf: 0, k: 12 }));
+        assert_eq!(parse_filter_instruction("21 0 7 34525"), Some(FilterInstruction { code: 0x15, jt: 0, jf: 7, k: 0x86dd }));
+        assert_eq!(parse_filter_instruction("6 0 0 262144"), Some(FilterInstruction { code: 0x06, jt: 0, jf: 0, k: 262144 }));
+        assert_eq!(parse_filter_instruction("21 0 7"), None);
+        assert_eq!(parse_filter_instruction("21 0 7 34525 1"), None);
+        assert_eq!(parse_filter_instruction("21 0 256 34525"), None);
+    }
+
+    #[test]
+    fn test_compile_like_libpcap() {
+        // tcpdump -dd ip
+        assert_eq!(compile(Linktype::ETHERNET, "ip").unwrap(), vec![
+            FilterInstruction { code: 0x28, jt: 0, jf: 0, k: 0x0000000c },
+            FilterInstruction { code: 0x15, jt: 0, jf: 1, k: 0x00000800 },
+            FilterInstruction { code: 0x06, jt: 0, jf: 0, k: 0x00040000 },
+            FilterInstruction { code: 0x06, jt: 0, jf: 0, k: 0x00000000 },
+        ]);
+    }
+
+    #[test]
+    fn test_capture_filter() {
+        let dns_query = ethernet(0x0800, &ipv4(17, 0, [192, 0, 2, 1], &ports(40000, 53)));
+        let dns_over_tcp = ethernet(0x86dd, &ipv6(6, &ports(53, 40000)));
+        let unreachable = ethernet(0x0800, &ipv4(1, 0, [192, 0, 2, 1], &[3, 3, 0, 0]));
+        let too_big = ethernet(0x86dd, &ipv6(58, &[2, 0, 0, 0]));
+        let echo = ethernet(0x0800, &ipv4(1, 0, [192, 0, 2, 1], &[8, 0, 0, 0]));
+        let web = ethernet(0x0800, &ipv4(6, 0, [192, 0, 2, 1], &ports(40000, 443)));
+        // a later fragment carries no ports, whatever its payload looks like
+        let fragment = ethernet(0x0800, &ipv4(17, 0x00b9, [192, 0, 2, 1], &ports(40000, 53)));
+        let solicitation = ethernet(0x86dd, &ipv6(58, &[135, 0, 0, 0]));
+        let arp = ethernet(0x0806, &[0; 28]);
+
+        for packet in [&dns_query, &dns_over_tcp, &unreachable, &too_big] {
+            assert!(accepts(Linktype::ETHERNET, CAPTURE_FILTER, packet));
+        }
+        for packet in [&echo, &web, &fragment, &solicitation, &arp] {
+            assert!(!accepts(Linktype::ETHERNET, CAPTURE_FILTER, packet));
+        }
+        assert!(accepts(Linktype::ETHERNET, NEIGHBOR_CAPTURE_FILTER, &solicitation));
+        assert!(accepts(Linktype::ETHERNET, NEIGHBOR_CAPTURE_FILTER, &arp));
+        assert!(!accepts(Linktype::ETHERNET, NEIGHBOR_CAPTURE_FILTER, &dns_query));
+
+        // the same packets without the link-layer header
+        assert!(accepts(Linktype::RAW, CAPTURE_FILTER, &dns_query[14..]));
+        assert!(accepts(Linktype::RAW, CAPTURE_FILTER, &dns_over_tcp[14..]));
+        assert!(!accepts(Linktype::RAW, CAPTURE_FILTER, &web[14..]));
+        assert!(!accepts(Linktype::RAW, NEIGHBOR_CAPTURE_FILTER, &dns_query[14..]));
+
+        // truncated packets fail the checks they are too short for
+        assert!(!accepts(Linktype::ETHERNET, CAPTURE_FILTER, &dns_query[..36]));
+    }
+
+    #[test]
+    fn test_addresses_and_ports() {
+        let query = ethernet(0x0800, &ipv4(17, 0, [192, 0, 2, 1], &ports(40000, 53)));
+        let query6 = ethernet(0x86dd, &ipv6(17, &ports(40000, 53)));
+
+        assert!(accepts(Linktype::ETHERNET, "src host 192.0.2.1", &query));
+        assert!(!accepts(Linktype::ETHERNET, "dst host 192.0.2.1", &query));
+        assert!(accepts(Linktype::ETHERNET, "host 192.0.2.53 and udp dst port 53", &query));
+        assert!(!accepts(Linktype::ETHERNET, "tcp port 53", &query));
+        assert!(accepts(Linktype::ETHERNET, "net 192.0.2.0/24", &query));
+        assert!(!accepts(Linktype::ETHERNET, "net 198.51.100.0/24", &query));
+        assert!(accepts(Linktype::ETHERNET, "not net 198.51.100.0/24", &query));
+        assert!(accepts(Linktype::ETHERNET, "ip6 and src net 2001:db8::/32", &query6));
+        assert!(accepts(Linktype::ETHERNET, "dst host 2001:db8::53 && port 40000", &query6));
+        assert!(!accepts(Linktype::ETHERNET, "ip src port 40000", &query6));
+        assert!(accepts(Linktype::ETHERNET, "ip[0] & 0xf != 5 and len >= 58", &query));
+
+        // and and or group from the left, as in libpcap
+        assert!(!accepts(Linktype::ETHERNET, "udp or tcp and arp", &query));
+        assert!(accepts(Linktype::ETHERNET, "udp or (tcp and arp)", &query));
+    }
+
+    #[test]
+    fn test_compile_errors() {
+        assert_eq!(compile(Linktype::ETHERNET, "udp port"), Err(FilterError::UnexpectedEnd));
+        assert_eq!(compile(Linktype::ETHERNET, "udp port 65536"), Err(FilterError::InvalidValue("65536".to_owned())));
+        assert_eq!(compile(Linktype::ETHERNET, "net 192.0.2.1/24"), Err(FilterError::InvalidValue("192.0.2.1/24".to_owned())));
+        assert_eq!(compile(Linktype::ETHERNET, "(udp"), Err(FilterError::UnexpectedEnd));
+        assert_eq!(compile(Linktype::ETHERNET, "udp)"), Err(FilterError::UnexpectedToken(")".to_owned())));
+        assert!(matches!(compile(Linktype::ETHERNET, "host example.com"), Err(FilterError::Unsupported(_))));
+        assert!(matches!(compile(Linktype::ETHERNET, "icmp port 53"), Err(FilterError::Unsupported(_))));
+        assert_eq!(compile(Linktype(105), "udp"), Err(FilterError::UnsupportedLinktype(Linktype(105))));
+    }
+}
@@ -0,0 +1,208 @@
+use std::collections::HashMap;
+use std::path::Path;
+
+use chrono::{DateTime, Duration, TimeZone, Utc};
+use rusqlite::{Connection, params};
+
+
+const PRUNE_INTERVAL: u64 = 1024;
+
+// records are collected in memory and written in one transaction once there are this many of them
+// or the oldest of them has waited this long
+const FLUSH_RECORDS: usize = 1024;
+const FLUSH_INTERVAL_SECS: i64 = 5;
+
+
+#[derive(Clone, Debug, Eq, Hash, PartialEq)]
+pub struct PassiveDnsRecord {
+    pub name: String,
+    pub record_type: String,
+    pub rdata: String,
+    pub first_seen: DateTime<Utc>,
+    pub last_seen: DateTime<Utc>,
+    pub count: u64,
+}
+
+
+struct PendingRecord {
+    first_seen: DateTime<Utc>,
+    last_seen: DateTime<Utc>,
+    count: u64,
+}
+
+
+/// An on-disk store of the resource records observed in responses.
+pub struct PassiveDnsStore {
+    connection: Connection,
+    max_records: u64,
+    inserts_since_prune: u64,
+    key_to_pending: HashMap<(String, String, String), PendingRecord>, // (name, record type, rdata)
+    oldest_pending: Option<DateTime<Utc>>,
+}
+impl PassiveDnsStore {
+    pub fn open<P: AsRef<Path>>(path: P, max_records: u64) -> Result<Self, rusqlite::Error> {
+        let connection = Connection::open(path)?;
+        connection.execute_batch("
+            PRAGMA journal_mode = WAL;
+            PRAGMA synchronous = NORMAL;
+            CREATE TABLE IF NOT EXISTS passive_dns
+            ( name TEXT NOT NULL
+            , record_type TEXT NOT NULL
+            , rdata TEXT NOT NULL
+            , first_seen INTEGER NOT NULL
+            , last_seen INTEGER NOT NULL
+            , count INTEGER NOT NULL
+            , PRIMARY KEY (name, record_type, rdata)
+            );
+            CREATE INDEX IF NOT EXISTS idx_passive_dns_last_seen ON passive_dns (last_seen);
+        ")?;
+        Ok(Self {
+            connection,
+            max_records,
+            inserts_since_prune: 0,
+            key_to_pending: HashMap::new(),
+            oldest_pending: None,
+        })
+    }
+
+    /// Records a sighting of a resource record. The sightings are collected in memory and written
+    /// out in batches, so they only turn up in lookups once they have been flushed.
+    pub fn record(&mut self, timestamp: DateTime<Utc>, name: &str, record_type: &str, rdata: &str) -> Result<(), rusqlite::Error> {
+        let key = (name.to_owned(), record_type.to_owned(), rdata.to_owned());
+        self.key_to_pending.entry(key)
+            .and_modify(|pending| {
+                pending.first_seen = pending.first_seen.min(timestamp);
+                pending.last_seen = pending.last_seen.max(timestamp);
+                pending.count += 1;
+            })
+            .or_insert(PendingRecord {
+                first_seen: timestamp,
+                last_seen: timestamp,
+                count: 1,
+            });
+
+        let oldest_pending = *self.oldest_pending.get_or_insert(timestamp);
+        if self.key_to_pending.len() >= FLUSH_RECORDS || timestamp - oldest_pending >= Duration::seconds(FLUSH_INTERVAL_SECS) {
+            self.flush()?;
+        }
+        Ok(())
+    }
+
+    /// Writes the records collected in memory to the store in a single transaction. If this fails,
+    /// the records are lost.
+    pub fn flush(&mut self) -> Result<(), rusqlite::Error> {
+        let key_to_pending = std::mem::take(&mut self.key_to_pending);
+        self.oldest_pending = None;
+        if key_to_pending.len() == 0 {
+            return Ok(());
+        }
+
+        let transaction = self.connection.transaction()?;
+        {
+            let mut statement = transaction.prepare_cached("
+                INSERT INTO passive_dns (name, record_type, rdata, first_seen, last_seen, count)
+                VALUES (?1, ?2, ?3, ?4, ?5, ?6)
+                ON CONFLICT (name, record_type, rdata) DO UPDATE SET
+                    first_seen = MIN(first_seen, excluded.first_seen),
+                    last_seen = MAX(last_seen, excluded.last_seen),
+                    count = count + excluded.count
+            ")?;
+            for ((name, record_type, rdata), pending) in &key_to_pending {
+                let count: i64 = pending.count.try_into().unwrap_or(i64::MAX);
+                statement.execute(params![name, record_type, rdata, pending.first_seen.timestamp(), pending.last_seen.timestamp(), count])?;
+            }
+        }
+        transaction.commit()?;
+
+        self.inserts_since_prune += key_to_pending.len() as u64;
+        if self.inserts_since_prune >= PRUNE_INTERVAL {
+            self.prune()?;
+        }
+        Ok(())
+    }
+
+    /// Removes the least recently seen records until the store is within its size limit.
+    pub fn prune(&mut self) -> Result<(), rusqlite::Error> {
+        // a negative LIMIT means "no limit" in SQLite, hence the MAX
+        let max_records: i64 = self.max_records.try_into().unwrap_or(i64::MAX);
+        self.connection.execute(
+            "
+                DELETE FROM passive_dns
+                WHERE rowid IN (
+                    SELECT rowid FROM passive_dns
+                    ORDER BY last_seen ASC
+                    LIMIT MAX(0, (SELECT COUNT(*) FROM passive_dns) - ?1)
+                )
+            ",
+            params![max_records],
+        )?;
+        self.inserts_since_prune = 0;
+        Ok(())
+    }
+
+    /// Looks up the records of a name, which is normalized like the stored names: in lowercase and
+    /// without the trailing dot.
+    pub fn lookup(&self, name: &str) -> Result<Vec<PassiveDnsRecord>, rusqlite::Error> {
+        let name = name.strip_suffix('.').unwrap_or(name).to_ascii_lowercase();
+        let mut statement = self.connection.prepare("
+            SELECT name, record_type, rdata, first_seen, last_seen, count
+            FROM passive_dns
+            WHERE name = ?1
+            ORDER BY last_seen DESC
+        ")?;
+        let rows = statement.query_map(params![name], |row| {
+            let first_seen: i64 = row.get(3)?;
+            let last_seen: i64 = row.get(4)?;
+            let count: i64 = row.get(5)?;
+            Ok(PassiveDnsRecord {
+                name: row.get(0)?,
+                record_type: row.get(1)?,
+                rdata: row.get(2)?,
+                first_seen: Utc.timestamp_opt(first_seen, 0).unwrap(),
+                last_seen: Utc.timestamp_opt(last_seen, 0).unwrap(),
+                count: count.try_into().unwrap_or(0),
+            })
+        })?;
+        rows.collect()
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use chrono::{Duration, TimeZone, Utc};
+
+    use super::PassiveDnsStore;
+
+    #[test]
+    fn test_record_and_prune() {
+        let mut store = PassiveDnsStore::open(":memory:", 2).unwrap();
+        let start = Utc.with_ymd_and_hms(2022, 10, 1, 12, 0, 0).unwrap();
+
+        store.record(start + Duration::seconds(5), "example.com", "A", "192.0.2.1").unwrap();
+        store.record(start, "example.com", "A", "192.0.2.1").unwrap();
+        store.record(start + Duration::seconds(1), "example.com", "A", "192.0.2.2").unwrap();
+        store.record(start + Duration::seconds(2), "example.org", "A", "192.0.2.3").unwrap();
+
+        // the records are only written once flushed
+        assert_eq!(store.lookup("example.com").unwrap().len(), 0);
+        store.flush().unwrap();
+        store.record(start + Duration::seconds(3), "example.com", "A", "192.0.2.1").unwrap();
+        store.flush().unwrap();
+
+        let records = store.lookup("example.com").unwrap();
+        assert_eq!(records.len(), 2);
+        assert_eq!(records[0].rdata, "192.0.2.1");
+        assert_eq!(records[0].first_seen, start);
+        assert_eq!(records[0].last_seen, start + Duration::seconds(5));
+        assert_eq!(records[0].count, 3);
+        assert_eq!(store.lookup("Example.COM.").unwrap(), records);
+
+        // the record least recently seen goes first
+        store.prune().unwrap();
+        let records = store.lookup("example.com").unwrap();
+        assert_eq!(records.len(), 1);
+        assert_eq!(records[0].rdata, "192.0.2.1");
+        assert_eq!(store.lookup("example.org").unwrap().len(), 1);
+    }
+}
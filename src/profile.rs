@@ -0,0 +1,120 @@
+use std::fmt;
+use std::path::PathBuf;
+use std::str::FromStr;
+
+use crate::tenant::{TenantParseError, TenantSelector};
+
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum ProfileParseError {
+    MissingName(String),
+    InvalidSelector(TenantParseError),
+    UnknownOption(String),
+}
+impl fmt::Display for ProfileParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::MissingName(s)
+                => write!(f, "profile {:?} is not of the form name=selector[,option...]", s),
+            Self::InvalidSelector(e)
+                => write!(f, "invalid profile selector: {}", e),
+            Self::UnknownOption(s)
+                => write!(f, "unknown profile option {:?}", s),
+        }
+    }
+}
+impl std::error::Error for ProfileParseError {
+}
+impl From<TenantParseError> for ProfileParseError {
+    fn from(e: TenantParseError) -> Self { Self::InvalidSelector(e) }
+}
+
+
+/// A sampling profile: an additional set of statistics, collected from the same capture but with
+/// its own selection of traffic, level of detail and event sinks.
+///
+/// Unlike tenants, profiles may overlap with each other and with the main statistics, e.g. to look
+/// at a subnet under investigation more closely.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct ProfileSpec {
+    pub name: String,
+    pub selector: Option<TenantSelector>, // None to select all traffic
+    pub headers_only: bool,
+    pub aggregate_answer_addresses: bool,
+    pub json_log: Option<PathBuf>,
+    pub filter: Option<String>,
+}
+impl FromStr for ProfileSpec {
+    type Err = ProfileParseError;
+
+    /// Parses a profile given as `name=selector[,option...]`. The selector is `all`, `vlan:ID` or
+    /// `address/prefix`; the options are `headers-only`, `aggregate-answers`, `json-log=PATH` and
+    /// `filter=EXPRESSION`, a capture filter (without commas) that narrows down the captured frames.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (name, rest) = s.split_once('=')
+            .ok_or_else(|| ProfileParseError::MissingName(s.to_owned()))?;
+        if name.len() == 0 {
+            return Err(ProfileParseError::MissingName(s.to_owned()));
+        }
+
+        let mut pieces = rest.split(',');
+        let selector = match pieces.next().unwrap() {
+            "all" => None,
+            other => Some(other.parse()?),
+        };
+        let mut spec = Self {
+            name: name.to_owned(),
+            selector,
+            headers_only: false,
+            aggregate_answer_addresses: false,
+            json_log: None,
+            filter: None,
+        };
+        for option in pieces {
+            if option == "headers-only" {
+                spec.headers_only = true;
+            } else if option == "aggregate-answers" {
+                spec.aggregate_answer_addresses = true;
+            } else if let Some(path) = option.strip_prefix("json-log=") {
+                spec.json_log = Some(PathBuf::from(path));
+            } else if let Some(expression) = option.strip_prefix("filter=") {
+                spec.filter = Some(expression.to_owned());
+            } else {
+                return Err(ProfileParseError::UnknownOption(option.to_owned()));
+            }
+        }
+        Ok(spec)
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use super::ProfileSpec;
+    use crate::tenant::TenantSelector;
+
+    #[test]
+    fn test_parse() {
+        let coarse: ProfileSpec = "coarse=all,headers-only".parse().unwrap();
+        assert_eq!(coarse.name, "coarse");
+        assert_eq!(coarse.selector, None);
+        assert!(coarse.headers_only);
+
+        let detailed: ProfileSpec = "lab=192.0.2.0/24,aggregate-answers,json-log=/var/log/lab.json".parse().unwrap();
+        assert_eq!(detailed.selector, Some(TenantSelector::Subnet { network: "192.0.2.0".parse().unwrap(), prefix_length: 24 }));
+        assert!(!detailed.headers_only);
+        assert!(detailed.aggregate_answer_addresses);
+        assert_eq!(detailed.json_log, Some(PathBuf::from("/var/log/lab.json")));
+        assert_eq!(detailed.filter, None);
+
+        let filtered: ProfileSpec = "tcp=all,filter=tcp port 53".parse().unwrap();
+        assert_eq!(filtered.filter.as_deref(), Some("tcp port 53"));
+
+        assert!("lab".parse::<ProfileSpec>().is_err());
+        assert!("=all".parse::<ProfileSpec>().is_err());
+        assert!("lab=vlan:0".parse::<ProfileSpec>().is_err());
+        assert!("lab=all,verbose".parse::<ProfileSpec>().is_err());
+    }
+}
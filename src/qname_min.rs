@@ -0,0 +1,102 @@
+use std::collections::{HashMap, VecDeque};
+use std::net::IpAddr;
+
+use chrono::{DateTime, Duration, Utc};
+use hickory_proto::rr::RecordType;
+
+
+/// Recognizes resolvers that practice QNAME minimization (RFC9156).
+///
+/// A minimizing resolver looking up `www.sub.example.com` first asks for the NS records of
+/// `sub.example.com` (and possibly further ancestors) before sending the full name. A query is
+/// thus considered minimized if the same resolver has recently queried NS records of one of the
+/// ancestors of its name. Cached delegations make this an underestimate.
+pub struct QnameMinimizationTracker {
+    window: Duration,
+    resolver_name_to_ns_query: HashMap<(IpAddr, String), DateTime<Utc>>,
+    expiry_queue: VecDeque<(DateTime<Utc>, (IpAddr, String))>,
+}
+impl QnameMinimizationTracker {
+    pub fn new(window: Duration) -> Self {
+        Self {
+            window,
+            resolver_name_to_ns_query: HashMap::new(),
+            expiry_queue: VecDeque::new(),
+        }
+    }
+
+    pub fn window(&self) -> Duration {
+        self.window
+    }
+
+    /// Forgets the NS queries that have left the window.
+    fn expire(&mut self, now: DateTime<Utc>) {
+        let cutoff = now - self.window;
+        while let Some((timestamp, _key)) = self.expiry_queue.front() {
+            if *timestamp >= cutoff {
+                break;
+            }
+            let (timestamp, key) = self.expiry_queue.pop_front().unwrap();
+
+            // the name might have been queried again since
+            if self.resolver_name_to_ns_query.get(&key) == Some(&timestamp) {
+                self.resolver_name_to_ns_query.remove(&key);
+            }
+        }
+    }
+
+    /// Processes a query from a resolver and returns whether it was minimized, or `None` if the
+    /// query tells us nothing either way.
+    pub fn observe(&mut self, timestamp: DateTime<Utc>, resolver: IpAddr, normalized_name: &str, record_type: RecordType) -> Option<bool> {
+        self.expire(timestamp);
+
+        if record_type == RecordType::NS {
+            let key = (resolver, normalized_name.to_owned());
+            self.resolver_name_to_ns_query.insert(key.clone(), timestamp);
+            self.expiry_queue.push_back((timestamp, key));
+            return None;
+        }
+
+        // only ancestors below the top-level domain count, so the name needs at least three labels
+        let ancestors: Vec<&str> = normalized_name.match_indices('.')
+            .map(|(dot_index, _)| &normalized_name[dot_index+1..])
+            .filter(|ancestor| ancestor.contains('.'))
+            .collect();
+        if ancestors.len() == 0 {
+            return None;
+        }
+
+        let minimized = ancestors.into_iter()
+            .any(|ancestor| self.resolver_name_to_ns_query.contains_key(&(resolver, ancestor.to_owned())));
+        Some(minimized)
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use chrono::{Duration, TimeZone, Utc};
+    use hickory_proto::rr::RecordType;
+
+    use super::QnameMinimizationTracker;
+
+    #[test]
+    fn test_observe() {
+        let start = Utc.with_ymd_and_hms(2022, 10, 1, 12, 0, 0).unwrap();
+        let minimizer = "192.0.2.1".parse().unwrap();
+        let other = "192.0.2.2".parse().unwrap();
+        let secs = |n| start + Duration::seconds(n);
+
+        let mut tracker = QnameMinimizationTracker::new(Duration::seconds(10));
+        assert_eq!(tracker.observe(secs(0), minimizer, "example.com", RecordType::NS), None);
+        assert_eq!(tracker.observe(secs(0), minimizer, "sub.example.com", RecordType::NS), None);
+        assert_eq!(tracker.observe(secs(1), minimizer, "www.sub.example.com", RecordType::A), Some(true));
+        assert_eq!(tracker.observe(secs(1), other, "www.sub.example.com", RecordType::A), Some(false));
+
+        // too short to tell
+        assert_eq!(tracker.observe(secs(1), other, "example.com", RecordType::A), None);
+
+        // the NS queries have been forgotten
+        assert_eq!(tracker.observe(secs(20), minimizer, "mail.sub.example.com", RecordType::A), Some(false));
+    }
+}
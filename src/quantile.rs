@@ -0,0 +1,185 @@
+use std::fmt;
+
+
+/// Estimates a single quantile of a stream of values in constant memory.
+///
+/// Implements the P² algorithm from Jain and Chlamtac, "The P² algorithm for dynamic calculation of
+/// quantiles and histograms without storing observations" (1985), which keeps five markers whose
+/// heights approximate the minimum, the p/2-, p- and (1+p)/2-quantiles and the maximum.
+#[derive(Clone, Debug, PartialEq)]
+pub struct P2Quantile {
+    p: f64,
+    count: usize,
+    heights: [f64; 5],
+    positions: [f64; 5],
+    desired_positions: [f64; 5],
+    increments: [f64; 5],
+}
+impl P2Quantile {
+    pub fn new(p: f64) -> Self {
+        Self {
+            p,
+            count: 0,
+            heights: [0.0; 5],
+            positions: [1.0, 2.0, 3.0, 4.0, 5.0],
+            desired_positions: [1.0, 1.0 + 2.0*p, 1.0 + 4.0*p, 3.0 + 2.0*p, 5.0],
+            increments: [0.0, p/2.0, p, (1.0 + p)/2.0, 1.0],
+        }
+    }
+
+    pub fn observe(&mut self, value: f64) {
+        if self.count < 5 {
+            // the first values become the initial markers
+            self.heights[self.count] = value;
+            self.count += 1;
+            if self.count == 5 {
+                self.heights.sort_unstable_by(|a, b| a.partial_cmp(b).unwrap());
+            }
+            return;
+        }
+        self.count += 1;
+
+        // find the cell containing the value, extending the extremes if necessary
+        let cell = if value < self.heights[0] {
+            self.heights[0] = value;
+            0
+        } else if value >= self.heights[4] {
+            self.heights[4] = value;
+            3
+        } else {
+            (0..4)
+                .find(|i| value < self.heights[i + 1])
+                .unwrap()
+        };
+        for position in &mut self.positions[cell+1..] {
+            *position += 1.0;
+        }
+        for (desired_position, increment) in self.desired_positions.iter_mut().zip(&self.increments) {
+            *desired_position += *increment;
+        }
+
+        // move the middle markers towards their desired positions
+        for i in 1..4 {
+            let offset = self.desired_positions[i] - self.positions[i];
+            let can_move_right = offset >= 1.0 && self.positions[i + 1] - self.positions[i] > 1.0;
+            let can_move_left = offset <= -1.0 && self.positions[i - 1] - self.positions[i] < -1.0;
+            if !can_move_right && !can_move_left {
+                continue;
+            }
+
+            let step = offset.signum();
+            let parabolic = self.parabolic(i, step);
+            self.heights[i] = if self.heights[i - 1] < parabolic && parabolic < self.heights[i + 1] {
+                parabolic
+            } else {
+                self.linear(i, step)
+            };
+            self.positions[i] += step;
+        }
+    }
+
+    fn parabolic(&self, i: usize, step: f64) -> f64 {
+        let (h, n) = (&self.heights, &self.positions);
+        h[i] + step / (n[i+1] - n[i-1]) * (
+            (n[i] - n[i-1] + step) * (h[i+1] - h[i]) / (n[i+1] - n[i])
+            + (n[i+1] - n[i] - step) * (h[i] - h[i-1]) / (n[i] - n[i-1])
+        )
+    }
+
+    fn linear(&self, i: usize, step: f64) -> f64 {
+        let neighbor = if step > 0.0 { i + 1 } else { i - 1 };
+        self.heights[i] + step * (self.heights[neighbor] - self.heights[i]) / (self.positions[neighbor] - self.positions[i])
+    }
+
+    pub fn estimate(&self) -> Option<f64> {
+        if self.count == 0 {
+            return None;
+        }
+        if self.count < 5 {
+            // too few values for the markers; pick from the sorted values directly
+            let mut values = self.heights[..self.count].to_vec();
+            values.sort_unstable_by(|a, b| a.partial_cmp(b).unwrap());
+            let index = (self.p * (self.count - 1) as f64).round() as usize;
+            return Some(values[index]);
+        }
+        Some(self.heights[2])
+    }
+}
+
+
+/// Estimates a set of quantiles of a stream of values, as exported by Prometheus summaries.
+#[derive(Clone, PartialEq)]
+pub struct QuantileSummary {
+    pub count: u64,
+    pub sum: f64,
+    estimators: Vec<P2Quantile>,
+}
+impl QuantileSummary {
+    pub fn new(quantiles: &[f64]) -> Self {
+        Self {
+            count: 0,
+            sum: 0.0,
+            estimators: quantiles.iter()
+                .map(|q| P2Quantile::new(*q))
+                .collect(),
+        }
+    }
+
+    pub fn observe(&mut self, value: f64) {
+        self.count += 1;
+        self.sum += value;
+        for estimator in &mut self.estimators {
+            estimator.observe(value);
+        }
+    }
+
+    /// Returns the estimate of each quantile, in the order in which they were given.
+    pub fn quantiles(&self) -> Vec<(f64, Option<f64>)> {
+        self.estimators.iter()
+            .map(|e| (e.p, e.estimate()))
+            .collect()
+    }
+}
+impl fmt::Debug for QuantileSummary {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("QuantileSummary")
+            .field("count", &self.count)
+            .field("sum", &self.sum)
+            .field("quantiles", &self.quantiles())
+            .finish()
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::{P2Quantile, QuantileSummary};
+
+    #[test]
+    fn test_few_values() {
+        let mut median = P2Quantile::new(0.5);
+        assert_eq!(median.estimate(), None);
+        for value in [3.0, 1.0, 2.0] {
+            median.observe(value);
+        }
+        assert_eq!(median.estimate(), Some(2.0));
+    }
+
+    #[test]
+    fn test_quantile_summary() {
+        let mut summary = QuantileSummary::new(&[0.5, 0.9, 0.99]);
+
+        // 1 to 10000 in a scrambled order
+        for i in 0..10_000u64 {
+            summary.observe(((i * 7919) % 10_000 + 1) as f64);
+        }
+        assert_eq!(summary.count, 10_000);
+        assert_eq!(summary.sum, 50_005_000.0);
+
+        let quantiles = summary.quantiles();
+        for ((q, estimate), expected) in quantiles.iter().zip([5000.0, 9000.0, 9900.0]) {
+            let estimate = estimate.unwrap();
+            assert!((estimate - expected).abs() < 100.0, "quantile {} estimated as {}", q, estimate);
+        }
+    }
+}
@@ -0,0 +1,131 @@
+use std::fmt::Write as _;
+use std::fs;
+use std::net::SocketAddr;
+use std::path::PathBuf;
+
+use chrono::{DateTime, Duration, Utc};
+use tracing::{debug, error, info};
+
+
+/// Formats bytes in the style of `hexdump -C`.
+pub fn hexdump(bs: &[u8]) -> String {
+    let mut dump = String::new();
+    let mut i = 0;
+
+    while i < bs.len() {
+        write!(dump, "{:08x}  ", i).unwrap();
+        for j in 0..16 {
+            if i + j < bs.len() {
+                write!(dump, "{:02x} ", bs[i + j]).unwrap();
+            } else {
+                dump.push_str("   ");
+            }
+
+            if j == 7 {
+                dump.push(' ');
+            }
+        }
+
+        dump.push_str(" |");
+
+        for j in 0..16 {
+            if i + j >= bs.len() {
+                break;
+            }
+
+            let b = bs[i + j];
+            if b >= 0x20 && b <= 0x7E {
+                dump.push(b as char);
+            } else {
+                dump.push('.');
+            }
+        }
+
+        dump.push_str("|\n");
+
+        i += 16;
+    }
+
+    dump
+}
+
+
+/// Writes DNS messages that the DNS library fails to decode into a directory, so that they can be
+/// attached to bug reports.
+///
+/// At most a given number of messages is written per minute.
+pub struct MalformedQuarantine {
+    directory: PathBuf,
+    max_per_minute: u32,
+    minute_start: Option<DateTime<Utc>>,
+    written_this_minute: u32,
+}
+impl MalformedQuarantine {
+    pub fn new(directory: PathBuf, max_per_minute: u32) -> Self {
+        Self {
+            directory,
+            max_per_minute,
+            minute_start: None,
+            written_this_minute: 0,
+        }
+    }
+
+    /// Checks whether another message may be written at the given time, counting it if so.
+    fn admit(&mut self, timestamp: DateTime<Utc>) -> bool {
+        let minute_over = match self.minute_start {
+            Some(ms) => timestamp - ms >= Duration::minutes(1),
+            None => true,
+        };
+        if minute_over {
+            self.minute_start = Some(timestamp);
+            self.written_this_minute = 0;
+        }
+        if self.written_this_minute >= self.max_per_minute {
+            return false;
+        }
+        self.written_this_minute += 1;
+        true
+    }
+
+    /// Writes the undecodable message, unless too many have been written in the current minute.
+    pub fn store(&mut self, timestamp: DateTime<Utc>, source: SocketAddr, destination: SocketAddr, transaction_id: u16, message: &[u8]) {
+        if !self.admit(timestamp) {
+            return;
+        }
+        debug!("undecodable DNS message from {} to {}:\n{}", source, destination, hexdump(message));
+
+        let file_name = format!("malformed-{}-{:04x}.bin", timestamp.format("%Y%m%dT%H%M%S%.6fZ"), transaction_id);
+        let path = self.directory.join(file_name);
+        match fs::write(&path, message) {
+            Ok(()) => info!("quarantined undecodable DNS message from {} to {} as {}", source, destination, path.display()),
+            Err(e) => error!("failed to quarantine undecodable DNS message as {}: {}", path.display(), e),
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use chrono::{Duration, TimeZone, Utc};
+
+    use super::{hexdump, MalformedQuarantine};
+
+    #[test]
+    fn test_hexdump() {
+        let dump = hexdump(b"\x12\x34\x01\x00example.com\x00\x00\x01");
+        assert_eq!(dump, concat!(
+            "00000000  12 34 01 00 65 78 61 6d  70 6c 65 2e 63 6f 6d 00  |.4..example.com.|\n",
+            "00000010  00 01                                             |..|\n",
+        ));
+    }
+
+    #[test]
+    fn test_rate_limit() {
+        let start = Utc.with_ymd_and_hms(2022, 10, 1, 12, 0, 0).unwrap();
+        let mut quarantine = MalformedQuarantine::new(".".into(), 2);
+        assert!(quarantine.admit(start));
+        assert!(quarantine.admit(start + Duration::seconds(10)));
+        assert!(!quarantine.admit(start + Duration::seconds(20)));
+        assert!(quarantine.admit(start + Duration::seconds(60)));
+    }
+}
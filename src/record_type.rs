@@ -0,0 +1,63 @@
+use std::str::FromStr;
+
+use hickory_proto::rr::RecordType;
+
+
+/// Obsolete or deprecated record types that legacy software still asks for: ANY, SPF, MAILA,
+/// MAILB and RP.
+pub const DEFAULT_DEPRECATED_RECORD_TYPES: [u16; 5] = [255, 99, 254, 253, 17];
+
+// the obsolete types the DNS library has no name for
+const EXTRA_NAMES: [(u16, &str); 14] = [
+    (3, "MD"), (4, "MF"), (7, "MB"), (8, "MG"), (9, "MR"), (11, "WKS"), (14, "MINFO"), (17, "RP"),
+    (18, "AFSDB"), (30, "NXT"), (38, "A6"), (99, "SPF"), (253, "MAILB"), (254, "MAILA"),
+];
+
+
+/// Returns the mnemonic of the given record type, or `TYPEnnn` (RFC3597) if it has none.
+#[cfg(any(test, feature = "http"))]
+pub fn record_type_name(code: u16) -> String {
+    if let Some((_code, name)) = EXTRA_NAMES.iter().find(|(c, _name)| *c == code) {
+        return (*name).to_owned();
+    }
+    match RecordType::from(code) {
+        RecordType::Unknown(_) => format!("TYPE{}", code),
+        record_type => record_type.to_string(),
+    }
+}
+
+
+/// Parses a record type given as a mnemonic, as `TYPEnnn` or as a plain number.
+pub fn parse_record_type(s: &str) -> Option<u16> {
+    let upper = s.to_ascii_uppercase();
+    let number_str = upper.strip_prefix("TYPE").unwrap_or(&upper);
+    if let Ok(code) = number_str.parse() {
+        return Some(code);
+    }
+    if let Some((code, _name)) = EXTRA_NAMES.iter().find(|(_c, name)| *name == upper) {
+        return Some(*code);
+    }
+    RecordType::from_str(&upper)
+        .ok()
+        .map(|record_type| u16::from(record_type))
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::{parse_record_type, record_type_name};
+
+    #[test]
+    fn test_names() {
+        assert_eq!(record_type_name(1), "A");
+        assert_eq!(record_type_name(99), "SPF");
+        assert_eq!(record_type_name(255), "ANY");
+        assert_eq!(record_type_name(65280), "TYPE65280");
+
+        assert_eq!(parse_record_type("aaaa"), Some(28));
+        assert_eq!(parse_record_type("MAILA"), Some(254));
+        assert_eq!(parse_record_type("TYPE17"), Some(17));
+        assert_eq!(parse_record_type("99"), Some(99));
+        assert_eq!(parse_record_type("BOGUS"), None);
+    }
+}
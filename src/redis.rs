@@ -0,0 +1,151 @@
+use std::collections::HashMap;
+use std::io;
+
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+use tokio::net::TcpStream;
+use tokio::sync::mpsc;
+use tokio::sync::mpsc::error::TrySendError;
+use tracing::{debug, warn};
+
+use crate::sink::{DnsMessageEvent, EventSink};
+
+
+const QUEUE_LENGTH: usize = 4096;
+const CONNECT_TIMEOUT_SECS: u64 = 5;
+
+
+/// Encodes a command as a RESP array of bulk strings.
+fn encode_command(buf: &mut Vec<u8>, args: &[&[u8]]) {
+    buf.extend_from_slice(format!("*{}\r\n", args.len()).as_bytes());
+    for arg in args {
+        buf.extend_from_slice(format!("${}\r\n", arg.len()).as_bytes());
+        buf.extend_from_slice(arg);
+        buf.extend_from_slice(b"\r\n");
+    }
+}
+
+
+/// Encodes the commands that increment each key by its count and (re)set its expiry.
+fn encode_increments(key_to_count: &HashMap<String, u64>, ttl_secs: u64) -> Vec<u8> {
+    let ttl_string = ttl_secs.to_string();
+    let mut buf = Vec::new();
+    for (key, count) in key_to_count {
+        encode_command(&mut buf, &[b"INCRBY", key.as_bytes(), count.to_string().as_bytes()]);
+        encode_command(&mut buf, &[b"EXPIRE", key.as_bytes(), ttl_string.as_bytes()]);
+    }
+    buf
+}
+
+
+async fn send_increments(connection: &mut BufReader<TcpStream>, key_to_count: &HashMap<String, u64>, ttl_secs: u64) -> Result<(), io::Error> {
+    // pipeline all commands, then collect the replies, which are all single-line integers
+    connection.get_mut().write_all(&encode_increments(key_to_count, ttl_secs)).await?;
+    let mut line = String::new();
+    for _ in 0..2*key_to_count.len() {
+        line.clear();
+        if connection.read_line(&mut line).await? == 0 {
+            return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "connection closed by Redis"));
+        }
+        if line.starts_with('-') {
+            warn!("Redis returned an error: {}", line.trim_end());
+        }
+    }
+    Ok(())
+}
+
+
+async fn run_sink(address: String, ttl_secs: u64, mut receiver: mpsc::Receiver<String>) {
+    let mut connection = None;
+    while let Some(first_key) = receiver.recv().await {
+        // coalesce everything that is already queued
+        let mut key_to_count = HashMap::new();
+        key_to_count.insert(first_key, 1);
+        while let Ok(key) = receiver.try_recv() {
+            let count = key_to_count.entry(key).or_insert(0);
+            *count += 1;
+        }
+
+        if connection.is_none() {
+            let connect_timeout = std::time::Duration::from_secs(CONNECT_TIMEOUT_SECS);
+            match tokio::time::timeout(connect_timeout, TcpStream::connect(&address)).await {
+                Ok(Ok(stream)) => connection = Some(BufReader::new(stream)),
+                Ok(Err(e)) => {
+                    warn!("failed to connect to Redis at {}; dropping {} counters: {}", address, key_to_count.len(), e);
+                    continue;
+                },
+                Err(_) => {
+                    warn!("timed out connecting to Redis at {}; dropping {} counters", address, key_to_count.len());
+                    continue;
+                },
+            }
+        }
+        if let Some(conn) = connection.as_mut() {
+            if let Err(e) = send_increments(conn, &key_to_count, ttl_secs).await {
+                warn!("failed to update counters in Redis; reconnecting: {}", e);
+                connection = None;
+            }
+        }
+    }
+}
+
+
+/// Increments per-source, per-name and per-type counters in Redis as queries are observed.
+///
+/// The counters are updated in the background; if Redis cannot keep up, updates are dropped.
+pub struct RedisSink {
+    key_prefix: String,
+    sender: mpsc::Sender<String>,
+    task: tokio::task::JoinHandle<()>,
+}
+impl RedisSink {
+    /// Starts the sink. Must be called from within the Tokio runtime.
+    pub fn new(address: String, key_prefix: String, ttl_secs: u64) -> Self {
+        let (sender, receiver) = mpsc::channel(QUEUE_LENGTH);
+        let task = tokio::spawn(run_sink(address, ttl_secs, receiver));
+        Self {
+            key_prefix,
+            sender,
+            task,
+        }
+    }
+
+    fn increment(&self, key: String) {
+        match self.sender.try_send(key) {
+            Ok(()) => {},
+            Err(TrySendError::Full(key)) => debug!("Redis queue full; dropping increment of {}", key),
+            Err(TrySendError::Closed(_)) => {},
+        }
+    }
+}
+impl EventSink for RedisSink {
+    fn emit(&mut self, event: &DnsMessageEvent<'_>) {
+        if !event.is_query() {
+            return;
+        }
+        self.increment(format!("{}:source:{}", self.key_prefix, event.source.ip()));
+        self.increment(format!("{}:name:{}", self.key_prefix, event.name));
+        self.increment(format!("{}:qtype:{}", self.key_prefix, event.record_type));
+    }
+
+    fn close(self: Box<Self>) -> Option<tokio::task::JoinHandle<()>> {
+        Some(self.task)
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use super::encode_increments;
+
+    #[test]
+    fn test_encode_increments() {
+        let mut key_to_count = HashMap::new();
+        key_to_count.insert("dns:qtype:A".to_owned(), 12);
+        assert_eq!(
+            encode_increments(&key_to_count, 3600),
+            b"*3\r\n$6\r\nINCRBY\r\n$11\r\ndns:qtype:A\r\n$2\r\n12\r\n*3\r\n$6\r\nEXPIRE\r\n$11\r\ndns:qtype:A\r\n$4\r\n3600\r\n",
+        );
+    }
+}
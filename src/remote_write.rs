@@ -0,0 +1,154 @@
+use crate::http::{HttpError, HttpUrl, post};
+use crate::metrics::MetricSample;
+
+
+// protobuf wire types
+const WIRE_TYPE_VARINT: u8 = 0;
+const WIRE_TYPE_I64: u8 = 1;
+const WIRE_TYPE_LEN: u8 = 2;
+
+// the longest literal that a snappy literal element with a two-byte length can hold
+const MAX_SNAPPY_LITERAL_LENGTH: usize = 65536;
+
+
+fn write_varint(buf: &mut Vec<u8>, mut value: u64) {
+    while value >= 0x80 {
+        buf.push(u8::try_from(value & 0x7F).unwrap() | 0x80);
+        value >>= 7;
+    }
+    buf.push(u8::try_from(value).unwrap());
+}
+
+fn write_key(buf: &mut Vec<u8>, field_number: u8, wire_type: u8) {
+    buf.push((field_number << 3) | wire_type);
+}
+
+fn write_bytes_field(buf: &mut Vec<u8>, field_number: u8, bytes: &[u8]) {
+    write_key(buf, field_number, WIRE_TYPE_LEN);
+    write_varint(buf, u64::try_from(bytes.len()).unwrap());
+    buf.extend_from_slice(bytes);
+}
+
+
+/// Encodes the samples as a Prometheus remote-write `WriteRequest` protobuf message.
+///
+/// The relevant parts of the schema (from prometheus/prompb) are:
+///
+/// ```protobuf
+/// message WriteRequest { repeated TimeSeries timeseries = 1; }
+/// message TimeSeries { repeated Label labels = 1; repeated Sample samples = 2; }
+/// message Label { string name = 1; string value = 2; }
+/// message Sample { double value = 1; int64 timestamp = 2; }
+/// ```
+pub fn encode_write_request(samples: &[MetricSample], timestamp_ms: i64) -> Vec<u8> {
+    let mut request = Vec::new();
+    for sample in samples {
+        let mut series = Vec::new();
+
+        // the metric name is a label too, and the labels must be sorted by name
+        let mut labels: Vec<(&str, &str)> = sample.labels.iter()
+            .map(|(k, v)| (k.as_str(), v.as_str()))
+            .collect();
+        labels.push(("__name__", sample.name.as_str()));
+        labels.sort_unstable();
+        for (name, value) in labels {
+            let mut label = Vec::new();
+            write_bytes_field(&mut label, 1, name.as_bytes());
+            write_bytes_field(&mut label, 2, value.as_bytes());
+            write_bytes_field(&mut series, 1, &label);
+        }
+
+        let mut value_and_timestamp = Vec::new();
+        write_key(&mut value_and_timestamp, 1, WIRE_TYPE_I64);
+        value_and_timestamp.extend_from_slice(&sample.value.to_le_bytes());
+        write_key(&mut value_and_timestamp, 2, WIRE_TYPE_VARINT);
+        write_varint(&mut value_and_timestamp, timestamp_ms as u64);
+        write_bytes_field(&mut series, 2, &value_and_timestamp);
+
+        write_bytes_field(&mut request, 1, &series);
+    }
+    request
+}
+
+
+/// Wraps the data in the snappy block format without actually compressing it, which every snappy
+/// decoder accepts.
+pub fn snappy_uncompressed(data: &[u8]) -> Vec<u8> {
+    let mut block = Vec::with_capacity(data.len() + data.len() / MAX_SNAPPY_LITERAL_LENGTH * 3 + 8);
+    write_varint(&mut block, u64::try_from(data.len()).unwrap());
+    for chunk in data.chunks(MAX_SNAPPY_LITERAL_LENGTH) {
+        // literal elements store their length minus one, inline if it is below 60
+        let length_minus_one = chunk.len() - 1;
+        if length_minus_one < 60 {
+            block.push(u8::try_from(length_minus_one << 2).unwrap());
+        } else if length_minus_one < 0x100 {
+            block.push(60 << 2);
+            block.push(u8::try_from(length_minus_one).unwrap());
+        } else {
+            block.push(61 << 2);
+            block.extend_from_slice(&u16::try_from(length_minus_one).unwrap().to_le_bytes());
+        }
+        block.extend_from_slice(chunk);
+    }
+    block
+}
+
+
+/// Pushes the samples to a Prometheus remote-write endpoint, passing the value of the
+/// `Authorization` header if the endpoint requires one.
+pub async fn push_remote_write(url: &HttpUrl, authorization: Option<&str>, samples: &[MetricSample], timestamp_ms: i64) -> Result<(), HttpError> {
+    let body = snappy_uncompressed(&encode_write_request(samples, timestamp_ms));
+    let mut headers = vec![
+        ("Content-Type", "application/x-protobuf"),
+        ("Content-Encoding", "snappy"),
+        ("X-Prometheus-Remote-Write-Version", "0.1.0"),
+        ("User-Agent", concat!("dns-sniff-exporter/", env!("CARGO_PKG_VERSION"))),
+    ];
+    if let Some(authorization) = authorization {
+        headers.push(("Authorization", authorization));
+    }
+    post(url, &headers, &body).await
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::{encode_write_request, snappy_uncompressed};
+    use crate::metrics::MetricSample;
+
+    #[test]
+    fn test_encode_write_request() {
+        let samples = [MetricSample {
+            name: "up".to_owned(),
+            labels: Vec::new(),
+            value: 1.0,
+        }];
+        let encoded = encode_write_request(&samples, 1000);
+        assert_eq!(
+            encoded,
+            [
+                0x0A, 0x1E, // timeseries
+                    0x0A, 0x0E, // label
+                        0x0A, 0x08, b'_', b'_', b'n', b'a', b'm', b'e', b'_', b'_',
+                        0x12, 0x02, b'u', b'p',
+                    0x12, 0x0C, // sample
+                        0x09, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0xF0, 0x3F,
+                        0x10, 0xE8, 0x07,
+            ],
+        );
+
+        let block = snappy_uncompressed(&encoded);
+        assert_eq!(&block[..2], &[0x20, 0x7C]);
+        assert_eq!(&block[2..], &encoded[..]);
+    }
+
+    #[test]
+    fn test_snappy_long_literals() {
+        let data = vec![0xAB; 70000];
+        let block = snappy_uncompressed(&data);
+        assert_eq!(&block[..3], &[0xF0, 0xA2, 0x04]); // 70000 as varint
+        assert_eq!(&block[3..6], &[61 << 2, 0xFF, 0xFF]);
+        assert_eq!(&block[6+65536..6+65536+3], &[61 << 2, 0x6F, 0x11]); // 4464 - 1
+        assert_eq!(block.len(), 3 + 3 + 65536 + 3 + 4464);
+    }
+}
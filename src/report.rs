@@ -0,0 +1,622 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::net::IpAddr;
+use std::path::Path;
+
+use clap::ValueEnum;
+use hickory_proto::op::ResponseCode;
+use serde_json::{json, Value};
+
+#[cfg(feature = "kubernetes")] use crate::kubernetes::PodInfo;
+use crate::stats::{AddressFamilyUsage, DnsStats, PerSourceStats};
+
+
+/// The latency quantiles estimated for a report unless others have been requested.
+pub const DEFAULT_REPORT_QUANTILES: [f64; 3] = [0.5, 0.9, 0.99];
+
+const REPORT_TOP_COUNT: usize = 10;
+
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq, ValueEnum)]
+pub enum ReportFormat {
+    Text,
+    Json,
+    Csv,
+}
+
+
+/// A table of values derived from the statistics, written as a CSV file or as part of a JSON
+/// report.
+struct Table {
+    name: &'static str,
+    columns: Vec<&'static str>,
+    rows: Vec<Vec<String>>,
+}
+impl Table {
+    fn from_stats(stats: &DnsStats) -> Vec<Self> {
+        let mut clients: Vec<(&IpAddr, &PerSourceStats)> = stats.source_to_stats.iter().collect();
+        clients.sort_unstable_by(|(a1, s1), (a2, s2)| s2.count.cmp(&s1.count).then_with(|| a1.cmp(a2)));
+        let client_rows = clients.into_iter()
+            .map(|(address, source_stats)| vec![address.to_string(), source_stats.count.to_string(), source_stats.query_type_entropy.to_string(), source_stats.fingerprint.name().to_owned()])
+            .collect();
+
+        let mut type_to_count: BTreeMap<String, u64> = BTreeMap::new();
+        for ((_opcode, _class, record_type), count) in &stats.query_kind_to_count {
+            let type_count = type_to_count.entry(record_type.to_string()).or_insert(0);
+            *type_count += *count;
+        }
+        let mut types: Vec<(String, u64)> = type_to_count.into_iter().collect();
+        types.sort_by(|(_t1, c1), (_t2, c2)| c2.cmp(c1));
+        let type_rows = types.into_iter()
+            .map(|(record_type, count)| vec![record_type, count.to_string()])
+            .collect();
+
+        // the busiest zones are found automatically while watched zones are always listed
+        let mut zone_rows: Vec<Vec<String>> = stats.top_zones.iter()
+            .map(|(zone, count)| vec![zone.clone(), count.to_string(), "false".to_owned()])
+            .collect();
+        for (zone, count) in &stats.watched_zone_to_query_count {
+            zone_rows.push(vec![zone.clone(), count.to_string(), "true".to_owned()]);
+        }
+
+        let scanner_rows = stats.suspected_scanners.iter()
+            .map(|(source, kinds)| {
+                let kind_names: Vec<&str> = kinds.iter().map(|k| k.name()).collect();
+                let query_count = stats.source_to_stats.get(source).map(|s| s.count).unwrap_or(0);
+                vec![source.to_string(), kind_names.join(" "), query_count.to_string()]
+            })
+            .collect();
+
+        // where do the server failures come from?
+        let mut zone_to_response_count: BTreeMap<&str, u64> = BTreeMap::new();
+        for ((zone, _response_code), count) in &stats.zone_response_code_to_count {
+            let response_count = zone_to_response_count.entry(zone.as_str()).or_insert(0);
+            *response_count += *count;
+        }
+        let mut servfail_zones: Vec<(&str, u64, u64)> = stats.zone_response_code_to_count.iter()
+            .filter(|((_zone, response_code), _count)| *response_code == ResponseCode::ServFail)
+            .map(|((zone, _response_code), count)| (zone.as_str(), *count, zone_to_response_count[zone.as_str()]))
+            .collect();
+        servfail_zones.sort_unstable_by(|(z1, c1, _), (z2, c2, _)| c2.cmp(c1).then_with(|| z1.cmp(z2)));
+        let servfail_zone_rows = servfail_zones.into_iter()
+            .take(REPORT_TOP_COUNT)
+            .map(|(zone, servfail_count, response_count)| vec![zone.to_owned(), servfail_count.to_string(), response_count.to_string()])
+            .collect();
+
+        let mut servfail_servers: Vec<(&IpAddr, &u64)> = stats.servfail_server_to_count.iter().collect();
+        servfail_servers.sort_unstable_by(|(s1, c1), (s2, c2)| c2.cmp(c1).then_with(|| s1.cmp(s2)));
+        let servfail_server_rows = servfail_servers.into_iter()
+            .take(REPORT_TOP_COUNT)
+            .map(|(server, count)| vec![server.to_string(), count.to_string()])
+            .collect();
+
+        // which names would break for IPv6-only clients?
+        let mut names_lacking_aaaa: Vec<(&String, &AddressFamilyUsage)> = stats.name_to_address_family_usage.iter()
+            .filter(|(_name, usage)| usage.lacks_aaaa())
+            .collect();
+        names_lacking_aaaa.sort_unstable_by(|(n1, u1), (n2, u2)| u2.a_query_count.cmp(&u1.a_query_count).then_with(|| n1.cmp(n2)));
+        let lacking_aaaa_rows = names_lacking_aaaa.into_iter()
+            .take(REPORT_TOP_COUNT)
+            .map(|(name, usage)| vec![name.clone(), usage.a_query_count.to_string(), usage.aaaa_query_count.to_string(), usage.aaaa_response_count.to_string()])
+            .collect();
+
+        let software_rows = stats.server_name_to_software_report.iter()
+            .map(|((server, name), text)| vec![server.to_string(), name.clone(), text.clone()])
+            .collect();
+
+        let tables = vec![
+            Self { name: "clients", columns: vec!["client", "queries", "query_type_entropy", "fingerprint"], rows: client_rows },
+            Self { name: "query_types", columns: vec!["type", "queries"], rows: type_rows },
+            Self { name: "zones", columns: vec!["zone", "queries", "watched"], rows: zone_rows },
+            Self { name: "suspected_scanners", columns: vec!["source", "kinds", "queries"], rows: scanner_rows },
+            Self { name: "servfail_zones", columns: vec!["zone", "servfail_responses", "responses"], rows: servfail_zone_rows },
+            Self { name: "servfail_servers", columns: vec!["server", "servfail_responses"], rows: servfail_server_rows },
+            Self { name: "server_software", columns: vec!["server", "name", "text"], rows: software_rows },
+            Self { name: "names_lacking_aaaa", columns: vec!["name", "a_queries", "aaaa_queries", "aaaa_responses"], rows: lacking_aaaa_rows },
+        ];
+
+        // the tables naming the clients are only added when their owners can be looked up
+        #[cfg(any(feature = "docker", feature = "kubernetes"))]
+        let mut tables = tables;
+
+        #[cfg(feature = "docker")]
+        {
+            let mut container_clients: Vec<(&IpAddr, &String)> = stats.source_to_stats.iter()
+                .filter_map(|(address, source_stats)| source_stats.container.as_ref().map(|container| (address, container)))
+                .collect();
+            container_clients.sort_unstable_by_key(|(address, _container)| *address);
+            let container_rows = container_clients.into_iter()
+                .map(|(address, container)| vec![address.to_string(), container.clone()])
+                .collect();
+            tables.push(Self { name: "client_containers", columns: vec!["client", "container"], rows: container_rows });
+        }
+
+        #[cfg(feature = "kubernetes")]
+        {
+            let mut pod_clients: Vec<(&IpAddr, &PodInfo)> = stats.source_to_stats.iter()
+                .filter_map(|(address, source_stats)| source_stats.pod.as_ref().map(|pod| (address, pod)))
+                .collect();
+            pod_clients.sort_unstable_by_key(|(address, _pod)| *address);
+            let pod_rows = pod_clients.into_iter()
+                .map(|(address, pod)| vec![address.to_string(), pod.namespace.clone(), pod.name.clone(), pod.workload.clone().unwrap_or_default()])
+                .collect();
+            tables.push(Self { name: "client_pods", columns: vec!["client", "namespace", "pod", "workload"], rows: pod_rows });
+        }
+
+        tables
+    }
+
+    fn to_json(&self) -> Value {
+        self.rows.iter()
+            .map(|row| {
+                let object: serde_json::Map<String, Value> = self.columns.iter()
+                    .zip(row.iter())
+                    .map(|(column, value)| ((*column).to_owned(), Value::from(value.as_str())))
+                    .collect();
+                Value::Object(object)
+            })
+            .collect()
+    }
+
+    fn write_csv<W: Write>(&self, writer: &mut W) -> io::Result<()> {
+        write_csv_record(writer, &self.columns)?;
+        for row in &self.rows {
+            let fields: Vec<&str> = row.iter()
+                .map(|f| f.as_str())
+                .collect();
+            write_csv_record(writer, &fields)?;
+        }
+        Ok(())
+    }
+}
+
+
+/// The parts of the statistics that end up in a report, in the order in which they are output.
+struct Report {
+    title: String,
+    summary: Vec<(&'static str, String)>,
+    top_clients: Vec<(String, u64)>,
+    top_names: Vec<(String, u64)>,
+    response_codes: Vec<(String, u64)>,
+    latency_quantiles: Vec<(f64, Option<f64>)>,
+    tables: Vec<Table>,
+}
+impl Report {
+    fn new(stats: &DnsStats) -> Self {
+        let mut title = "DNS report".to_owned();
+        if let Some(interface) = &stats.interface {
+            title.push_str(&format!(" for interface {}", interface));
+        }
+        if let Some(tenant) = &stats.tenant {
+            title.push_str(&format!(" for tenant {}", tenant));
+        }
+        if let Some(profile) = &stats.profile {
+            title.push_str(&format!(" in profile {}", profile));
+        }
+
+        let summary = vec![
+            ("duration_seconds", stats.actual_sample_duration.as_secs_f64().to_string()),
+            ("queries", stats.total_count.to_string()),
+            ("responses", stats.response_count.to_string()),
+            ("matched_responses", stats.matched_response_count.to_string()),
+            ("distinct_clients", stats.distinct_clients.estimate().to_string()),
+            ("distinct_query_names", stats.distinct_query_names.estimate().to_string()),
+            ("capture_completeness", stats.capture_loss.completeness().to_string()),
+        ];
+
+        let top_clients = stats.top_clients.top().into_iter()
+            .take(REPORT_TOP_COUNT)
+            .map(|(client, count)| (client.to_string(), count.count))
+            .collect();
+        let top_names = stats.top_query_names.top().into_iter()
+            .take(REPORT_TOP_COUNT)
+            .map(|(name, count)| (name, count.count))
+            .collect();
+
+        let mut response_codes: Vec<(String, u64)> = stats.response_code_to_count.iter()
+            .map(|(response_code, count)| (response_code.to_str().to_owned(), *count))
+            .collect();
+        response_codes.sort_unstable_by(|(n1, c1), (n2, c2)| c2.cmp(c1).then_with(|| n1.cmp(n2)));
+
+        let latency_quantiles = stats.latency_quantiles.as_ref()
+            .map(|lq| lq.quantiles())
+            .unwrap_or_default();
+
+        Self {
+            title,
+            summary,
+            top_clients,
+            top_names,
+            response_codes,
+            latency_quantiles,
+            tables: Table::from_stats(stats),
+        }
+    }
+
+    fn write_text<W: Write>(&self, writer: &mut W) -> io::Result<()> {
+        writeln!(writer, "{}", self.title)?;
+        for (key, value) in &self.summary {
+            writeln!(writer, "  {}: {}", key, value)?;
+        }
+        let sections = [
+            ("top clients", &self.top_clients),
+            ("top query names", &self.top_names),
+            ("response codes", &self.response_codes),
+        ];
+        for (heading, entries) in sections {
+            writeln!(writer, "{}:", heading)?;
+            for (key, count) in entries {
+                writeln!(writer, "  {:>10}  {}", count, key)?;
+            }
+        }
+        writeln!(writer, "latency:")?;
+        for (p, estimate) in &self.latency_quantiles {
+            match estimate {
+                Some(e) => writeln!(writer, "  p{}: {:.3} ms", p * 100.0, e * 1000.0)?,
+                None => writeln!(writer, "  p{}: -", p * 100.0)?,
+            }
+        }
+        Ok(())
+    }
+
+    fn to_json(&self) -> Value {
+        let counts_to_json = |entries: &[(String, u64)]| -> Value {
+            entries.iter()
+                .map(|(key, count)| json!({"key": key, "count": count}))
+                .collect()
+        };
+        let summary: serde_json::Map<String, Value> = self.summary.iter()
+            .map(|(key, value)| ((*key).to_owned(), Value::from(value.as_str())))
+            .collect();
+        let latency_quantiles: Vec<Value> = self.latency_quantiles.iter()
+            .map(|(p, estimate)| json!({"quantile": p, "seconds": estimate}))
+            .collect();
+        let tables: serde_json::Map<String, Value> = self.tables.iter()
+            .map(|t| (t.name.to_owned(), t.to_json()))
+            .collect();
+        json!({
+            "title": self.title,
+            "summary": summary,
+            "top_clients": counts_to_json(&self.top_clients),
+            "top_query_names": counts_to_json(&self.top_names),
+            "response_codes": counts_to_json(&self.response_codes),
+            "latency_quantiles": latency_quantiles,
+            "tables": tables,
+        })
+    }
+
+    fn write_csv<W: Write>(&self, writer: &mut W) -> io::Result<()> {
+        for (key, value) in &self.summary {
+            write_csv_row(writer, &self.title, "summary", key, value)?;
+        }
+        let sections = [
+            ("top_client", &self.top_clients),
+            ("top_query_name", &self.top_names),
+            ("response_code", &self.response_codes),
+        ];
+        for (section, entries) in sections {
+            for (key, count) in entries {
+                write_csv_row(writer, &self.title, section, key, &count.to_string())?;
+            }
+        }
+        for (p, estimate) in &self.latency_quantiles {
+            let estimate_str = estimate.map(|e| e.to_string()).unwrap_or_default();
+            write_csv_row(writer, &self.title, "latency_quantile_seconds", &p.to_string(), &estimate_str)?;
+        }
+        Ok(())
+    }
+}
+
+
+/// Quotes a CSV field if necessary (RFC 4180 § 2).
+fn csv_field(value: &str) -> String {
+    if value.contains(|c| c == ',' || c == '"' || c == '\r' || c == '\n') {
+        format!("\"{}\"", value.replace('"', "\"\""))
+    } else {
+        value.to_owned()
+    }
+}
+
+fn write_csv_record<W: Write>(writer: &mut W, fields: &[&str]) -> io::Result<()> {
+    let quoted_fields: Vec<String> = fields.iter()
+        .map(|f| csv_field(f))
+        .collect();
+    write!(writer, "{}\r\n", quoted_fields.join(","))
+}
+
+fn write_csv_row<W: Write>(writer: &mut W, report: &str, section: &str, key: &str, value: &str) -> io::Result<()> {
+    write_csv_record(writer, &[report, section, key, value])
+}
+
+
+/// The counts of a report that can be compared with those of another report.
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct ReportCounts {
+    sections: BTreeMap<&'static str, BTreeMap<String, u64>>,
+}
+impl ReportCounts {
+    const SECTIONS: [&'static str; 3] = ["top_clients", "top_query_names", "response_codes"];
+
+    pub fn from_stats(stats: &DnsStats) -> Self {
+        let report = Report::new(stats);
+        let entries = [report.top_clients, report.top_names, report.response_codes];
+        let sections = Self::SECTIONS.into_iter()
+            .zip(entries)
+            .map(|(section, counts)| (section, counts.into_iter().collect()))
+            .collect();
+        Self {
+            sections,
+        }
+    }
+
+    /// Reads the counts from a single report as output in the JSON format.
+    pub fn from_json(report: &Value) -> Option<Self> {
+        let mut sections = BTreeMap::new();
+        for section in Self::SECTIONS {
+            let mut counts = BTreeMap::new();
+            for entry in report.get(section)?.as_array()? {
+                let key = entry.get("key")?.as_str()?;
+                let count = entry.get("count")?.as_u64()?;
+                counts.insert(key.to_owned(), count);
+            }
+            sections.insert(section, counts);
+        }
+        Some(Self {
+            sections,
+        })
+    }
+}
+
+
+/// Writes the entries whose counts changed the most between the two reports.
+pub fn write_diff<W: Write>(before: &ReportCounts, after: &ReportCounts, writer: &mut W) -> io::Result<()> {
+    let empty = BTreeMap::new();
+    for section in ReportCounts::SECTIONS {
+        let before_counts = before.sections.get(section).unwrap_or(&empty);
+        let after_counts = after.sections.get(section).unwrap_or(&empty);
+        let keys: BTreeSet<&String> = before_counts.keys().chain(after_counts.keys()).collect();
+
+        let mut changes: Vec<(&String, u64, u64)> = keys.into_iter()
+            .map(|k| (k, before_counts.get(k).copied().unwrap_or(0), after_counts.get(k).copied().unwrap_or(0)))
+            .filter(|(_k, b, a)| b != a)
+            .collect();
+        changes.sort_by(|(k1, b1, a1), (k2, b2, a2)| b2.abs_diff(*a2).cmp(&b1.abs_diff(*a1)).then_with(|| k1.cmp(k2)));
+        changes.truncate(REPORT_TOP_COUNT);
+
+        writeln!(writer, "{}:", section)?;
+        for (key, before_count, after_count) in changes {
+            let change = i128::from(after_count) - i128::from(before_count);
+            writeln!(writer, "  {:>+10}  {} ({} -> {})", change, key, before_count, after_count)?;
+        }
+    }
+    Ok(())
+}
+
+
+/// Writes the tables (clients, query types, zones, suspected scanners and the origins of server
+/// failures) of each set of statistics as CSV files into the given directory.
+///
+/// The file names are prefixed with the interface, the tenant and the profile of the statistics, if
+/// any.
+pub fn write_csv_tables(all_stats: &[DnsStats], directory: &Path) -> io::Result<()> {
+    for stats in all_stats {
+        let mut prefix = String::new();
+        if let Some(interface) = &stats.interface {
+            prefix.push_str(&format!("interface-{}-", interface));
+        }
+        if let Some(tenant) = &stats.tenant {
+            prefix.push_str(&format!("tenant-{}-", tenant));
+        }
+        if let Some(profile) = &stats.profile {
+            prefix.push_str(&format!("profile-{}-", profile));
+        }
+        let prefix = prefix.replace(|c| c == '/' || c == '\\', "_");
+
+        for table in Table::from_stats(stats) {
+            let file = File::create(directory.join(format!("{}{}.csv", prefix, table.name)))?;
+            let mut writer = BufWriter::new(file);
+            table.write_csv(&mut writer)?;
+            writer.flush()?;
+        }
+    }
+    Ok(())
+}
+
+
+/// Writes a human- or machine-readable summary of the statistics.
+pub fn write_report<W: Write>(all_stats: &[DnsStats], format: ReportFormat, writer: &mut W) -> io::Result<()> {
+    let reports: Vec<Report> = all_stats.iter()
+        .map(|s| Report::new(s))
+        .collect();
+    match format {
+        ReportFormat::Text => {
+            for (i, report) in reports.iter().enumerate() {
+                if i > 0 {
+                    writeln!(writer)?;
+                }
+                report.write_text(writer)?;
+            }
+        },
+        ReportFormat::Json => {
+            let json_reports: Vec<Value> = reports.iter()
+                .map(|r| r.to_json())
+                .collect();
+            writeln!(writer, "{}", Value::Array(json_reports))?;
+        },
+        ReportFormat::Csv => {
+            write_csv_record(writer, &["report", "section", "key", "value"])?;
+            for report in &reports {
+                report.write_csv(writer)?;
+            }
+        },
+    }
+    Ok(())
+}
+
+
+#[cfg(test)]
+mod tests {
+    use std::net::IpAddr;
+
+    use hickory_proto::op::ResponseCode;
+    use hickory_proto::rr::RecordType;
+
+    use serde_json::json;
+
+    use super::{ReportCounts, ReportFormat, Table, write_diff, write_report};
+    use crate::scanner::ScannerKind;
+    use crate::stats::DnsStats;
+
+    #[test]
+    fn test_csv_report() {
+        let mut stats = DnsStats::new();
+        stats.tenant = Some("acme, inc.".to_owned());
+        let client: IpAddr = "192.0.2.1".parse().unwrap();
+        stats.top_clients.add(&client);
+        stats.top_query_names.add(&"a,b.example".to_owned());
+        stats.response_code_to_count.insert(ResponseCode::NXDomain, 2);
+        stats.response_code_to_count.insert(ResponseCode::NoError, 5);
+
+        let mut output = Vec::new();
+        write_report(&[stats], ReportFormat::Csv, &mut output).unwrap();
+        let output = String::from_utf8(output).unwrap();
+        let lines: Vec<&str> = output.split("\r\n").collect();
+        assert_eq!(lines[0], "report,section,key,value");
+        assert_eq!(lines[2], "\"DNS report for tenant acme, inc.\",summary,queries,0");
+        assert!(lines.contains(&"\"DNS report for tenant acme, inc.\",top_client,192.0.2.1,1"));
+        assert!(lines.contains(&"\"DNS report for tenant acme, inc.\",top_query_name,\"a,b.example\",1"));
+
+        // response codes are sorted by descending count
+        let no_error_index = lines.iter().position(|l| l.contains("response_code,No Error,5")).unwrap();
+        let nx_domain_index = lines.iter().position(|l| l.contains("response_code,Non-Existent Domain,2")).unwrap();
+        assert!(no_error_index < nx_domain_index);
+    }
+
+    #[test]
+    fn test_diff() {
+        let before = ReportCounts::from_json(&json!({
+            "top_clients": [{"key": "192.0.2.1", "count": 10}, {"key": "192.0.2.2", "count": 5}],
+            "top_query_names": [{"key": "example.com", "count": 15}],
+            "response_codes": [{"key": "No Error", "count": 15}],
+        })).unwrap();
+        let mut stats = DnsStats::new();
+        let client: IpAddr = "192.0.2.2".parse().unwrap();
+        for _ in 0..8 {
+            stats.top_clients.add(&client);
+            stats.top_query_names.add(&"example.com".to_owned());
+        }
+        stats.response_code_to_count.insert(ResponseCode::NoError, 6);
+        stats.response_code_to_count.insert(ResponseCode::ServFail, 2);
+        let after = ReportCounts::from_stats(&stats);
+
+        let mut output = Vec::new();
+        write_diff(&before, &after, &mut output).unwrap();
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            concat!(
+                "top_clients:\n",
+                "         -10  192.0.2.1 (10 -> 0)\n",
+                "          +3  192.0.2.2 (5 -> 8)\n",
+                "top_query_names:\n",
+                "          -7  example.com (15 -> 8)\n",
+                "response_codes:\n",
+                "          -9  No Error (15 -> 6)\n",
+                "          +2  Server Failure (0 -> 2)\n",
+            ),
+        );
+
+        assert_eq!(ReportCounts::from_json(&json!({"top_clients": []})), None);
+    }
+
+    #[test]
+    fn test_tables() {
+        let mut stats = DnsStats::new();
+        stats.top_zones.push(("example.com".to_owned(), 3));
+        stats.watched_zone_to_query_count.insert("example.net".to_owned(), 1);
+
+        let tables = Table::from_stats(&stats);
+        let zones = tables.iter().find(|t| t.name == "zones").unwrap();
+
+        let mut output = Vec::new();
+        zones.write_csv(&mut output).unwrap();
+        assert_eq!(String::from_utf8(output).unwrap(), "zone,queries,watched\r\nexample.com,3,false\r\nexample.net,1,true\r\n");
+        assert_eq!(
+            zones.to_json(),
+            json!([
+                {"zone": "example.com", "queries": "3", "watched": "false"},
+                {"zone": "example.net", "queries": "1", "watched": "true"},
+            ]),
+        );
+
+        let scanner: IpAddr = "192.0.2.66".parse().unwrap();
+        stats.suspected_scanners.insert(scanner, vec![ScannerKind::AnyFlood, ScannerKind::VersionProbe]);
+        let tables = Table::from_stats(&stats);
+        let scanners = tables.iter().find(|t| t.name == "suspected_scanners").unwrap();
+        assert_eq!(scanners.to_json(), json!([{"source": "192.0.2.66", "kinds": "any_flood version_probe", "queries": "0"}]));
+    }
+
+    #[test]
+    fn test_servfail_tables() {
+        let mut stats = DnsStats::new();
+        let server: IpAddr = "192.0.2.53".parse().unwrap();
+        let other_server: IpAddr = "192.0.2.54".parse().unwrap();
+        stats.add_zone_response_code(server, "example.com", ResponseCode::NoError);
+        stats.add_zone_response_code(server, "example.com", ResponseCode::ServFail);
+        stats.add_zone_response_code(server, "broken.example", ResponseCode::ServFail);
+        stats.add_zone_response_code(other_server, "broken.example", ResponseCode::ServFail);
+
+        let tables = Table::from_stats(&stats);
+        let zones = tables.iter().find(|t| t.name == "servfail_zones").unwrap();
+        assert_eq!(zones.rows, vec![
+            vec!["broken.example".to_owned(), "2".to_owned(), "2".to_owned()],
+            vec!["example.com".to_owned(), "1".to_owned(), "2".to_owned()],
+        ]);
+        let servers = tables.iter().find(|t| t.name == "servfail_servers").unwrap();
+        assert_eq!(servers.rows, vec![
+            vec!["192.0.2.53".to_owned(), "2".to_owned()],
+            vec!["192.0.2.54".to_owned(), "1".to_owned()],
+        ]);
+    }
+
+    #[test]
+    fn test_names_lacking_aaaa() {
+        let mut stats = DnsStats::new();
+        for _ in 0..3 {
+            stats.add_address_family_query("legacy.example.com", RecordType::A);
+        }
+        stats.add_address_family_query("legacy.example.com", RecordType::AAAA);
+        stats.add_aaaa_response("legacy.example.com", false);
+        stats.add_address_family_query("v4only.example.com", RecordType::A);
+        stats.add_address_family_query("dual.example.com", RecordType::A);
+        stats.add_address_family_query("dual.example.com", RecordType::AAAA);
+        stats.add_aaaa_response("dual.example.com", true);
+        stats.add_address_family_query("dual.example.com", RecordType::MX);
+
+        let tables = Table::from_stats(&stats);
+        let names = tables.iter().find(|t| t.name == "names_lacking_aaaa").unwrap();
+        assert_eq!(names.rows, vec![
+            vec!["legacy.example.com".to_owned(), "3".to_owned(), "1".to_owned(), "1".to_owned()],
+            vec!["v4only.example.com".to_owned(), "1".to_owned(), "0".to_owned(), "0".to_owned()],
+        ]);
+    }
+
+    #[test]
+    fn test_server_software() {
+        let mut stats = DnsStats::new();
+        let server: IpAddr = "192.0.2.53".parse().unwrap();
+        stats.add_software_report(server, "version.bind", "9.16.1".to_owned());
+        stats.add_software_report(server, "hostname.bind", "ns1".to_owned());
+        stats.add_software_report(server, "version.bind", "9.18.24".to_owned());
+
+        let tables = Table::from_stats(&stats);
+        let software = tables.iter().find(|t| t.name == "server_software").unwrap();
+        assert_eq!(
+            software.to_json(),
+            json!([
+                {"server": "192.0.2.53", "name": "hostname.bind", "text": "ns1"},
+                {"server": "192.0.2.53", "name": "version.bind", "text": "9.18.24"},
+            ]),
+        );
+    }
+}
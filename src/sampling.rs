@@ -1,43 +1,115 @@
+use std::collections::{BTreeMap, HashMap};
 use std::fmt;
-use std::time::{Duration, Instant};
+use std::io;
+use std::net::{IpAddr, SocketAddr};
+use std::path::Path;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::Duration;
 
-use chrono::{TimeZone, Utc};
-use pcap::{Capture, Device};
+use chrono::{DateTime, Utc};
+use macaddr::MacAddr6;
+#[cfg(feature = "libpcap")] use pcap::{Capture, Direction};
 use tokio::sync::mpsc;
-use tracing::{debug, error, warn};
-use trust_dns_proto::op::{Message, MessageType};
-use trust_dns_proto::serialize::binary::BinDecodable;
+use tokio::time::{Instant, Interval, sleep_until};
+use tokio::time::MissedTickBehavior;
+#[cfg(feature = "http")] use serde_json::json;
+use tracing::{debug, debug_span, error, info, warn};
+use hickory_proto::op::{Message, ResponseCode};
+use hickory_proto::rr::{DNSClass, Name, RData, Record, RecordType};
 
-use crate::ethernet::{
-    EthernetHeader, ETHERTYPE_IPV4, ETHERTYPE_IPV6, ETHERTYPE_VLAN_TAG, VlanTagHeader,
+use crate::anomaly::AnomalyDetector;
+use crate::answer_watch::AnswerWatchlist;
+use crate::app_category::AppCategories;
+use crate::arp::ArpPacket;
+use crate::blocklist::{Blocklist, BlocklistSource, normalize_name};
+use crate::cast::CastKind;
+use crate::capture::{
+    CaptureBackend, CaptureBackendKind, CaptureError, CaptureStats, compile_filter, list_interfaces,
+    open_capture_file, open_raw_capture,
 };
-use crate::ip::{IpHeader, Ipv4Header, Ipv6Header, PROTO_UDP};
-use crate::packet::{OwnedPacket, PacketDissection};
-use crate::stats::DnsStats;
-use crate::tcp_udp::UdpHeader;
+use crate::client_capture::ClientCapture;
+use crate::comparison::InterfaceComparison;
+use crate::correlation::{CorrelationOutcome, CorrelationTable, FlowKey};
+use crate::dhcp::DhcpTracker;
+use crate::dissect::{DetailLevel, dissect_frame, DnsEvent};
+#[cfg(feature = "docker")] use crate::docker::ContainerDirectory;
+use crate::dns::Opcode;
+use crate::edns::{CookieUse, ExtendedError};
+use crate::flight_recorder::FlightRecorder;
+use crate::icmp::{IcmpFailureReason, NeighborDiscovery};
+use crate::interface::{Device, interface_members};
+use crate::ip::{IpHeader, mask_address};
+use crate::log_limit::WarningLimiter;
+use crate::netns::enter_netns;
+use crate::nod::{NodTracker, registered_domain};
+use crate::packet::{Linktype, OwnedPacket, Precision};
+use crate::packet_filter::{filter_accepts, FilterInstruction};
+use crate::qname_min::QnameMinimizationTracker;
+#[cfg(feature = "passive-dns")] use crate::passive_dns::PassiveDnsStore;
+use crate::quarantine::MalformedQuarantine;
+use crate::record_type::DEFAULT_DEPRECATED_RECORD_TYPES;
+use crate::scanner::VERSION_PROBE_NAMES;
+#[cfg(feature = "scripting")] use crate::script::{ScriptEvent, ScriptHook, ScriptVerdict};
+use crate::sink::{DnsMessageEvent, EventSink};
+use crate::stage_timer::{PipelineStage, StageTimer};
+use crate::stats::{BlocklistHit, DnsStats, RuntimeStats, ZoneOperation};
+#[cfg(feature = "tcp-tracking")] use crate::tcp_connection::TcpConnectionTracker;
+#[cfg(feature = "tcp-tracking")] use crate::tcp_udp::TcpHeader;
+use crate::tenant::{TenantMap, TenantSelector};
+#[cfg(feature = "http")] use crate::webhook::WebhookNotifier;
+#[cfg(feature = "tcp-tracking")] use crate::zone_transfer::ZoneTransferTracker;
 
 
-#[derive(Debug, Eq, PartialEq)]
+#[derive(Debug)]
 pub enum SamplingError {
-    GetInterfaceList(pcap::Error),
+    #[cfg(feature = "libpcap")] GetInterfaceList(pcap::Error),
+    ListInterfaces(io::Error),
     InterfaceIndexTooHigh { index: usize, count: usize },
-    ConvertCaptureDevice(pcap::Error),
-    OpenCaptureDevice(pcap::Error),
-    SetFilter(pcap::Error),
+    #[cfg(feature = "libpcap")] ConvertCaptureDevice(pcap::Error),
+    #[cfg(feature = "libpcap")] OpenCaptureDevice(pcap::Error),
+    OpenRawCapture { interface: String, error: CaptureError },
+    ListMembers { interface: String, error: io::Error },
+    EnterNetworkNamespace(io::Error),
+    SetFilter { linktype: Linktype, error: CaptureError },
+    #[cfg(feature = "libpcap")] SetDirection(pcap::Error),
+    OpenCaptureFile(CaptureError),
+    ReadCaptureFile(CaptureError),
 }
 impl fmt::Display for SamplingError {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match self {
+            #[cfg(feature = "libpcap")]
             Self::GetInterfaceList(e)
                 => write!(f, "error getting interface list: {}", e),
+            Self::ListInterfaces(e)
+                => write!(f, "error listing network interfaces: {}", e),
             Self::InterfaceIndexTooHigh { index, count }
                 => write!(f, "requested device with index {} but system only lists {} devices", index, count),
+            #[cfg(feature = "libpcap")]
             Self::ConvertCaptureDevice(e)
                 => write!(f, "failed to convert the device into a capture: {}", e),
+            #[cfg(feature = "libpcap")]
             Self::OpenCaptureDevice(e)
                 => write!(f, "failed to open the capture device: {}", e),
-            Self::SetFilter(e)
-                => write!(f, "failed to set capture filter: {}", e),
+            Self::OpenRawCapture { interface, error }
+                => write!(f, "failed to open raw socket capture on {}: {}", interface, error),
+            Self::ListMembers { interface, error }
+                => write!(f, "failed to list the members of {}: {}", interface, error),
+            Self::EnterNetworkNamespace(e)
+                => write!(f, "failed to enter network namespace: {}", e),
+            Self::SetFilter { linktype, error }
+                => write!(
+                    f, "failed to compile capture filter for link type {}: {}",
+                    linktype.name().map(|n| n.to_owned()).unwrap_or_else(|| linktype.0.to_string()), error,
+                ),
+            #[cfg(feature = "libpcap")]
+            Self::SetDirection(e)
+                => write!(f, "failed to restrict the capture to inbound packets: {}", e),
+            Self::OpenCaptureFile(e)
+                => write!(f, "failed to open the capture file: {}", e),
+            Self::ReadCaptureFile(e)
+                => write!(f, "failed to read from the capture file: {}", e),
         }
     }
 }
@@ -45,170 +117,1956 @@ impl std::error::Error for SamplingError {
 }
 
 
-pub async fn collect_sample(
+const DNS_CAPTURE_FILTER: &str = "udp port 53 or tcp port 53";
+const DNS_PORT: u16 = 53;
+
+/// The size above which responses are considered large; the EDNS buffer size recommended by DNS
+/// Flag Day 2020 to avoid fragmentation.
+pub const DEFAULT_LARGE_RESPONSE_BYTES: usize = 1232;
+const DEFAULT_WARNING_SUMMARY_SECS: u64 = 60;
+
+// the packet queue fill levels (in percent) at which the detail is reduced and restored
+const REDUCE_DETAIL_QUEUE_PERCENT: usize = 90;
+const RESTORE_DETAIL_QUEUE_PERCENT: usize = 50;
+
+// how long to wait before trying to open a failed capture again; doubles with each attempt
+const MIN_REOPEN_DELAY_MS: u64 = 500;
+const MAX_REOPEN_DELAY_MS: u64 = 60_000;
+
+// resolvers give up on longer CNAME chains; BIND stops after 16 aliases
+const MAX_CNAME_CHAIN_LENGTH: usize = 16;
+
+
+/// Configuration and long-lived state consulted while processing the packets of a sample.
+pub struct SampleContext {
+    pub global_labels: BTreeMap<String, String>,
+    pub sanctioned_resolvers: Vec<IpAddr>,
+    pub watched_zones: Vec<String>,
+    pub tenants: TenantMap,
+    pub profile_name: Option<String>,
+    pub profile_selector: Option<TenantSelector>, // None to look at all traffic
+    pub profile_filter: Option<String>, // narrows down the frames a profile looks at
+    profile_filter_programs: HashMap<Linktype, Option<Vec<FilterInstruction>>>, // None if the filter does not compile
+    pub profiles: Vec<SampleContext>,
+    pub quantiles: Vec<f64>,
+    pub server_latency_bounds: Vec<Duration>,
+    pub server_latency_label_sets: usize, // 0 to disable
+    pub large_response_bytes: usize,
+    pub deprecated_record_types: Vec<u16>,
+    pub blocklist: Blocklist,
+    pub blocklist_sources: Vec<BlocklistSource>,
+    pub blocklist_refresh_interval: Option<Duration>, // None to load the blocklists only once
+    pub answer_watchlist: AnswerWatchlist,
+    pub app_categories: AppCategories,
+    pub nod_tracker: Option<NodTracker>,
+    pub qname_minimization: Option<QnameMinimizationTracker>,
+    pub aggregate_answer_addresses: bool,
+    pub correlation_table: CorrelationTable,
+    #[cfg(feature = "tcp-tracking")]
+    pub tcp_connections: TcpConnectionTracker,
+    #[cfg(feature = "tcp-tracking")]
+    pub zone_transfers: ZoneTransferTracker,
+    pub neighbors: Option<HashMap<IpAddr, MacAddr6>>,
+    pub dhcp_tracker: Option<DhcpTracker>,
+    pub interface_comparison: Option<InterfaceComparison>,
+    pub anomaly_detector: Option<AnomalyDetector>,
+    #[cfg(feature = "http")]
+    pub webhook: Option<WebhookNotifier>,
+    pub flight_recorder: Option<FlightRecorder>,
+    pub malformed_quarantine: Option<MalformedQuarantine>,
+    pub client_captures: Vec<ClientCapture>,
+    pub warning_limiter: WarningLimiter,
+    pub adaptive_detail: bool,
+    pub detail_level: DetailLevel,
+    pub event_sinks: Vec<Box<dyn EventSink>>,
+    #[cfg(feature = "passive-dns")]
+    pub passive_dns_store: Option<PassiveDnsStore>,
+    #[cfg(feature = "scripting")]
+    pub script_hook: Option<ScriptHook>,
+    #[cfg(feature = "docker")]
+    pub container_directory: Option<ContainerDirectory>,
+}
+impl SampleContext {
+    pub fn new(correlation_window: chrono::Duration) -> Self {
+        Self {
+            global_labels: BTreeMap::new(),
+            sanctioned_resolvers: Vec::new(),
+            watched_zones: Vec::new(),
+            tenants: TenantMap::new(),
+            profile_name: None,
+            profile_selector: None,
+            profile_filter: None,
+            profile_filter_programs: HashMap::new(),
+            profiles: Vec::new(),
+            quantiles: Vec::new(),
+            server_latency_bounds: Vec::new(),
+            server_latency_label_sets: 0,
+            large_response_bytes: DEFAULT_LARGE_RESPONSE_BYTES,
+            deprecated_record_types: DEFAULT_DEPRECATED_RECORD_TYPES.to_vec(),
+            blocklist: Blocklist::new(),
+            blocklist_sources: Vec::new(),
+            blocklist_refresh_interval: None,
+            answer_watchlist: AnswerWatchlist::new(),
+            app_categories: AppCategories::new(),
+            nod_tracker: None,
+            qname_minimization: None,
+            aggregate_answer_addresses: false,
+            correlation_table: CorrelationTable::new(correlation_window),
+            #[cfg(feature = "tcp-tracking")]
+            tcp_connections: TcpConnectionTracker::new(),
+            #[cfg(feature = "tcp-tracking")]
+            zone_transfers: ZoneTransferTracker::new(),
+            neighbors: None,
+            dhcp_tracker: None,
+            interface_comparison: None,
+            anomaly_detector: None,
+            #[cfg(feature = "http")]
+            webhook: None,
+            flight_recorder: None,
+            malformed_quarantine: None,
+            client_captures: Vec::new(),
+            warning_limiter: WarningLimiter::new(Duration::from_secs(DEFAULT_WARNING_SUMMARY_SECS)),
+            adaptive_detail: true,
+            detail_level: DetailLevel::Full,
+            event_sinks: Vec::new(),
+            #[cfg(feature = "passive-dns")]
+            passive_dns_store: None,
+            #[cfg(feature = "scripting")]
+            script_hook: None,
+            #[cfg(feature = "docker")]
+            container_directory: None,
+        }
+    }
+
+    /// Creates the context of a profile, which starts out with the configuration of this context
+    /// but keeps its own state and sinks.
+    pub fn new_profile(&self, name: String, correlation_window: chrono::Duration) -> Self {
+        let mut profile = Self::new(correlation_window);
+        profile.profile_name = Some(name);
+        profile.global_labels = self.global_labels.clone();
+        profile.sanctioned_resolvers = self.sanctioned_resolvers.clone();
+        profile.watched_zones = self.watched_zones.clone();
+        profile.tenants = self.tenants.clone();
+        profile.quantiles = self.quantiles.clone();
+        profile.server_latency_bounds = self.server_latency_bounds.clone();
+        profile.server_latency_label_sets = self.server_latency_label_sets;
+        profile.large_response_bytes = self.large_response_bytes;
+        profile.deprecated_record_types = self.deprecated_record_types.clone();
+        profile.blocklist = self.blocklist.clone();
+        profile.answer_watchlist = self.answer_watchlist.clone();
+        profile.app_categories = self.app_categories.clone();
+        // the domains new to the profile are logged by the main statistics already
+        profile.nod_tracker = self.nod_tracker.as_ref().map(|nt| nt.fork());
+        profile.qname_minimization = self.qname_minimization.as_ref()
+            .map(|qm| QnameMinimizationTracker::new(qm.window()));
+        profile.aggregate_answer_addresses = self.aggregate_answer_addresses;
+        profile.warning_limiter = WarningLimiter::new(self.warning_limiter.interval());
+        profile.adaptive_detail = self.adaptive_detail;
+        profile
+    }
+
+    /// Returns whether a frame passes the capture filter of this profile. The filter is compiled
+    /// for each link type when a frame of it first turns up.
+    fn profile_filter_accepts(&mut self, packet: &OwnedPacket, linktype: Linktype) -> bool {
+        let filter = match &self.profile_filter {
+            Some(f) => f,
+            None => return true,
+        };
+        let program = self.profile_filter_programs.entry(linktype)
+            .or_insert_with(|| match compile_filter(linktype, filter) {
+                Ok(p) => Some(p),
+                Err(e) => {
+                    error!("failed to compile profile capture filter {:?} for link type {:?}, skipping its frames: {}", filter, linktype, e);
+                    None
+                },
+            });
+        match program {
+            Some(p) => filter_accepts(p, &packet.data, packet.header.len),
+            None => false,
+        }
+    }
+}
+
+
+/// The statistics collected on one interface (or all of them, if merged), split up by tenant, along
+/// with those of each profile.
+struct InterfaceStatistics {
+    untenanted: DnsStats,
+    tenant_to_stats: BTreeMap<String, DnsStats>,
+    profiles: Vec<InterfaceStatistics>, // in the order of SampleContext::profiles
+}
+impl InterfaceStatistics {
+    fn new(interface: Option<String>, context: &SampleContext) -> Self {
+        let make_stats = |tenant: Option<&str>| {
+            let mut statistics = DnsStats::new();
+            statistics.interface = interface.clone();
+            statistics.tenant = tenant.map(|t| t.to_owned());
+            statistics.profile = context.profile_name.clone();
+            if context.quantiles.len() > 0 {
+                statistics.enable_quantiles(&context.quantiles);
+            }
+            if context.server_latency_label_sets > 0 {
+                statistics.enable_server_type_latency(&context.server_latency_bounds, context.server_latency_label_sets);
+            }
+            statistics.watch_deprecated_record_types(&context.deprecated_record_types);
+            statistics
+        };
+        let tenant_to_stats = context.tenants.tenant_names().into_iter()
+            .map(|t| (t.to_owned(), make_stats(Some(t))))
+            .collect();
+        let profiles = context.profiles.iter()
+            .map(|profile_context| Self::new(interface.clone(), profile_context))
+            .collect();
+        Self {
+            untenanted: make_stats(None),
+            tenant_to_stats,
+            profiles,
+        }
+    }
+
+    fn for_tenant(&mut self, tenant: Option<&str>) -> &mut DnsStats {
+        match tenant {
+            Some(t) => self.tenant_to_stats.get_mut(t).unwrap(),
+            None => &mut self.untenanted,
+        }
+    }
+
+    fn iter_mut(&mut self) -> impl Iterator<Item = &mut DnsStats> {
+        std::iter::once(&mut self.untenanted)
+            .chain(self.tenant_to_stats.values_mut())
+    }
+}
+
+
+/// Follows the chain of CNAME records starting at the given name, returning the normalized names of
+/// the aliases' targets in order. Chains that loop or exceed [`MAX_CNAME_CHAIN_LENGTH`] are cut off.
+fn follow_cname_chain(name: &Name, answers: &[Record]) -> Vec<String> {
+    let mut targets = Vec::new();
+    let mut current = normalize_name(name);
+
+    // each record can only be part of the chain once; anything longer is a loop
+    while targets.len() < answers.len().min(MAX_CNAME_CHAIN_LENGTH) {
+        let next = answers.iter()
+            .filter_map(|r| match r.data() {
+                Some(RData::CNAME(target)) if normalize_name(r.name()) == current => Some(&target.0),
+                _ => None,
+            })
+            .next();
+        match next {
+            Some(target) => {
+                current = normalize_name(target);
+                targets.push(current.clone());
+            },
+            None => break,
+        }
+    }
+
+    targets
+}
+
+
+fn is_zone_transfer(record_type: RecordType) -> bool {
+    record_type == RecordType::AXFR || record_type == RecordType::IXFR
+}
+
+
+/// Checks whether the given name is the given zone or one of its subdomains (both normalized).
+fn is_in_zone(name: &str, zone: &str) -> bool {
+    name == zone
+        || zone.len() == 0
+        || (name.ends_with(zone) && name[..name.len()-zone.len()].ends_with('.'))
+}
+
+
+/// Returns the zone a response speaks for: the deepest owner of an SOA or NS record in the answer
+/// or authority section that contains the queried name (all normalized).
+///
+/// As the records are given by the server being judged, the zone cut may not lie above the deepest
+/// watched zone containing the queried name or, if there is none, above its registered domain;
+/// otherwise, a forged NS record for a top-level domain or the root would vouch for anything.
+fn response_zone_cut(query_name: &str, watched_zones: &[String], dns: &Message) -> Option<String> {
+    let highest_cut = watched_zones.iter()
+        .filter(|zone| is_in_zone(query_name, zone))
+        .max_by_key(|zone| zone.len())
+        .map(|zone| zone.as_str())
+        .unwrap_or_else(|| registered_domain(query_name));
+    dns.answers().iter()
+        .chain(dns.name_servers().iter())
+        .filter(|r| r.record_type() == RecordType::SOA || r.record_type() == RecordType::NS)
+        .map(|r| normalize_name(r.name()))
+        .filter(|owner| owner.len() > 0 && is_in_zone(query_name, owner) && is_in_zone(owner, highest_cut))
+        .max_by_key(|owner| owner.len())
+}
+
+
+/// Checks whether the response contains records outside the bailiwick of the query.
+///
+/// Records answering for the queried name or the targets of its CNAME chain are always acceptable;
+/// all others must be within the zone cut given in the response or, if there is none, below the
+/// queried name.
+fn has_out_of_bailiwick_records(query_name: &str, cname_targets: &[String], watched_zones: &[String], dns: &Message) -> bool {
+    let zone = response_zone_cut(query_name, watched_zones, dns)
+        .unwrap_or_else(|| query_name.to_owned());
+    let is_acceptable = |record: &Record| {
+        let owner = normalize_name(record.name());
+        owner == query_name
+            || cname_targets.contains(&owner)
+            || is_in_zone(&owner, &zone)
+    };
+
+    dns.answers().iter()
+        .chain(dns.name_servers().iter())
+        .chain(dns.additionals().iter())
+        .filter(|r| r.record_type() != RecordType::OPT)
+        .any(|r| !is_acceptable(r))
+}
+
+
+/// Learns the MAC address of the sender of an ARP request or reply.
+fn process_arp(arp: &ArpPacket, neighbors: &mut HashMap<IpAddr, MacAddr6>) {
+    // ARP probes do not have a sender address yet
+    if arp.sender_protocol_address.is_unspecified() {
+        return;
+    }
+    neighbors.insert(IpAddr::V4(arp.sender_protocol_address), arp.sender_hardware_address);
+}
+
+
+/// Learns the MAC addresses announced in an IPv6 neighbor solicitation or advertisement.
+fn process_neighbor_discovery(source_address: IpAddr, discovery: &NeighborDiscovery, neighbors: &mut HashMap<IpAddr, MacAddr6>) {
+    // the source link-layer address belongs to the sender, the target link-layer address to the target
+    if let Some(mac) = discovery.source_link_layer_address {
+        if !source_address.is_unspecified() {
+            neighbors.insert(source_address, mac);
+        }
+    }
+    if let Some(mac) = discovery.target_link_layer_address {
+        neighbors.insert(IpAddr::V6(discovery.target_address), mac);
+    }
+}
+
+
+/// Counts an ICMP error reporting the failure of a DNS query.
+fn process_icmp_failure(reason: IcmpFailureReason, client: SocketAddr, server: SocketAddr, transaction_id: Option<u16>, context: &mut SampleContext, statistics: &mut DnsStats, timestamp: DateTime<Utc>) {
+    // if the transaction ID has been quoted too, we can find out which query failed
+    let mut correlated = false;
+    if let Some(tid) = transaction_id {
+        let flow_key = FlowKey {
+            client,
+            server,
+            transaction_id: tid,
+        };
+        correlated = context.correlation_table.fail_query(flow_key, timestamp).is_some();
+    }
+
+    debug!("DNS query from {} to {} failed with ICMP error {:?}", client.ip(), server.ip(), reason);
+    statistics.add_icmp_failure(reason, correlated);
+}
+
+
+/// Follows a DNS-over-TCP connection and records the timings completed by one of its segments.
+#[cfg(feature = "tcp-tracking")]
+fn process_tcp_segment(source: SocketAddr, destination: SocketAddr, vlan_id: Option<u16>, tcp_header: &TcpHeader, carries_data: bool, payload: &[u8], context: &mut SampleContext, all_statistics: &mut InterfaceStatistics, timestamp: DateTime<Utc>) {
+    // zone transfers are only counted once they have ended
+    let mut finished_transfers = context.zone_transfers.expire(timestamp);
+    finished_transfers.extend(context.zone_transfers.observe(timestamp, source, destination, tcp_header, payload));
+    for transfer in finished_transfers {
+        let statistics = all_statistics.for_tenant(context.tenants.tenant(transfer.vlan_id, transfer.client.ip()));
+        statistics.add_zone_transfer_response(transfer.client.ip(), transfer.server.ip(), transfer.message_count, transfer.byte_count, transfer.complete);
+    }
+
+    let timing = match context.tcp_connections.observe(timestamp, source, destination, tcp_header, carries_data) {
+        Some(t) => t,
+        None => return,
+    };
+
+    // timings are completed by the server's segments
+    let statistics = all_statistics.for_tenant(context.tenants.tenant(vlan_id, destination.ip()));
+    statistics.add_tcp_timing(timing);
+}
+
+
+/// Counts a fragment of a UDP datagram by whether it belongs to a query or a response. Fragments
+/// other than the first carry no ports; these mostly belong to large responses.
+fn process_fragment(source: IpAddr, destination: IpAddr, vlan_id: Option<u16>, ports: Option<(u16, u16)>, context: &mut SampleContext, all_statistics: &mut InterfaceStatistics) {
+    let (kind, client) = match ports {
+        Some((DNS_PORT, _)) => ("response", destination),
+        Some((_, DNS_PORT)) => ("query", source),
+        Some(_) => return,
+        None => ("continuation", destination),
+    };
+    let statistics = all_statistics.for_tenant(context.tenants.tenant(vlan_id, client));
+    statistics.add_fragment(kind);
+}
+
+
+/// Sends webhook alerts for the anomalies detected since the last call.
+fn alert_new_anomalies(context: &mut SampleContext, timestamp: DateTime<Utc>, linktype: Linktype) {
+    let ad = match context.anomaly_detector.as_mut() {
+        Some(ad) => ad,
+        None => return,
+    };
+    for anomaly in ad.take_new_anomalies() {
+        debug!("anomalous {:?}: {} (baseline {})", anomaly.metric, anomaly.value, anomaly.baseline);
+        if let Some(fr) = context.flight_recorder.as_mut() {
+            fr.trigger(timestamp, linktype, "anomaly");
+        }
+        #[cfg(feature = "http")]
+        if let Some(webhook) = context.webhook.as_mut() {
+            webhook.notify(
+                timestamp,
+                "anomaly",
+                &format!("{:?}", anomaly.metric),
+                format!("anomalous {:?}: {} (baseline {})", anomaly.metric, anomaly.value, anomaly.baseline),
+                json!({
+                    "metric": format!("{:?}", anomaly.metric),
+                    "value": anomaly.value,
+                    "baseline": anomaly.baseline,
+                }),
+            );
+        }
+    }
+}
+
+
+/// Processes a captured frame, timing each stage of the pipeline.
+fn process_packet(packet: &OwnedPacket, linktype: Linktype, on_secondary: bool, precision: Precision, context: &mut SampleContext, all_statistics: &mut InterfaceStatistics) {
+    let _packet_span = debug_span!("packet", on_secondary).entered();
+    let mut timer = StageTimer::new();
+    evaluate_packet(packet, linktype, on_secondary, precision, context, all_statistics, &mut timer);
+    timer.finish();
+
+    if !on_secondary {
+        all_statistics.untenanted.add_stage_timings(timer.timings());
+
+        // each profile evaluates the packet anew, with its own configuration and state
+        for (profile_context, profile_statistics) in context.profiles.iter_mut().zip(all_statistics.profiles.iter_mut()) {
+            if !profile_context.profile_filter_accepts(packet, linktype) {
+                continue;
+            }
+            process_packet(packet, linktype, false, precision, profile_context, profile_statistics);
+        }
+    }
+}
+
+
+/// Dissects a captured frame and updates the statistics with the DNS traffic it contains.
+fn evaluate_packet(packet: &OwnedPacket, linktype: Linktype, on_secondary: bool, precision: Precision, context: &mut SampleContext, all_statistics: &mut InterfaceStatistics, timer: &mut StageTimer) {
+    // the secondary interface only contributes to the comparison
+    if !on_secondary {
+        all_statistics.untenanted.capture_loss.packets_captured += 1;
+    }
+
+    if !on_secondary && context.detail_level != DetailLevel::Full {
+        all_statistics.untenanted.reduced_detail_packet_count += 1;
+    }
+
+    let event = match dissect_frame(&packet.data, linktype, context.detail_level, timer) {
+        Ok(e) => e,
+        Err(e) => {
+            let (layer, reason) = e.layer_and_reason();
+            if context.warning_limiter.admit(&format!("failed to dissect frame at {} layer: {}", layer, reason), std::time::Instant::now()) {
+                warn!("failed to dissect frame ({}): {:?}", e, packet.data.as_slice());
+            }
+            if !on_secondary {
+                all_statistics.untenanted.capture_loss.add_undissectable(layer, reason);
+            }
+            return;
+        },
+    };
+    timer.enter(PipelineStage::Stats);
+
+    let timestamp = match packet.timestamp(precision) {
+        Some(ts) => ts,
+        None => {
+            if context.warning_limiter.admit("packet has invalid timestamp", std::time::Instant::now()) {
+                warn!("packet has invalid timestamp {}.{}: {:?}", packet.header.ts.tv_sec, packet.header.ts.tv_usec, packet.data.as_slice());
+            }
+            if !on_secondary {
+                all_statistics.untenanted.capture_loss.add_undissectable("capture", "invalid timestamp");
+            }
+            return;
+        },
+    };
+
+    #[cfg(feature = "tcp-tracking")]
+    let mut tcp_payload = Vec::new();
+    let (source, destination, vlan_id, link_destination, ip_header, tcp_header, header, questions, edns, response) = match event {
+        DnsEvent::Query { source, destination, vlan_id, link_destination, ip_header, tcp_header, header, questions, edns } => (source, destination, vlan_id, link_destination, ip_header, tcp_header, header, questions, edns, None),
+        DnsEvent::Response { source, destination, vlan_id, link_destination, ip_header, tcp_header, header, questions, edns, answer_headers, message_length, message, #[cfg(feature = "tcp-tracking")] tcp_payload: payload } => {
+            #[cfg(feature = "tcp-tracking")]
+            {
+                tcp_payload = payload;
+            }
+            (source, destination, vlan_id, link_destination, ip_header, tcp_header, header, questions, edns, Some((answer_headers, message_length, message)))
+        },
+        DnsEvent::IcmpFailure { reason, client, server, transaction_id } => {
+            // the VLAN of the ICMP message may well differ from that of the query
+            let statistics = all_statistics.for_tenant(context.tenants.tenant(None, client.ip()));
+            process_icmp_failure(reason, client, server, transaction_id, context, statistics, timestamp);
+            return;
+        },
+        DnsEvent::NeighborDiscovery { source_address, discovery } => {
+            if let Some(neighbors) = context.neighbors.as_mut() {
+                process_neighbor_discovery(source_address, &discovery, neighbors);
+            }
+            return;
+        },
+        DnsEvent::Arp(arp) => {
+            if let Some(neighbors) = context.neighbors.as_mut() {
+                process_arp(&arp, neighbors);
+            }
+            return;
+        },
+        DnsEvent::Dhcp(dhcp) => {
+            if let Some(tracker) = context.dhcp_tracker.as_mut() {
+                tracker.observe(&dhcp);
+            }
+            return;
+        },
+        #[cfg(feature = "tcp-tracking")]
+        DnsEvent::TcpSegment { source, destination, vlan_id, header, payload } => {
+            if !on_secondary {
+                process_tcp_segment(source, destination, vlan_id, &header, payload.len() > 0, &payload, context, all_statistics, timestamp);
+            }
+            return;
+        },
+        DnsEvent::Fragment { source, destination, vlan_id, ports } => {
+            if !on_secondary {
+                process_fragment(source, destination, vlan_id, ports, context, all_statistics);
+            }
+            return;
+        },
+        DnsEvent::Unrelated => return,
+    };
+
+    if let Some(ic) = context.interface_comparison.as_mut() {
+        ic.observe(timestamp, on_secondary, &header, &questions);
+    }
+    if on_secondary {
+        return;
+    }
+
+    // a profile only looks at the DNS messages of the clients it has selected
+    let client = if response.is_none() { source.ip() } else { destination.ip() };
+    if let Some(selector) = &context.profile_selector {
+        if !selector.matches(vlan_id, client) {
+            return;
+        }
+    }
+
+    // site-specific classification may drop the message or attach extra labels to it
+    #[cfg(feature = "scripting")]
+    let script_labels = match &context.script_hook {
+        Some(hook) => {
+            let server = if response.is_none() { destination.ip() } else { source.ip() };
+            let script_event = ScriptEvent {
+                is_response: response.is_some(),
+                client,
+                server,
+                vlan_id,
+                header: &header,
+                questions: &questions,
+            };
+            match hook.classify(&script_event) {
+                Ok(ScriptVerdict::Keep(labels)) => labels,
+                Ok(ScriptVerdict::Drop) => {
+                    all_statistics.for_tenant(context.tenants.tenant(vlan_id, client)).script_dropped_count += 1;
+                    return;
+                },
+                Err(e) => {
+                    if context.warning_limiter.admit("classification script failed", std::time::Instant::now()) {
+                        warn!("classification script failed: {}", e);
+                    }
+                    Vec::new()
+                },
+            }
+        },
+        None => Vec::new(),
+    };
+
+    for client_capture in &mut context.client_captures {
+        client_capture.observe(timestamp, linktype, source.ip(), destination.ip(), packet);
+    }
+
+    #[cfg(feature = "tcp-tracking")]
+    if let Some(th) = &tcp_header {
+        process_tcp_segment(source, destination, vlan_id, th, true, &tcp_payload, context, all_statistics, timestamp);
+    }
+
+    let statistics = all_statistics.for_tenant(context.tenants.tenant(vlan_id, client));
+    #[cfg(feature = "scripting")]
+    statistics.add_script_labels(script_labels);
+
+    // IP options are rare in legitimate DNS traffic but can be used to evade intrusion detection
+    if let IpHeader::V4(ipv4_header) = &ip_header {
+        statistics.add_dont_fragment(response.is_some(), ipv4_header.dont_fragment());
+        match ipv4_header.parsed_options() {
+            Some(options) => {
+                for option in &options {
+                    statistics.add_ipv4_option(option.kind_name());
+                }
+            },
+            None => statistics.add_ipv4_option("malformed"),
+        }
+    }
+
+    // DNS is unicast; anything else points to misconfigured clients or leaking multicast DNS
+    let network_cast = CastKind::of_network_address(destination.ip());
+    let link_cast = link_destination.map(|d| CastKind::of_link_address(d));
+    statistics.add_destination_cast(link_cast, network_cast);
+    let unicast = network_cast == CastKind::Unicast && link_cast.unwrap_or(CastKind::Unicast) == CastKind::Unicast;
+    if !unicast && context.warning_limiter.admit("DNS message to non-unicast destination", std::time::Instant::now()) {
+        warn!("DNS message from {} to non-unicast destination {}", source, destination);
+    }
+
+    let server = if response.is_none() { destination.ip() } else { source.ip() };
+    statistics.add_header_flags(server, &header);
+    statistics.add_hop_limit(server, response.is_some(), ip_header.hop_limit());
+    statistics.add_traffic_class(response.is_some(), ip_header.dscp(), ip_header.ecn());
+
+    // DNS cookies protect against off-path spoofing; watch how widely they are adopted
+    let cookie_use = CookieUse::from_edns(edns.as_ref());
+    if response.is_none() {
+        statistics.add_query_cookie(source.ip(), cookie_use);
+    } else {
+        statistics.add_response_cookie(cookie_use);
+
+        // anycast deployments can tell which instance answered
+        if let Some(nsid) = edns.as_ref().and_then(|e| e.name_server_identifier()) {
+            statistics.add_instance_response(source.ip(), nsid);
+        }
+
+        // Extended DNS Errors explain failures in more detail than the response code
+        for error in edns.iter().flat_map(|e| ExtendedError::from_edns(e)) {
+            debug!("extended DNS error {} ({}) from {} to {}: {:?}", error.info_code, ExtendedError::name(error.info_code), source, destination, error.extra_text);
+            statistics.add_extended_error(error.info_code);
+        }
+    }
+
+    match response {
+        None => {
+            let flow_key = FlowKey {
+                client: source,
+                server: destination,
+                transaction_id: header.id,
+            };
+            context.correlation_table.add_query(flow_key, timestamp, questions.clone());
+            if let Some(ad) = context.anomaly_detector.as_mut() {
+                ad.observe_query(timestamp);
+            }
+            alert_new_anomalies(context, timestamp, linktype);
+
+            // if we know which resolvers clients should be using, watch out for those who don't
+            if context.sanctioned_resolvers.len() > 0 && !context.sanctioned_resolvers.contains(&destination.ip()) {
+                statistics.add_resolver_bypass(source.ip());
+            }
+
+            // UPDATE and NOTIFY name the zone they concern in the (zone) question
+            let opcode = header.opcode();
+            if opcode == Opcode::Update || opcode == Opcode::Notify {
+                let zone = questions.first()
+                    .map(|q| q.name.as_str().into_owned())
+                    .unwrap_or_else(|| String::new());
+                let kind = if opcode == Opcode::Update { "UPDATE message" } else { "NOTIFY message" };
+                if context.warning_limiter.admit(kind, std::time::Instant::now()) {
+                    info!("{:?} from {} to {} for zone {:?}", opcode, source.ip(), destination.ip(), zone);
+                }
+                statistics.add_zone_operation(ZoneOperation {
+                    timestamp,
+                    opcode,
+                    source: source.ip(),
+                    destination: destination.ip(),
+                    zone,
+                });
+            }
+
+            // zone transfers are rarely expected, let alone from clients
+            if questions.iter().any(|q| is_zone_transfer(q.record_type())) {
+                if context.warning_limiter.admit("zone transfer requested", std::time::Instant::now()) {
+                    warn!("{} requested a zone transfer from {}", source.ip(), destination.ip());
+                }
+                statistics.add_zone_transfer_request(source.ip(), destination.ip());
+
+                // the response is followed through the TCP stream
+                #[cfg(feature = "tcp-tracking")]
+                if tcp_header.is_some() {
+                    let incremental = questions.iter().any(|q| q.record_type() == RecordType::IXFR);
+                    context.zone_transfers.expect(timestamp, source, destination, vlan_id, incremental);
+                }
+            }
+
+            // we are interested in query type and name of requests
+            for question in &questions {
+                let query_type = question.record_type();
+                let normalized_name = question.name.as_str();
+
+                if let Some(entry) = context.blocklist.matching_entry(&normalized_name) {
+                    if context.warning_limiter.admit("blocklisted name queried", std::time::Instant::now()) {
+                        warn!("{} queried blocklisted name {} (matching {})", source.ip(), normalized_name, entry);
+                    }
+                    if let Some(fr) = context.flight_recorder.as_mut() {
+                        fr.trigger(timestamp, linktype, "blocklist");
+                    }
+                    #[cfg(feature = "http")]
+                    if let Some(webhook) = context.webhook.as_mut() {
+                        webhook.notify(
+                            timestamp,
+                            "blocklist_hit",
+                            &format!("{} {}", source.ip(), entry),
+                            format!("{} queried blocklisted name {}", source.ip(), normalized_name),
+                            json!({
+                                "source": source.ip().to_string(),
+                                "name": normalized_name,
+                                "entry": entry,
+                            }),
+                        );
+                    }
+                    statistics.add_blocklist_hit(BlocklistHit {
+                        timestamp,
+                        source: source.ip(),
+                        record_type: query_type,
+                        name: normalized_name.to_string(),
+                        entry: entry.to_owned(),
+                    });
+                }
+
+                statistics.add_scan_signals(source.ip(), question);
+                statistics.add_client_traits(source.ip(), timestamp, question, edns.as_ref());
+                if let Some(category) = context.app_categories.category(&normalized_name) {
+                    statistics.add_app_category(category);
+                }
+
+                // under pressure, only the counts are kept
+                if context.detail_level == DetailLevel::HeadersOnly {
+                    statistics.add_query_counts(timestamp, source.ip(), opcode, question.record_class(), query_type);
+                    continue;
+                }
+
+                if let Some(nt) = context.nod_tracker.as_mut() {
+                    if nt.observe(timestamp, source.ip(), &normalized_name) {
+                        statistics.add_newly_observed_domain();
+                        #[cfg(feature = "http")]
+                        if let Some(webhook) = context.webhook.as_mut() {
+                            let domain = registered_domain(&normalized_name);
+                            webhook.notify(
+                                timestamp,
+                                "newly_observed_domain",
+                                domain,
+                                format!("{} queried newly observed domain {}", source.ip(), domain),
+                                json!({
+                                    "source": source.ip().to_string(),
+                                    "name": normalized_name,
+                                    "domain": domain,
+                                }),
+                            );
+                        }
+                    }
+                }
+
+                if let Some(qm) = context.qname_minimization.as_mut() {
+                    if let Some(minimized) = qm.observe(timestamp, source.ip(), &normalized_name, query_type) {
+                        statistics.add_qname_minimization(source.ip(), minimized);
+                    }
+                }
+                statistics.add_address_family_query(&normalized_name, query_type);
+
+                let event = DnsMessageEvent {
+                    timestamp,
+                    source,
+                    destination,
+                    transaction_id: header.id,
+                    name: &normalized_name,
+                    record_type: query_type,
+                    over_tcp: tcp_header.is_some(),
+                    response_code: None,
+                    labels: &context.global_labels,
+                };
+                for sink in &mut context.event_sinks {
+                    sink.emit(&event);
+                }
+
+                statistics.add_query(timestamp, source.ip(), opcode, question.record_class(), query_type, &normalized_name);
+            }
+        },
+        Some((answer_headers, message_length, message)) => {
+            // the flags tell us what kind of server is answering
+            statistics.add_response(source.ip(), header.authoritative(), header.recursion_available(), header.response_code());
+            if let Some(question) = questions.first() {
+                let name = question.name.as_str();
+                statistics.add_zone_response_code(source.ip(), registered_domain(&name), header.response_code());
+                for zone in &context.watched_zones {
+                    if is_in_zone(&name, zone) {
+                        statistics.add_watched_zone_response(zone, header.response_code(), header.answer_count);
+                    }
+                }
+            }
+            if let Some(ad) = context.anomaly_detector.as_mut() {
+                ad.observe_response(timestamp, header.response_code());
+            }
+            alert_new_anomalies(context, timestamp, linktype);
+            statistics.add_response_size(message_length);
+            if message_length > context.large_response_bytes {
+                statistics.add_large_response(tcp_header.is_some());
+            }
+
+            let questions_to_emit = if context.detail_level == DetailLevel::Full { &questions[..] } else { &[] };
+            for question in questions_to_emit {
+                let event = DnsMessageEvent {
+                    timestamp,
+                    source,
+                    destination,
+                    transaction_id: header.id,
+                    name: &question.name.as_str(),
+                    record_type: question.record_type(),
+                    over_tcp: tcp_header.is_some(),
+                    response_code: Some(header.response_code()),
+                    labels: &context.global_labels,
+                };
+                for sink in &mut context.event_sinks {
+                    sink.emit(&event);
+                }
+            }
+
+            // an IXFR response over UDP is a single message; it is truncated if the changes do not fit
+            if tcp_header.is_none() && questions.iter().any(|q| is_zone_transfer(q.record_type())) {
+                let complete = header.response_code() == ResponseCode::NoError && !header.truncated();
+                statistics.add_zone_transfer_response(destination.ip(), source.ip(), 1, message_length.try_into().unwrap(), complete);
+            }
+
+            // the types are known even if the DNS library cannot make sense of the records
+            for answer_header in &answer_headers {
+                statistics.add_answer_record_type(RecordType::from(answer_header.record_type));
+            }
+            let dns = match message {
+                Some(Ok(m)) => Some(m),
+                None => None,
+                Some(Err(e)) => {
+                    debug!("failed to fully decode response from {} to {} (transaction ID 0x{:04X}): {}", source, destination, header.id, e.error);
+                    if let Some(q) = context.malformed_quarantine.as_mut() {
+                        q.store(timestamp, source, destination, header.id, &e.bytes);
+                    }
+                    None
+                },
+            };
+            let answers = dns.as_ref()
+                .map(|d| d.answers())
+                .unwrap_or(&[]);
+
+            // does this response answer a query we have seen?
+            let flow_key = FlowKey {
+                client: destination,
+                server: source,
+                transaction_id: header.id,
+            };
+            match context.correlation_table.match_response(flow_key, timestamp, &questions, answers) {
+                CorrelationOutcome::Matched(query) => {
+                    // both timestamps come from the capture, so processing delays do not skew this
+                    // (negative if the packets were captured out of order)
+                    let latency = (timestamp - query.timestamp).to_std().ok();
+                    statistics.add_matched_response(latency);
+                    if let (Some(l), Some(question)) = (latency, query.questions.first()) {
+                        statistics.add_server_type_latency(source.ip(), question.record_type(), l);
+                    }
+                },
+                CorrelationOutcome::Duplicate { differing } => {
+                    if differing && context.warning_limiter.admit("differing duplicate response", std::time::Instant::now()) {
+                        warn!("differing duplicate response from {} to {} (transaction ID 0x{:04X})", source, destination, header.id);
+                    }
+                    statistics.add_duplicate_response(source.ip(), differing);
+                },
+                CorrelationOutcome::QuestionMismatch|CorrelationOutcome::Unsolicited => {
+                    debug!("unsolicited response from {} to {} (transaction ID 0x{:04X})", source, destination, header.id);
+                    statistics.add_unsolicited_response(source.ip());
+                },
+            }
+
+            // the remainder requires the records themselves
+            let dns = match dns {
+                Some(d) => d,
+                None => return,
+            };
+
+            let mut out_of_bailiwick = false;
+            for query in dns.queries() {
+                let cname_targets = follow_cname_chain(query.name(), dns.answers());
+                if has_out_of_bailiwick_records(&normalize_name(query.name()), &cname_targets, &context.watched_zones, &dns) {
+                    out_of_bailiwick = true;
+                }
+                statistics.add_cname_chain(cname_targets);
+            }
+            if out_of_bailiwick {
+                statistics.add_out_of_bailiwick_response(source.ip());
+            }
+
+            // is the name reachable over IPv6 yet?
+            let has_aaaa = dns.answers().iter()
+                .any(|record| matches!(record.data(), Some(RData::AAAA(_))));
+            for query in dns.queries() {
+                if query.query_type() == RecordType::AAAA {
+                    statistics.add_aaaa_response(&normalize_name(query.name()), has_aaaa);
+                }
+            }
+
+            // which addresses do the clients end up connecting to?
+            for record in dns.answers() {
+                let (address, aggregate_prefix_length) = match record.data() {
+                    Some(RData::A(a)) => (IpAddr::V4(a.0), 24),
+                    Some(RData::AAAA(a)) => (IpAddr::V6(a.0), 48),
+                    _ => continue,
+                };
+                if context.aggregate_answer_addresses {
+                    statistics.add_answer_network(mask_address(address, aggregate_prefix_length), aggregate_prefix_length);
+                } else {
+                    let full_prefix_length = if address.is_ipv4() { 32 } else { 128 };
+                    statistics.add_answer_network(address, full_prefix_length);
+                }
+            }
+
+            // servers answering CHAOS TXT queries tell us which software they run
+            for query in dns.queries() {
+                if query.query_class() != DNSClass::CH || query.query_type() != RecordType::TXT {
+                    continue;
+                }
+                let name = normalize_name(query.name());
+                if !VERSION_PROBE_NAMES.contains(&name.as_str()) {
+                    continue;
+                }
+                let texts: Vec<String> = dns.answers().iter()
+                    .filter_map(|record| match record.data() {
+                        Some(RData::TXT(t)) => Some(
+                            t.txt_data().iter()
+                                .map(|d| String::from_utf8_lossy(d))
+                                .collect()
+                        ),
+                        _ => None,
+                    })
+                    .collect();
+                if texts.len() > 0 {
+                    statistics.add_software_report(source.ip(), &name, texts.join(" "));
+                }
+            }
+
+            // critical names should only resolve to the addresses we know, lest they be hijacked
+            if !context.answer_watchlist.is_empty() {
+                let addresses: Vec<IpAddr> = dns.answers().iter()
+                    .filter_map(|record| match record.data() {
+                        Some(RData::A(a)) => Some(IpAddr::V4(a.0)),
+                        Some(RData::AAAA(a)) => Some(IpAddr::V6(a.0)),
+                        _ => None,
+                    })
+                    .collect();
+                for query in dns.queries() {
+                    let name = normalize_name(query.name());
+                    for unexpected in context.answer_watchlist.observe(&name, &addresses) {
+                        statistics.add_unexpected_answer(&name);
+                        if !unexpected.first_seen {
+                            continue;
+                        }
+                        warn!("{} answered {} to {} with unexpected address {}", source.ip(), name, destination.ip(), unexpected.address);
+                        if let Some(fr) = context.flight_recorder.as_mut() {
+                            fr.trigger(timestamp, linktype, "unexpected_answer");
+                        }
+                        #[cfg(feature = "http")]
+                        if let Some(webhook) = context.webhook.as_mut() {
+                            webhook.notify(
+                                timestamp,
+                                "unexpected_answer",
+                                &format!("{} {}", name, unexpected.address),
+                                format!("{} resolved to unexpected address {}", name, unexpected.address),
+                                json!({
+                                    "name": name,
+                                    "address": unexpected.address.to_string(),
+                                    "server": source.ip().to_string(),
+                                    "client": destination.ip().to_string(),
+                                }),
+                            );
+                        }
+                    }
+                }
+            }
+
+            #[cfg(feature = "passive-dns")]
+            if let Some(store) = context.passive_dns_store.as_mut() {
+                for record in dns.answers() {
+                    let rdata = match record.data() {
+                        Some(d) => d.to_string(),
+                        None => continue,
+                    };
+                    let store_result = store.record(
+                        timestamp,
+                        &normalize_name(record.name()),
+                        &record.record_type().to_string(),
+                        &rdata,
+                    );
+                    if let Err(e) = store_result {
+                        error!("failed to store passive DNS record: {}", e);
+                    }
+                }
+            }
+        },
+    }
+}
+
+
+/// Opens a capture on the device at the given index of the device list. Captures that are only
+/// inbound skip the packets sent from the interface.
+fn open_capture(
+    backend: CaptureBackendKind,
+    device_list: &[Device],
     interface_index: usize,
-    sample_duration: Duration,
     filter: Option<&str>,
-    buffer_size: Option<usize>,
-) -> Result<DnsStats, SamplingError> {
-    // get device
-    let mut device_list = Device::list()
-        .map_err(|e| SamplingError::GetInterfaceList(e))?;
+    inbound_only: bool,
+    precision: Precision,
+) -> Result<Box<dyn CaptureBackend>, SamplingError> {
     if interface_index >= device_list.len() {
         return Err(SamplingError::InterfaceIndexTooHigh { index: interface_index, count: device_list.len() });
     }
 
-    let device = device_list.swap_remove(interface_index);
+    let device = &device_list[interface_index];
     debug!("capturing on {}", device.desc.as_ref().map(|d| d.as_str()).unwrap_or(device.name.as_str()));
-    let cap_inact = Capture::from_device(device)
+    match backend {
+        #[cfg(feature = "libpcap")]
+        CaptureBackendKind::Pcap => open_pcap_capture(&device.name, filter, inbound_only, precision),
+        CaptureBackendKind::Rawsocket => open_raw_capture(&device.name, filter, inbound_only, precision)
+            .map_err(|e| SamplingError::OpenRawCapture { interface: device.name.clone(), error: e }),
+    }
+}
+
+
+#[cfg(feature = "libpcap")]
+fn open_pcap_capture(device_name: &str, filter: Option<&str>, inbound_only: bool, precision: Precision) -> Result<Box<dyn CaptureBackend>, SamplingError> {
+    let cap_inact = Capture::from_device(device_name)
         .map_err(|e| SamplingError::ConvertCaptureDevice(e))?
-        .timeout(1000);
-    let mut cap = cap_inact
-        .open().map_err(|e| SamplingError::OpenCaptureDevice(e))?;
+        .timeout(1000)
+        .precision(precision.into());
+    let mut cap = match cap_inact.open() {
+        Ok(c) => c,
+        Err(e) => return open_fallback_capture(device_name, filter, inbound_only, precision, e),
+    };
+    if inbound_only {
+        cap.direction(Direction::In)
+            .map_err(|e| SamplingError::SetDirection(e))?;
+    }
     if let Some(f) = filter {
+        let linktype = cap.get_datalink().into();
         cap.filter(f, true)
-            .map_err(|e| SamplingError::SetFilter(e))?;
+            .map_err(|e| SamplingError::SetFilter { linktype, error: e.into() })?;
     }
+    Ok(Box::new(cap))
+}
+
 
-    let (packet_sender, mut packet_receiver) = mpsc::channel(buffer_size.unwrap_or(32));
+/// Captures on the given device without libpcap if libpcap has failed to open it.
+#[cfg(feature = "libpcap")]
+fn open_fallback_capture(device_name: &str, filter: Option<&str>, inbound_only: bool, precision: Precision, pcap_error: pcap::Error) -> Result<Box<dyn CaptureBackend>, SamplingError> {
+    match open_raw_capture(device_name, filter, inbound_only, precision) {
+        Ok(cap) => {
+            warn!("libpcap failed to open {} ({}); capturing using a raw socket instead", device_name, pcap_error);
+            Ok(cap)
+        },
+        Err(e) => {
+            warn!("failed to open raw socket capture on {}: {}", device_name, e);
+            Err(SamplingError::OpenCaptureDevice(pcap_error))
+        },
+    }
+}
 
-    let packet_handler_handle = tokio::task::spawn_blocking(move || {
-        let start_time = Instant::now();
-        while Instant::now() - start_time < sample_duration {
+
+/// Lists the capture devices; the interface indexes refer to this list.
+pub fn list_devices(backend: CaptureBackendKind) -> Result<Vec<Device>, SamplingError> {
+    match backend {
+        #[cfg(feature = "libpcap")]
+        CaptureBackendKind::Pcap => pcap::Device::list()
+            .map(|devices| devices.into_iter().map(Device::from).collect())
+            .map_err(|e| SamplingError::GetInterfaceList(e)),
+        CaptureBackendKind::Rawsocket => {
+            let interface_names = list_interfaces()
+                .map_err(|e| SamplingError::ListInterfaces(e))?;
+            Ok(interface_names.iter().map(|name| Device::from(name.as_str())).collect())
+        },
+    }
+}
+
+
+/// Everything needed to open a capture again after its interface has gone away.
+#[derive(Clone)]
+struct CaptureSource {
+    backend: CaptureBackendKind,
+    device: Device,
+    filter: Option<String>,
+    inbound_only: bool,
+    precision: Precision,
+    netns: Option<String>,
+}
+impl CaptureSource {
+    fn open(&self) -> Result<Box<dyn CaptureBackend>, SamplingError> {
+        open_capture(self.backend, std::slice::from_ref(&self.device), 0, self.filter.as_deref(), self.inbound_only, self.precision)
+    }
+
+    fn reopen(&self) -> Result<Box<dyn CaptureBackend>, SamplingError> {
+        let _netns_guard = enter_netns(self.netns.as_deref())
+            .map_err(|e| SamplingError::EnterNetworkNamespace(e))?;
+        self.open()
+    }
+}
+
+
+fn reopen_delay(attempt: u32) -> Duration {
+    let factor = 1u64.checked_shl(attempt.saturating_sub(1)).unwrap_or(u64::MAX);
+    Duration::from_millis(MIN_REOPEN_DELAY_MS.saturating_mul(factor).min(MAX_REOPEN_DELAY_MS))
+}
+
+
+/// Keeps trying to open the capture again, waiting longer after each failure. Returns `None` if the
+/// stop flag is set before the capture could be opened.
+///
+/// Some captures can be opened on an interface that is down and only fail once they are read from,
+/// so the failures are counted until a packet has been read successfully.
+fn reopen_capture(source: &CaptureSource, capture_stop_flag: &AtomicBool, consecutive_failures: &mut u32) -> Option<Box<dyn CaptureBackend>> {
+    loop {
+        *consecutive_failures += 1;
+        let reopen_time = std::time::Instant::now() + reopen_delay(*consecutive_failures);
+        while std::time::Instant::now() < reopen_time {
+            if capture_stop_flag.load(Ordering::SeqCst) {
+                return None;
+            }
+            std::thread::sleep(Duration::from_millis(100));
+        }
+        match source.reopen() {
+            Ok(cap) => {
+                info!("reopened capture on {}", source.device.name);
+                return Some(cap);
+            },
+            Err(e) => debug!("failed to reopen capture on {}: {}", source.device.name, e),
+        }
+    }
+}
+
+
+/// Forwards the packets captured on the given device until the stop flag is set, tagging them with
+/// the index of the capture, their link type and whether they come from the secondary interface of
+/// a comparison.
+///
+/// If the capture fails, for example because the interface has gone down, it is opened again.
+/// Returns the capture statistics along with the number of times the capture had to be reopened.
+fn spawn_capture(
+    mut cap: Box<dyn CaptureBackend>,
+    source: CaptureSource,
+    capture_index: usize,
+    on_secondary: bool,
+    packet_sender: mpsc::Sender<(usize, bool, Linktype, OwnedPacket)>,
+    capture_stop_flag: Arc<AtomicBool>,
+    capture_pause_flag: Arc<AtomicBool>,
+) -> tokio::task::JoinHandle<(Option<CaptureStats>, u64)> {
+    tokio::task::spawn_blocking(move || {
+        let mut linktype = cap.linktype();
+        let mut restart_count = 0;
+        let mut consecutive_failures = 0;
+        let mut earlier_stats = CaptureStats::default();
+        while !capture_stop_flag.load(Ordering::SeqCst) {
             let packet = match cap.next_packet() {
-                Ok(p) => OwnedPacket::from(p),
-                Err(pcap::Error::TimeoutExpired) => continue,
+                Ok(Some(p)) => {
+                    consecutive_failures = 0;
+                    p
+                },
+                Ok(None) => {
+                    consecutive_failures = 0;
+                    continue;
+                },
                 Err(e) => {
-                    error!("error while capturing packets: {}", e);
-                    break;
+                    if consecutive_failures == 0 {
+                        error!("error while capturing packets on {}; reopening capture: {}", source.device.name, e);
+                    } else {
+                        debug!("error while capturing packets on {} again: {}", source.device.name, e);
+                    }
+                    if let Ok(s) = cap.stats() {
+                        earlier_stats.received += s.received;
+                        earlier_stats.dropped += s.dropped;
+                        earlier_stats.dropped_by_interface += s.dropped_by_interface;
+                    }
+                    match reopen_capture(&source, &capture_stop_flag, &mut consecutive_failures) {
+                        Some(c) => cap = c,
+                        None => break,
+                    }
+                    linktype = cap.linktype();
+                    restart_count += 1;
+                    continue;
                 },
             };
-            if let Err(e) = packet_sender.blocking_send(packet) {
+            if capture_pause_flag.load(Ordering::SeqCst) {
+                // keep draining the capture buffer so that no stale packets pile up
+                continue;
+            }
+            if let Err(e) = packet_sender.blocking_send((capture_index, on_secondary, linktype, packet)) {
+                // nobody is listening anymore
                 error!("error enqueuing packet: {}", e);
+                break;
             }
         }
-    });
 
-    let mut statistics = DnsStats::new();
-    while let Some(packet) = packet_receiver.recv().await {
-        // FIXME: assuming Ethernet Layer-2 encapsulation
-        let (eth, rest) = match EthernetHeader::try_take(&packet.data) {
-            PacketDissection::Success { header, rest } => (header, rest),
-            other => {
-                warn!("non-Ethernet frame slipped through the cracks ({:?}): {:?}", other, packet.data.as_slice());
-                continue;
+        // the packet queue blocks when full, so any overflow shows up as drops by the capture
+        let capture_stats = match cap.stats() {
+            Ok(s) => Some(CaptureStats {
+                received: earlier_stats.received + s.received,
+                dropped: earlier_stats.dropped + s.dropped,
+                dropped_by_interface: earlier_stats.dropped_by_interface + s.dropped_by_interface,
+            }),
+            Err(e) => {
+                warn!("failed to obtain capture statistics: {}", e);
+                None
             },
         };
+        (capture_stats, restart_count)
+    })
+}
 
-        let ip_bytes = match eth.ethertype {
-            ETHERTYPE_VLAN_TAG => {
-                // try to unpack
-                let (_tag, rest) = match VlanTagHeader::try_take(&rest) {
-                    PacketDissection::Success { header, rest } => (header, rest),
-                    other => {
-                        warn!("VLAN-tagged Ethernet frame but failed to extract header ({:?}): {:?}", other, packet.data.as_slice());
-                        continue;
-                    },
-                };
 
-                let (inner_eth, rest) = match EthernetHeader::try_take(rest) {
-                    PacketDissection::Success { header, rest } => (header, rest),
-                    other => {
-                        warn!("VLAN tag detected but failed to decode inner Ethernet payload ({:?}): {:?}", other, packet.data.as_slice());
-                        continue;
-                    },
-                };
-                if inner_eth.ethertype != ETHERTYPE_IPV4 && inner_eth.ethertype != ETHERTYPE_IPV6 {
-                    warn!("VLAN-tagged Ethernet frame with unknown (inner) ethertype 0x{:04X} slipped through the cracks: {:?}", inner_eth.ethertype, packet.data.as_slice());
-                    continue;
-                }
-                rest
-            },
-            ETHERTYPE_IPV4|ETHERTYPE_IPV6 => {
-                rest
-            },
-            other => {
-                warn!("Ethernet frame with unknown ethertype 0x{:04X} slipped through the cracks: {:?}", other, packet.data.as_slice());
-                continue;
-            },
-        };
+/// Pauses the capture on SIGUSR1 and resumes it on SIGUSR2.
+#[cfg(unix)]
+fn spawn_pause_control(capture_pause_flag: Arc<AtomicBool>) -> Option<tokio::task::JoinHandle<()>> {
+    use tokio::signal::unix::{signal, SignalKind};
 
-        // check IP version by peeking
-        if ip_bytes.len() < 1 {
-            warn!("Ethernet frame ends before IP header");
+    let (mut pause_signal, mut resume_signal) = match (signal(SignalKind::user_defined1()), signal(SignalKind::user_defined2())) {
+        (Ok(p), Ok(r)) => (p, r),
+        (Err(e), _)|(_, Err(e)) => {
+            error!("failed to listen for pause/resume signals: {}", e);
+            return None;
+        },
+    };
+    Some(tokio::spawn(async move {
+        loop {
+            let pause = tokio::select! {
+                _ = pause_signal.recv() => true,
+                _ = resume_signal.recv() => false,
+            };
+            let was_paused = capture_pause_flag.swap(pause, Ordering::SeqCst);
+            if pause && !was_paused {
+                info!("capture paused");
+            } else if !pause && was_paused {
+                info!("capture resumed");
+            }
+        }
+    }))
+}
+
+#[cfg(not(unix))]
+fn spawn_pause_control(_capture_pause_flag: Arc<AtomicBool>) -> Option<tokio::task::JoinHandle<()>> {
+    None
+}
+
+
+#[cfg(feature = "docker")]
+fn container_refresh_interval(context: &SampleContext) -> Option<Interval> {
+    let cd = context.container_directory.as_ref()?;
+    let mut interval = tokio::time::interval(cd.refresh_interval());
+    interval.set_missed_tick_behavior(MissedTickBehavior::Delay);
+    Some(interval)
+}
+
+#[cfg(not(feature = "docker"))]
+fn container_refresh_interval(_context: &SampleContext) -> Option<Interval> {
+    None
+}
+
+
+fn blocklist_refresh_interval(context: &SampleContext) -> Option<Interval> {
+    // the blocklists have just been loaded
+    let period = context.blocklist_refresh_interval?;
+    let mut interval = tokio::time::interval_at(Instant::now() + period, period);
+    interval.set_missed_tick_behavior(MissedTickBehavior::Delay);
+    Some(interval)
+}
+
+
+/// Loads the blocklists again, keeping the previous ones if that fails.
+async fn refresh_blocklist(context: &mut SampleContext) {
+    match Blocklist::load(&context.blocklist_sources).await {
+        Ok(blocklist) => {
+            debug!("refreshed blocklists");
+            for profile_context in &mut context.profiles {
+                profile_context.blocklist = blocklist.clone();
+            }
+            context.blocklist = blocklist;
+        },
+        Err(e) => {
+            if context.warning_limiter.admit("failed to refresh blocklists", std::time::Instant::now()) {
+                warn!("failed to refresh blocklists; keeping the previous ones: {}", e);
+            }
+        },
+    }
+}
+
+
+#[cfg(feature = "docker")]
+async fn refresh_containers(context: &mut SampleContext) {
+    if let Some(cd) = context.container_directory.as_mut() {
+        if let Err(e) = cd.refresh().await {
+            if context.warning_limiter.admit("failed to list Docker containers", std::time::Instant::now()) {
+                warn!("failed to list Docker containers: {}", e);
+            }
+        }
+    }
+}
+
+#[cfg(not(feature = "docker"))]
+async fn refresh_containers(_context: &mut SampleContext) {
+}
+
+
+/// Captures DNS traffic on the given interfaces for the given duration.
+///
+/// Returns one set of statistics per interface, or a single set if `merge_interfaces` is set, each
+/// followed by one set per configured tenant; the sets of the profiles come last.
+///
+/// If `expand_members` is set, bonds and bridges are captured on their member ports instead, with
+/// the packets of all members counted towards the bond or bridge.
+pub async fn collect_sample(
+    interface_indexes: &[usize],
+    merge_interfaces: bool,
+    expand_members: bool,
+    sample_duration: Duration,
+    filter: Option<&str>,
+    buffer_size: Option<usize>,
+    precision: Precision,
+    backend: CaptureBackendKind,
+    netns: Option<&str>,
+    context: &mut SampleContext,
+) -> Result<Vec<DnsStats>, SamplingError> {
+    // the captures are opened in the other namespace and keep capturing there
+    let netns_guard = enter_netns(netns)
+        .map_err(|e| SamplingError::EnterNetworkNamespace(e))?;
+
+    // get devices
+    let device_list = list_devices(backend)?;
+    let capture_source = |device: Device, filter: Option<&str>, inbound_only: bool| CaptureSource {
+        backend,
+        device,
+        filter: filter.map(|f| f.to_owned()),
+        inbound_only,
+        precision,
+        netns: netns.map(|n| n.to_owned()),
+    };
+    if expand_members && netns.is_some() {
+        // sysfs shows the interfaces of the namespace it was mounted in
+        warn!("bond and bridge members cannot be looked up in another network namespace; capturing on the interfaces themselves");
+    }
+
+    // each capture belongs to one of the interfaces and may be on one of its members
+    let mut caps = Vec::with_capacity(interface_indexes.len());
+    let mut capture_targets: Vec<(usize, Option<String>)> = Vec::with_capacity(interface_indexes.len());
+    for (interface_position, interface_index) in interface_indexes.iter().enumerate() {
+        let device = device_list.get(*interface_index)
+            .ok_or(SamplingError::InterfaceIndexTooHigh { index: *interface_index, count: device_list.len() })?;
+        let members = if expand_members && netns.is_none() {
+            interface_members(&device.name)
+                .map_err(|e| SamplingError::ListMembers { interface: device.name.clone(), error: e })?
+        } else {
+            Vec::new()
+        };
+        if members.len() == 0 {
+            let source = capture_source(device.clone(), filter, false);
+            caps.push((source.open()?, source));
+            capture_targets.push((interface_position, None));
             continue;
         }
-        let ip_version = (ip_bytes[0] & 0b1111_0000) >> 4;
-        let (ip_header, rest) = match ip_version {
-            4 => {
-                match Ipv4Header::try_take(ip_bytes) {
-                    PacketDissection::Success { header, rest } => (IpHeader::V4(header), rest),
-                    other => {
-                        warn!("failed to parse IPv4 header ({:?}) of {:?}", other, packet.data.as_slice());
-                        continue;
-                    },
-                }
+        info!("capturing on the members of {}: {}", device.name, members.join(", "));
+        for member in members {
+            let member_device = device_list.iter()
+                .find(|d| d.name == member)
+                .cloned()
+                .unwrap_or_else(|| Device::from(member.as_str()));
+            // frames forwarded between the members would be seen leaving one and arriving at another
+            let source = capture_source(member_device, filter, true);
+            caps.push((source.open()?, source));
+            capture_targets.push((interface_position, Some(member)));
+        }
+    }
+
+    // the secondary interface is only used for comparing the DNS traffic
+    let secondary_cap = match &context.interface_comparison {
+        Some(ic) => {
+            let cap = open_capture(backend, &device_list, ic.secondary_interface_index, Some(DNS_CAPTURE_FILTER), false, precision)?;
+            Some((cap, capture_source(device_list[ic.secondary_interface_index].clone(), Some(DNS_CAPTURE_FILTER), false)))
+        },
+        None => None,
+    };
+    drop(netns_guard);
+
+    let packet_queue_capacity = buffer_size.unwrap_or(32);
+    let (packet_sender, mut packet_receiver) = mpsc::channel(packet_queue_capacity);
+    let mut packet_queue_max_depth = 0;
+
+    // the capture threads check this flag whenever the capture timeout expires
+    let stop_capture = Arc::new(AtomicBool::new(false));
+
+    // while this flag is set, the capture threads discard the packets they receive
+    let pause_capture = Arc::new(AtomicBool::new(false));
+    let pause_control_handle = spawn_pause_control(Arc::clone(&pause_capture));
+
+    let mut packet_handler_handles = Vec::new();
+    if let Some((sc, source)) = secondary_cap {
+        packet_handler_handles.push((None, spawn_capture(sc, source, 0, true, packet_sender.clone(), Arc::clone(&stop_capture), Arc::clone(&pause_capture))));
+    }
+    for (capture_index, (cap, source)) in caps.into_iter().enumerate() {
+        packet_handler_handles.push((Some(capture_index), spawn_capture(cap, source, capture_index, false, packet_sender.clone(), Arc::clone(&stop_capture), Arc::clone(&pause_capture))));
+    }
+    drop(packet_sender);
+
+    // the deadline is based on the monotonic clock, so changes to the wall clock do not affect it
+    let start_time = Instant::now();
+    let deadline = sleep_until(start_time + sample_duration);
+    tokio::pin!(deadline);
+    let shutdown = tokio::signal::ctrl_c();
+    tokio::pin!(shutdown);
+    let mut stop_time = None;
+    let mut shutdown_signal_failed = false;
+
+    // containers come and go during the sample, so their addresses are listed regularly
+    let mut container_refresh = container_refresh_interval(context);
+    let mut blocklist_refresh = blocklist_refresh_interval(context);
+
+    // label the statistics with the interface they were collected on
+    let mut all_statistics = Vec::new();
+    if merge_interfaces {
+        all_statistics.push(InterfaceStatistics::new(None, context));
+    } else {
+        for interface_index in interface_indexes {
+            let interface = device_list[*interface_index].name.clone();
+            all_statistics.push(InterfaceStatistics::new(Some(interface), context));
+        }
+    }
+
+    // the packets are counted per member of a bond or bridge
+    let mut capture_packet_counts = vec![0u64; capture_targets.len()];
+
+    loop {
+        // keep processing the packets that are still queued after the capture has been stopped
+        let (capture_index, on_secondary, linktype, packet) = tokio::select! {
+            received = packet_receiver.recv() => match received {
+                Some(p) => p,
+                None => break, // all capture threads have stopped
+            },
+            _ = &mut deadline, if stop_time.is_none() => {
+                stop_time = Some(Instant::now());
+                stop_capture.store(true, Ordering::SeqCst);
+                continue;
             },
-            6 => {
-                match Ipv6Header::try_take(ip_bytes) {
-                    PacketDissection::Success { header, rest } => (IpHeader::V6(header), rest),
-                    other => {
-                        warn!("failed to parse IPv6 header ({:?}) of {:?}", other, packet.data.as_slice());
-                        continue;
+            signal_result = &mut shutdown, if stop_time.is_none() && !shutdown_signal_failed => {
+                match signal_result {
+                    Ok(()) => {
+                        info!("shutdown requested; ending sample early");
+                        stop_time = Some(Instant::now());
+                        stop_capture.store(true, Ordering::SeqCst);
+                    },
+                    Err(e) => {
+                        error!("failed to listen for shutdown signal: {}", e);
+                        shutdown_signal_failed = true;
                     },
                 }
+                continue;
+            },
+            _ = async { container_refresh.as_mut().unwrap().tick().await }, if container_refresh.is_some() => {
+                refresh_containers(context).await;
+                continue;
             },
-            other => {
-                warn!("Ethernet frame with IP packet with unexpected version {} slipped through the cracks: {:?}", other, packet.data.as_slice());
+            _ = async { blocklist_refresh.as_mut().unwrap().tick().await }, if blocklist_refresh.is_some() => {
+                refresh_blocklist(context).await;
                 continue;
             },
         };
 
-        // FIXME: TCP?
-        if ip_header.inner_protocol() != PROTO_UDP {
-            warn!("Ethernet frame with IP packet with unexpected inner protocol {} slipped through the cracks: {:?}", ip_header.inner_protocol(), packet.data.as_slice());
-            continue;
+        // including the packet just received
+        let packet_queue_depth = packet_receiver.len() + 1;
+        packet_queue_max_depth = packet_queue_max_depth.max(packet_queue_depth);
+
+        // if we cannot keep up, process the packets more superficially until the queue has drained
+        if context.adaptive_detail {
+            let fill_percent = packet_queue_depth * 100 / packet_queue_capacity;
+            if context.detail_level == DetailLevel::Full && fill_percent >= REDUCE_DETAIL_QUEUE_PERCENT {
+                warn!("packet queue is {}% full; reducing detail to keep up", fill_percent);
+                context.detail_level = DetailLevel::HeadersOnly;
+            } else if context.detail_level == DetailLevel::HeadersOnly && fill_percent <= RESTORE_DETAIL_QUEUE_PERCENT {
+                info!("packet queue is down to {}% full; restoring full detail", fill_percent);
+                context.detail_level = DetailLevel::Full;
+            }
         }
 
-        let (pseudo_header_bytes, pseudo_header_length) = ip_header.to_pseudo_header();
-        let (_udp_header, rest) = match UdpHeader::try_take(rest, &pseudo_header_bytes[0..pseudo_header_length]) {
-            PacketDissection::Success { header, rest } => (header, rest),
-            other => {
-                warn!("failed to parse UDP header ({:?}) of {:?}", other, packet.data.as_slice());
-                continue;
-            },
-        };
+        let statistics_index = if merge_interfaces { 0 } else { capture_targets[capture_index].0 };
+        if !on_secondary {
+            capture_packet_counts[capture_index] += 1;
+        }
+        process_packet(&packet, linktype, on_secondary, precision, context, &mut all_statistics[statistics_index]);
 
-        let dns = match Message::from_bytes(rest) {
-            Ok(d) => d,
+        // record the packet after processing it so that a dump triggered by it still includes it
+        if let (Some(fr), false) = (context.flight_recorder.as_mut(), on_secondary) {
+            if let Some(timestamp) = packet.timestamp(precision) {
+                fr.record(timestamp, linktype, packet);
+            }
+        }
+    }
+
+    if let Some(pch) = pause_control_handle {
+        pch.abort();
+    }
+    for (capture_index, packet_handler_handle) in packet_handler_handles {
+        let (capture_stats, restart_count) = match packet_handler_handle.await {
+            Ok(r) => r,
             Err(e) => {
-                warn!("failed to decode DNS packet {:?}: {}", packet.data.as_slice(), e);
-                continue;
+                error!("packet handler panicked: {}", e);
+                (None, 0)
             },
         };
+        if let Some(ci) = capture_index {
+            let (interface_position, member) = &capture_targets[ci];
+            let statistics_index = if merge_interfaces { 0 } else { *interface_position };
+            let capture_loss = &mut all_statistics[statistics_index].untenanted.capture_loss;
+            if let Some(m) = member {
+                let member_count = capture_loss.member_to_packet_count.entry(m.clone()).or_insert(0);
+                *member_count += capture_packet_counts[ci];
+            }
+            capture_loss.interface_restarts += restart_count;
+            if let Some(cs) = capture_stats {
+                capture_loss.packets_dropped += cs.dropped;
+                capture_loss.packets_dropped_by_interface += cs.dropped_by_interface;
+            }
+        }
+    }
 
-        let timestamp_raw = packet.header.ts;
-        let timestamp = Utc.timestamp(
-            timestamp_raw.tv_sec.into(),
-            u32::try_from(timestamp_raw.tv_usec).unwrap() * 1000,
-        );
+    let runtime = RuntimeStats::current(packet_queue_capacity, packet_queue_max_depth, context.detail_level);
+    context.detail_level = DetailLevel::Full;
+    for interface_statistics in &mut all_statistics {
+        interface_statistics.untenanted.runtime = Some(runtime.clone());
+    }
 
-        // we are interested in query type and name of requests
-        if dns.message_type() != MessageType::Query {
-            continue;
+    let actual_sample_duration = stop_time.unwrap_or_else(|| Instant::now()) - start_time;
+    Ok(finish_sample(all_statistics, context, sample_duration, actual_sample_duration))
+}
+
+
+/// Reads the packets from a capture file and collects their statistics.
+///
+/// Returns a single set of statistics, followed by one set per configured tenant and then by those
+/// of the profiles. The sample duration is the time between the first and the last packet in the
+/// file.
+pub fn replay_file(
+    path: &Path,
+    filter: Option<&str>,
+    precision: Precision,
+    context: &mut SampleContext,
+) -> Result<Vec<DnsStats>, SamplingError> {
+    let mut cap = open_capture_file(path, precision)
+        .map_err(|e| SamplingError::OpenCaptureFile(e))?;
+    let linktype = cap.linktype();
+    if let Some(f) = filter {
+        cap.set_filter(f)
+            .map_err(|e| SamplingError::SetFilter { linktype, error: e })?;
+    }
+
+    let mut all_statistics = vec![InterfaceStatistics::new(None, context)];
+    let mut first_and_last_timestamp = None;
+    loop {
+        let packet = match cap.next_packet() {
+            Ok(Some(p)) => p,
+            Ok(None) => break,
+            Err(e) => return Err(SamplingError::ReadCaptureFile(e)),
+        };
+        process_packet(&packet, linktype, false, precision, context, &mut all_statistics[0]);
+
+        if let Some(timestamp) = packet.timestamp(precision) {
+            first_and_last_timestamp = match first_and_last_timestamp {
+                None => Some((timestamp, timestamp)),
+                Some((first, _last)) => Some((first, timestamp)),
+            };
+            if let Some(fr) = context.flight_recorder.as_mut() {
+                fr.record(timestamp, linktype, packet);
+            }
+        }
+    }
+
+    let sample_duration = match first_and_last_timestamp {
+        Some((first, last)) => (last - first).to_std().unwrap_or(Duration::ZERO),
+        None => Duration::ZERO,
+    };
+    Ok(finish_sample(all_statistics, context, sample_duration, sample_duration))
+}
+
+
+/// Completes the statistics once all packets have been processed and flattens them into a list.
+fn finish_sample(
+    mut all_statistics: Vec<InterfaceStatistics>,
+    context: &mut SampleContext,
+    configured_sample_duration: Duration,
+    actual_sample_duration: Duration,
+) -> Vec<DnsStats> {
+    if let Some(fr) = context.flight_recorder.as_mut() {
+        fr.close();
+    }
+    for client_capture in &mut context.client_captures {
+        client_capture.close();
+    }
+    context.warning_limiter.summarize();
+
+    // the profiles share the capture as well, but are completed according to their own configuration
+    let mut profile_statistics: Vec<Vec<InterfaceStatistics>> = context.profiles.iter()
+        .map(|_| Vec::new())
+        .collect();
+    for interface_statistics in &mut all_statistics {
+        for (profile_index, mut statistics) in interface_statistics.profiles.drain(..).enumerate() {
+            statistics.untenanted.capture_loss = interface_statistics.untenanted.capture_loss.clone();
+            statistics.untenanted.runtime = interface_statistics.untenanted.runtime.clone();
+            profile_statistics[profile_index].push(statistics);
         }
-        for query in dns.queries() {
-            let query_type = query.query_type();
-            let name = query.name();
+    }
+
+    // the tenants share the capture, and with it its losses
+    for interface_statistics in &mut all_statistics {
+        let capture_loss = &interface_statistics.untenanted.capture_loss;
+        for statistics in interface_statistics.tenant_to_stats.values_mut() {
+            statistics.capture_loss = capture_loss.clone();
+        }
+    }
 
-            // TODO: store this
-            statistics.add_query(timestamp, ip_header.source_address(), query_type, name.clone());
+    for statistics in all_statistics.iter_mut().flat_map(|s| s.iter_mut()) {
+        if let Some(neighbors) = &context.neighbors {
+            statistics.set_source_mac_addresses(neighbors);
         }
+        if let Some(tracker) = &context.dhcp_tracker {
+            statistics.set_source_host_names(tracker);
+        }
+        #[cfg(feature = "docker")]
+        if let Some(cd) = &context.container_directory {
+            statistics.set_source_containers(cd);
+        }
+        statistics.summarize_query_names(&context.watched_zones);
+        statistics.summarize_query_type_entropy();
+        statistics.summarize_scanners();
+        statistics.summarize_fingerprints();
+        statistics.set_watched_name_address_counts(&context.answer_watchlist);
+        if let Some(ad) = &context.anomaly_detector {
+            statistics.anomaly_active = ad.active_anomalies();
+        }
+        statistics.global_labels = context.global_labels.clone();
+        statistics.configured_sample_duration = configured_sample_duration;
+        statistics.actual_sample_duration = actual_sample_duration;
+    }
+
+    // the comparison is between the secondary interface and all the others
+    if let Some(ic) = context.interface_comparison.as_mut() {
+        all_statistics[0].untenanted.interface_comparison = Some(ic.take_stats());
+    }
+
+    let mut flattened: Vec<DnsStats> = all_statistics.into_iter()
+        .flat_map(|s| std::iter::once(s.untenanted).chain(s.tenant_to_stats.into_values()))
+        .collect();
+    for (profile_context, statistics) in context.profiles.iter_mut().zip(profile_statistics) {
+        flattened.extend(finish_sample(statistics, profile_context, configured_sample_duration, actual_sample_duration));
     }
+    flattened
+}
+
+
+#[cfg(test)]
+mod tests {
+    use std::net::{IpAddr, Ipv4Addr};
+    use std::str::FromStr;
+    use std::time::Duration;
+
+    use hickory_proto::op::{Message, Query};
+    use hickory_proto::rr::{DNSClass, Name, RData, Record, RecordType};
+    use hickory_proto::rr::rdata::{A, CNAME, NS};
 
-    if let Err(e) = packet_handler_handle.await {
-        error!("packet handler panicked: {}", e);
+    use super::{follow_cname_chain, has_out_of_bailiwick_records, InterfaceStatistics, MAX_CNAME_CHAIN_LENGTH, process_packet, reopen_delay, SampleContext};
+    use crate::dissect::DetailLevel;
+    use crate::dns::Opcode;
+    use crate::edns::CookieUse;
+    use crate::packet::{Linktype, OwnedPacket, PacketHeader, Precision};
+    use crate::stage_timer::PipelineStage;
+
+    /// Loads a frame from a fixture file: hex bytes separated by whitespace, comment lines start
+    /// with `#`.
+    fn load_fixture(file_name: &str, offset: Duration) -> OwnedPacket {
+        let path = format!("{}/tests/fixtures/{}", env!("CARGO_MANIFEST_DIR"), file_name);
+        let text = std::fs::read_to_string(&path)
+            .expect("failed to read fixture");
+        let data: Vec<u8> = text.lines()
+            .filter(|l| !l.starts_with('#'))
+            .flat_map(|l| l.split_whitespace())
+            .map(|b| u8::from_str_radix(b, 16).expect("invalid byte in fixture"))
+            .collect();
+
+        // 2022-10-01 12:00:00 UTC
+        let mut header: PacketHeader = unsafe { std::mem::zeroed() };
+        header.ts.tv_sec = (1_664_625_600 + offset.as_secs()).try_into().unwrap();
+        header.ts.tv_usec = offset.subsec_micros().try_into().unwrap();
+        header.caplen = data.len().try_into().unwrap();
+        header.len = data.len().try_into().unwrap();
+        OwnedPacket {
+            header,
+            data,
+        }
     }
 
-    Ok(statistics)
+    fn test_context() -> SampleContext {
+        SampleContext::new(chrono::Duration::seconds(5))
+    }
+
+    /// Runs the given fixtures, 10 ms apart, through the dissection path.
+    fn process_fixtures(mut context: SampleContext, file_names: &[&str]) -> InterfaceStatistics {
+        let mut statistics = InterfaceStatistics::new(None, &context);
+        for (i, file_name) in file_names.iter().enumerate() {
+            let packet = load_fixture(file_name, Duration::from_millis(10) * u32::try_from(i).unwrap());
+            process_packet(&packet, Linktype::ETHERNET, false, Precision::Micro, &mut context, &mut statistics);
+        }
+        statistics
+    }
+
+    #[test]
+    fn test_udp_ipv4() {
+        let stats = process_fixtures(test_context(), &["udp_query_ipv4.hex", "udp_response_ipv4.hex"]).untenanted;
+        let client: IpAddr = "192.0.2.1".parse().unwrap();
+        let server: IpAddr = "192.0.2.53".parse().unwrap();
+
+        assert_eq!(stats.total_count, 1);
+        assert_eq!(stats.source_to_stats[&client].count, 1);
+        assert_eq!(stats.source_to_stats[&client].type_to_count[&RecordType::A], 1);
+        assert_eq!(stats.query_kind_to_count[&(Opcode::Query, DNSClass::IN, RecordType::A)], 1);
+        assert_eq!(stats.response_count, 1);
+        assert_eq!(stats.server_to_stats[&server].response_count, 1);
+        assert_eq!(stats.server_message_to_count[&(server, false)], 1);
+        assert_eq!(stats.server_flag_to_count[&(server, false, "RD")], 1);
+        assert_eq!(stats.server_flag_to_count[&(server, true, "RA")], 1);
+        assert_eq!(stats.matched_response_count, 1);
+        assert_eq!(stats.stage_to_processing_time[&PipelineStage::Dns].count, 2);
+        assert_eq!(stats.latency.count, 1);
+        assert_eq!(stats.latency.sum, Duration::from_millis(10));
+        assert_eq!(stats.answer_network_to_count[&("192.0.2.80".parse().unwrap(), 32)], 1);
+        assert_eq!(stats.answer_record_type_to_count[&RecordType::A], 1);
+        assert_eq!(stats.unsolicited_server_to_count.len(), 0);
+    }
+
+    #[test]
+    fn test_vlan_tagged() {
+        let stats = process_fixtures(test_context(), &["vlan_query_ipv4.hex"]).untenanted;
+        let client: IpAddr = "192.0.2.1".parse().unwrap();
+
+        assert_eq!(stats.total_count, 1);
+        assert_eq!(stats.source_to_stats[&client].count, 1);
+    }
+
+    #[test]
+    fn test_tenants() {
+        let mut context = test_context();
+        context.tenants.add_rule("acme=vlan:100").unwrap();
+        context.tenants.add_rule("globex=192.0.2.0/24").unwrap();
+        let statistics = process_fixtures(context, &["vlan_query_ipv4.hex", "udp_query_ipv4.hex", "udp_response_ipv4.hex", "udp_query_ipv6.hex"]);
+
+        let acme = &statistics.tenant_to_stats["acme"];
+        assert_eq!(acme.tenant.as_deref(), Some("acme"));
+        assert_eq!(acme.total_count, 1);
+        assert_eq!(acme.response_count, 0);
+
+        // responses are attributed to the tenant of the client they are sent to
+        let globex = &statistics.tenant_to_stats["globex"];
+        assert_eq!(globex.total_count, 1);
+        assert_eq!(globex.matched_response_count, 1);
+
+        assert_eq!(statistics.untenanted.tenant, None);
+        assert_eq!(statistics.untenanted.total_count, 1);
+    }
+
+    #[test]
+    fn test_profiles() {
+        let mut context = test_context();
+        let mut lab = test_context();
+        lab.profile_name = Some("lab".to_owned());
+        lab.profile_selector = Some("192.0.2.0/24".parse().unwrap());
+        context.profiles.push(lab);
+
+        // a profile built from the main context shares its configuration
+        context.tenants.add_rule("acme=vlan:100").unwrap();
+        let mut v6 = context.new_profile("v6".to_owned(), chrono::Duration::seconds(5));
+        assert_eq!(v6.tenants, context.tenants);
+        v6.profile_filter = Some("ip6".to_owned());
+        context.profiles.push(v6);
+
+        let statistics = process_fixtures(context, &["udp_query_ipv4.hex", "udp_response_ipv4.hex", "udp_query_ipv6.hex"]);
+
+        // the main statistics see everything, the profile only its subnet
+        assert_eq!(statistics.untenanted.profile, None);
+        assert_eq!(statistics.untenanted.total_count, 2);
+        let lab = &statistics.profiles[0].untenanted;
+        assert_eq!(lab.profile.as_deref(), Some("lab"));
+        assert_eq!(lab.total_count, 1);
+        assert_eq!(lab.matched_response_count, 1);
+        let v6 = &statistics.profiles[1].untenanted;
+        assert_eq!(v6.profile.as_deref(), Some("v6"));
+        assert_eq!(v6.total_count, 1);
+        assert_eq!(v6.matched_response_count, 0);
+    }
+
+    #[test]
+    fn test_udp_ipv6() {
+        let stats = process_fixtures(test_context(), &["udp_query_ipv6.hex", "udp_response_ipv6.hex"]).untenanted;
+        let client: IpAddr = "2001:db8::1".parse().unwrap();
+
+        assert_eq!(stats.total_count, 1);
+        assert_eq!(stats.source_to_stats[&client].type_to_count[&RecordType::AAAA], 1);
+        assert_eq!(stats.matched_response_count, 1);
+        assert_eq!(stats.answer_network_to_count[&("2001:db8::80".parse().unwrap(), 128)], 1);
+    }
+
+    #[test]
+    fn test_edns_and_truncated() {
+        let stats = process_fixtures(test_context(), &["edns_query_ipv4.hex", "truncated_response_ipv4.hex"]).untenanted;
+
+        // the OPT pseudo-record is neither a question nor an out-of-bailiwick record
+        assert_eq!(stats.total_count, 1);
+        assert_eq!(stats.matched_response_count, 1);
+        assert_eq!(stats.out_of_bailiwick_server_to_count.len(), 0);
+        assert_eq!(stats.answer_network_to_count.len(), 0);
+
+        // neither message carries a cookie
+        let client: IpAddr = "192.0.2.1".parse().unwrap();
+        assert_eq!(stats.query_cookie_to_count[&CookieUse::None], 1);
+        assert_eq!(stats.response_cookie_to_count[&CookieUse::None], 1);
+        assert_eq!(stats.source_to_stats[&client].query_message_count, 1);
+        assert_eq!(stats.source_to_stats[&client].cookie_query_count, 0);
+    }
+
+    #[test]
+    fn test_notify() {
+        let stats = process_fixtures(test_context(), &["notify_ipv4.hex"]).untenanted;
+        let primary: IpAddr = "192.0.2.53".parse().unwrap();
+        let secondary: IpAddr = "192.0.2.54".parse().unwrap();
+
+        assert_eq!(stats.query_kind_to_count[&(Opcode::Notify, DNSClass::IN, RecordType::SOA)], 1);
+        assert_eq!(stats.notify_count, 1);
+        assert_eq!(stats.notify_pair_to_count[&(primary, secondary)], 1);
+        assert_eq!(stats.recent_zone_operations[0].zone, "example.com");
+        assert_eq!(stats.update_count, 0);
+    }
+
+    #[test]
+    fn test_fragmented() {
+        // without reassembly, the UDP checksum of the first fragment cannot be verified
+        let stats = process_fixtures(test_context(), &["fragmented_response_ipv4.hex"]).untenanted;
+
+        assert_eq!(stats.response_count, 0);
+        assert_eq!(stats.fragment_kind_to_count["response"], 1);
+    }
+
+    #[test]
+    fn test_reduced_detail() {
+        let mut context = test_context();
+        context.detail_level = DetailLevel::HeadersOnly;
+        let stats = process_fixtures(context, &["udp_query_ipv4.hex", "udp_response_ipv4.hex"]).untenanted;
+
+        // the totals are still accurate, but the names are not recorded
+        assert_eq!(stats.total_count, 1);
+        assert_eq!(stats.response_count, 1);
+        assert_eq!(stats.matched_response_count, 1);
+        assert_eq!(stats.answer_record_type_to_count[&RecordType::A], 1);
+        assert_eq!(stats.distinct_query_names.estimate(), 0);
+        assert_eq!(stats.answer_network_to_count.len(), 0);
+        assert_eq!(stats.reduced_detail_packet_count, 2);
+    }
+
+    #[test]
+    fn test_tcp() {
+        // the query fits into a single segment
+        let stats = process_fixtures(test_context(), &["tcp_query_ipv4.hex"]).untenanted;
+
+        assert_eq!(stats.total_count, 1);
+    }
+
+    #[cfg(feature = "tcp-tracking")]
+    #[test]
+    fn test_zone_transfer() {
+        // the response is split across two segments, the first ending within a record
+        let stats = process_fixtures(test_context(), &["tcp_axfr_query_ipv4.hex", "tcp_axfr_response_1_ipv4.hex", "tcp_axfr_response_2_ipv4.hex"]).untenanted;
+        let client: IpAddr = "192.0.2.1".parse().unwrap();
+        let server: IpAddr = "192.0.2.53".parse().unwrap();
+
+        let transfer_stats = &stats.zone_transfer_pair_to_stats[&(client, server)];
+        assert_eq!(transfer_stats.request_count, 1);
+        assert_eq!(transfer_stats.response_count, 1);
+        assert_eq!(transfer_stats.complete_response_count, 1);
+        assert_eq!(transfer_stats.response_message_count, 1);
+        assert_eq!(transfer_stats.response_byte_count, 226);
+    }
+
+    #[test]
+    fn test_reopen_delay() {
+        assert_eq!(reopen_delay(1), Duration::from_millis(500));
+        assert_eq!(reopen_delay(2), Duration::from_secs(1));
+        assert_eq!(reopen_delay(5), Duration::from_secs(8));
+        assert_eq!(reopen_delay(8), Duration::from_secs(60));
+        assert_eq!(reopen_delay(200), Duration::from_secs(60));
+    }
+
+    #[test]
+    fn test_follow_cname_chain() {
+        let name = |n: &str| Name::from_str(n).unwrap();
+        let alias = |owner: &str, target: &str| Record::from_rdata(name(owner), 300, RData::CNAME(CNAME(name(target))));
+        let address = |owner: &str| Record::from_rdata(name(owner), 300, RData::A(A(Ipv4Addr::new(192, 0, 2, 80))));
+
+        let answers = [alias("www.example.com.", "cdn.example.net."), alias("cdn.example.net.", "edge.example.org."), address("edge.example.org.")];
+        assert_eq!(follow_cname_chain(&name("www.example.com."), &answers), vec!["cdn.example.net", "edge.example.org"]);
+
+        // the chain ends where the target has no records in the response
+        let answers = [alias("www.example.com.", "gone.example.net.")];
+        assert_eq!(follow_cname_chain(&name("www.example.com."), &answers), vec!["gone.example.net"]);
+
+        // a loop is followed once around
+        let answers = [alias("a.example.com.", "b.example.com."), alias("b.example.com.", "a.example.com.")];
+        assert_eq!(follow_cname_chain(&name("a.example.com."), &answers), vec!["b.example.com", "a.example.com"]);
+
+        let answers: Vec<Record> = (0..MAX_CNAME_CHAIN_LENGTH + 4)
+            .map(|i| alias(&format!("{}.example.com.", i), &format!("{}.example.com.", i + 1)))
+            .collect();
+        let targets = follow_cname_chain(&name("0.example.com."), &answers);
+        assert_eq!(targets.len(), MAX_CNAME_CHAIN_LENGTH);
+        assert_eq!(targets.last().unwrap(), &format!("{}.example.com", MAX_CNAME_CHAIN_LENGTH));
+    }
+
+    #[test]
+    fn test_out_of_bailiwick() {
+        let name = |n: &str| Name::from_str(n).unwrap();
+        let address = |owner: &str| Record::from_rdata(name(owner), 300, RData::A(A(Ipv4Addr::new(192, 0, 2, 80))));
+
+        // a referral from the co.uk servers may only carry glue within the delegated zone
+        let mut referral = Message::new();
+        referral.add_query(Query::query(name("www.example.co.uk."), RecordType::A));
+        referral.add_name_server(Record::from_rdata(name("example.co.uk."), 3600, RData::NS(NS(name("ns1.example.co.uk.")))));
+        referral.add_additional(address("ns1.example.co.uk."));
+        assert!(!has_out_of_bailiwick_records("www.example.co.uk", &[], &[], &referral));
+        referral.add_additional(address("www.other.co.uk."));
+        assert!(has_out_of_bailiwick_records("www.example.co.uk", &[], &[], &referral));
+
+        // without a zone cut, only the queried name and its aliases are acceptable
+        let mut answer = Message::new();
+        answer.add_query(Query::query(name("www.example.com."), RecordType::A));
+        answer.add_answer(Record::from_rdata(name("www.example.com."), 300, RData::CNAME(CNAME(name("cdn.example.net.")))));
+        answer.add_answer(address("cdn.example.net."));
+        assert!(!has_out_of_bailiwick_records("www.example.com", &["cdn.example.net".to_owned()], &[], &answer));
+        answer.add_additional(address("mail.example.com."));
+        assert!(has_out_of_bailiwick_records("www.example.com", &["cdn.example.net".to_owned()], &[], &answer));
+
+        // a cut above the registered domain vouches for nothing, unless it is watched
+        let mut forged = Message::new();
+        forged.add_query(Query::query(name("www.example.com."), RecordType::A));
+        forged.add_name_server(Record::from_rdata(name("com."), 3600, RData::NS(NS(name("ns.attacker.example.")))));
+        forged.add_additional(address("www.bank.com."));
+        assert!(has_out_of_bailiwick_records("www.example.com", &[], &[], &forged));
+        assert!(!has_out_of_bailiwick_records("www.example.com", &[], &["com".to_owned()], &forged));
+
+        // and neither does the root
+        let mut forged = Message::new();
+        forged.add_query(Query::query(name("www.example.com."), RecordType::A));
+        forged.add_name_server(Record::from_rdata(Name::root(), 3600, RData::NS(NS(name("ns.attacker.example.")))));
+        forged.add_additional(address("www.bank.com."));
+        assert!(has_out_of_bailiwick_records("www.example.com", &[], &[], &forged));
+        assert!(has_out_of_bailiwick_records("www.example.com", &[], &["".to_owned()], &forged));
+    }
 }
@@ -0,0 +1,236 @@
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+#[cfg(not(feature = "libpcap"))] use std::io::BufReader;
+#[cfg(any(test, not(feature = "libpcap")))] use std::io::Read;
+use std::path::Path;
+
+use crate::packet::{Linktype, OwnedPacket, Precision};
+#[cfg(any(test, not(feature = "libpcap")))] use crate::packet::PacketHeader;
+
+
+const MAGIC_MICROSECONDS: u32 = 0xa1b2c3d4;
+const MAGIC_NANOSECONDS: u32 = 0xa1b23c4d;
+#[cfg(any(test, not(feature = "libpcap")))] const MAGIC_PCAPNG: u32 = 0x0a0d0d0a;
+const VERSION_MAJOR: u16 = 2;
+const VERSION_MINOR: u16 = 4;
+const SNAPSHOT_LENGTH: u32 = 262144;
+
+const FILE_HEADER_LENGTH: usize = 24;
+const RECORD_HEADER_LENGTH: usize = 16;
+
+// the upper bits of the link type field may describe a frame check sequence
+#[cfg(any(test, not(feature = "libpcap")))] const LINKTYPE_MASK: u32 = 0x03ff_ffff;
+
+// larger records are taken as a sign of a corrupt file rather than allocated
+#[cfg(any(test, not(feature = "libpcap")))] const MAX_RECORD_LENGTH: u32 = 16 * 1024 * 1024;
+
+
+/// Writes packets to a file in the pcap format, in the byte order of the host as libpcap does.
+pub struct SavefileWriter<W: Write> {
+    writer: W,
+}
+impl SavefileWriter<BufWriter<File>> {
+    pub fn create(path: &Path, linktype: Linktype, precision: Precision) -> Result<Self, io::Error> {
+        Self::new(BufWriter::new(File::create(path)?), linktype, precision)
+    }
+}
+impl<W: Write> SavefileWriter<W> {
+    pub fn new(mut writer: W, linktype: Linktype, precision: Precision) -> Result<Self, io::Error> {
+        let magic = match precision {
+            Precision::Micro => MAGIC_MICROSECONDS,
+            Precision::Nano => MAGIC_NANOSECONDS,
+        };
+        let mut header = Vec::with_capacity(FILE_HEADER_LENGTH);
+        header.extend_from_slice(&magic.to_ne_bytes());
+        header.extend_from_slice(&VERSION_MAJOR.to_ne_bytes());
+        header.extend_from_slice(&VERSION_MINOR.to_ne_bytes());
+        header.extend_from_slice(&0i32.to_ne_bytes()); // time zone offset
+        header.extend_from_slice(&0u32.to_ne_bytes()); // timestamp accuracy
+        header.extend_from_slice(&SNAPSHOT_LENGTH.to_ne_bytes());
+        header.extend_from_slice(&(linktype.0 as u32).to_ne_bytes());
+        writer.write_all(&header)?;
+        Ok(Self { writer })
+    }
+
+    pub fn write(&mut self, packet: &OwnedPacket) -> Result<(), io::Error> {
+        let mut header = [0u8; RECORD_HEADER_LENGTH];
+        header[0..4].copy_from_slice(&(packet.header.ts.tv_sec as u32).to_ne_bytes());
+        header[4..8].copy_from_slice(&(packet.header.ts.tv_usec as u32).to_ne_bytes());
+        header[8..12].copy_from_slice(&(packet.data.len() as u32).to_ne_bytes());
+        header[12..16].copy_from_slice(&packet.header.len.to_ne_bytes());
+        self.writer.write_all(&header)?;
+        self.writer.write_all(&packet.data)
+    }
+
+    pub fn flush(&mut self) -> Result<(), io::Error> {
+        self.writer.flush()
+    }
+}
+
+
+/// Reads packets from a file in the pcap format, in either byte order and with either timestamp
+/// precision; pcapng files are not supported.
+#[cfg(any(test, not(feature = "libpcap")))]
+pub struct SavefileReader<R: Read> {
+    reader: R,
+    linktype: Linktype,
+    swapped: bool,
+    file_precision: Precision,
+    precision: Precision,
+}
+#[cfg(not(feature = "libpcap"))]
+impl SavefileReader<BufReader<File>> {
+    pub fn open(path: &Path, precision: Precision) -> Result<Self, io::Error> {
+        Self::new(BufReader::new(File::open(path)?), precision)
+    }
+}
+#[cfg(any(test, not(feature = "libpcap")))]
+impl<R: Read> SavefileReader<R> {
+    /// Reads the file header. The timestamps of the packets are converted to the given precision.
+    pub fn new(mut reader: R, precision: Precision) -> Result<Self, io::Error> {
+        let mut header = [0u8; FILE_HEADER_LENGTH];
+        reader.read_exact(&mut header)?;
+        let magic = u32::from_ne_bytes(header[0..4].try_into().unwrap());
+        let (swapped, file_precision) = match magic {
+            MAGIC_MICROSECONDS => (false, Precision::Micro),
+            MAGIC_NANOSECONDS => (false, Precision::Nano),
+            m if m.swap_bytes() == MAGIC_MICROSECONDS => (true, Precision::Micro),
+            m if m.swap_bytes() == MAGIC_NANOSECONDS => (true, Precision::Nano),
+            MAGIC_PCAPNG => return Err(io::Error::new(io::ErrorKind::InvalidData, "pcapng files can only be read when built with libpcap")),
+            _ => return Err(io::Error::new(io::ErrorKind::InvalidData, "not a pcap file")),
+        };
+
+        let mut savefile = Self {
+            reader,
+            linktype: Linktype::NULL,
+            swapped,
+            file_precision,
+            precision,
+        };
+        let linktype = savefile.read_u32(&header[20..24]) & LINKTYPE_MASK;
+        savefile.linktype = Linktype(linktype as i32);
+        Ok(savefile)
+    }
+
+    fn read_u32(&self, bytes: &[u8]) -> u32 {
+        let value = u32::from_ne_bytes(bytes.try_into().unwrap());
+        if self.swapped { value.swap_bytes() } else { value }
+    }
+
+    pub fn linktype(&self) -> Linktype {
+        self.linktype
+    }
+
+    /// Reads the next packet, returning `None` at the end of the file.
+    pub fn next_packet(&mut self) -> Result<Option<OwnedPacket>, io::Error> {
+        let mut header = [0u8; RECORD_HEADER_LENGTH];
+        let mut header_read = 0;
+        while header_read < header.len() {
+            match self.reader.read(&mut header[header_read..]) {
+                Ok(0) if header_read == 0 => return Ok(None),
+                Ok(0) => return Err(io::ErrorKind::UnexpectedEof.into()),
+                Ok(n) => header_read += n,
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => {},
+                Err(e) => return Err(e),
+            }
+        }
+
+        let seconds = self.read_u32(&header[0..4]);
+        let subseconds = self.read_u32(&header[4..8]);
+        let captured_length = self.read_u32(&header[8..12]);
+        let length = self.read_u32(&header[12..16]);
+        if captured_length > MAX_RECORD_LENGTH {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "packet record too long"));
+        }
+        let mut data = vec![0; captured_length as usize];
+        self.reader.read_exact(&mut data)?;
+
+        let subseconds = match (self.file_precision, self.precision) {
+            (Precision::Micro, Precision::Nano) => subseconds.saturating_mul(1000),
+            (Precision::Nano, Precision::Micro) => subseconds / 1000,
+            _ => subseconds,
+        };
+        Ok(Some(OwnedPacket {
+            header: PacketHeader {
+                ts: libc::timeval {
+                    tv_sec: seconds as libc::time_t,
+                    tv_usec: subseconds as libc::suseconds_t,
+                },
+                caplen: captured_length,
+                len: length,
+            },
+            data,
+        }))
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use crate::packet::{Linktype, OwnedPacket, PacketHeader, Precision};
+
+    use super::{FILE_HEADER_LENGTH, RECORD_HEADER_LENGTH, SavefileReader, SavefileWriter};
+
+    fn packet(seconds: i64, subseconds: i64, data: &[u8]) -> OwnedPacket {
+        OwnedPacket {
+            header: PacketHeader {
+                ts: libc::timeval {
+                    tv_sec: seconds as libc::time_t,
+                    tv_usec: subseconds as libc::suseconds_t,
+                },
+                caplen: data.len() as u32,
+                len: 1500,
+            },
+            data: data.to_vec(),
+        }
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let mut buffer = Vec::new();
+        let mut writer = SavefileWriter::new(&mut buffer, Linktype::RAW, Precision::Nano).unwrap();
+        writer.write(&packet(1664625600, 123_456_789, b"first")).unwrap();
+        writer.write(&packet(1664625601, 0, b"")).unwrap();
+        writer.flush().unwrap();
+
+        let mut reader = SavefileReader::new(buffer.as_slice(), Precision::Micro).unwrap();
+        assert_eq!(reader.linktype(), Linktype::RAW);
+        let first = reader.next_packet().unwrap().unwrap();
+        assert_eq!(first.header.ts.tv_sec, 1664625600);
+        assert_eq!(first.header.ts.tv_usec, 123_456);
+        assert_eq!((first.header.caplen, first.header.len), (5, 1500));
+        assert_eq!(first.data, b"first");
+        assert_eq!(reader.next_packet().unwrap().unwrap().data, b"");
+        assert!(reader.next_packet().unwrap().is_none());
+
+        // a file cut off within a record
+        let truncated = &buffer[..FILE_HEADER_LENGTH + RECORD_HEADER_LENGTH + 2];
+        let mut reader = SavefileReader::new(truncated, Precision::Micro).unwrap();
+        assert!(reader.next_packet().is_err());
+    }
+
+    #[test]
+    fn test_other_byte_order() {
+        // the header and record of a big-endian file with microsecond timestamps
+        let mut file = vec![
+            0xa1, 0xb2, 0xc3, 0xd4, 0x00, 0x02, 0x00, 0x04,
+            0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+            0x00, 0x04, 0x00, 0x00, 0x00, 0x00, 0x00, 0x01,
+            0x63, 0x38, 0x2b, 0xc0, 0x00, 0x01, 0xe2, 0x40,
+            0x00, 0x00, 0x00, 0x02, 0x00, 0x00, 0x00, 0x3c,
+        ];
+        file.extend_from_slice(b"hi");
+
+        let mut reader = SavefileReader::new(file.as_slice(), Precision::Nano).unwrap();
+        assert_eq!(reader.linktype(), Linktype::ETHERNET);
+        let packet = reader.next_packet().unwrap().unwrap();
+        assert_eq!(packet.header.ts.tv_sec, 1664625600);
+        assert_eq!(packet.header.ts.tv_usec, 123_456_000);
+        assert_eq!((packet.header.caplen, packet.header.len), (2, 60));
+        assert_eq!(packet.data, b"hi");
+
+        // pcapng is recognized, but not read
+        let pcapng = [0x0a, 0x0d, 0x0d, 0x0a].iter().copied().chain([0; 20]).collect::<Vec<u8>>();
+        assert!(SavefileReader::new(pcapng.as_slice(), Precision::Micro).is_err());
+    }
+}
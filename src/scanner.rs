@@ -0,0 +1,138 @@
+use hickory_proto::rr::DNSClass;
+
+use crate::dns::DnsQuestion;
+
+
+const MIN_ANY_QUERIES: u64 = 20;
+const MIN_LEGACY_TYPE_QUERIES: u64 = 3;
+const MIN_CASE_RANDOMIZATION_QUERIES: u64 = 20;
+const MIN_SWEEP_QUERIES: u64 = 200;
+const MIN_SWEEP_NAME_ENTROPY: f64 = 5.5; // bits; the name histogram reaches at most 6
+
+const RECORD_TYPE_ANY: u16 = 255;
+
+/// The names asked for by fingerprinting tools, usually in the CHAOS class.
+pub const VERSION_PROBE_NAMES: [&str; 4] = ["version.bind", "hostname.bind", "id.server", "version.server"];
+
+// obsolete, experimental or historic types that ordinary clients never ask for:
+// MD, MF, MB, MG, MR, NULL, WKS, MINFO, A6, MAILB, MAILA
+const LEGACY_RECORD_TYPES: [u16; 11] = [3, 4, 7, 8, 9, 10, 11, 14, 38, 253, 254];
+
+
+/// A kind of scanning behavior.
+#[derive(Clone, Copy, Debug, Eq, Hash, Ord, PartialEq, PartialOrd)]
+pub enum ScannerKind {
+    NameSweep,
+    AnyFlood,
+    VersionProbe,
+    ChaosProbe,
+    LegacyTypeProbe,
+    CaseRandomization,
+}
+impl ScannerKind {
+    #[cfg(feature = "http")]
+    pub const ALL: [Self; 6] = [
+        Self::NameSweep, Self::AnyFlood, Self::VersionProbe, Self::ChaosProbe,
+        Self::LegacyTypeProbe, Self::CaseRandomization,
+    ];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::NameSweep => "name_sweep",
+            Self::AnyFlood => "any_flood",
+            Self::VersionProbe => "version_probe",
+            Self::ChaosProbe => "chaos_probe",
+            Self::LegacyTypeProbe => "legacy_type_probe",
+            Self::CaseRandomization => "case_randomization",
+        }
+    }
+}
+
+
+/// The peculiarities in the queries of a single source that hint at scanning.
+#[derive(Clone, Copy, Debug, Default, Eq, Hash, PartialEq)]
+pub struct ScanSignals {
+    pub any_count: u64,
+    pub version_probe_count: u64,
+    pub chaos_count: u64, // other than version probes
+    pub legacy_type_count: u64,
+    pub mixed_case_count: u64,
+}
+impl ScanSignals {
+    pub fn observe(&mut self, question: &DnsQuestion) {
+        let normalized_name = question.name.as_str();
+        if VERSION_PROBE_NAMES.contains(&&*normalized_name) {
+            self.version_probe_count += 1;
+        } else if question.record_class() == DNSClass::CH {
+            self.chaos_count += 1;
+        }
+
+        if question.query_type == RECORD_TYPE_ANY {
+            self.any_count += 1;
+        } else if LEGACY_RECORD_TYPES.contains(&question.query_type) {
+            self.legacy_type_count += 1;
+        }
+
+        if question.mixed_case {
+            self.mixed_case_count += 1;
+        }
+    }
+
+    /// Returns the kinds of scanning the source is suspected of, given how many queries it has
+    /// sent and how varied the names in them are.
+    ///
+    /// Resolvers randomize the case of names too (0x20 encoding), so case randomization is only
+    /// suspicious when looking at clients.
+    pub fn suspicions(&self, query_count: u64, name_entropy: f64) -> Vec<ScannerKind> {
+        let mut kinds = Vec::new();
+        if query_count >= MIN_SWEEP_QUERIES && name_entropy >= MIN_SWEEP_NAME_ENTROPY {
+            kinds.push(ScannerKind::NameSweep);
+        }
+        if self.any_count >= MIN_ANY_QUERIES {
+            kinds.push(ScannerKind::AnyFlood);
+        }
+        if self.version_probe_count > 0 {
+            kinds.push(ScannerKind::VersionProbe);
+        }
+        if self.chaos_count > 0 {
+            kinds.push(ScannerKind::ChaosProbe);
+        }
+        if self.legacy_type_count >= MIN_LEGACY_TYPE_QUERIES {
+            kinds.push(ScannerKind::LegacyTypeProbe);
+        }
+        if query_count >= MIN_CASE_RANDOMIZATION_QUERIES && self.mixed_case_count * 2 >= query_count {
+            kinds.push(ScannerKind::CaseRandomization);
+        }
+        kinds
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::{ScannerKind, ScanSignals};
+    use crate::dns::DnsQuestion;
+
+    #[test]
+    fn test_suspicions() {
+        let question = |name: &str, query_type, query_class, mixed_case| DnsQuestion {
+            name: name.parse().unwrap(),
+            query_type,
+            query_class,
+            mixed_case,
+        };
+
+        let mut signals = ScanSignals::default();
+        signals.observe(&question("version.bind", 16, 3, false));
+        signals.observe(&question("example.com", 1, 1, false));
+        assert_eq!(signals.suspicions(2, 1.0), vec![ScannerKind::VersionProbe]);
+
+        let mut signals = ScanSignals::default();
+        for _ in 0..20 {
+            signals.observe(&question("example.com", 255, 1, true));
+        }
+        signals.observe(&question("example.com", 254, 1, false));
+        assert_eq!(signals.suspicions(21, 0.0), vec![ScannerKind::AnyFlood, ScannerKind::CaseRandomization]);
+        assert_eq!(signals.suspicions(400, 5.9), vec![ScannerKind::NameSweep, ScannerKind::AnyFlood]);
+    }
+}
@@ -0,0 +1,179 @@
+use std::fmt;
+use std::fs;
+use std::io;
+use std::net::IpAddr;
+use std::path::Path;
+
+use mlua::{Function, Lua, Value};
+
+use crate::dns::{DnsHeader, DnsQuestion};
+
+
+#[derive(Debug)]
+pub enum ScriptError {
+    Io(io::Error),
+    Lua(mlua::Error),
+    MissingFunction,
+    InvalidResult(String),
+}
+impl fmt::Display for ScriptError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Io(e)
+                => write!(f, "failed to read script: {}", e),
+            Self::Lua(e)
+                => write!(f, "script failed: {}", e),
+            Self::MissingFunction
+                => write!(f, "script does not define a classify function"),
+            Self::InvalidResult(type_name)
+                => write!(f, "classify returned a {}; expected nil, a boolean or a table of labels", type_name),
+        }
+    }
+}
+impl std::error::Error for ScriptError {
+}
+impl From<io::Error> for ScriptError {
+    fn from(e: io::Error) -> Self { Self::Io(e) }
+}
+impl From<mlua::Error> for ScriptError {
+    fn from(e: mlua::Error) -> Self { Self::Lua(e) }
+}
+
+
+/// A DNS message as passed to the script.
+#[derive(Clone, Copy, Debug)]
+pub struct ScriptEvent<'a> {
+    pub is_response: bool,
+    pub client: IpAddr,
+    pub server: IpAddr,
+    pub vlan_id: Option<u16>,
+    pub header: &'a DnsHeader,
+    pub questions: &'a [DnsQuestion],
+}
+
+
+/// What the script decided to do with a DNS message.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum ScriptVerdict {
+    Keep(Vec<(String, String)>), // with extra labels
+    Drop,
+}
+
+
+/// A Lua script that classifies DNS messages according to site-specific rules.
+///
+/// The script defines a function `classify(event)` which is called with a table describing each
+/// DNS message (`kind`, `client`, `server`, `vlan`, `id`, `opcode`, `rcode` and `questions`, each
+/// with `name`, `type` and `class`). It returns `nil` or `true` to keep the message, `false` to
+/// drop it from the statistics, or a table of extra labels to count the message under.
+pub struct ScriptHook {
+    lua: Lua,
+}
+impl ScriptHook {
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self, ScriptError> {
+        let source = fs::read_to_string(path.as_ref())?;
+        Self::from_source(&source, &path.as_ref().display().to_string())
+    }
+
+    pub fn from_source(source: &str, name: &str) -> Result<Self, ScriptError> {
+        let lua = Lua::new();
+        lua.load(source).set_name(name).exec()?;
+        if !matches!(lua.globals().get::<_, Value>("classify")?, Value::Function(_)) {
+            return Err(ScriptError::MissingFunction);
+        }
+        Ok(Self {
+            lua,
+        })
+    }
+
+    pub fn classify(&self, event: &ScriptEvent) -> Result<ScriptVerdict, ScriptError> {
+        let table = self.lua.create_table()?;
+        table.set("kind", if event.is_response { "response" } else { "query" })?;
+        table.set("client", event.client.to_string())?;
+        table.set("server", event.server.to_string())?;
+        table.set("vlan", event.vlan_id)?;
+        table.set("id", event.header.id)?;
+        table.set("opcode", format!("{:?}", event.header.opcode()))?;
+        if event.is_response {
+            table.set("rcode", event.header.response_code().to_string())?;
+        }
+        let questions = self.lua.create_table()?;
+        for (i, question) in event.questions.iter().enumerate() {
+            let question_table = self.lua.create_table()?;
+            question_table.set("name", question.name.as_str().into_owned())?;
+            question_table.set("type", question.record_type().to_string())?;
+            question_table.set("class", question.record_class().to_string())?;
+            questions.set(i + 1, question_table)?;
+        }
+        table.set("questions", questions)?;
+
+        let classify: Function = self.lua.globals().get("classify")?;
+        match classify.call::<_, Value>(table)? {
+            Value::Nil|Value::Boolean(true) => Ok(ScriptVerdict::Keep(Vec::new())),
+            Value::Boolean(false) => Ok(ScriptVerdict::Drop),
+            Value::Table(labels) => {
+                let mut label_pairs: Vec<(String, String)> = labels.pairs::<String, String>()
+                    .collect::<Result<_, _>>()?;
+                label_pairs.sort_unstable();
+                Ok(ScriptVerdict::Keep(label_pairs))
+            },
+            other => Err(ScriptError::InvalidResult(other.type_name().to_owned())),
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use crate::dns::{DnsHeader, DnsQuestion};
+    use super::{ScriptEvent, ScriptHook, ScriptVerdict};
+
+    const SCRIPT: &str = r#"
+        function classify(event)
+            local name = event.questions[1].name
+            if name:sub(-#"corp.example") == "corp.example" then
+                return { site = "corp", kind = event.kind }
+            elseif event.client == "192.0.2.99" then
+                return false
+            end
+        end
+    "#;
+
+    #[test]
+    fn test_classify() {
+        let hook = ScriptHook::from_source(SCRIPT, "test").unwrap();
+        let header = DnsHeader {
+            id: 0x1234,
+            flags: 0x0100,
+            question_count: 1,
+            answer_count: 0,
+            authority_count: 0,
+            additional_count: 0,
+        };
+        let question = |name: &str| DnsQuestion {
+            name: name.parse().unwrap(),
+            query_type: 1,
+            query_class: 1,
+            mixed_case: false,
+        };
+        let event = |client: &str, questions| ScriptEvent {
+            is_response: false,
+            client: client.parse().unwrap(),
+            server: "192.0.2.53".parse().unwrap(),
+            vlan_id: None,
+            header: &header,
+            questions,
+        };
+
+        let corp = [question("intranet.corp.example")];
+        assert_eq!(
+            hook.classify(&event("192.0.2.1", &corp)).unwrap(),
+            ScriptVerdict::Keep(vec![("kind".to_owned(), "query".to_owned()), ("site".to_owned(), "corp".to_owned())]),
+        );
+        let other = [question("example.com")];
+        assert_eq!(hook.classify(&event("192.0.2.1", &other)).unwrap(), ScriptVerdict::Keep(Vec::new()));
+        assert_eq!(hook.classify(&event("192.0.2.99", &other)).unwrap(), ScriptVerdict::Drop);
+
+        assert!(ScriptHook::from_source("x = 1", "test").is_err());
+    }
+}
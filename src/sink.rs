@@ -0,0 +1,58 @@
+use std::collections::BTreeMap;
+use std::net::SocketAddr;
+
+use chrono::{DateTime, Utc};
+use hickory_proto::op::ResponseCode;
+use hickory_proto::rr::RecordType;
+use tokio::task::JoinHandle;
+
+use crate::nod::fnv1a;
+
+
+/// A question of a DNS query or response, as passed to the event sinks.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct DnsMessageEvent<'a> {
+    pub timestamp: DateTime<Utc>,
+    pub source: SocketAddr,
+    pub destination: SocketAddr,
+    pub transaction_id: u16,
+    pub name: &'a str,
+    pub record_type: RecordType,
+    pub over_tcp: bool,
+    pub response_code: Option<ResponseCode>, // None for queries
+    pub labels: &'a BTreeMap<String, String>, // the global labels, also attached to the metrics
+}
+impl<'a> DnsMessageEvent<'a> {
+    pub fn is_query(&self) -> bool {
+        self.response_code.is_none()
+    }
+
+    /// The address of the client, which sends the queries and receives the responses.
+    pub fn client(&self) -> SocketAddr {
+        if self.is_query() { self.source } else { self.destination }
+    }
+
+    /// The address of the server, which receives the queries and sends the responses.
+    pub fn server(&self) -> SocketAddr {
+        if self.is_query() { self.destination } else { self.source }
+    }
+
+    /// Identifies the flow between client and server; a query and its response share the same
+    /// flow ID.
+    pub fn flow_id(&self) -> u64 {
+        let flow = format!("{} {}", self.client(), self.server());
+        fnv1a(flow.as_bytes(), 0xCBF2_9CE4_8422_2325)
+    }
+}
+
+
+/// Passes the individual queries and responses on to another system.
+pub trait EventSink {
+    fn emit(&mut self, event: &DnsMessageEvent<'_>);
+
+    /// Stops accepting events. If the sink sends them in the background, returns the task that
+    /// finishes sending the events still queued.
+    fn close(self: Box<Self>) -> Option<JoinHandle<()>> {
+        None
+    }
+}
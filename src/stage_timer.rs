@@ -0,0 +1,80 @@
+use std::time::{Duration, Instant};
+
+use tracing::debug_span;
+use tracing::span::EnteredSpan;
+
+
+/// A stage of the pipeline that each captured packet passes through.
+#[derive(Clone, Copy, Debug, Eq, Hash, Ord, PartialEq, PartialOrd)]
+pub enum PipelineStage {
+    Link,
+    Ip,
+    Transport,
+    Dns,
+    Stats,
+}
+impl PipelineStage {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::Link => "link",
+            Self::Ip => "ip",
+            Self::Transport => "transport",
+            Self::Dns => "dns",
+            Self::Stats => "stats",
+        }
+    }
+}
+
+
+/// Measures how long a packet spends in each stage of the pipeline, entering a tracing span for
+/// each stage along the way.
+pub struct StageTimer {
+    current: Option<(PipelineStage, Instant, EnteredSpan)>,
+    timings: Vec<(PipelineStage, Duration)>,
+}
+impl StageTimer {
+    pub fn new() -> Self {
+        Self {
+            current: None,
+            timings: Vec::new(),
+        }
+    }
+
+    /// Finishes the current stage, if any, and starts the given one.
+    pub fn enter(&mut self, stage: PipelineStage) {
+        self.finish();
+        let span = debug_span!("stage", name = stage.name()).entered();
+        self.current = Some((stage, Instant::now(), span));
+    }
+
+    /// Finishes the current stage, if any.
+    pub fn finish(&mut self) {
+        if let Some((stage, start, span)) = self.current.take() {
+            self.timings.push((stage, start.elapsed()));
+            span.exit();
+        }
+    }
+
+    /// The time spent in each finished stage, in the order of the stages.
+    pub fn timings(&self) -> &[(PipelineStage, Duration)] {
+        &self.timings
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::{PipelineStage, StageTimer};
+
+    #[test]
+    fn test_stage_timer() {
+        let mut timer = StageTimer::new();
+        timer.enter(PipelineStage::Link);
+        timer.enter(PipelineStage::Ip);
+        assert_eq!(timer.timings().len(), 1);
+        timer.finish();
+        timer.finish();
+        let stages: Vec<PipelineStage> = timer.timings().iter().map(|(s, _)| *s).collect();
+        assert_eq!(stages, vec![PipelineStage::Link, PipelineStage::Ip]);
+    }
+}
@@ -1,58 +1,1400 @@
-use std::collections::HashMap;
+use std::collections::{BTreeMap, BTreeSet, HashMap, VecDeque};
+use std::collections::hash_map::DefaultHasher;
+use std::fmt;
+use std::hash::{Hash, Hasher};
 use std::net::IpAddr;
+use std::time::Duration;
 
 use chrono::{DateTime, Utc};
-use trust_dns_proto::rr::{Name, RecordType};
+use macaddr::MacAddr6;
+use hickory_proto::op::ResponseCode;
+use hickory_proto::rr::{DNSClass, RecordType};
+use tracing::debug;
 
+use crate::anomaly::AnomalyMetric;
+use crate::answer_watch::AnswerWatchlist;
+use crate::cast::CastKind;
+use crate::comparison::InterfaceComparisonStats;
+use crate::decay::DecayingCounter;
+use crate::dhcp::DhcpTracker;
+use crate::dissect::DetailLevel;
+#[cfg(feature = "docker")] use crate::docker::ContainerDirectory;
+use crate::dns::{DnsHeader, DnsQuestion, Opcode};
+use crate::edns::{CookieUse, Edns};
+use crate::fingerprint::{ClientTraits, Fingerprint};
+use crate::hyperloglog::HyperLogLog;
+use crate::icmp::IcmpFailureReason;
+#[cfg(feature = "kubernetes")] use crate::kubernetes::{PodDirectory, PodInfo};
+use crate::name_tree::NameTree;
+use crate::quantile::QuantileSummary;
+use crate::scanner::{ScannerKind, ScanSignals};
+use crate::stage_timer::PipelineStage;
+#[cfg(feature = "tcp-tracking")] use crate::tcp_connection::TcpTiming;
 
-#[derive(Clone, Debug, Default, Eq, PartialEq)]
+
+const MAX_RECENT_BLOCKLIST_HITS: usize = 100;
+const MAX_RECENT_ZONE_OPERATIONS: usize = 100;
+const MAX_INSTANCES_PER_SERVER: usize = 32;
+const MAX_ADDRESS_FAMILY_NAMES: usize = 10000;
+const MAX_RESPONSE_CODE_ZONES: usize = 10000;
+const MAX_QUERY_NAME_NODES: usize = 100000;
+const MAX_SOFTWARE_REPORTS: usize = 256;
+#[cfg(feature = "scripting")] const MAX_SCRIPT_LABELS: usize = 1000;
+const LOW_HOP_LIMIT: u8 = 2; // traceroute probes and packets crafted to expire just past the target
+const TOP_ZONE_DEPTH: usize = 2;
+const TOP_ZONE_COUNT: usize = 20;
+pub const HEAVY_HITTER_CAPACITY: usize = 100;
+const NAME_HISTOGRAM_BUCKETS: usize = 64;
+const DEFAULT_LATENCY_BOUNDS_MS: [u64; 12] = [1, 2, 5, 10, 25, 50, 100, 250, 500, 1000, 2500, 5000];
+const PROCESSING_TIME_BOUNDS_US: [u64; 10] = [1, 2, 5, 10, 25, 50, 100, 250, 500, 1000];
+
+/// Calculates the Shannon entropy, in bits, of the distribution given by the counts.
+fn entropy<I: IntoIterator<Item = u64>>(counts: I) -> f64 {
+    let counts: Vec<u64> = counts.into_iter()
+        .filter(|c| *c > 0)
+        .collect();
+    let total: u64 = counts.iter().sum();
+    if total == 0 {
+        return 0.0;
+    }
+
+    let total = total as f64;
+    counts.iter()
+        .map(|c| {
+            let probability = (*c as f64) / total;
+            -probability * probability.log2()
+        })
+        .sum()
+}
+
+
+/// Counts names in a fixed number of buckets chosen by their hash.
+///
+/// The entropy of the bucket counts approximates the entropy of the names, but cannot exceed
+/// log2(number of buckets) = 6 bits; this is enough to tell clients querying a handful of names
+/// apart from those querying a different name every time.
+#[derive(Clone, Default, Eq, PartialEq)]
+pub struct NameHistogram {
+    bucket_counts: Vec<u64>,
+}
+impl NameHistogram {
+    pub fn new() -> Self {
+        Self {
+            bucket_counts: vec![0; NAME_HISTOGRAM_BUCKETS],
+        }
+    }
+
+    pub fn add(&mut self, normalized_name: &str) {
+        let mut hasher = DefaultHasher::new();
+        normalized_name.hash(&mut hasher);
+        let bucket_index = usize::try_from(hasher.finish() % u64::try_from(self.bucket_counts.len()).unwrap()).unwrap();
+        self.bucket_counts[bucket_index] += 1;
+    }
+
+    pub fn entropy(&self) -> f64 {
+        entropy(self.bucket_counts.iter().map(|c| *c))
+    }
+}
+impl fmt::Debug for NameHistogram {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("NameHistogram")
+            .field("entropy", &self.entropy())
+            .finish()
+    }
+}
+
+
+#[derive(Clone, Debug, Default, PartialEq)]
 pub struct PerSourceStats {
     pub count: u64,
+    pub last_query_time: Option<DateTime<Utc>>,
     pub type_to_count: HashMap<RecordType, u64>,
+    pub names: NameHistogram,
+    pub query_type_entropy: f64, // in bits; calculated at the end of the sample
+    pub mac_address: Option<MacAddr6>,
+    pub host_name: Option<String>,
+    #[cfg(feature = "kubernetes")] pub pod: Option<PodInfo>,
+    #[cfg(feature = "docker")] pub container: Option<String>,
+    pub query_message_count: u64,
+    pub cookie_query_count: u64, // queries carrying a DNS cookie
+    pub minimizable_query_count: u64,
+    pub minimized_query_count: u64,
+    pub scan_signals: ScanSignals,
+    pub client_traits: ClientTraits,
+    pub fingerprint: Fingerprint, // guessed at the end of the sample
 }
 impl PerSourceStats {
     pub fn new() -> Self {
         Self {
             count: 0,
+            last_query_time: None,
             type_to_count: HashMap::new(),
+            names: NameHistogram::new(),
+            query_type_entropy: 0.0,
+            mac_address: None,
+            host_name: None,
+            #[cfg(feature = "kubernetes")] pod: None,
+            #[cfg(feature = "docker")] container: None,
+            query_message_count: 0,
+            cookie_query_count: 0,
+            minimizable_query_count: 0,
+            minimized_query_count: 0,
+            scan_signals: ScanSignals::default(),
+            client_traits: ClientTraits::default(),
+            fingerprint: Fingerprint::Unknown,
         }
     }
 }
 
 
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct DurationHistogram {
+    pub upper_bounds: Vec<Duration>,
+    pub bucket_counts: Vec<u64>, // one more than upper_bounds, for the values above the last bound
+    pub count: u64,
+    pub sum: Duration,
+}
+impl DurationHistogram {
+    pub fn new(upper_bounds: Vec<Duration>) -> Self {
+        let bucket_counts = vec![0; upper_bounds.len() + 1];
+        Self {
+            upper_bounds,
+            bucket_counts,
+            count: 0,
+            sum: Duration::ZERO,
+        }
+    }
+
+    pub fn new_latency() -> Self {
+        Self::new(
+            DEFAULT_LATENCY_BOUNDS_MS.iter()
+                .map(|ms| Duration::from_millis(*ms))
+                .collect()
+        )
+    }
+
+    pub fn new_processing_time() -> Self {
+        Self::new(
+            PROCESSING_TIME_BOUNDS_US.iter()
+                .map(|us| Duration::from_micros(*us))
+                .collect()
+        )
+    }
+
+    pub fn observe(&mut self, value: Duration) {
+        let bucket_index = self.upper_bounds.iter()
+            .position(|bound| value <= *bound)
+            .unwrap_or(self.upper_bounds.len());
+        self.bucket_counts[bucket_index] += 1;
+        self.count += 1;
+        self.sum += value;
+    }
+}
+impl Default for DurationHistogram {
+    fn default() -> Self { Self::new_latency() }
+}
+
+
+/// Latency histograms broken down by server and query type.
+///
+/// Only so many combinations receive their own histogram; the responses for all further ones are
+/// collected under `None`.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct ServerTypeLatency {
+    pub upper_bounds: Vec<Duration>,
+    pub max_label_sets: usize,
+    pub label_set_to_histogram: HashMap<Option<(IpAddr, RecordType)>, DurationHistogram>,
+}
+impl ServerTypeLatency {
+    pub fn new(upper_bounds: Vec<Duration>, max_label_sets: usize) -> Self {
+        Self {
+            upper_bounds,
+            max_label_sets,
+            label_set_to_histogram: HashMap::new(),
+        }
+    }
+
+    pub fn observe(&mut self, server: IpAddr, record_type: RecordType, latency: Duration) {
+        let mut label_set = Some((server, record_type));
+        if !self.label_set_to_histogram.contains_key(&label_set) && self.label_set_to_histogram.len() >= self.max_label_sets {
+            label_set = None;
+        }
+        let upper_bounds = &self.upper_bounds;
+        self.label_set_to_histogram
+            .entry(label_set)
+            .or_insert_with(|| DurationHistogram::new(upper_bounds.clone()))
+            .observe(latency);
+    }
+}
+
+
+#[derive(Clone, Copy, Debug, Eq, Hash, Ord, PartialEq, PartialOrd)]
+pub struct HeavyHitterCount {
+    pub count: u64,
+    pub max_overestimate: u64,
+}
+
+
+/// Finds the most frequent items using a fixed number of counters.
+///
+/// Implements the Space-Saving algorithm from Metwally et al., "Efficient Computation of Frequent
+/// and Top-k Elements in Data Streams" (2005): when all counters are taken, the item with the
+/// lowest count is replaced by the new item, which inherits the count. Any item that occurs more
+/// often than (total count / capacity) is guaranteed to be kept.
+#[derive(Clone, Eq, PartialEq)]
+pub struct SpaceSaving<K: Eq + Hash + Ord> {
+    capacity: usize,
+    key_to_count: HashMap<K, HeavyHitterCount>,
+    count_and_key: BTreeSet<(u64, K)>,
+}
+impl<K: Clone + Eq + Hash + Ord> SpaceSaving<K> {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            capacity,
+            key_to_count: HashMap::new(),
+            count_and_key: BTreeSet::new(),
+        }
+    }
+
+    pub fn add(&mut self, key: &K) {
+        if let Some(hh_count) = self.key_to_count.get_mut(key) {
+            self.count_and_key.remove(&(hh_count.count, key.clone()));
+            hh_count.count += 1;
+            self.count_and_key.insert((hh_count.count, key.clone()));
+            return;
+        }
+
+        if self.capacity == 0 {
+            return;
+        }
+        let mut hh_count = HeavyHitterCount {
+            count: 1,
+            max_overestimate: 0,
+        };
+        if self.key_to_count.len() >= self.capacity {
+            let (min_count, min_key) = self.count_and_key.pop_first().unwrap();
+            self.key_to_count.remove(&min_key);
+            hh_count.count = min_count + 1;
+            hh_count.max_overestimate = min_count;
+        }
+        self.key_to_count.insert(key.clone(), hh_count);
+        self.count_and_key.insert((hh_count.count, key.clone()));
+    }
+
+    /// Returns the items with their estimated counts, most frequent first.
+    pub fn top(&self) -> Vec<(K, HeavyHitterCount)> {
+        self.count_and_key.iter()
+            .rev()
+            .map(|(_count, key)| (key.clone(), self.key_to_count[key]))
+            .collect()
+    }
+}
+impl<K: Clone + Eq + Hash + Ord> Default for SpaceSaving<K> {
+    fn default() -> Self { Self::new(HEAVY_HITTER_CAPACITY) }
+}
+impl<K: Clone + Eq + fmt::Debug + Hash + Ord> fmt::Debug for SpaceSaving<K> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_map()
+            .entries(self.top())
+            .finish()
+    }
+}
+
+
+#[derive(Clone, Copy, Debug, Eq, Hash, Ord, PartialEq, PartialOrd)]
+pub enum ResponderRole {
+    Authoritative,
+    Recursive,
+    Unknown,
+}
+impl ResponderRole {
+    pub fn from_flags(authoritative: bool, recursion_available: bool) -> Self {
+        if authoritative {
+            Self::Authoritative
+        } else if recursion_available {
+            Self::Recursive
+        } else {
+            Self::Unknown
+        }
+    }
+}
+
+
+/// The upper bounds of the buckets into which the hop limits of responses are sorted; the common
+/// initial values (64, 128, 255) minus a few hops end up in the bucket above.
+pub const HOP_LIMIT_BOUNDS: [u8; 8] = [1, 2, 8, 32, 64, 128, 192, 255];
+
+
 #[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct PerServerStats {
+    pub response_count: u64,
+    pub role_to_count: HashMap<ResponderRole, u64>,
+    pub instance_to_response_count: BTreeMap<String, u64>, // by NSID
+    pub hop_limit_bucket_counts: [u64; HOP_LIMIT_BOUNDS.len()],
+    pub hop_limit_sum: u64,
+}
+impl PerServerStats {
+    pub fn new() -> Self {
+        Self {
+            response_count: 0,
+            role_to_count: HashMap::new(),
+            instance_to_response_count: BTreeMap::new(),
+            hop_limit_bucket_counts: [0; HOP_LIMIT_BOUNDS.len()],
+            hop_limit_sum: 0,
+        }
+    }
+}
+
+
+/// How the IPv4 and IPv6 addresses of a single name are asked for and answered.
+#[derive(Clone, Copy, Debug, Default, Eq, Hash, PartialEq)]
+pub struct AddressFamilyUsage {
+    pub a_query_count: u64,
+    pub aaaa_query_count: u64,
+    pub aaaa_response_count: u64,
+    pub aaaa_answered_count: u64, // responses containing at least one AAAA record
+}
+impl AddressFamilyUsage {
+    /// Whether the name is looked up over IPv4 but has not (yet) been seen with an IPv6 address.
+    pub fn lacks_aaaa(&self) -> bool {
+        self.a_query_count > 0 && self.aaaa_answered_count == 0
+    }
+}
+
+
+/// The responses for names within a watched zone, by whether they were negative.
+#[derive(Clone, Copy, Debug, Default, Eq, Hash, PartialEq)]
+pub struct ZoneResponseCounts {
+    pub response_count: u64,
+    pub nodata_count: u64, // no error, but no answers either
+    pub nxdomain_count: u64,
+}
+
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct BlocklistHit {
+    pub timestamp: DateTime<Utc>,
+    pub source: IpAddr,
+    pub record_type: RecordType,
+    pub name: String,
+    pub entry: String,
+}
+
+
+/// The zone transfers between a client and a server. Responses over TCP are counted once the
+/// transfer has ended, which requires following the TCP stream (the `tcp-tracking` feature).
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct ZoneTransferStats {
+    pub request_count: u64,
+    pub response_count: u64,
+    pub complete_response_count: u64, // ending with the closing SOA record
+    pub response_message_count: u64,
+    pub response_byte_count: u64,
+}
+
+
+/// How much of the traffic on an interface made it into the statistics.
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct CaptureLossStats {
+    pub packets_captured: u64,
+    pub packets_dropped: u64, // by the capture buffer
+    pub packets_dropped_by_interface: u64,
+    pub packets_undissectable: u64, // including those with incorrect checksums
+    pub interface_restarts: u64, // captures reopened after failing
+    pub member_to_packet_count: BTreeMap<String, u64>, // captured on the members of bonds and bridges
+    pub failure_to_count: BTreeMap<(&'static str, &'static str), u64>, // (layer, reason)
+}
+impl CaptureLossStats {
+    pub fn add_undissectable(&mut self, layer: &'static str, reason: &'static str) {
+        self.packets_undissectable += 1;
+        let count = self.failure_to_count.entry((layer, reason)).or_insert(0);
+        *count += 1;
+    }
+
+    /// Returns the share of the packets arriving at the capture that could be processed, between 0
+    /// and 1. Counts derived from a window with a low completeness should not be trusted.
+    pub fn completeness(&self) -> f64 {
+        let arrived = self.packets_captured + self.packets_dropped + self.packets_dropped_by_interface;
+        if arrived == 0 {
+            return 1.0;
+        }
+        let processed = self.packets_captured.saturating_sub(self.packets_undissectable);
+        processed as f64 / arrived as f64
+    }
+}
+
+
+/// The state of the async runtime and of the queue between the capture threads and the packet
+/// processing at the end of a sample.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct RuntimeStats {
+    pub worker_count: usize,
+    pub alive_task_count: usize,
+    pub global_queue_depth: usize,
+    pub blocking_thread_count: Option<usize>, // the blocking pool metrics require tokio_unstable
+    pub idle_blocking_thread_count: Option<usize>,
+    pub blocking_queue_depth: Option<usize>,
+    pub packet_queue_capacity: usize,
+    pub packet_queue_max_depth: usize, // over the whole sample
+    pub detail_level: DetailLevel,
+}
+impl RuntimeStats {
+    /// Takes the metrics of the current runtime. Must be called from within the Tokio runtime.
+    pub fn current(packet_queue_capacity: usize, packet_queue_max_depth: usize, detail_level: DetailLevel) -> Self {
+        let metrics = tokio::runtime::Handle::current().metrics();
+        #[cfg(tokio_unstable)]
+        let (blocking_thread_count, idle_blocking_thread_count, blocking_queue_depth) = (
+            Some(metrics.num_blocking_threads()),
+            Some(metrics.num_idle_blocking_threads()),
+            Some(metrics.blocking_queue_depth()),
+        );
+        #[cfg(not(tokio_unstable))]
+        let (blocking_thread_count, idle_blocking_thread_count, blocking_queue_depth) = (None, None, None);
+        Self {
+            worker_count: metrics.num_workers(),
+            alive_task_count: metrics.num_alive_tasks(),
+            global_queue_depth: metrics.global_queue_depth(),
+            blocking_thread_count,
+            idle_blocking_thread_count,
+            blocking_queue_depth,
+            packet_queue_capacity,
+            packet_queue_max_depth,
+            detail_level,
+        }
+    }
+}
+
+
+/// A DNS UPDATE or NOTIFY message.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct ZoneOperation {
+    pub timestamp: DateTime<Utc>,
+    pub opcode: Opcode,
+    pub source: IpAddr,
+    pub destination: IpAddr,
+    pub zone: String,
+}
+
+
+#[derive(Clone, Debug, Default, PartialEq)]
 pub struct DnsStats {
+    pub interface: Option<String>, // None if the traffic of all interfaces has been merged
+    pub tenant: Option<String>,
+    pub profile: Option<String>,
+    pub global_labels: BTreeMap<String, String>,
+    pub configured_sample_duration: Duration,
+    pub actual_sample_duration: Duration,
+    pub capture_loss: CaptureLossStats,
+    pub runtime: Option<RuntimeStats>, // only for live captures, and only in the untenanted statistics
+    pub reduced_detail_packet_count: u64,
+    pub stage_to_processing_time: BTreeMap<PipelineStage, DurationHistogram>,
+    pub ipv4_option_to_count: BTreeMap<&'static str, u64>,
+    pub destination_cast_to_count: BTreeMap<(&'static str, CastKind), u64>, // (layer, kind)
     pub total_count: u64,
     pub source_to_stats: HashMap<IpAddr, PerSourceStats>,
-    pub top_level_domains: Vec<(DateTime<Utc>, IpAddr, RecordType, String)>,
+    pub query_kind_to_count: HashMap<(Opcode, DNSClass, RecordType), u64>,
+    pub top_level_domain_to_count: DecayingCounter<String>,
+    pub query_names: NameTree,
+    pub distinct_query_names: HyperLogLog,
+    pub distinct_clients: HyperLogLog,
+    pub top_query_names: SpaceSaving<String>,
+    pub top_clients: SpaceSaving<IpAddr>,
+    pub top_zones: Vec<(String, u64)>,
+    pub suspected_scanners: BTreeMap<IpAddr, Vec<ScannerKind>>, // calculated at the end of the sample
+    pub watched_name_to_unexpected_count: BTreeMap<String, u64>,
+    pub app_category_to_query_count: BTreeMap<String, u64>,
+    pub script_label_to_count: BTreeMap<(String, String), u64>, // (label, value)
+    pub script_dropped_count: u64,
+    pub watched_name_to_address_count: BTreeMap<String, usize>, // since startup; set at the end of the sample
+    pub watched_zone_to_query_count: BTreeMap<String, u64>,
+    pub watched_zone_to_response_counts: BTreeMap<String, ZoneResponseCounts>,
+    pub name_to_address_family_usage: HashMap<String, AddressFamilyUsage>,
+    pub response_count: u64,
+    pub server_to_stats: HashMap<IpAddr, PerServerStats>,
+    pub server_message_to_count: HashMap<(IpAddr, bool), u64>, // (server, is_response)
+    pub low_hop_limit_message_to_count: BTreeMap<bool, u64>, // by is_response
+    pub fragment_kind_to_count: BTreeMap<&'static str, u64>, // query, response or continuation
+    pub dont_fragment_message_to_count: BTreeMap<(bool, bool), u64>, // IPv4 only; (is_response, DF bit)
+    pub large_response_transport_to_count: BTreeMap<bool, u64>, // by over_tcp
+    pub dscp_message_to_count: BTreeMap<(bool, u8), u64>, // (is_response, DSCP)
+    pub ecn_message_to_count: BTreeMap<(bool, u8), u64>, // (is_response, ECN)
+    pub server_flag_to_count: HashMap<(IpAddr, bool, &'static str), u64>, // (server, is_response, flag)
+    pub query_cookie_to_count: BTreeMap<CookieUse, u64>,
+    pub response_cookie_to_count: BTreeMap<CookieUse, u64>,
+    pub extended_error_to_count: BTreeMap<u16, u64>,
+    pub role_to_response_count: HashMap<ResponderRole, u64>,
+    pub response_code_to_count: HashMap<ResponseCode, u64>,
+    pub zone_response_code_to_count: HashMap<(String, ResponseCode), u64>, // by registered domain
+    pub untracked_zone_response_count: u64, // responses past MAX_RESPONSE_CODE_ZONES
+    pub servfail_server_to_count: HashMap<IpAddr, u64>,
+    pub server_name_to_software_report: BTreeMap<(IpAddr, String), String>, // e.g. (server, "version.bind")
+    pub bypass_source_to_count: HashMap<IpAddr, u64>,
+    pub blocklist_hit_count: u64,
+    pub blocklist_entry_to_hit_count: HashMap<String, u64>,
+    pub recent_blocklist_hits: VecDeque<BlocklistHit>,
+    pub newly_observed_domain_count: u64,
+    pub answer_network_to_count: HashMap<(IpAddr, u8), u64>,
+    pub cname_chain_length_to_count: BTreeMap<usize, u64>,
+    pub top_cname_targets: SpaceSaving<String>,
+    pub matched_response_count: u64,
+    pub latency: DurationHistogram,
+    pub latency_quantiles: Option<QuantileSummary>, // in seconds
+    pub server_type_latency: Option<ServerTypeLatency>,
+    pub aaaa_first_pair_gap: DurationHistogram,
+    pub a_first_pair_gap: DurationHistogram,
+    pub tcp_connection_setup: DurationHistogram,
+    pub tcp_first_response: DurationHistogram,
+    pub response_size_quantiles: Option<QuantileSummary>, // in bytes
+    pub unsolicited_server_to_count: HashMap<IpAddr, u64>,
+    pub duplicate_response_count: u64,
+    pub differing_duplicate_server_to_count: HashMap<IpAddr, u64>,
+    pub out_of_bailiwick_server_to_count: HashMap<IpAddr, u64>,
+    pub icmp_failure_reason_to_count: HashMap<IcmpFailureReason, u64>,
+    pub icmp_failure_correlated_count: u64,
+    pub interface_comparison: Option<InterfaceComparisonStats>,
+    pub answer_record_type_to_count: HashMap<RecordType, u64>,
+    pub deprecated_type_to_query_count: BTreeMap<u16, u64>, // only the watched types
+    pub update_count: u64,
+    pub update_client_to_count: HashMap<IpAddr, u64>,
+    pub update_zone_to_count: HashMap<String, u64>,
+    pub notify_count: u64,
+    pub notify_pair_to_count: HashMap<(IpAddr, IpAddr), u64>, // (primary, secondary)
+    pub recent_zone_operations: VecDeque<ZoneOperation>,
+    pub zone_transfer_pair_to_stats: HashMap<(IpAddr, IpAddr), ZoneTransferStats>, // (client, server)
+    pub anomaly_active: BTreeMap<AnomalyMetric, bool>,
 }
 impl DnsStats {
     pub fn new() -> Self {
         Self {
+            interface: None,
+            tenant: None,
+            profile: None,
+            global_labels: BTreeMap::new(),
+            configured_sample_duration: Duration::ZERO,
+            actual_sample_duration: Duration::ZERO,
+            capture_loss: CaptureLossStats::default(),
+            runtime: None,
+            reduced_detail_packet_count: 0,
+            stage_to_processing_time: BTreeMap::new(),
+            ipv4_option_to_count: BTreeMap::new(),
+            destination_cast_to_count: BTreeMap::new(),
             total_count: 0,
             source_to_stats: HashMap::new(),
-            top_level_domains: Vec::new(),
+            query_kind_to_count: HashMap::new(),
+            top_level_domain_to_count: DecayingCounter::default(),
+            query_names: NameTree::with_max_nodes(MAX_QUERY_NAME_NODES),
+            distinct_query_names: HyperLogLog::new(),
+            distinct_clients: HyperLogLog::new(),
+            top_query_names: SpaceSaving::default(),
+            top_clients: SpaceSaving::default(),
+            top_zones: Vec::new(),
+            suspected_scanners: BTreeMap::new(),
+            watched_name_to_unexpected_count: BTreeMap::new(),
+            app_category_to_query_count: BTreeMap::new(),
+            script_label_to_count: BTreeMap::new(),
+            script_dropped_count: 0,
+            watched_name_to_address_count: BTreeMap::new(),
+            watched_zone_to_query_count: BTreeMap::new(),
+            watched_zone_to_response_counts: BTreeMap::new(),
+            name_to_address_family_usage: HashMap::new(),
+            response_count: 0,
+            server_to_stats: HashMap::new(),
+            server_message_to_count: HashMap::new(),
+            low_hop_limit_message_to_count: BTreeMap::new(),
+            fragment_kind_to_count: BTreeMap::new(),
+            dont_fragment_message_to_count: BTreeMap::new(),
+            large_response_transport_to_count: BTreeMap::new(),
+            dscp_message_to_count: BTreeMap::new(),
+            ecn_message_to_count: BTreeMap::new(),
+            server_flag_to_count: HashMap::new(),
+            query_cookie_to_count: BTreeMap::new(),
+            response_cookie_to_count: BTreeMap::new(),
+            extended_error_to_count: BTreeMap::new(),
+            role_to_response_count: HashMap::new(),
+            response_code_to_count: HashMap::new(),
+            zone_response_code_to_count: HashMap::new(),
+            untracked_zone_response_count: 0,
+            servfail_server_to_count: HashMap::new(),
+            server_name_to_software_report: BTreeMap::new(),
+            bypass_source_to_count: HashMap::new(),
+            blocklist_hit_count: 0,
+            blocklist_entry_to_hit_count: HashMap::new(),
+            recent_blocklist_hits: VecDeque::new(),
+            newly_observed_domain_count: 0,
+            answer_network_to_count: HashMap::new(),
+            cname_chain_length_to_count: BTreeMap::new(),
+            top_cname_targets: SpaceSaving::default(),
+            matched_response_count: 0,
+            latency: DurationHistogram::new_latency(),
+            latency_quantiles: None,
+            server_type_latency: None,
+            aaaa_first_pair_gap: DurationHistogram::new_latency(),
+            a_first_pair_gap: DurationHistogram::new_latency(),
+            tcp_connection_setup: DurationHistogram::new_latency(),
+            tcp_first_response: DurationHistogram::new_latency(),
+            response_size_quantiles: None,
+            unsolicited_server_to_count: HashMap::new(),
+            duplicate_response_count: 0,
+            differing_duplicate_server_to_count: HashMap::new(),
+            out_of_bailiwick_server_to_count: HashMap::new(),
+            icmp_failure_reason_to_count: HashMap::new(),
+            icmp_failure_correlated_count: 0,
+            interface_comparison: None,
+            answer_record_type_to_count: HashMap::new(),
+            deprecated_type_to_query_count: BTreeMap::new(),
+            update_count: 0,
+            update_client_to_count: HashMap::new(),
+            update_zone_to_count: HashMap::new(),
+            notify_count: 0,
+            notify_pair_to_count: HashMap::new(),
+            recent_zone_operations: VecDeque::new(),
+            zone_transfer_pair_to_stats: HashMap::new(),
+            anomaly_active: BTreeMap::new(),
         }
     }
 
-    pub fn add_query(&mut self, timestamp: DateTime<Utc>, source: IpAddr, record_type: RecordType, name: Name) {
+    /// Counts a query without recording its name.
+    pub fn add_query_counts(&mut self, timestamp: DateTime<Utc>, source: IpAddr, opcode: Opcode, record_class: DNSClass, record_type: RecordType) {
         self.total_count += 1;
 
+        let kind_count = self.query_kind_to_count
+            .entry((opcode, record_class, record_type))
+            .or_insert(0);
+        *kind_count += 1;
+
+        if let Some(deprecated_count) = self.deprecated_type_to_query_count.get_mut(&u16::from(record_type)) {
+            *deprecated_count += 1;
+        }
+
         let per_source_stats = self.source_to_stats
             .entry(source)
             .or_insert_with(|| PerSourceStats::new());
         per_source_stats.count += 1;
+        if per_source_stats.last_query_time.map(|t| t < timestamp).unwrap_or(true) {
+            per_source_stats.last_query_time = Some(timestamp);
+        }
         let per_type_count = per_source_stats.type_to_count
             .entry(record_type)
             .or_insert(0);
         *per_type_count += 1;
 
-        let name_parts: Vec<&[u8]> = name.iter().collect();
-        if name_parts.len() == 1 {
+        self.distinct_clients.add(&source);
+        self.top_clients.add(&source);
+    }
+
+    pub fn add_query(&mut self, timestamp: DateTime<Utc>, source: IpAddr, opcode: Opcode, record_class: DNSClass, record_type: RecordType, normalized_name: &str) {
+        self.add_query_counts(timestamp, source, opcode, record_class, record_type);
+
+        let per_source_stats = self.source_to_stats.get_mut(&source).unwrap();
+        per_source_stats.names.add(normalized_name);
+
+        self.query_names.add(normalized_name, 1);
+        self.distinct_query_names.add(normalized_name);
+        self.top_query_names.add(&normalized_name.to_owned());
+
+        if normalized_name.len() > 0 && !normalized_name.contains('.') {
             // it's a top-level domain
-            if let Ok(tld) = String::from_utf8(Vec::from(name_parts[0])) {
-                self.top_level_domains.push((timestamp, source, record_type, tld));
+            self.top_level_domain_to_count.observe(&normalized_name.to_owned(), timestamp);
+        }
+    }
+
+    pub fn add_response(&mut self, server: IpAddr, authoritative: bool, recursion_available: bool, response_code: ResponseCode) {
+        self.response_count += 1;
+
+        let code_count = self.response_code_to_count
+            .entry(response_code)
+            .or_insert(0);
+        *code_count += 1;
+
+        // AA trumps RA: a recursive resolver answering from its own zones is authoritative for them
+        let role = ResponderRole::from_flags(authoritative, recursion_available);
+
+        let per_server_stats = self.server_to_stats
+            .entry(server)
+            .or_insert_with(|| PerServerStats::new());
+        per_server_stats.response_count += 1;
+        *per_server_stats.role_to_count.entry(role).or_insert(0) += 1;
+
+        *self.role_to_response_count.entry(role).or_insert(0) += 1;
+    }
+
+    /// Counts an address query (A or AAAA) by its name. Once enough names are known, new ones are
+    /// no longer tracked.
+    pub fn add_address_family_query(&mut self, normalized_name: &str, record_type: RecordType) {
+        if record_type != RecordType::A && record_type != RecordType::AAAA {
+            return;
+        }
+        let usage = match self.address_family_usage_mut(normalized_name) {
+            Some(u) => u,
+            None => return,
+        };
+        if record_type == RecordType::A {
+            usage.a_query_count += 1;
+        } else {
+            usage.aaaa_query_count += 1;
+        }
+    }
+
+    /// Counts a response to an AAAA query by its name and whether it contained any IPv6 addresses.
+    pub fn add_aaaa_response(&mut self, normalized_name: &str, answered: bool) {
+        let usage = match self.address_family_usage_mut(normalized_name) {
+            Some(u) => u,
+            None => return,
+        };
+        usage.aaaa_response_count += 1;
+        if answered {
+            usage.aaaa_answered_count += 1;
+        }
+    }
+
+    fn address_family_usage_mut(&mut self, normalized_name: &str) -> Option<&mut AddressFamilyUsage> {
+        if self.name_to_address_family_usage.len() >= MAX_ADDRESS_FAMILY_NAMES && !self.name_to_address_family_usage.contains_key(normalized_name) {
+            return None;
+        }
+        Some(
+            self.name_to_address_family_usage
+                .entry(normalized_name.to_owned())
+                .or_default()
+        )
+    }
+
+    /// Counts a response by the identity of the server instance that sent it. Behind an anycast
+    /// address, only so many instances are told apart; the rest are counted as "other".
+    pub fn add_instance_response(&mut self, server: IpAddr, name_server_identifier: String) {
+        let per_server_stats = self.server_to_stats
+            .entry(server)
+            .or_insert_with(|| PerServerStats::new());
+        let instances = &mut per_server_stats.instance_to_response_count;
+        let instance = if instances.len() < MAX_INSTANCES_PER_SERVER || instances.contains_key(&name_server_identifier) {
+            name_server_identifier
+        } else {
+            "other".to_owned()
+        };
+        let instance_count = instances.entry(instance).or_insert(0);
+        *instance_count += 1;
+    }
+
+    /// Counts a response code by the zone of the question and, for server failures, by the server,
+    /// so that bursts of failures can be traced back to their origin. Only so many zones are told
+    /// apart; responses concerning further zones are merely counted.
+    pub fn add_zone_response_code(&mut self, server: IpAddr, zone: &str, response_code: ResponseCode) {
+        let key = (zone.to_owned(), response_code);
+        if self.zone_response_code_to_count.len() < MAX_RESPONSE_CODE_ZONES || self.zone_response_code_to_count.contains_key(&key) {
+            let zone_code_count = self.zone_response_code_to_count
+                .entry(key)
+                .or_insert(0);
+            *zone_code_count += 1;
+        } else {
+            self.untracked_zone_response_count += 1;
+        }
+
+        if response_code == ResponseCode::ServFail {
+            let server_count = self.servfail_server_to_count
+                .entry(server)
+                .or_insert(0);
+            *server_count += 1;
+        }
+    }
+
+    /// Remembers what a server reported about itself when asked for e.g. `version.bind`. Only so
+    /// many reports are kept.
+    pub fn add_software_report(&mut self, server: IpAddr, normalized_name: &str, text: String) {
+        let key = (server, normalized_name.to_owned());
+        if self.server_name_to_software_report.len() >= MAX_SOFTWARE_REPORTS && !self.server_name_to_software_report.contains_key(&key) {
+            return;
+        }
+        self.server_name_to_software_report.insert(key, text);
+    }
+
+    /// Counts a response for a name within a watched zone. Many negative responses point to stale
+    /// delegations or to clients appending the zone as a search domain.
+    pub fn add_watched_zone_response(&mut self, zone: &str, response_code: ResponseCode, answer_count: u16) {
+        let counts = self.watched_zone_to_response_counts
+            .entry(zone.to_owned())
+            .or_default();
+        counts.response_count += 1;
+        if response_code == ResponseCode::NXDomain {
+            counts.nxdomain_count += 1;
+        } else if response_code == ResponseCode::NoError && answer_count == 0 {
+            counts.nodata_count += 1;
+        }
+    }
+
+    pub fn add_stage_timings(&mut self, timings: &[(PipelineStage, Duration)]) {
+        for (stage, duration) in timings {
+            self.stage_to_processing_time
+                .entry(*stage)
+                .or_insert_with(|| DurationHistogram::new_processing_time())
+                .observe(*duration);
+        }
+    }
+
+    pub fn add_header_flags(&mut self, server: IpAddr, header: &DnsHeader) {
+        let message_count = self.server_message_to_count
+            .entry((server, header.is_response()))
+            .or_insert(0);
+        *message_count += 1;
+
+        for flag in header.flag_names() {
+            let flag_count = self.server_flag_to_count
+                .entry((server, header.is_response(), flag))
+                .or_insert(0);
+            *flag_count += 1;
+        }
+    }
+
+    pub fn add_query_cookie(&mut self, source: IpAddr, cookie_use: CookieUse) {
+        let cookie_count = self.query_cookie_to_count.entry(cookie_use).or_insert(0);
+        *cookie_count += 1;
+
+        let per_source_stats = self.source_to_stats
+            .entry(source)
+            .or_insert_with(|| PerSourceStats::new());
+        per_source_stats.query_message_count += 1;
+        if cookie_use != CookieUse::None {
+            per_source_stats.cookie_query_count += 1;
+        }
+    }
+
+    pub fn add_qname_minimization(&mut self, source: IpAddr, minimized: bool) {
+        let per_source_stats = self.source_to_stats
+            .entry(source)
+            .or_insert_with(|| PerSourceStats::new());
+        per_source_stats.minimizable_query_count += 1;
+        if minimized {
+            per_source_stats.minimized_query_count += 1;
+        }
+    }
+
+    pub fn add_scan_signals(&mut self, source: IpAddr, question: &DnsQuestion) {
+        let per_source_stats = self.source_to_stats
+            .entry(source)
+            .or_insert_with(|| PerSourceStats::new());
+        per_source_stats.scan_signals.observe(question);
+    }
+
+    pub fn add_client_traits(&mut self, source: IpAddr, timestamp: DateTime<Utc>, question: &DnsQuestion, edns: Option<&Edns>) {
+        let per_source_stats = self.source_to_stats
+            .entry(source)
+            .or_insert_with(|| PerSourceStats::new());
+        let address_pair = per_source_stats.client_traits.observe(timestamp, question, edns);
+
+        // how long do clients wait between asking for the IPv6 and the IPv4 addresses?
+        if let Some(pair) = address_pair {
+            let gap = match pair.gap.to_std() {
+                Ok(g) => g,
+                Err(_) => return, // captured out of order
+            };
+            if pair.aaaa_first {
+                self.aaaa_first_pair_gap.observe(gap);
+            } else {
+                self.a_first_pair_gap.observe(gap);
+            }
+        }
+    }
+
+    pub fn add_app_category(&mut self, category: &str) {
+        let category_count = self.app_category_to_query_count
+            .entry(category.to_owned())
+            .or_insert(0);
+        *category_count += 1;
+    }
+
+    /// Counts the labels a classification script has attached to a DNS message. New labels are
+    /// ignored once there are too many.
+    #[cfg(feature = "scripting")]
+    pub fn add_script_labels(&mut self, labels: Vec<(String, String)>) {
+        for label in labels {
+            if self.script_label_to_count.len() >= MAX_SCRIPT_LABELS && !self.script_label_to_count.contains_key(&label) {
+                continue;
+            }
+            let label_count = self.script_label_to_count
+                .entry(label)
+                .or_insert(0);
+            *label_count += 1;
+        }
+    }
+
+    pub fn add_unexpected_answer(&mut self, normalized_name: &str) {
+        let unexpected_count = self.watched_name_to_unexpected_count
+            .entry(normalized_name.to_owned())
+            .or_insert(0);
+        *unexpected_count += 1;
+    }
+
+    pub fn add_response_cookie(&mut self, cookie_use: CookieUse) {
+        let cookie_count = self.response_cookie_to_count.entry(cookie_use).or_insert(0);
+        *cookie_count += 1;
+    }
+
+    pub fn add_extended_error(&mut self, info_code: u16) {
+        let error_count = self.extended_error_to_count.entry(info_code).or_insert(0);
+        *error_count += 1;
+    }
+
+    /// Counts the hop limit (TTL) with which a message has arrived; for responses, its distribution
+    /// is kept for each server.
+    pub fn add_hop_limit(&mut self, server: IpAddr, is_response: bool, hop_limit: u8) {
+        if hop_limit <= LOW_HOP_LIMIT {
+            let low_count = self.low_hop_limit_message_to_count.entry(is_response).or_insert(0);
+            *low_count += 1;
+        }
+        if is_response {
+            let per_server_stats = self.server_to_stats
+                .entry(server)
+                .or_insert_with(|| PerServerStats::new());
+            let bucket_index = HOP_LIMIT_BOUNDS.iter()
+                .position(|bound| hop_limit <= *bound)
+                .unwrap(); // the last bound is the maximum
+            per_server_stats.hop_limit_bucket_counts[bucket_index] += 1;
+            per_server_stats.hop_limit_sum += u64::from(hop_limit);
+        }
+    }
+
+    pub fn add_fragment(&mut self, kind: &'static str) {
+        let fragment_count = self.fragment_kind_to_count.entry(kind).or_insert(0);
+        *fragment_count += 1;
+    }
+
+    pub fn add_dont_fragment(&mut self, is_response: bool, dont_fragment: bool) {
+        let df_count = self.dont_fragment_message_to_count.entry((is_response, dont_fragment)).or_insert(0);
+        *df_count += 1;
+    }
+
+    pub fn add_large_response(&mut self, over_tcp: bool) {
+        let large_count = self.large_response_transport_to_count.entry(over_tcp).or_insert(0);
+        *large_count += 1;
+    }
+
+    pub fn add_traffic_class(&mut self, is_response: bool, dscp: u8, ecn: u8) {
+        let dscp_count = self.dscp_message_to_count.entry((is_response, dscp)).or_insert(0);
+        *dscp_count += 1;
+        let ecn_count = self.ecn_message_to_count.entry((is_response, ecn)).or_insert(0);
+        *ecn_count += 1;
+    }
+
+    pub fn add_destination_cast(&mut self, link_cast: Option<CastKind>, network_cast: CastKind) {
+        if let Some(lc) = link_cast {
+            let link_count = self.destination_cast_to_count.entry(("link", lc)).or_insert(0);
+            *link_count += 1;
+        }
+        let network_count = self.destination_cast_to_count.entry(("network", network_cast)).or_insert(0);
+        *network_count += 1;
+    }
+
+    pub fn add_ipv4_option(&mut self, kind_name: &'static str) {
+        let count = self.ipv4_option_to_count.entry(kind_name).or_insert(0);
+        *count += 1;
+    }
+
+    pub fn add_resolver_bypass(&mut self, source: IpAddr) {
+        let bypass_count = self.bypass_source_to_count
+            .entry(source)
+            .or_insert(0);
+        *bypass_count += 1;
+    }
+
+    pub fn add_blocklist_hit(&mut self, hit: BlocklistHit) {
+        self.blocklist_hit_count += 1;
+
+        let entry_hit_count = self.blocklist_entry_to_hit_count
+            .entry(hit.entry.clone())
+            .or_insert(0);
+        *entry_hit_count += 1;
+
+        // only keep the most recent hits
+        while self.recent_blocklist_hits.len() >= MAX_RECENT_BLOCKLIST_HITS {
+            self.recent_blocklist_hits.pop_front();
+        }
+        self.recent_blocklist_hits.push_back(hit);
+    }
+
+    pub fn add_newly_observed_domain(&mut self) {
+        self.newly_observed_domain_count += 1;
+    }
+
+    pub fn add_answer_network(&mut self, network: IpAddr, prefix_length: u8) {
+        let answer_count = self.answer_network_to_count
+            .entry((network, prefix_length))
+            .or_insert(0);
+        *answer_count += 1;
+    }
+
+    pub fn add_cname_chain(&mut self, targets: Vec<String>) {
+        let length_count = self.cname_chain_length_to_count
+            .entry(targets.len())
+            .or_insert(0);
+        *length_count += 1;
+
+        for target in targets {
+            self.top_cname_targets.add(&target);
+        }
+    }
+
+    pub fn add_matched_response(&mut self, latency: Option<Duration>) {
+        self.matched_response_count += 1;
+        if let Some(l) = latency {
+            self.latency.observe(l);
+            if let Some(lq) = self.latency_quantiles.as_mut() {
+                lq.observe(l.as_secs_f64());
+            }
+        }
+    }
+
+    pub fn add_server_type_latency(&mut self, server: IpAddr, record_type: RecordType, latency: Duration) {
+        if let Some(stl) = self.server_type_latency.as_mut() {
+            stl.observe(server, record_type, latency);
+        }
+    }
+
+    #[cfg(feature = "tcp-tracking")]
+    pub fn add_tcp_timing(&mut self, timing: TcpTiming) {
+        match timing {
+            TcpTiming::ConnectionSetup(d) => self.tcp_connection_setup.observe(d),
+            TcpTiming::FirstResponse(d) => self.tcp_first_response.observe(d),
+        }
+    }
+
+    pub fn add_response_size(&mut self, message_length: usize) {
+        if let Some(rsq) = self.response_size_quantiles.as_mut() {
+            rsq.observe(message_length as f64);
+        }
+    }
+
+    /// Additionally keeps latency histograms per server and query type, with the given bucket
+    /// boundaries (or the usual ones, if none are given), for up to the given number of
+    /// combinations.
+    pub fn enable_server_type_latency(&mut self, upper_bounds: &[Duration], max_label_sets: usize) {
+        let upper_bounds = if upper_bounds.len() > 0 {
+            upper_bounds.to_vec()
+        } else {
+            DurationHistogram::new_latency().upper_bounds
+        };
+        self.server_type_latency = Some(ServerTypeLatency::new(upper_bounds, max_label_sets));
+    }
+
+    /// Additionally estimates the given quantiles of the latency and the response size.
+    /// Starts counting the queries for the given obsolete or deprecated record types.
+    pub fn watch_deprecated_record_types(&mut self, record_types: &[u16]) {
+        for record_type in record_types {
+            self.deprecated_type_to_query_count.insert(*record_type, 0);
+        }
+    }
+
+    pub fn enable_quantiles(&mut self, quantiles: &[f64]) {
+        self.latency_quantiles = Some(QuantileSummary::new(quantiles));
+        self.response_size_quantiles = Some(QuantileSummary::new(quantiles));
+    }
+
+    pub fn add_unsolicited_response(&mut self, server: IpAddr) {
+        let unsolicited_count = self.unsolicited_server_to_count
+            .entry(server)
+            .or_insert(0);
+        *unsolicited_count += 1;
+    }
+
+    pub fn add_duplicate_response(&mut self, server: IpAddr, differing: bool) {
+        self.duplicate_response_count += 1;
+        if differing {
+            let differing_count = self.differing_duplicate_server_to_count
+                .entry(server)
+                .or_insert(0);
+            *differing_count += 1;
+        }
+    }
+
+    pub fn add_out_of_bailiwick_response(&mut self, server: IpAddr) {
+        let out_of_bailiwick_count = self.out_of_bailiwick_server_to_count
+            .entry(server)
+            .or_insert(0);
+        *out_of_bailiwick_count += 1;
+    }
+
+    pub fn add_icmp_failure(&mut self, reason: IcmpFailureReason, correlated: bool) {
+        let reason_count = self.icmp_failure_reason_to_count
+            .entry(reason)
+            .or_insert(0);
+        *reason_count += 1;
+
+        if correlated {
+            self.icmp_failure_correlated_count += 1;
+        }
+    }
+
+    pub fn add_answer_record_type(&mut self, record_type: RecordType) {
+        let type_count = self.answer_record_type_to_count
+            .entry(record_type)
+            .or_insert(0);
+        *type_count += 1;
+    }
+
+    pub fn add_zone_operation(&mut self, operation: ZoneOperation) {
+        match operation.opcode {
+            Opcode::Update => {
+                self.update_count += 1;
+                let client_count = self.update_client_to_count
+                    .entry(operation.source)
+                    .or_insert(0);
+                *client_count += 1;
+                let zone_count = self.update_zone_to_count
+                    .entry(operation.zone.clone())
+                    .or_insert(0);
+                *zone_count += 1;
+            },
+            Opcode::Notify => {
+                // the primary notifies the secondary
+                self.notify_count += 1;
+                let pair_count = self.notify_pair_to_count
+                    .entry((operation.source, operation.destination))
+                    .or_insert(0);
+                *pair_count += 1;
+            },
+            _ => return,
+        }
+
+        // only keep the most recent operations
+        while self.recent_zone_operations.len() >= MAX_RECENT_ZONE_OPERATIONS {
+            self.recent_zone_operations.pop_front();
+        }
+        self.recent_zone_operations.push_back(operation);
+    }
+
+    pub fn add_zone_transfer_request(&mut self, client: IpAddr, server: IpAddr) {
+        let transfer_stats = self.zone_transfer_pair_to_stats
+            .entry((client, server))
+            .or_insert_with(|| ZoneTransferStats::default());
+        transfer_stats.request_count += 1;
+    }
+
+    pub fn add_zone_transfer_response(&mut self, client: IpAddr, server: IpAddr, message_count: u64, byte_count: u64, complete: bool) {
+        let transfer_stats = self.zone_transfer_pair_to_stats
+            .entry((client, server))
+            .or_insert_with(|| ZoneTransferStats::default());
+        transfer_stats.response_count += 1;
+        if complete {
+            transfer_stats.complete_response_count += 1;
+        }
+        transfer_stats.response_message_count += message_count;
+        transfer_stats.response_byte_count += byte_count;
+    }
+
+    /// Sums up the queries for the most popular zones and for the given zones of interest.
+    pub fn summarize_query_names(&mut self, watched_zones: &[String]) {
+        if self.query_names.node_count() >= MAX_QUERY_NAME_NODES {
+            debug!("the query name tree is full; the names left out were only counted towards their zones");
+        }
+        self.top_zones = self.query_names.top_zones(TOP_ZONE_DEPTH, TOP_ZONE_COUNT);
+        for zone in watched_zones {
+            self.watched_zone_to_query_count.insert(zone.clone(), self.query_names.zone_count(zone));
+        }
+    }
+
+    /// Calculates how varied the record types queried by each source are.
+    ///
+    /// Normal clients mostly ask for addresses, while scanners and tunnels tend to use many types.
+    pub fn summarize_query_type_entropy(&mut self) {
+        for per_source_stats in self.source_to_stats.values_mut() {
+            per_source_stats.query_type_entropy = entropy(per_source_stats.type_to_count.values().map(|c| *c));
+        }
+    }
+
+    /// Picks out the sources whose queries look like scanning.
+    pub fn summarize_scanners(&mut self) {
+        self.suspected_scanners.clear();
+        for (source, per_source_stats) in &self.source_to_stats {
+            let kinds = per_source_stats.scan_signals.suspicions(per_source_stats.count, per_source_stats.names.entropy());
+            if kinds.len() > 0 {
+                self.suspected_scanners.insert(*source, kinds);
             }
         }
     }
+
+    /// Guesses the client software of each source.
+    pub fn summarize_fingerprints(&mut self) {
+        for per_source_stats in self.source_to_stats.values_mut() {
+            per_source_stats.fingerprint = per_source_stats.client_traits.classify();
+        }
+    }
+
+    pub fn set_watched_name_address_counts(&mut self, watchlist: &AnswerWatchlist) {
+        self.watched_name_to_address_count = watchlist.seen_address_counts()
+            .map(|(name, count)| (name.to_owned(), count))
+            .collect();
+    }
+
+    /// Labels each source with the MAC address it is known to be using.
+    pub fn set_source_mac_addresses(&mut self, ip_to_mac: &HashMap<IpAddr, MacAddr6>) {
+        for (source, per_source_stats) in &mut self.source_to_stats {
+            per_source_stats.mac_address = ip_to_mac.get(source).map(|m| *m);
+        }
+    }
+
+    /// Labels each source with the host name it announced via DHCP.
+    pub fn set_source_host_names(&mut self, dhcp_tracker: &DhcpTracker) {
+        for (source, per_source_stats) in &mut self.source_to_stats {
+            per_source_stats.host_name = dhcp_tracker.host_name(*source)
+                .map(|h| h.to_owned());
+        }
+    }
+
+    /// Labels each source with the name of the Docker container using its address.
+    #[cfg(feature = "docker")]
+    pub fn set_source_containers(&mut self, container_directory: &ContainerDirectory) {
+        for (source, per_source_stats) in &mut self.source_to_stats {
+            per_source_stats.container = container_directory.container(*source)
+                .map(|c| c.to_owned());
+        }
+    }
+
+    /// Labels each source with the Kubernetes pod which used its address when it last queried.
+    #[cfg(feature = "kubernetes")]
+    pub fn set_source_pods(&mut self, pod_directory: &PodDirectory) {
+        for (source, per_source_stats) in &mut self.source_to_stats {
+            per_source_stats.pod = per_source_stats.last_query_time
+                .and_then(|t| pod_directory.pod_at(*source, t))
+                .cloned();
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use std::net::IpAddr;
+    use std::time::Duration;
+
+    use chrono::{TimeZone, Utc};
+    use hickory_proto::op::ResponseCode;
+    use hickory_proto::rr::{DNSClass, RecordType};
+
+    use crate::dns::Opcode;
+    use super::{
+        CaptureLossStats, DnsStats, entropy, HeavyHitterCount, MAX_INSTANCES_PER_SERVER,
+        MAX_QUERY_NAME_NODES, MAX_RESPONSE_CODE_ZONES, NameHistogram, SpaceSaving,
+    };
+
+    #[test]
+    fn test_space_saving() {
+        let mut heavy_hitters = SpaceSaving::new(2);
+        for name in ["a", "b", "a", "c", "a", "a", "d"] {
+            heavy_hitters.add(&name);
+        }
+
+        // b was replaced by c (inheriting its count of 1), which was in turn replaced by d
+        assert_eq!(
+            heavy_hitters.top(),
+            vec![
+                ("a", HeavyHitterCount { count: 4, max_overestimate: 0 }),
+                ("d", HeavyHitterCount { count: 3, max_overestimate: 2 }),
+            ],
+        );
+    }
+
+    #[test]
+    fn test_entropy() {
+        assert_eq!(entropy([]), 0.0);
+        assert_eq!(entropy([5]), 0.0);
+        assert_eq!(entropy([3, 3]), 1.0);
+        assert_eq!(entropy([1, 1, 1, 1, 0]), 2.0);
+
+        let mut repetitive = NameHistogram::new();
+        let mut varied = NameHistogram::new();
+        for i in 0..256 {
+            repetitive.add("www.example.com");
+            varied.add(&format!("{:x}.tunnel.example", i * 7919));
+        }
+        assert_eq!(repetitive.entropy(), 0.0);
+        assert!(varied.entropy() > 5.5, "entropy {}", varied.entropy());
+    }
+
+    #[test]
+    fn test_capture_completeness() {
+        assert_eq!(CaptureLossStats::default().completeness(), 1.0);
+        let capture_loss = CaptureLossStats {
+            packets_captured: 90,
+            packets_dropped: 8,
+            packets_dropped_by_interface: 2,
+            packets_undissectable: 10,
+            ..CaptureLossStats::default()
+        };
+        assert_eq!(capture_loss.completeness(), 0.8);
+    }
+
+    #[test]
+    fn test_instance_limit() {
+        let server: IpAddr = "192.0.2.53".parse().unwrap();
+        let mut stats = DnsStats::new();
+        for i in 0..MAX_INSTANCES_PER_SERVER + 2 {
+            stats.add_instance_response(server, format!("ns{}", i));
+        }
+        stats.add_instance_response(server, "ns0".to_owned());
+
+        let instances = &stats.server_to_stats[&server].instance_to_response_count;
+        assert_eq!(instances.len(), MAX_INSTANCES_PER_SERVER + 1);
+        assert_eq!(instances["ns0"], 2);
+        assert_eq!(instances["other"], 2);
+    }
+
+    #[test]
+    fn test_query_name_limit() {
+        let client: IpAddr = "192.0.2.1".parse().unwrap();
+        let timestamp = Utc.with_ymd_and_hms(2022, 10, 1, 12, 0, 0).unwrap();
+        let mut stats = DnsStats::new();
+        for i in 0..MAX_QUERY_NAME_NODES + 100 {
+            stats.add_query(timestamp, client, Opcode::Query, DNSClass::IN, RecordType::A, &format!("host{}.example.com", i));
+        }
+
+        // the names beyond the limit still count towards their zone
+        assert_eq!(stats.query_names.node_count(), MAX_QUERY_NAME_NODES);
+        assert_eq!(stats.query_names.zone_count("example.com"), (MAX_QUERY_NAME_NODES + 100) as u64);
+        assert_eq!(stats.query_names.zone_count(&format!("host{}.example.com", MAX_QUERY_NAME_NODES + 50)), 0);
+    }
+
+    #[test]
+    fn test_zone_response_code_limit() {
+        let server: IpAddr = "192.0.2.53".parse().unwrap();
+        let mut stats = DnsStats::new();
+        for i in 0..MAX_RESPONSE_CODE_ZONES + 2 {
+            stats.add_zone_response_code(server, &format!("zone{}.example", i), ResponseCode::NoError);
+        }
+        stats.add_zone_response_code(server, "zone0.example", ResponseCode::NoError);
+        stats.add_zone_response_code(server, "zone0.example", ResponseCode::ServFail);
+
+        assert_eq!(stats.zone_response_code_to_count.len(), MAX_RESPONSE_CODE_ZONES);
+        assert_eq!(stats.zone_response_code_to_count[&("zone0.example".to_owned(), ResponseCode::NoError)], 2);
+        assert_eq!(stats.untracked_zone_response_count, 3);
+        assert_eq!(stats.servfail_server_to_count[&server], 1);
+    }
+
+    #[test]
+    fn test_server_type_latency() {
+        let mut stats = DnsStats::new();
+        stats.enable_server_type_latency(&[Duration::from_millis(10)], 2);
+        let server: IpAddr = "192.0.2.53".parse().unwrap();
+        stats.add_server_type_latency(server, RecordType::A, Duration::from_millis(5));
+        stats.add_server_type_latency(server, RecordType::AAAA, Duration::from_millis(50));
+        stats.add_server_type_latency(server, RecordType::DNSKEY, Duration::from_millis(80));
+        stats.add_server_type_latency(server, RecordType::A, Duration::from_millis(15));
+
+        let histograms = &stats.server_type_latency.as_ref().unwrap().label_set_to_histogram;
+        assert_eq!(histograms.len(), 3);
+        assert_eq!(histograms[&Some((server, RecordType::A))].bucket_counts, vec![1, 1]);
+        assert_eq!(histograms[&None].bucket_counts, vec![0, 1]);
+    }
+
+    #[test]
+    fn test_hop_limit() {
+        let server: IpAddr = "192.0.2.53".parse().unwrap();
+        let mut stats = DnsStats::new();
+        stats.add_hop_limit(server, false, 1);
+        stats.add_hop_limit(server, true, 1);
+        stats.add_hop_limit(server, true, 60);
+        stats.add_hop_limit(server, true, 255);
+
+        assert_eq!(stats.low_hop_limit_message_to_count[&false], 1);
+        assert_eq!(stats.low_hop_limit_message_to_count[&true], 1);
+        let per_server_stats = &stats.server_to_stats[&server];
+        assert_eq!(per_server_stats.hop_limit_bucket_counts, [1, 0, 0, 0, 1, 0, 0, 1]);
+        assert_eq!(per_server_stats.hop_limit_sum, 316);
+    }
 }
@@ -0,0 +1,185 @@
+use std::collections::{HashMap, VecDeque};
+use std::net::SocketAddr;
+
+use chrono::{DateTime, Duration, Utc};
+
+use crate::tcp_udp::{TcpFlags, TcpHeader};
+
+
+const DNS_PORT: u16 = 53;
+
+// connections without any traffic for this long are forgotten
+const IDLE_TIMEOUT_SECS: i64 = 120;
+
+
+#[derive(Clone, Copy, Debug, Eq, Hash, Ord, PartialEq, PartialOrd)]
+struct ConnectionKey {
+    client: SocketAddr,
+    server: SocketAddr,
+}
+
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+struct ConnectionState {
+    syn_timestamp: Option<DateTime<Utc>>,
+    established: bool,
+    first_query_timestamp: Option<DateTime<Utc>>,
+    responded: bool,
+    last_seen: DateTime<Utc>,
+}
+
+
+/// A timing derived from the segments of a DNS-over-TCP connection.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum TcpTiming {
+    /// From the client's SYN to the server's SYN-ACK.
+    ConnectionSetup(std::time::Duration),
+
+    /// From the first byte of the client's first query to the first byte of the server's first
+    /// response.
+    FirstResponse(std::time::Duration),
+}
+
+
+/// Follows DNS-over-TCP connections through their handshake and first exchange.
+#[derive(Clone, Debug)]
+pub struct TcpConnectionTracker {
+    connections: HashMap<ConnectionKey, ConnectionState>,
+    expiry_queue: VecDeque<(DateTime<Utc>, ConnectionKey)>,
+}
+impl TcpConnectionTracker {
+    pub fn new() -> Self {
+        Self {
+            connections: HashMap::new(),
+            expiry_queue: VecDeque::new(),
+        }
+    }
+
+    /// Forgets the connections that have been idle for too long.
+    fn expire(&mut self, now: DateTime<Utc>) {
+        let cutoff = now - Duration::seconds(IDLE_TIMEOUT_SECS);
+        while let Some((timestamp, key)) = self.expiry_queue.front().copied() {
+            if timestamp >= cutoff {
+                break;
+            }
+            self.expiry_queue.pop_front();
+
+            // the connection might have seen traffic since it was queued
+            let last_seen = self.connections.get(&key).map(|c| c.last_seen);
+            match last_seen {
+                Some(ls) if ls < cutoff => {
+                    self.connections.remove(&key);
+                },
+                Some(ls) => self.expiry_queue.push_back((ls, key)),
+                None => {},
+            }
+        }
+    }
+
+    /// Processes a segment and returns the timing it completes, if any.
+    pub fn observe(&mut self, timestamp: DateTime<Utc>, source: SocketAddr, destination: SocketAddr, header: &TcpHeader, carries_data: bool) -> Option<TcpTiming> {
+        self.expire(timestamp);
+
+        // the initial SYN tells us who the client is; otherwise, go by the port
+        let from_client = if header.flags.contains(TcpFlags::SYN) {
+            !header.flags.contains(TcpFlags::ACK)
+        } else {
+            destination.port() == DNS_PORT
+        };
+        let key = if from_client {
+            ConnectionKey { client: source, server: destination }
+        } else {
+            ConnectionKey { client: destination, server: source }
+        };
+
+        if header.flags.intersects(TcpFlags::FIN | TcpFlags::RST) {
+            self.connections.remove(&key);
+            return None;
+        }
+
+        if header.flags.contains(TcpFlags::SYN) && from_client {
+            // a new connection (or a retransmitted SYN, which we time from the first one)
+            let fresh_state = ConnectionState {
+                syn_timestamp: Some(timestamp),
+                established: false,
+                first_query_timestamp: None,
+                responded: false,
+                last_seen: timestamp,
+            };
+            let state = self.connections.entry(key).or_insert(fresh_state);
+            if state.established {
+                *state = fresh_state;
+            }
+            state.last_seen = timestamp;
+            self.expiry_queue.push_back((timestamp, key));
+            return None;
+        }
+
+        // only time connections whose beginning we have seen
+        let state = self.connections.get_mut(&key)?;
+        state.last_seen = timestamp;
+        if header.flags.contains(TcpFlags::SYN) {
+            if state.established {
+                return None;
+            }
+            state.established = true;
+            let setup_time = (timestamp - state.syn_timestamp?).to_std().ok()?;
+            return Some(TcpTiming::ConnectionSetup(setup_time));
+        }
+        if !carries_data {
+            return None;
+        }
+        if from_client {
+            if state.first_query_timestamp.is_none() {
+                state.first_query_timestamp = Some(timestamp);
+            }
+            return None;
+        }
+        if state.responded {
+            return None;
+        }
+        state.responded = true;
+        let response_time = (timestamp - state.first_query_timestamp?).to_std().ok()?;
+        Some(TcpTiming::FirstResponse(response_time))
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use chrono::{Duration, TimeZone, Utc};
+
+    use super::{TcpConnectionTracker, TcpTiming};
+    use crate::tcp_udp::{TcpFlags, TcpHeader};
+
+    #[test]
+    fn test_connection_timings() {
+        let start = Utc.with_ymd_and_hms(2022, 10, 1, 12, 0, 0).unwrap();
+        let client = "192.0.2.1:54325".parse().unwrap();
+        let server = "192.0.2.53:53".parse().unwrap();
+        let segment = |flags| TcpHeader { flags, ..TcpHeader::default() };
+
+        let mut tracker = TcpConnectionTracker::new();
+        let ms = |n| start + Duration::milliseconds(n);
+        assert_eq!(tracker.observe(ms(0), client, server, &segment(TcpFlags::SYN), false), None);
+        assert_eq!(
+            tracker.observe(ms(20), server, client, &segment(TcpFlags::SYN | TcpFlags::ACK), false),
+            Some(TcpTiming::ConnectionSetup(std::time::Duration::from_millis(20))),
+        );
+        assert_eq!(tracker.observe(ms(21), client, server, &segment(TcpFlags::ACK), false), None);
+        assert_eq!(tracker.observe(ms(22), client, server, &segment(TcpFlags::PSH | TcpFlags::ACK), true), None);
+        assert_eq!(tracker.observe(ms(40), server, client, &segment(TcpFlags::ACK), false), None);
+        assert_eq!(
+            tracker.observe(ms(52), server, client, &segment(TcpFlags::PSH | TcpFlags::ACK), true),
+            Some(TcpTiming::FirstResponse(std::time::Duration::from_millis(30))),
+        );
+        // only the first response is timed
+        assert_eq!(tracker.observe(ms(60), client, server, &segment(TcpFlags::PSH | TcpFlags::ACK), true), None);
+        assert_eq!(tracker.observe(ms(70), server, client, &segment(TcpFlags::PSH | TcpFlags::ACK), true), None);
+
+        // connections whose handshake we missed are not timed
+        let other_client = "192.0.2.2:40000".parse().unwrap();
+        assert_eq!(tracker.observe(ms(80), other_client, server, &segment(TcpFlags::PSH | TcpFlags::ACK), true), None);
+        assert_eq!(tracker.observe(ms(90), server, other_client, &segment(TcpFlags::PSH | TcpFlags::ACK), true), None);
+    }
+}
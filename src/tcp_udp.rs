@@ -6,6 +6,92 @@ use crate::ip::internet_checksum;
 use crate::packet::PacketDissection;
 
 
+// TCP option kinds, managed by IANA: https://www.iana.org/assignments/tcp-parameters/tcp-parameters.xhtml
+const TCP_OPTION_END_OF_LIST: u8 = 0;
+const TCP_OPTION_NO_OPERATION: u8 = 1;
+const TCP_OPTION_MAXIMUM_SEGMENT_SIZE: u8 = 2;
+const TCP_OPTION_WINDOW_SCALE: u8 = 3;
+const TCP_OPTION_SACK_PERMITTED: u8 = 4;
+const TCP_OPTION_SACK: u8 = 5;
+const TCP_OPTION_TIMESTAMPS: u8 = 8;
+
+
+/// The options of a TCP header that we understand.
+#[derive(Clone, Copy, Debug, Default, Eq, Hash, Ord, PartialEq, PartialOrd)]
+pub struct TcpOptions {
+    pub maximum_segment_size: Option<u16>,
+    pub window_scale: Option<u8>,
+    pub sack_permitted: bool,
+    pub sack_blocks: [Option<(u32, u32)>; 4], // (left edge, right edge); at most 4 fit
+    pub timestamps: Option<(u32, u32)>, // (value, echo reply)
+}
+impl TcpOptions {
+    /// Parses the options area of a TCP header.
+    ///
+    /// Options are type-length-value records of arbitrary length, except for the end-of-list and
+    /// no-operation options, which consist of their kind alone; anything after the end-of-list
+    /// option is padding. Options of unknown kind are skipped. Returns the reason if the options
+    /// are malformed.
+    pub fn parse(bytes: &[u8]) -> Result<Self, &'static str> {
+        let mut options = Self::default();
+        let mut i = 0;
+        while i < bytes.len() {
+            let kind = bytes[i];
+            if kind == TCP_OPTION_END_OF_LIST {
+                break;
+            }
+            if kind == TCP_OPTION_NO_OPERATION {
+                i += 1;
+                continue;
+            }
+
+            let length = match bytes.get(i + 1) {
+                Some(l) => usize::from(*l),
+                None => return Err("option length missing"),
+            };
+            if length < 2 {
+                return Err("option length too small");
+            }
+            if i + length > bytes.len() {
+                return Err("option extends beyond the header");
+            }
+            let data = &bytes[i+2..i+length];
+            match (kind, data.len()) {
+                (TCP_OPTION_MAXIMUM_SEGMENT_SIZE, 2) => {
+                    options.maximum_segment_size = Some(u16::from_be_bytes(data.try_into().unwrap()));
+                },
+                (TCP_OPTION_WINDOW_SCALE, 1) => {
+                    options.window_scale = Some(data[0]);
+                },
+                (TCP_OPTION_SACK_PERMITTED, 0) => {
+                    options.sack_permitted = true;
+                },
+                (TCP_OPTION_SACK, l) if l % 8 == 0 => {
+                    for (slot, block) in options.sack_blocks.iter_mut().zip(data.chunks_exact(8)) {
+                        *slot = Some((
+                            u32::from_be_bytes(block[0..4].try_into().unwrap()),
+                            u32::from_be_bytes(block[4..8].try_into().unwrap()),
+                        ));
+                    }
+                },
+                (TCP_OPTION_TIMESTAMPS, 8) => {
+                    options.timestamps = Some((
+                        u32::from_be_bytes(data[0..4].try_into().unwrap()),
+                        u32::from_be_bytes(data[4..8].try_into().unwrap()),
+                    ));
+                },
+                (TCP_OPTION_MAXIMUM_SEGMENT_SIZE|TCP_OPTION_WINDOW_SCALE|TCP_OPTION_SACK_PERMITTED|TCP_OPTION_SACK|TCP_OPTION_TIMESTAMPS, _) => {
+                    return Err("option has an invalid length");
+                },
+                _ => {},
+            }
+            i += length;
+        }
+        Ok(options)
+    }
+}
+
+
 #[derive(Clone, Copy, Debug, Default, Eq, Hash, Ord, PartialEq, PartialOrd)]
 // as defined in RFC9293 section 3.1
 pub struct TcpHeader {
@@ -13,13 +99,12 @@ pub struct TcpHeader {
     pub destination_port: u16,
     pub sequence_number: u32,
     pub acknowledgement_number: u32,
-    // data offset is implicit (how many elements of at the beginning of self.options are Some(_)?)
-    // offset is stored as the number of 32-bit words!
+    pub data_offset: u8, // as the number of 32-bit words!
     pub flags: TcpFlags,
     pub window: u16,
     pub checksum: u16,
     pub urgent_pointer: u16,
-    pub options: [Option<[u8; 4]>; 10], // up to 10 words of 32 bits each
+    pub options: TcpOptions,
 }
 impl TcpHeader {
     pub fn try_take<'b, 'h>(bytes: &'b [u8], pseudo_header: &'h [u8]) -> PacketDissection<'b, Self> {
@@ -35,7 +120,7 @@ impl TcpHeader {
         let data_offset_w32 = (bytes[12] & 0b1111_0000) >> 4;
         let data_offset_bytes = usize::from(data_offset_w32) * 4;
         if data_offset_bytes < 20 {
-            return PacketDissection::TooShort;
+            return PacketDissection::Malformed { reason: "data offset smaller than the header" };
         }
         if bytes.len() < data_offset_bytes {
             return PacketDissection::TooShort;
@@ -54,18 +139,17 @@ impl TcpHeader {
         let checksum = u16::from_be_bytes(bytes[16..18].try_into().unwrap());
         let urgent_pointer = u16::from_be_bytes(bytes[18..20].try_into().unwrap());
 
-        let mut options = [None; 10];
-        let mut i = 0;
-        while 20 + (i * 4) < data_offset_bytes {
-            options[i] = Some(bytes[20+(i*4)..20+(i*4)+4].try_into().unwrap());
-            i += 1;
-        }
+        let options = match TcpOptions::parse(&bytes[20..data_offset_bytes]) {
+            Ok(o) => o,
+            Err(reason) => return PacketDissection::Malformed { reason },
+        };
 
         let header = Self {
             source_port,
             destination_port,
             sequence_number,
             acknowledgement_number,
+            data_offset: data_offset_w32,
             flags,
             window,
             checksum,
@@ -129,3 +213,63 @@ impl UdpHeader {
         PacketDissection::Success { header, rest: &bytes[8..] }
     }
 }
+
+
+#[cfg(test)]
+mod tests {
+    use super::{TcpFlags, TcpHeader, TcpOptions};
+    use crate::packet::PacketDissection;
+
+    #[test]
+    fn test_tcp_syn_options() {
+        // SYN from 192.0.2.1:54326 to 192.0.2.53:53 with the options Linux sends
+        let segment = [
+            0xd4, 0x36, 0x00, 0x35, 0x1a, 0x2b, 0x3c, 0x4d, 0x00, 0x00, 0x00, 0x00, 0xa0, 0x02, 0xfa, 0xf0,
+            0x5b, 0xa5, 0x00, 0x00, 0x02, 0x04, 0x05, 0xb4, 0x04, 0x02, 0x08, 0x0a, 0x00, 0x0f, 0x42, 0x40,
+            0x00, 0x00, 0x00, 0x00, 0x01, 0x03, 0x03, 0x07,
+        ];
+        let pseudo_header = [192, 0, 2, 1, 192, 0, 2, 53, 0, 6, 0, 40];
+        let header = match TcpHeader::try_take(&segment, &pseudo_header) {
+            PacketDissection::Success { header, rest } => {
+                assert_eq!(rest.len(), 0);
+                header
+            },
+            other => panic!("failed to dissect SYN: {:?}", other),
+        };
+        assert_eq!(header.flags, TcpFlags::SYN);
+        assert_eq!(header.data_offset, 10);
+        assert_eq!(header.options, TcpOptions {
+            maximum_segment_size: Some(1460),
+            window_scale: Some(7),
+            sack_permitted: true,
+            sack_blocks: [None; 4],
+            timestamps: Some((1_000_000, 0)),
+        });
+    }
+
+    #[test]
+    fn test_tcp_options_unaligned() {
+        // SACK with one block right after an unknown option of odd length, then padding
+        let options = TcpOptions::parse(&[
+            0xfe, 0x03, 0x00, 0x05, 0x0a, 0x00, 0x00, 0x00, 0x01, 0x00, 0x00, 0x00, 0x02, 0x00, 0x09,
+        ]).unwrap();
+        assert_eq!(options.sack_blocks, [Some((1, 2)), None, None, None]);
+        assert_eq!(options.maximum_segment_size, None);
+    }
+
+    #[test]
+    fn test_tcp_options_malformed() {
+        assert_eq!(TcpOptions::parse(&[0x01, 0x08]), Err("option length missing"));
+        assert_eq!(TcpOptions::parse(&[0xfe, 0x00, 0x00, 0x00]), Err("option length too small"));
+        assert_eq!(TcpOptions::parse(&[0x08, 0x0a, 0x00, 0x00]), Err("option extends beyond the header"));
+        assert_eq!(TcpOptions::parse(&[0x02, 0x03, 0x05, 0x00]), Err("option has an invalid length"));
+
+        // data offset of 4 words
+        let mut segment = [0u8; 20];
+        segment[12] = 0x40;
+        assert!(matches!(
+            TcpHeader::try_take(&segment, &[]),
+            PacketDissection::Malformed { reason: "data offset smaller than the header" },
+        ));
+    }
+}
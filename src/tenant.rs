@@ -0,0 +1,149 @@
+use std::fmt;
+use std::net::IpAddr;
+use std::str::FromStr;
+
+use crate::ip::mask_address;
+
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum TenantParseError {
+    MissingName(String),
+    InvalidVlanId(String),
+    InvalidSubnet(String),
+}
+impl fmt::Display for TenantParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::MissingName(s)
+                => write!(f, "tenant {:?} is not of the form name=vlan:ID or name=address/prefix", s),
+            Self::InvalidVlanId(s)
+                => write!(f, "invalid VLAN ID {:?}", s),
+            Self::InvalidSubnet(s)
+                => write!(f, "invalid subnet {:?}", s),
+        }
+    }
+}
+impl std::error::Error for TenantParseError {
+}
+
+
+/// Decides which traffic belongs to a tenant.
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub enum TenantSelector {
+    Vlan(u16),
+    Subnet { network: IpAddr, prefix_length: u8 },
+}
+impl TenantSelector {
+    pub fn matches(&self, vlan_id: Option<u16>, client: IpAddr) -> bool {
+        match self {
+            Self::Vlan(id) => vlan_id == Some(*id),
+            Self::Subnet { network, prefix_length } => {
+                network.is_ipv4() == client.is_ipv4()
+                    && mask_address(client, *prefix_length) == *network
+            },
+        }
+    }
+}
+impl FromStr for TenantSelector {
+    type Err = TenantParseError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if let Some(vlan_id_str) = s.strip_prefix("vlan:") {
+            let vlan_id: u16 = vlan_id_str.parse()
+                .map_err(|_| TenantParseError::InvalidVlanId(vlan_id_str.to_owned()))?;
+            if vlan_id == 0 || vlan_id >= 0xFFF {
+                // 0 means "no VLAN" and 0xFFF is reserved
+                return Err(TenantParseError::InvalidVlanId(vlan_id_str.to_owned()));
+            }
+            return Ok(Self::Vlan(vlan_id));
+        }
+
+        let (address_str, prefix_length_str) = s.split_once('/')
+            .ok_or_else(|| TenantParseError::InvalidSubnet(s.to_owned()))?;
+        let address: IpAddr = address_str.parse()
+            .map_err(|_| TenantParseError::InvalidSubnet(s.to_owned()))?;
+        let prefix_length: u8 = prefix_length_str.parse()
+            .map_err(|_| TenantParseError::InvalidSubnet(s.to_owned()))?;
+        let max_prefix_length = if address.is_ipv4() { 32 } else { 128 };
+        if prefix_length > max_prefix_length {
+            return Err(TenantParseError::InvalidSubnet(s.to_owned()));
+        }
+        Ok(Self::Subnet {
+            network: mask_address(address, prefix_length),
+            prefix_length,
+        })
+    }
+}
+
+
+/// Assigns traffic to named tenants, e.g. the customers of a managed service provider.
+///
+/// The rules are checked in order; the first matching one decides the tenant.
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct TenantMap {
+    rules: Vec<(String, TenantSelector)>,
+}
+impl TenantMap {
+    pub fn new() -> Self {
+        Self {
+            rules: Vec::new(),
+        }
+    }
+
+    /// Adds a rule given as `name=vlan:ID` or `name=address/prefix`.
+    pub fn add_rule(&mut self, rule: &str) -> Result<(), TenantParseError> {
+        let (name, selector_str) = rule.split_once('=')
+            .ok_or_else(|| TenantParseError::MissingName(rule.to_owned()))?;
+        if name.len() == 0 {
+            return Err(TenantParseError::MissingName(rule.to_owned()));
+        }
+        let selector = selector_str.parse()?;
+        self.rules.push((name.to_owned(), selector));
+        Ok(())
+    }
+
+    /// The names of all tenants, in the order in which they were first mentioned.
+    pub fn tenant_names(&self) -> Vec<&str> {
+        let mut names: Vec<&str> = Vec::new();
+        for (name, _selector) in &self.rules {
+            if !names.contains(&name.as_str()) {
+                names.push(name.as_str());
+            }
+        }
+        names
+    }
+
+    pub fn tenant(&self, vlan_id: Option<u16>, client: IpAddr) -> Option<&str> {
+        self.rules.iter()
+            .find(|(_name, selector)| selector.matches(vlan_id, client))
+            .map(|(name, _selector)| name.as_str())
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::TenantMap;
+
+    #[test]
+    fn test_tenant_map() {
+        let mut tenants = TenantMap::new();
+        tenants.add_rule("acme=vlan:100").unwrap();
+        tenants.add_rule("globex=192.0.2.0/25").unwrap();
+        tenants.add_rule("globex=2001:db8:1::/48").unwrap();
+        tenants.add_rule("initech=192.0.2.200/24").unwrap();
+        assert!(tenants.add_rule("vlan:100").is_err());
+        assert!(tenants.add_rule("acme=vlan:4095").is_err());
+        assert!(tenants.add_rule("acme=192.0.2.0/33").is_err());
+        assert!(tenants.add_rule("acme=192.0.2.0").is_err());
+
+        assert_eq!(tenants.tenant_names(), vec!["acme", "globex", "initech"]);
+
+        // VLANs take precedence as their rule comes first
+        assert_eq!(tenants.tenant(Some(100), "192.0.2.1".parse().unwrap()), Some("acme"));
+        assert_eq!(tenants.tenant(Some(101), "192.0.2.1".parse().unwrap()), Some("globex"));
+        assert_eq!(tenants.tenant(None, "192.0.2.129".parse().unwrap()), Some("initech"));
+        assert_eq!(tenants.tenant(None, "2001:db8:1:2::3".parse().unwrap()), Some("globex"));
+        assert_eq!(tenants.tenant(None, "198.51.100.1".parse().unwrap()), None);
+    }
+}
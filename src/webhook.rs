@@ -0,0 +1,57 @@
+use std::collections::HashMap;
+
+use chrono::{DateTime, Duration, Utc};
+use serde_json::{json, Value};
+use tracing::{debug, error};
+
+use crate::http::{HttpUrl, post};
+
+
+/// Posts alerts as JSON to a webhook, suppressing repetitions of the same alert within the
+/// deduplication window.
+#[derive(Debug)]
+pub struct WebhookNotifier {
+    url: HttpUrl,
+    dedup_window: Duration,
+    key_to_last_sent: HashMap<String, DateTime<Utc>>,
+}
+impl WebhookNotifier {
+    pub fn new(url: HttpUrl, dedup_window: Duration) -> Self {
+        Self {
+            url,
+            dedup_window,
+            key_to_last_sent: HashMap::new(),
+        }
+    }
+
+    /// Sends an alert in the background unless an alert with the same key has been sent recently.
+    ///
+    /// Must be called from within the Tokio runtime.
+    pub fn notify(&mut self, timestamp: DateTime<Utc>, kind: &str, dedup_key: &str, summary: String, details: Value) {
+        let key = format!("{}/{}", kind, dedup_key);
+        if let Some(last_sent) = self.key_to_last_sent.get(&key) {
+            if timestamp < *last_sent + self.dedup_window {
+                debug!("suppressing repeated alert {}", key);
+                return;
+            }
+        }
+        self.key_to_last_sent.insert(key, timestamp);
+
+        // forget alerts that can no longer suppress anything
+        let dedup_window = self.dedup_window;
+        self.key_to_last_sent.retain(|_k, last_sent| timestamp < *last_sent + dedup_window);
+
+        let body = json!({
+            "kind": kind,
+            "timestamp": timestamp.to_rfc3339(),
+            "summary": summary,
+            "details": details,
+        }).to_string();
+        let url = self.url.clone();
+        tokio::spawn(async move {
+            if let Err(e) = post(&url, &[("Content-Type", "application/json")], body.as_bytes()).await {
+                error!("giving up on webhook alert: {}", e);
+            }
+        });
+    }
+}
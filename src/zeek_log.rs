@@ -0,0 +1,319 @@
+use std::collections::{HashMap, VecDeque};
+use std::fs::{File, OpenOptions};
+use std::io::{self, BufWriter, Write};
+use std::net::SocketAddr;
+use std::path::Path;
+
+use chrono::{DateTime, Duration, Utc};
+use hickory_proto::op::ResponseCode;
+use hickory_proto::rr::RecordType;
+use tracing::error;
+
+use crate::json_log::response_code_mnemonic;
+use crate::sink::{DnsMessageEvent, EventSink};
+
+
+const FIELDS: [(&str, &str); 24] = [
+    ("ts", "time"),
+    ("uid", "string"),
+    ("id.orig_h", "addr"),
+    ("id.orig_p", "port"),
+    ("id.resp_h", "addr"),
+    ("id.resp_p", "port"),
+    ("proto", "enum"),
+    ("trans_id", "count"),
+    ("rtt", "interval"),
+    ("query", "string"),
+    ("qclass", "count"),
+    ("qclass_name", "string"),
+    ("qtype", "count"),
+    ("qtype_name", "string"),
+    ("rcode", "count"),
+    ("rcode_name", "string"),
+    ("AA", "bool"),
+    ("TC", "bool"),
+    ("RD", "bool"),
+    ("RA", "bool"),
+    ("Z", "count"),
+    ("answers", "vector[string]"),
+    ("TTLs", "vector[interval]"),
+    ("rejected", "bool"),
+];
+const UNSET: &str = "-";
+const BASE62_DIGITS: &[u8; 62] = b"0123456789ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz";
+
+
+/// Derives a Zeek-style connection UID from a flow ID.
+fn connection_uid(flow_id: u64) -> String {
+    let mut digits = Vec::new();
+    let mut rest = flow_id;
+    loop {
+        digits.push(BASE62_DIGITS[usize::try_from(rest % 62).unwrap()]);
+        rest /= 62;
+        if rest == 0 {
+            break;
+        }
+    }
+    digits.push(b'C');
+    digits.reverse();
+    String::from_utf8(digits).unwrap()
+}
+
+
+/// Escapes the characters that would break the TSV structure the way Zeek does.
+fn escape_field(value: &str) -> String {
+    let mut escaped = String::with_capacity(value.len());
+    for c in value.chars() {
+        if c.is_ascii_control() || c == '\\' {
+            escaped.push_str(&format!("\\x{:02x}", u32::from(c)));
+        } else {
+            escaped.push(c);
+        }
+    }
+    escaped
+}
+
+
+fn format_time(timestamp: DateTime<Utc>) -> String {
+    format!("{}.{:06}", timestamp.timestamp(), timestamp.timestamp_subsec_micros())
+}
+
+
+#[derive(Clone, Debug, Eq, Hash, PartialEq)]
+struct TransactionKey {
+    flow_id: u64,
+    transaction_id: u16,
+    name: String,
+    record_type: RecordType,
+}
+
+
+#[derive(Clone, Debug)]
+struct PendingQuery {
+    timestamp: DateTime<Utc>,
+    client: SocketAddr,
+    server: SocketAddr,
+    over_tcp: bool,
+}
+
+
+/// Writes a Zeek-compatible `dns.log`, with one line per query and its response.
+///
+/// Queries that remain unanswered for longer than the correlation window are written without a
+/// response code.
+pub struct ZeekLogSink<W: Write> {
+    writer: W,
+    correlation_window: Duration,
+    pending: HashMap<TransactionKey, PendingQuery>,
+    pending_order: VecDeque<(DateTime<Utc>, TransactionKey)>,
+}
+impl ZeekLogSink<BufWriter<File>> {
+    pub fn open<P: AsRef<Path>>(path: P, correlation_window: Duration) -> Result<Self, io::Error> {
+        let file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)?;
+        Self::new(BufWriter::new(file), correlation_window, Utc::now())
+    }
+}
+impl<W: Write> ZeekLogSink<W> {
+    pub fn new(mut writer: W, correlation_window: Duration, now: DateTime<Utc>) -> Result<Self, io::Error> {
+        writeln!(writer, "#separator \\x09")?;
+        writeln!(writer, "#set_separator\t,")?;
+        writeln!(writer, "#empty_field\t(empty)")?;
+        writeln!(writer, "#unset_field\t{}", UNSET)?;
+        writeln!(writer, "#path\tdns")?;
+        writeln!(writer, "#open\t{}", now.format("%Y-%m-%d-%H-%M-%S"))?;
+        let names: Vec<&str> = FIELDS.iter().map(|(n, _)| *n).collect();
+        let types: Vec<&str> = FIELDS.iter().map(|(_, t)| *t).collect();
+        writeln!(writer, "#fields\t{}", names.join("\t"))?;
+        writeln!(writer, "#types\t{}", types.join("\t"))?;
+        Ok(Self {
+            writer,
+            correlation_window,
+            pending: HashMap::new(),
+            pending_order: VecDeque::new(),
+        })
+    }
+
+    fn write_record(&mut self, key: &TransactionKey, query: &PendingQuery, response: Option<(DateTime<Utc>, ResponseCode)>) -> Result<(), io::Error> {
+        let (rtt, rcode, rcode_name) = match response {
+            Some((response_timestamp, rc)) => {
+                let rtt = (response_timestamp - query.timestamp).num_microseconds()
+                    .map(|us| format!("{}.{:06}", us / 1_000_000, us % 1_000_000))
+                    .unwrap_or_else(|| UNSET.to_owned());
+                (rtt, u16::from(rc).to_string(), response_code_mnemonic(rc))
+            },
+            None => (UNSET.to_owned(), UNSET.to_owned(), UNSET.to_owned()),
+        };
+        let name = key.name.trim_end_matches('.');
+        let fields = [
+            format_time(query.timestamp),
+            connection_uid(key.flow_id),
+            query.client.ip().to_string(),
+            query.client.port().to_string(),
+            query.server.ip().to_string(),
+            query.server.port().to_string(),
+            if query.over_tcp { "tcp".to_owned() } else { "udp".to_owned() },
+            key.transaction_id.to_string(),
+            rtt,
+            if name.len() == 0 { "(empty)".to_owned() } else { escape_field(name) },
+            // we only look at questions of class IN
+            "1".to_owned(),
+            "C_INTERNET".to_owned(),
+            u16::from(key.record_type).to_string(),
+            key.record_type.to_string(),
+            rcode,
+            rcode_name,
+        ];
+        // the flags and answers are not part of the events
+        let unset_count = FIELDS.len() - fields.len();
+        let unset_fields = vec![UNSET; unset_count];
+        writeln!(self.writer, "{}\t{}", fields.join("\t"), unset_fields.join("\t"))
+    }
+
+    /// Writes the queries that have remained unanswered since before the given time.
+    fn expire(&mut self, before: DateTime<Utc>) -> Result<(), io::Error> {
+        while let Some((timestamp, _)) = self.pending_order.front() {
+            if *timestamp >= before {
+                break;
+            }
+            let (timestamp, key) = self.pending_order.pop_front().unwrap();
+            let is_current = self.pending.get(&key)
+                .map(|q| q.timestamp == timestamp)
+                .unwrap_or(false);
+            if is_current {
+                let query = self.pending.remove(&key).unwrap();
+                self.write_record(&key, &query, None)?;
+            }
+        }
+        Ok(())
+    }
+
+    fn process(&mut self, event: &DnsMessageEvent<'_>) -> Result<(), io::Error> {
+        self.expire(event.timestamp - self.correlation_window)?;
+
+        let key = TransactionKey {
+            flow_id: event.flow_id(),
+            transaction_id: event.transaction_id,
+            name: event.name.to_lowercase(),
+            record_type: event.record_type,
+        };
+        let query = PendingQuery {
+            timestamp: event.timestamp,
+            client: event.client(),
+            server: event.server(),
+            over_tcp: event.over_tcp,
+        };
+        match event.response_code {
+            None => {
+                // a retransmission supersedes the original query
+                if let Some(previous) = self.pending.insert(key.clone(), query) {
+                    self.write_record(&key, &previous, None)?;
+                }
+                self.pending_order.push_back((event.timestamp, key));
+            },
+            Some(rc) => {
+                // unsolicited responses are logged too, timed by their arrival
+                let query = self.pending.remove(&key).unwrap_or(query);
+                self.write_record(&key, &query, Some((event.timestamp, rc)))?;
+            },
+        }
+        Ok(())
+    }
+
+    fn finish(&mut self) -> Result<(), io::Error> {
+        let mut remaining: Vec<(TransactionKey, PendingQuery)> = self.pending.drain().collect();
+        remaining.sort_by_key(|(_, q)| q.timestamp);
+        for (key, query) in &remaining {
+            self.write_record(key, query, None)?;
+        }
+        self.pending_order.clear();
+        writeln!(self.writer, "#close\t{}", Utc::now().format("%Y-%m-%d-%H-%M-%S"))?;
+        self.writer.flush()
+    }
+}
+impl<W: Write> EventSink for ZeekLogSink<W> {
+    fn emit(&mut self, event: &DnsMessageEvent<'_>) {
+        if let Err(e) = self.process(event) {
+            error!("failed to write Zeek DNS log: {}", e);
+        }
+    }
+
+    fn close(mut self: Box<Self>) -> Option<tokio::task::JoinHandle<()>> {
+        if let Err(e) = self.finish() {
+            error!("failed to write Zeek DNS log: {}", e);
+        }
+        None
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+
+    use chrono::{Duration, TimeZone, Utc};
+    use hickory_proto::op::ResponseCode;
+    use hickory_proto::rr::RecordType;
+
+    use super::{connection_uid, ZeekLogSink};
+    use crate::sink::DnsMessageEvent;
+
+    #[test]
+    fn test_connection_uid() {
+        assert_eq!(connection_uid(0), "C0");
+        assert_eq!(connection_uid(62 * 62 + 61), "C10z");
+    }
+
+    #[test]
+    fn test_zeek_log() {
+        let start = Utc.with_ymd_and_hms(2022, 10, 1, 12, 0, 0).unwrap();
+        let mut sink = ZeekLogSink::new(Vec::new(), Duration::seconds(5), start).unwrap();
+        let query = DnsMessageEvent {
+            timestamp: start,
+            source: "192.0.2.1:54321".parse().unwrap(),
+            destination: "192.0.2.53:53".parse().unwrap(),
+            transaction_id: 0x1234,
+            name: "example.com.",
+            record_type: RecordType::A,
+            over_tcp: false,
+            response_code: None,
+            labels: &BTreeMap::new(),
+        };
+        sink.process(&query).unwrap();
+        sink.process(&DnsMessageEvent {
+            timestamp: start + Duration::milliseconds(25),
+            source: query.destination,
+            destination: query.source,
+            response_code: Some(ResponseCode::NXDomain),
+            ..query.clone()
+        }).unwrap();
+        sink.process(&DnsMessageEvent {
+            timestamp: start + Duration::seconds(1),
+            name: "example.net.",
+            ..query.clone()
+        }).unwrap();
+        sink.process(&DnsMessageEvent {
+            timestamp: start + Duration::seconds(10),
+            name: "example.org.",
+            ..query.clone()
+        }).unwrap();
+
+        let log = String::from_utf8(sink.writer.clone()).unwrap();
+        let records: Vec<Vec<&str>> = log.lines()
+            .filter(|l| !l.starts_with('#'))
+            .map(|l| l.split('\t').collect())
+            .collect();
+        assert_eq!(records.len(), 2);
+        assert_eq!(records[0][..16], [
+            "1664625600.000000", &connection_uid(query.flow_id()), "192.0.2.1", "54321", "192.0.2.53", "53",
+            "udp", "4660", "0.025000", "example.com", "1", "C_INTERNET", "1", "A", "3", "NXDOMAIN",
+        ]);
+        assert_eq!(records[0].len(), 24);
+        // expired without a response
+        assert_eq!(records[1][9], "example.net");
+        assert_eq!(records[1][15], "-");
+        assert!(log.lines().any(|l| l == "#path\tdns"));
+    }
+}
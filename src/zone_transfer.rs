@@ -0,0 +1,384 @@
+use std::collections::{HashMap, VecDeque};
+use std::net::SocketAddr;
+
+use chrono::{DateTime, Duration, Utc};
+use hickory_proto::op::ResponseCode;
+
+use crate::dns::{DnsHeader, DnsName, Questions, RecordHeaders};
+use crate::packet::PacketDissection;
+use crate::tcp_udp::{TcpFlags, TcpHeader};
+
+
+const SOA_RECORD_TYPE: u16 = 6;
+
+// transfers without any traffic for this long are given up on
+const IDLE_TIMEOUT_SECS: i64 = 120;
+
+// beyond this, requested transfers are not followed; each may hold up to a message in memory
+const MAX_TRANSFERS: usize = 256;
+
+
+#[derive(Clone, Copy, Debug, Eq, Hash, Ord, PartialEq, PartialOrd)]
+struct TransferKey {
+    client: SocketAddr,
+    server: SocketAddr,
+}
+
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+struct TransferState {
+    vlan_id: Option<u16>,
+    incremental: bool, // IXFR was requested
+    next_sequence_number: Option<u32>,
+    pending: Vec<u8>, // the beginning of a message continued in the next segment
+    in_sync: bool, // false once a segment has been missed, as the message boundaries are lost
+    message_count: u64,
+    byte_count: u64,
+    record_count: u64,
+    first_serial: Option<u32>,
+    incremental_format: bool,
+    first_serial_count: u64,
+    last_seen: DateTime<Utc>,
+}
+impl TransferState {
+    fn new(vlan_id: Option<u16>, incremental: bool, now: DateTime<Utc>) -> Self {
+        Self {
+            vlan_id,
+            incremental,
+            next_sequence_number: None,
+            pending: Vec::new(),
+            in_sync: true,
+            message_count: 0,
+            byte_count: 0,
+            record_count: 0,
+            first_serial: None,
+            incremental_format: false,
+            first_serial_count: 0,
+            last_seen: now,
+        }
+    }
+
+    /// Goes through the answers of a message of the transfer. Returns whether the transfer has
+    /// ended and, if so, whether it was complete.
+    ///
+    /// A transfer starts with the SOA record of the zone and ends with the same SOA record. An
+    /// incremental transfer additionally brackets each set of changes with the SOA records of the
+    /// versions before and after, so the SOA record of the newest version is seen three times
+    /// (RFC1995 § 4).
+    fn evaluate_message(&mut self, message: &[u8]) -> Option<bool> {
+        let header = match DnsHeader::try_take(message) {
+            PacketDissection::Success { header, .. } => header,
+            _ => return Some(false),
+        };
+        if header.response_code() != ResponseCode::NoError {
+            return Some(false);
+        }
+
+        // only the first message repeats the question, but skip it wherever it appears
+        let mut questions = Questions::new(message, &header);
+        if questions.by_ref().any(|q| q.is_err()) {
+            return Some(false);
+        }
+        for record in RecordHeaders::new(message, questions.offset(), header.answer_count) {
+            let record = match record {
+                Ok(r) => r,
+                Err(_) => return Some(false),
+            };
+            let serial = if record.record_type == SOA_RECORD_TYPE {
+                match soa_serial(message, record.data_offset) {
+                    Some(s) => Some(s),
+                    None => return Some(false),
+                }
+            } else {
+                None
+            };
+
+            match self.record_count {
+                0 => {
+                    if serial.is_none() {
+                        return Some(false);
+                    }
+                    self.first_serial = serial;
+                },
+                1 => {
+                    // an IXFR response may also be a full transfer
+                    self.incremental_format = self.incremental && serial.is_some() && serial != self.first_serial;
+                },
+                _ => {},
+            }
+            self.record_count += 1;
+
+            if serial.is_some() && serial == self.first_serial {
+                self.first_serial_count += 1;
+                let closing_count = if self.incremental_format { 3 } else { 2 };
+                if self.first_serial_count == closing_count {
+                    return Some(true);
+                }
+            }
+        }
+        None
+    }
+
+    /// Adds the next part of the server's byte stream. Returns whether the transfer has ended and,
+    /// if so, whether it was complete.
+    fn add_payload(&mut self, sequence_number: u32, payload: &[u8]) -> Option<bool> {
+        let mut payload = payload;
+        let mut start = sequence_number;
+        if let Some(expected) = self.next_sequence_number {
+            let offset = sequence_number.wrapping_sub(expected) as i32;
+            if offset < 0 {
+                // skip what we have already seen of a retransmission
+                let seen = usize::try_from(offset.unsigned_abs()).unwrap();
+                if seen >= payload.len() {
+                    return None;
+                }
+                payload = &payload[seen..];
+                start = expected;
+            } else if offset > 0 {
+                self.in_sync = false;
+                self.pending.clear();
+            }
+        }
+        self.next_sequence_number = Some(start.wrapping_add(payload.len() as u32));
+        self.byte_count += u64::try_from(payload.len()).unwrap();
+        if !self.in_sync {
+            return None;
+        }
+
+        // messages are prefixed with their length (RFC1035 § 4.2.2)
+        self.pending.extend_from_slice(payload);
+        while self.pending.len() >= 2 {
+            let message_length = usize::from(u16::from_be_bytes(self.pending[0..2].try_into().unwrap()));
+            if self.pending.len() < 2 + message_length {
+                break;
+            }
+            let message: Vec<u8> = self.pending.drain(..2 + message_length).skip(2).collect();
+            self.message_count += 1;
+            if let Some(complete) = self.evaluate_message(&message) {
+                return Some(complete);
+            }
+        }
+        None
+    }
+}
+
+
+/// Reads the serial number from the data of an SOA record, which follows the names of the primary
+/// server and of the responsible mailbox.
+fn soa_serial(message: &[u8], data_offset: usize) -> Option<u32> {
+    let (_primary, mailbox_offset) = DnsName::read(message, data_offset).ok()?;
+    let (_mailbox, serial_offset) = DnsName::read(message, mailbox_offset).ok()?;
+    let serial_bytes = message.get(serial_offset..serial_offset+4)?;
+    Some(u32::from_be_bytes(serial_bytes.try_into().unwrap()))
+}
+
+
+/// A zone transfer over TCP that has ended, either with its closing SOA record or otherwise.
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub struct FinishedTransfer {
+    pub client: SocketAddr,
+    pub server: SocketAddr,
+    pub vlan_id: Option<u16>,
+    pub message_count: u64,
+    pub byte_count: u64, // of the server's byte stream, including the length prefixes
+    pub complete: bool,
+}
+
+
+/// Follows zone transfers over TCP, whose responses span many messages (of which only the first
+/// repeats the question) and many more segments.
+///
+/// The stream from the server is put back together as long as no segment is missing; after a gap,
+/// the bytes are still counted, but the transfer only ends when the connection does.
+#[derive(Clone, Debug)]
+pub struct ZoneTransferTracker {
+    transfers: HashMap<TransferKey, TransferState>,
+    expiry_queue: VecDeque<(DateTime<Utc>, TransferKey)>,
+}
+impl ZoneTransferTracker {
+    pub fn new() -> Self {
+        Self {
+            transfers: HashMap::new(),
+            expiry_queue: VecDeque::new(),
+        }
+    }
+
+    /// Gives up on the transfers that have been idle for too long, returning them as incomplete.
+    pub fn expire(&mut self, now: DateTime<Utc>) -> Vec<FinishedTransfer> {
+        let cutoff = now - Duration::seconds(IDLE_TIMEOUT_SECS);
+        let mut expired = Vec::new();
+        while let Some((timestamp, key)) = self.expiry_queue.front().copied() {
+            if timestamp >= cutoff {
+                break;
+            }
+            self.expiry_queue.pop_front();
+
+            // the transfer might have seen traffic since it was queued
+            let last_seen = self.transfers.get(&key).map(|t| t.last_seen);
+            match last_seen {
+                Some(ls) if ls < cutoff => {
+                    let state = self.transfers.remove(&key).unwrap();
+                    if state.byte_count > 0 {
+                        expired.push(finished(key, &state, false));
+                    }
+                },
+                Some(ls) => self.expiry_queue.push_back((ls, key)),
+                None => {},
+            }
+        }
+        expired
+    }
+
+    /// Starts following the connection on which a client has requested a zone transfer.
+    pub fn expect(&mut self, now: DateTime<Utc>, client: SocketAddr, server: SocketAddr, vlan_id: Option<u16>, incremental: bool) {
+        let key = TransferKey { client, server };
+        if self.transfers.len() >= MAX_TRANSFERS && !self.transfers.contains_key(&key) {
+            return;
+        }
+        self.transfers.insert(key, TransferState::new(vlan_id, incremental, now));
+        self.expiry_queue.push_back((now, key));
+    }
+
+    /// Processes a segment and returns the transfer it ends, if any.
+    pub fn observe(&mut self, now: DateTime<Utc>, source: SocketAddr, destination: SocketAddr, header: &TcpHeader, payload: &[u8]) -> Option<FinishedTransfer> {
+        let from_server = TransferKey { client: destination, server: source };
+        let from_client = TransferKey { client: source, server: destination };
+        let closing = header.flags.intersects(TcpFlags::FIN | TcpFlags::RST);
+
+        if let Some(state) = self.transfers.get_mut(&from_server) {
+            state.last_seen = now;
+            if payload.len() > 0 {
+                if let Some(complete) = state.add_payload(header.sequence_number, payload) {
+                    let state = self.transfers.remove(&from_server).unwrap();
+                    return Some(finished(from_server, &state, complete));
+                }
+            }
+            if closing {
+                let state = self.transfers.remove(&from_server).unwrap();
+                return Some(finished(from_server, &state, false));
+            }
+        } else if closing {
+            // the client has given up
+            let state = self.transfers.remove(&from_client)?;
+            return Some(finished(from_client, &state, false));
+        }
+        None
+    }
+}
+
+
+fn finished(key: TransferKey, state: &TransferState, complete: bool) -> FinishedTransfer {
+    FinishedTransfer {
+        client: key.client,
+        server: key.server,
+        vlan_id: state.vlan_id,
+        message_count: state.message_count,
+        byte_count: state.byte_count,
+        complete,
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use chrono::{Duration, TimeZone, Utc};
+
+    use super::{FinishedTransfer, ZoneTransferTracker};
+    use crate::tcp_udp::{TcpFlags, TcpHeader};
+
+    fn name(labels: &[&str]) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        for label in labels {
+            bytes.push(label.len().try_into().unwrap());
+            bytes.extend_from_slice(label.as_bytes());
+        }
+        bytes.push(0);
+        bytes
+    }
+
+    fn record(record_type: u16, data: &[u8]) -> Vec<u8> {
+        let mut bytes = name(&["example", "com"]);
+        bytes.extend_from_slice(&record_type.to_be_bytes());
+        bytes.extend_from_slice(&1u16.to_be_bytes()); // IN
+        bytes.extend_from_slice(&3600u32.to_be_bytes());
+        bytes.extend_from_slice(&u16::try_from(data.len()).unwrap().to_be_bytes());
+        bytes.extend_from_slice(data);
+        bytes
+    }
+
+    fn soa(serial: u32) -> Vec<u8> {
+        let mut data = name(&["ns1", "example", "com"]);
+        data.extend_from_slice(&name(&["hostmaster", "example", "com"]));
+        data.extend_from_slice(&serial.to_be_bytes());
+        data.extend_from_slice(&[0; 16]); // refresh, retry, expire, minimum
+        record(6, &data)
+    }
+
+    /// Builds a length-prefixed response message; only the first repeats the question.
+    fn message(with_question: bool, answers: &[Vec<u8>]) -> Vec<u8> {
+        let mut bytes = vec![0x12, 0x34, 0x84, 0x00];
+        bytes.extend_from_slice(&u16::from(with_question).to_be_bytes());
+        bytes.extend_from_slice(&u16::try_from(answers.len()).unwrap().to_be_bytes());
+        bytes.extend_from_slice(&[0, 0, 0, 0]);
+        if with_question {
+            bytes.extend_from_slice(&name(&["example", "com"]));
+            bytes.extend_from_slice(&[0, 252, 0, 1]); // AXFR IN
+        }
+        for answer in answers {
+            bytes.extend_from_slice(answer);
+        }
+        let mut prefixed = u16::try_from(bytes.len()).unwrap().to_be_bytes().to_vec();
+        prefixed.extend_from_slice(&bytes);
+        prefixed
+    }
+
+    #[test]
+    fn test_full_transfer() {
+        let start = Utc.with_ymd_and_hms(2022, 10, 1, 12, 0, 0).unwrap();
+        let client = "192.0.2.1:54325".parse().unwrap();
+        let server = "192.0.2.53:53".parse().unwrap();
+        let segment = |sequence_number, flags| TcpHeader { sequence_number, flags, ..TcpHeader::default() };
+        let address = record(1, &[192, 0, 2, 80]);
+
+        let mut stream = message(true, &[soa(2022100101), address.clone()]);
+        stream.extend_from_slice(&message(false, &[address.clone(), address.clone()]));
+        stream.extend_from_slice(&message(false, &[address.clone(), soa(2022100101)]));
+
+        // split into segments regardless of the message boundaries
+        let mut tracker = ZoneTransferTracker::new();
+        tracker.expect(start, client, server, None, false);
+        let ack = TcpFlags::PSH | TcpFlags::ACK;
+        let mut sequence_number = 1000u32;
+        let mut result = None;
+        for (i, chunk) in stream.chunks(40).enumerate() {
+            assert_eq!(result, None);
+            result = tracker.observe(start + Duration::milliseconds(i as i64), server, client, &segment(sequence_number, ack), chunk);
+
+            // retransmissions are not counted twice
+            assert_eq!(tracker.observe(start, server, client, &segment(sequence_number, ack), chunk), None);
+            sequence_number += u32::try_from(chunk.len()).unwrap();
+        }
+        assert_eq!(result, Some(FinishedTransfer {
+            client,
+            server,
+            vlan_id: None,
+            message_count: 3,
+            byte_count: stream.len().try_into().unwrap(),
+            complete: true,
+        }));
+
+        // an incremental transfer brackets each change with the old and new SOA records
+        tracker.expect(start, client, server, None, true);
+        let stream = message(true, &[soa(3), soa(2), address.clone(), soa(3), address.clone(), soa(3)]);
+        let result = tracker.observe(start, server, client, &segment(5000, ack), &stream);
+        assert_eq!(result.map(|r| r.complete), Some(true));
+
+        // missing segments only let the end of the connection end the transfer
+        tracker.expect(start, client, server, None, false);
+        let stream = message(true, &[soa(7), address.clone(), soa(7)]);
+        assert_eq!(tracker.observe(start, server, client, &segment(1, ack), &stream[..20]), None);
+        assert_eq!(tracker.observe(start, server, client, &segment(41, ack), &stream[40..]), None);
+        let result = tracker.observe(start, server, client, &segment(1, TcpFlags::FIN | TcpFlags::ACK), &[]);
+        assert_eq!(result.map(|r| (r.byte_count, r.complete)), Some((20 + u64::try_from(stream.len() - 40).unwrap(), false)));
+    }
+}